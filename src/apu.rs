@@ -0,0 +1,497 @@
+/// M-cycles between steps of the 512 Hz frame sequencer.
+const FRAME_SEQUENCER_CYCLES: usize = 2048;
+
+/// M-cycles per second.
+const CYCLE_RATE: usize = 1_048_576;
+
+/// The stereo samples per second the mixer produces.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// The part of a square wave period that is high for each duty setting.
+const DUTY_CYCLES: [f32; 4] = [0.125, 0.25, 0.5, 0.75];
+
+/// How much of its charge the capacitor that filters out the DC offset of the
+/// output keeps per sample, following Pan Docs' 0.999958 per T-cycle.
+const CAPACITOR_CHARGE: f32 = 0.996;
+
+/// Bits of 0xff10..=0xff25 that always read as set.
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10-NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf, // NR20-NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30-NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf, // NR40-NR44
+    0x00, 0x00, // NR50-NR51
+];
+
+const NR52: usize = 0x16;
+
+/// The state of a single sound channel as it would be heard.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelState {
+    pub enabled: bool,
+    /// The frequency of the produced tone in Hz. For the noise channel, this
+    /// is the rate at which the noise is clocked.
+    pub frequency: f32,
+    /// The current volume, from 0 to 15.
+    pub volume: u8,
+    /// The amount of times the channel has been triggered.
+    pub triggers: usize,
+}
+
+#[derive(Default)]
+struct Channel {
+    enabled: bool,
+    volume: u8,
+    envelope_timer: u8,
+    length: u16,
+    triggers: usize,
+    /// How far the channel is into the period of its waveform, from 0 to 1.
+    /// For the noise channel, this counts towards the next shift instead.
+    phase: f32,
+    /// The noise channel's linear feedback shift register.
+    lfsr: u16,
+}
+
+/// The sound registers and the channel state derived from them, mixed into
+/// stereo samples when [`Apu::sampling`] is set.
+///
+/// The channels are sampled at [`SAMPLE_RATE`] rather than emulated cycle by
+/// cycle, which is plenty for listening and recording but doesn't reproduce
+/// tricks like changing the wave RAM while it plays.
+pub struct Apu {
+    registers: [u8; 0x17],
+    pub wave_ram: [u8; 0x10],
+    channels: [Channel; 4],
+
+    clock: usize,
+    step: u8,
+
+    /// Whether samples are produced, which is only needed when something
+    /// listens to them.
+    pub(crate) sampling: bool,
+    sample_clock: usize,
+    samples: Vec<i16>,
+    capacitors: [f32; 2],
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            registers: [0; 0x17],
+            wave_ram: [0; 0x10],
+            channels: Default::default(),
+
+            clock: 0,
+            step: 0,
+
+            sampling: false,
+            sample_clock: 0,
+            samples: Vec::new(),
+            capacitors: [0.0; 2],
+        }
+    }
+
+    pub fn cycle(&mut self, cycles: usize) {
+        if self.sampling {
+            self.sample_clock += cycles * SAMPLE_RATE as usize;
+            while self.sample_clock >= CYCLE_RATE {
+                self.sample_clock -= CYCLE_RATE;
+
+                let [left, right] = self.mix();
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+
+        if !self.powered() {
+            return;
+        }
+
+        self.clock += cycles;
+        while self.clock >= FRAME_SEQUENCER_CYCLES {
+            self.clock -= FRAME_SEQUENCER_CYCLES;
+
+            if self.step & 1 == 0 {
+                self.clock_length();
+            }
+
+            if self.step == 7 {
+                self.clock_envelope();
+            }
+
+            self.step = (self.step + 1) % 8;
+        }
+    }
+
+    pub fn channel(&self, index: usize) -> ChannelState {
+        let channel = &self.channels[index];
+        let base = 5 * index;
+
+        let period = self.registers[base + 3] as u16 | (self.registers[base + 4] as u16 & 7) << 8;
+        let frequency = match index {
+            0 | 1 => 131072.0 / (2048 - period) as f32,
+            2 => 65536.0 / (2048 - period) as f32,
+            _ => {
+                let polynomial = self.registers[base + 3];
+                let divisor = match polynomial & 0b111 {
+                    0 => 0.5,
+                    r => r as f32,
+                };
+
+                524288.0 / divisor / (2 << (polynomial >> 4)) as f32
+            }
+        };
+
+        ChannelState {
+            enabled: channel.enabled,
+            frequency,
+            volume: channel.volume,
+            triggers: channel.triggers,
+        }
+    }
+
+    /// Takes the samples mixed since the last call, interleaved as left and
+    /// right.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Mixes the channels into a left and a right sample as NR50 and NR51 set
+    /// them up, moving every channel one sample further.
+    fn mix(&mut self) -> [i16; 2] {
+        let mut mixed = [0.0; 2];
+
+        if self.powered() {
+            let panning = self.registers[0x15];
+            for index in 0..4 {
+                let amplitude = self.sample_channel(index);
+                if panning & 0x10 << index != 0 {
+                    mixed[0] += amplitude;
+                }
+                if panning & 1 << index != 0 {
+                    mixed[1] += amplitude;
+                }
+            }
+
+            let volumes = self.registers[0x14];
+            mixed[0] *= ((volumes >> 4 & 7) + 1) as f32 / 8.0;
+            mixed[1] *= ((volumes & 7) + 1) as f32 / 8.0;
+        }
+
+        let mut samples = [0; 2];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let filtered = mixed[i] - self.capacitors[i];
+            self.capacitors[i] = mixed[i] - filtered * CAPACITOR_CHARGE;
+
+            // All four channels at full volume reach half the range, which
+            // leaves room for the filter to overshoot.
+            *sample = (filtered * i16::MAX as f32 / 8.0) as i16;
+        }
+
+        samples
+    }
+
+    /// The output of a channel's DAC, from -1 to 1, after moving the channel
+    /// one sample further.
+    fn sample_channel(&mut self, index: usize) -> f32 {
+        if !self.channels[index].enabled {
+            return 0.0;
+        }
+
+        let step = self.channel(index).frequency / SAMPLE_RATE as f32;
+        let duty = DUTY_CYCLES[(self.registers[5 * index + 1] >> 6) as usize];
+        let wave_volume = self.registers[0x0c] >> 5 & 0b11;
+        let short_noise = self.registers[0x12] & 0b1000 != 0;
+        let channel = &mut self.channels[index];
+
+        let level = match index {
+            0 | 1 => {
+                channel.phase = (channel.phase + step).fract();
+                if channel.phase < duty {
+                    channel.volume
+                } else {
+                    0
+                }
+            }
+            2 => {
+                channel.phase = (channel.phase + step).fract();
+                let position = (channel.phase * 32.0) as usize;
+                let byte = self.wave_ram[position / 2];
+                let nibble = if position & 1 == 0 {
+                    byte >> 4
+                } else {
+                    byte & 0xf
+                };
+
+                match wave_volume {
+                    0 => 0,
+                    volume => nibble >> (volume - 1),
+                }
+            }
+            _ => {
+                channel.phase += step;
+                while channel.phase >= 1.0 {
+                    channel.phase -= 1.0;
+
+                    let feedback = (channel.lfsr ^ channel.lfsr >> 1) & 1;
+                    channel.lfsr = channel.lfsr >> 1 | feedback << 14;
+                    if short_noise {
+                        channel.lfsr = channel.lfsr & !(1 << 6) | feedback << 6;
+                    }
+                }
+
+                if channel.lfsr & 1 == 0 {
+                    channel.volume
+                } else {
+                    0
+                }
+            }
+        };
+
+        level as f32 / 7.5 - 1.0
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        let offset = address as usize - 0xff10;
+
+        if offset == NR52 {
+            let enabled = self
+                .channels
+                .iter()
+                .enumerate()
+                .filter(|(_, channel)| channel.enabled)
+                .fold(0, |bits, (i, _)| bits | 1 << i);
+
+            self.registers[NR52] & 0x80 | 0x70 | enabled
+        } else {
+            self.registers[offset] | READ_MASKS[offset]
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let offset = address as usize - 0xff10;
+
+        if offset == NR52 {
+            self.registers[NR52] = value & 0x80;
+
+            if !self.powered() {
+                self.registers = [0; 0x17];
+                self.channels = Default::default();
+                self.step = 0;
+            }
+
+            return;
+        }
+
+        if !self.powered() {
+            return;
+        }
+
+        self.registers[offset] = value;
+
+        let index = offset / 5;
+        if index >= self.channels.len() {
+            return;
+        }
+
+        match offset % 5 {
+            1 => {
+                self.channels[index].length = if index == 2 {
+                    256 - value as u16
+                } else {
+                    64 - (value & 0x3f) as u16
+                };
+            }
+            0 | 2 if !self.dac_enabled(index) => self.channels[index].enabled = false,
+            4 if value & 0x80 != 0 => self.trigger(index),
+            _ => {}
+        }
+    }
+
+    fn powered(&self) -> bool {
+        self.registers[NR52] & 0x80 != 0
+    }
+
+    fn dac_enabled(&self, index: usize) -> bool {
+        if index == 2 {
+            self.registers[0x0a] & 0x80 != 0
+        } else {
+            self.registers[5 * index + 2] & 0xf8 != 0
+        }
+    }
+
+    fn trigger(&mut self, index: usize) {
+        let enabled = self.dac_enabled(index);
+        let envelope = self.registers[5 * index + 2];
+        let channel = &mut self.channels[index];
+
+        channel.enabled = enabled;
+        channel.triggers += 1;
+        channel.lfsr = 0x7fff;
+
+        if channel.length == 0 {
+            channel.length = if index == 2 { 256 } else { 64 };
+        }
+
+        if index == 2 {
+            channel.volume = [0, 15, 7, 3][(envelope >> 5 & 0b11) as usize];
+        } else {
+            channel.volume = envelope >> 4;
+            channel.envelope_timer = envelope & 0b111;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let length_enabled = self.registers[5 * index + 4] & 0x40 != 0;
+
+            if length_enabled && channel.length > 0 {
+                channel.length -= 1;
+
+                if channel.length == 0 {
+                    channel.enabled = false;
+                }
+            }
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        for index in [0, 1, 3].iter().copied() {
+            let envelope = self.registers[5 * index + 2];
+            let period = envelope & 0b111;
+            let channel = &mut self.channels[index];
+
+            if period == 0 || channel.envelope_timer == 0 {
+                continue;
+            }
+
+            channel.envelope_timer -= 1;
+            if channel.envelope_timer == 0 {
+                channel.envelope_timer = period;
+
+                if envelope & 0b1000 != 0 && channel.volume < 15 {
+                    channel.volume += 1;
+                } else if envelope & 0b1000 == 0 && channel.volume > 0 {
+                    channel.volume -= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Apu, CYCLE_RATE, FRAME_SEQUENCER_CYCLES, SAMPLE_RATE};
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xff26, 0x80);
+        apu
+    }
+
+    #[test]
+    fn trigger() {
+        let mut apu = powered_apu();
+        apu.write(0xff17, 0xf0);
+        apu.write(0xff18, 0xd6);
+        apu.write(0xff19, 0x86);
+
+        let channel = apu.channel(1);
+        assert!(channel.enabled);
+        assert_eq!(channel.volume, 15);
+        assert_eq!(channel.triggers, 1);
+        assert!((channel.frequency - 440.0).abs() < 1.0);
+        assert_eq!(apu.read(0xff26), 0xf2);
+
+        // Turning off the DAC disables the channel.
+        apu.write(0xff17, 0x00);
+        assert!(!apu.channel(1).enabled);
+        assert_eq!(apu.read(0xff26), 0xf0);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut apu = powered_apu();
+        apu.write(0xff12, 0xa1);
+        apu.write(0xff14, 0x80);
+
+        let mut volumes = Vec::new();
+        for _ in 0..4 {
+            apu.cycle(8 * FRAME_SEQUENCER_CYCLES);
+            volumes.push(apu.channel(0).volume);
+        }
+
+        assert_eq!(volumes, vec![9, 8, 7, 6]);
+    }
+
+    #[test]
+    fn length() {
+        let mut apu = powered_apu();
+        apu.write(0xff21, 0xf0);
+        apu.write(0xff20, 0x3e);
+        apu.write(0xff23, 0xc0);
+
+        apu.cycle(2 * FRAME_SEQUENCER_CYCLES);
+        assert!(apu.channel(3).enabled);
+
+        apu.cycle(2 * FRAME_SEQUENCER_CYCLES);
+        assert!(!apu.channel(3).enabled);
+    }
+
+    #[test]
+    fn mixes_square_wave() {
+        let mut apu = powered_apu();
+        apu.sampling = true;
+        apu.write(0xff24, 0x77);
+        apu.write(0xff25, 0x11);
+        apu.write(0xff11, 0x80);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff13, 0xd6);
+        apu.write(0xff14, 0x86);
+
+        // A sixteenth of a second, one M-cycle at a time.
+        for _ in 0..CYCLE_RATE / 16 {
+            apu.cycle(1);
+        }
+
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 2 * SAMPLE_RATE as usize / 16);
+        assert!(apu.take_samples().is_empty());
+
+        let left: Vec<_> = samples.iter().step_by(2).copied().collect();
+        let right: Vec<_> = samples.iter().skip(1).step_by(2).copied().collect();
+        assert_eq!(left, right);
+
+        // A 440 Hz tone goes up and down 27.5 times in a sixteenth of a
+        // second.
+        let crossings = left
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        assert!((53..=57).contains(&crossings), "{} crossings", crossings);
+
+        apu.write(0xff25, 0x10);
+        apu.cycle(CYCLE_RATE / 16);
+        let samples = apu.take_samples();
+        // The filter lets the right channel fade out rather than cut off.
+        let right: Vec<_> = samples.iter().skip(1).step_by(2).collect();
+        assert!(right[right.len() - 100..]
+            .iter()
+            .all(|sample| **sample == 0));
+        assert!(samples.iter().step_by(2).any(|sample| *sample != 0));
+    }
+
+    #[test]
+    fn power_off() {
+        let mut apu = powered_apu();
+        apu.write(0xff24, 0x77);
+        assert_eq!(apu.read(0xff24), 0x77);
+
+        apu.write(0xff26, 0x00);
+        assert_eq!(apu.read(0xff24), 0x00);
+        assert_eq!(apu.read(0xff26), 0x70);
+
+        apu.write(0xff24, 0x77);
+        assert_eq!(apu.read(0xff24), 0x00);
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use gameboy::device::Device;
+
+/// The T-cycles the real hardware runs per second.
+const CLOCK_SPEED: f64 = 4_194_304.0;
+
+/// Runs the device as fast as possible for the given wall-clock time, then
+/// prints how much faster than the real hardware that was and where the time
+/// went.
+pub fn run_bench(mut device: Device, duration: Duration) {
+    device.set_timing(true);
+
+    let start_frames = device.frame_count();
+    let start_cycles = device.cycles();
+    let start = Instant::now();
+
+    while start.elapsed() < duration && device.stop_reason().is_none() {
+        device.step_frame();
+    }
+
+    let elapsed = start.elapsed();
+    let emulated = (device.cycles() - start_cycles) as f64 / CLOCK_SPEED;
+
+    if let Some(reason) = device.stop_reason() {
+        println!("warning: emulation stopped early: {}", reason);
+    }
+
+    println!(
+        "ran {} frames ({:.1}s emulated) in {:.1}s: {:.2}x real time",
+        device.frame_count() - start_frames,
+        emulated,
+        elapsed.as_secs_f64(),
+        emulated / elapsed.as_secs_f64()
+    );
+
+    let times = device.subsystem_times().unwrap_or_default();
+    let cpu = elapsed
+        .checked_sub(times.ppu + times.framebuffer)
+        .unwrap_or_default();
+    for (name, time) in [
+        ("cpu", cpu),
+        ("ppu", times.ppu),
+        ("framebuffer", times.framebuffer),
+    ]
+    .iter()
+    {
+        println!(
+            "  {:<12} {:>5.1}%",
+            name,
+            100.0 * time.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+
+    match memory_usage() {
+        Some((resident, peak)) => println!(
+            "memory: {:.1} MiB resident, {:.1} MiB peak",
+            resident as f64 / 1024.0,
+            peak as f64 / 1024.0
+        ),
+        None => println!("memory: unknown on this platform"),
+    }
+}
+
+/// The current and peak resident memory in KiB, which is only known on Linux.
+fn memory_usage() -> Option<(u64, u64)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| {
+        status
+            .lines()
+            .find(|line| line.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()
+    };
+
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
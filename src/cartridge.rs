@@ -1,12 +1,14 @@
 use std::{
-    ffi::CStr,
-    fs::{create_dir_all, File},
+    fs::{self, create_dir_all, File},
     io::{self, BufReader, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use crate::memory::{Memory, MemoryError};
-use anyhow::anyhow;
+use crate::{
+    journal::{self, journal_path},
+    memory::{Memory, MemoryError},
+};
+use thiserror::Error;
 
 const LOGO: [u8; 0x30] = [
     0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
@@ -14,6 +16,14 @@ const LOGO: [u8; 0x30] = [
     0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
 ];
 
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("failed to read save file {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to write save file {path:?}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+}
+
 struct MBC1State {
     enable_ram: bool,
     ram_mode: bool,
@@ -22,7 +32,7 @@ struct MBC1State {
 }
 
 impl MBC1State {
-    pub fn new() -> MBC1State {
+    fn new() -> MBC1State {
         MBC1State {
             enable_ram: false,
             ram_mode: false,
@@ -31,13 +41,13 @@ impl MBC1State {
         }
     }
 
-    pub fn rom_offset(&self) -> (usize, usize) {
+    fn rom_offset(&self) -> (usize, usize) {
         let lower = if self.ram_mode { self.bank2 << 5 } else { 0 } as usize;
         let upper = ((self.bank2 << 5) | self.bank1) as usize;
         (0x4000 * lower, 0x4000 * upper)
     }
 
-    pub fn ram_offset(&self) -> usize {
+    fn ram_offset(&self) -> usize {
         let bank = if self.ram_mode {
             self.bank2 as usize
         } else {
@@ -53,7 +63,7 @@ struct MBC3State {
 }
 
 impl MBC3State {
-    pub fn new() -> MBC3State {
+    fn new() -> MBC3State {
         MBC3State {
             bank: 1,
             map_select: 0,
@@ -71,6 +81,7 @@ pub struct Cartridge {
     bytes: Vec<u8>,
     ram: Vec<u8>,
     mbc: MBC,
+    save_name: Option<String>,
 }
 
 impl Cartridge {
@@ -79,6 +90,10 @@ impl Cartridge {
         let mut buffer = Vec::new();
         reader.read_to_end(&mut buffer)?;
 
+        Ok(Cartridge::from_bytes(buffer))
+    }
+
+    pub fn from_bytes(buffer: Vec<u8>) -> Cartridge {
         let mbc = match buffer[0x147] {
             0x00 => MBC::None,
             0x01..=0x03 => MBC::MBC1(MBC1State::new()),
@@ -94,58 +109,180 @@ impl Cartridge {
             _ => 0,
         };
 
-        Ok(Cartridge {
+        Cartridge {
             bytes: buffer,
             mbc,
             ram: vec![0; ram_size],
-        })
+            save_name: None,
+        }
     }
 
+    /// The title in the header, up to the first zero byte. `None` if it isn't
+    /// valid UTF-8.
     pub fn title(&self) -> Option<&str> {
-        unsafe { CStr::from_ptr(&self.bytes[0x134] as *const u8 as *const _) }
-            .to_str()
-            .ok()
+        let title = &self.bytes[0x134..0x144];
+        let len = title.iter().position(|c| *c == 0).unwrap_or(title.len());
+        std::str::from_utf8(&title[..len]).ok()
     }
 
-    pub fn verify(&self) -> bool {
-        self.bytes[0x104..=0x133] == LOGO && self.verify_header_checksum()
+    /// Whether the header marks the game as made for the CGB, either as
+    /// CGB-only or as also running on the DMG.
+    pub fn supports_cgb(&self) -> bool {
+        self.bytes[0x143] & 0x80 != 0
     }
 
-    pub fn try_load(&mut self) {
-        let file_name = format!(
-            "saves/{}.sav",
-            self.title().expect("game has invalid title")
-        );
+    /// Whether the header marks the game as CGB-only, so it won't run, or
+    /// not properly, on the DMG.
+    pub fn requires_cgb(&self) -> bool {
+        self.bytes[0x143] == 0xc0
+    }
 
-        let path = Path::new(&file_name);
+    /// A hash of the whole ROM, which tells games apart even when their
+    /// titles are the same.
+    pub fn hash(&self) -> u64 {
+        rom_hash(&self.bytes)
+    }
 
-        if path.exists() {
-            self.load(File::open(path).expect("failed to open save file"));
+    /// The file name the save is kept under. This is the title, unless it is
+    /// empty or can't be used in a file name, in which case a hash of the ROM
+    /// gives every game its own save.
+    pub fn save_name(&self) -> String {
+        if let Some(name) = &self.save_name {
+            return name.clone();
+        }
+
+        match self.title() {
+            Some(title) if is_file_name(title) => format!("{}.sav", title),
+            _ => format!("{:016x}.sav", self.hash()),
         }
     }
 
-    fn load(&mut self, file: File) {
-        let mut reader = BufReader::new(file);
-        reader
-            .read_to_end(&mut self.ram)
-            .expect("failed to read save file");
+    /// Keeps the save under the given file name instead.
+    pub fn set_save_name(&mut self, name: String) {
+        self.save_name = Some(name);
+    }
+
+    /// The path of the save file in the given directory.
+    pub fn save_path<P: AsRef<Path>>(&self, dir: P) -> PathBuf {
+        dir.as_ref().join(self.save_name())
+    }
+
+    /// The number of 16 KiB ROM banks.
+    pub fn rom_banks(&self) -> usize {
+        (self.bytes.len() / 0x4000).max(1)
+    }
+
+    /// The ROM bank currently mapped at an address from 0x0000 to 0x7fff.
+    pub fn rom_bank(&self, address: u16) -> usize {
+        let offset = match self.mbc {
+            MBC::None => address as usize & 0x4000,
+            MBC::MBC1(ref state) => {
+                let (lower, upper) = state.rom_offset();
+                if address < 0x4000 {
+                    lower
+                } else {
+                    upper
+                }
+            }
+            MBC::MBC3(ref state) if address >= 0x4000 => 0x4000 * state.bank as usize,
+            MBC::MBC3(_) => 0,
+        };
+
+        (offset / 0x4000) % self.rom_banks()
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let file_name = format!(
-            "saves/{}.sav",
-            self.title()
-                .ok_or_else(|| anyhow!("game has invalid title"))?
-        );
+    /// Whether the cartridge has a memory bank controller, which takes writes
+    /// to ROM as commands.
+    pub fn has_mbc(&self) -> bool {
+        !matches!(self.mbc, MBC::None)
+    }
+
+    pub fn verify(&self) -> bool {
+        self.bytes[0x104..=0x133] == LOGO && self.verify_header_checksum()
+    }
+
+    /// Loads the battery-backed RAM from the save for this game in the given
+    /// directory, returning the path it was loaded from if there was one.
+    ///
+    /// Changes in a journal left behind by a crash while journaling (see
+    /// [`SaveJournal`](crate::journal::SaveJournal)) are recovered and written
+    /// to the save.
+    pub fn try_load<P: AsRef<Path>>(&mut self, dir: P) -> Result<Option<PathBuf>, SaveError> {
+        let path = self.save_path(&dir);
+        let journal = journal_path(&path);
+
+        if !path.exists() && !journal.exists() {
+            return Ok(None);
+        }
+
+        if path.exists() {
+            if let Err(source) = File::open(&path).and_then(|file| self.load(file)) {
+                return Err(SaveError::Read { path, source });
+            }
+        }
+
+        if journal.exists() {
+            let ram = &mut self.ram;
+            if let Err(source) = File::open(&journal).and_then(|file| journal::replay(ram, file)) {
+                return Err(SaveError::Read {
+                    path: journal,
+                    source,
+                });
+            }
+
+            // Fold the journal into the save right away, so it can't be
+            // replayed over a newer save later.
+            self.save(&dir)?;
+        }
+
+        Ok(Some(path))
+    }
 
-        create_dir_all("saves")?;
+    fn load<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(self.ram.len());
+        BufReader::new(reader).read_to_end(&mut bytes)?;
 
-        let mut file = File::create(file_name)?;
-        file.write_all(&self.ram)?;
+        let len = bytes.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&bytes[..len]);
 
         Ok(())
     }
 
+    /// Writes the battery-backed RAM to the given directory, returning the path
+    /// of the save file.
+    ///
+    /// The save is written to a temporary file first, so a crash while writing
+    /// leaves the old save intact. Any journal is removed once the new save is
+    /// in place.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf, SaveError> {
+        let path = self.save_path(&dir);
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(".tmp");
+
+        create_dir_all(dir)
+            .and_then(|_| File::create(&temp_path))
+            .and_then(|mut file| {
+                file.write_all(&self.ram)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &path))
+            .and_then(|_| match fs::remove_file(journal_path(&path)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            })
+            .map_err(|source| SaveError::Write {
+                path: path.clone(),
+                source,
+            })?;
+
+        Ok(path)
+    }
+
+    /// The battery-backed RAM, empty if the cartridge has none.
+    pub(crate) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
     fn verify_header_checksum(&self) -> bool {
         let mut x = 0u8;
 
@@ -175,6 +312,22 @@ impl Cartridge {
     }
 }
 
+/// Whether a title can be used as a file name on every platform.
+fn is_file_name(title: &str) -> bool {
+    !title.trim().is_empty()
+        && !title.starts_with('.')
+        && title
+            .chars()
+            .all(|c| !c.is_control() && !"/\\:*?\"<>|".contains(c))
+}
+
+/// The 64-bit FNV-1a hash of the ROM.
+fn rom_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 impl Memory for Cartridge {
     fn read(&self, address: u16) -> Result<u8, MemoryError> {
         match self.mbc {
@@ -235,3 +388,81 @@ impl Memory for Cartridge {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Cartridge;
+    use crate::memory::Memory;
+
+    #[test]
+    fn load_save() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut cart = Cartridge::from_bytes(rom.clone());
+        cart.load(&[0x12, 0x34][..]).unwrap();
+        assert_eq!(cart.ram.len(), 0x2000);
+        assert_eq!(&cart.ram[..3], &[0x12, 0x34, 0x00]);
+
+        let mut cart = Cartridge::from_bytes(rom);
+        cart.load(&vec![0xab; 0x4000][..]).unwrap();
+        assert_eq!(cart.ram, vec![0xab; 0x2000]);
+    }
+
+    #[test]
+    fn save_name() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13a].copy_from_slice(b"TETRIS");
+        assert_eq!(Cartridge::from_bytes(rom.clone()).save_name(), "TETRIS.sav");
+
+        // Empty titles or ones that aren't file names fall back to a hash.
+        rom[0x134..0x13a].copy_from_slice(b"AC/DC\0");
+        let cart = Cartridge::from_bytes(rom.clone());
+        assert_eq!(cart.title(), Some("AC/DC"));
+        assert_eq!(cart.save_name().len(), 16 + 4);
+        rom[0x134..0x13a].copy_from_slice(&[0; 6]);
+        let mut other = Cartridge::from_bytes(rom);
+        assert!(other.save_name().ends_with(".sav"));
+        assert_ne!(other.save_name(), cart.save_name());
+
+        other.set_save_name("homebrew.sav".to_owned());
+        assert_eq!(other.save_path("saves"), Path::new("saves/homebrew.sav"));
+    }
+
+    #[test]
+    fn cgb_flag() {
+        let mut rom = vec![0; 0x8000];
+        let cart = Cartridge::from_bytes(rom.clone());
+        assert!(!cart.supports_cgb() && !cart.requires_cgb());
+
+        rom[0x143] = 0x80;
+        let cart = Cartridge::from_bytes(rom.clone());
+        assert!(cart.supports_cgb() && !cart.requires_cgb());
+
+        rom[0x143] = 0xc0;
+        let cart = Cartridge::from_bytes(rom);
+        assert!(cart.supports_cgb() && cart.requires_cgb());
+    }
+
+    #[test]
+    fn rom_bank() {
+        let mut rom = vec![0; 8 * 0x4000];
+        rom[0x147] = 0x01;
+
+        let mut cart = Cartridge::from_bytes(rom.clone());
+        assert_eq!(cart.rom_banks(), 8);
+        assert_eq!((cart.rom_bank(0x0100), cart.rom_bank(0x4000)), (0, 1));
+        cart.write(0x2000, 5).unwrap();
+        assert_eq!((cart.rom_bank(0x0100), cart.rom_bank(0x7fff)), (0, 5));
+        cart.write(0x2000, 13).unwrap();
+        assert_eq!(cart.rom_bank(0x4000), 5);
+
+        rom[0x147] = 0x13;
+        let mut cart = Cartridge::from_bytes(rom);
+        cart.write(0x2000, 6).unwrap();
+        assert_eq!((cart.rom_bank(0x3fff), cart.rom_bank(0x4000)), (0, 6));
+    }
+}
@@ -0,0 +1,232 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Context};
+use gameboy::device::Device;
+
+/// What a [`BreakCondition`] looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    /// The byte at an address, written as `[c000]`.
+    Memory(u16),
+}
+
+const REGISTERS: [(&str, Operand); 13] = [
+    ("a", Operand::A),
+    ("f", Operand::F),
+    ("b", Operand::B),
+    ("c", Operand::C),
+    ("d", Operand::D),
+    ("e", Operand::E),
+    ("h", Operand::H),
+    ("l", Operand::L),
+    ("af", Operand::Af),
+    ("bc", Operand::Bc),
+    ("de", Operand::De),
+    ("hl", Operand::Hl),
+    ("sp", Operand::Sp),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Less,
+    Greater,
+}
+
+/// Two-character operators come first, so `<=` isn't taken for `<`.
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+/// A condition a breakpoint only stops on when it holds, comparing a register
+/// or the byte at an address to a value, like `a == 12`, `hl >= c000` or
+/// `[ff44] != 90`. Numbers are hexadecimal, with or without a `0x` prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakCondition {
+    operand: Operand,
+    comparison: Comparison,
+    value: u16,
+}
+
+impl BreakCondition {
+    pub fn holds(&self, device: &Device) -> bool {
+        let cpu = device.cpu();
+        let pair = |high: u8, low: u8| u16::from_be_bytes([high, low]);
+
+        let actual = match self.operand {
+            Operand::A => cpu.a as u16,
+            Operand::F => cpu.f as u16,
+            Operand::B => cpu.b as u16,
+            Operand::C => cpu.c as u16,
+            Operand::D => cpu.d as u16,
+            Operand::E => cpu.e as u16,
+            Operand::H => cpu.h as u16,
+            Operand::L => cpu.l as u16,
+            Operand::Af => pair(cpu.a, cpu.f),
+            Operand::Bc => pair(cpu.b, cpu.c),
+            Operand::De => pair(cpu.d, cpu.e),
+            Operand::Hl => pair(cpu.h, cpu.l),
+            Operand::Sp => cpu.sp,
+            Operand::Memory(address) => match device.read(address) {
+                Ok(value) => value as u16,
+                Err(_) => return false,
+            },
+        };
+
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::LessOrEqual => actual <= self.value,
+            Comparison::GreaterOrEqual => actual >= self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::Greater => actual > self.value,
+        }
+    }
+}
+
+impl FromStr for BreakCondition {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> anyhow::Result<BreakCondition> {
+        let (index, symbol, comparison) = COMPARISONS
+            .iter()
+            .find_map(|(symbol, comparison)| {
+                source
+                    .find(symbol)
+                    .map(|index| (index, *symbol, *comparison))
+            })
+            .ok_or_else(|| anyhow!("missing comparison in condition {:?}", source))?;
+
+        let left = source[..index].trim().to_ascii_lowercase();
+        let right = source[index + symbol.len()..].trim();
+
+        let operand = if left.starts_with('[') && left.ends_with(']') {
+            Operand::Memory(parse_hex(&left[1..left.len() - 1])?)
+        } else {
+            REGISTERS
+                .iter()
+                .find(|(name, _)| *name == left)
+                .map(|(_, operand)| *operand)
+                .ok_or_else(|| anyhow!("unknown register {:?}", left))?
+        };
+
+        let value = parse_hex(right)?;
+        let byte_sized = !matches!(
+            operand,
+            Operand::Af | Operand::Bc | Operand::De | Operand::Hl | Operand::Sp
+        );
+        if byte_sized && value > 0xff {
+            bail!("{} doesn't fit in a byte", right);
+        }
+
+        Ok(BreakCondition {
+            operand,
+            comparison,
+            value,
+        })
+    }
+}
+
+impl fmt::Display for BreakCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operand {
+            Operand::Memory(address) => write!(f, "[{:#06x}]", address)?,
+            operand => {
+                let (name, _) = REGISTERS
+                    .iter()
+                    .find(|(_, register)| *register == operand)
+                    .expect("every register has a name");
+                write!(f, "{}", name)?;
+            }
+        }
+
+        let (symbol, _) = COMPARISONS
+            .iter()
+            .find(|(_, comparison)| *comparison == self.comparison)
+            .expect("every comparison has a symbol");
+        write!(f, " {} {:#x}", symbol, self.value)
+    }
+}
+
+fn parse_hex(value: &str) -> anyhow::Result<u16> {
+    let digits = value.trim().trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).with_context(|| format!("invalid hex number {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use gameboy::{cartridge::Cartridge, device::Device};
+
+    use super::BreakCondition;
+
+    #[test]
+    fn parse_and_display() {
+        let cases = [
+            ("a == 12", "a == 0x12"),
+            ("HL>=0xC000", "hl >= 0xc000"),
+            ("[ff44] != 90", "[0xff44] != 0x90"),
+            ("sp < fff0", "sp < 0xfff0"),
+            (" c <= 0x1 ", "c <= 0x1"),
+            ("e > 0", "e > 0x0"),
+        ];
+
+        for (source, shown) in cases.iter() {
+            let condition: BreakCondition = source.parse().unwrap();
+            assert_eq!(condition.to_string(), *shown);
+            assert_eq!(shown.parse::<BreakCondition>().unwrap(), condition);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [
+            ("a", "missing comparison in condition \"a\""),
+            ("x == 1", "unknown register \"x\""),
+            ("a == zz", "invalid hex number \"zz\""),
+            ("a == 100", "100 doesn't fit in a byte"),
+            ("[10000] == 1", "invalid hex number \"10000\""),
+        ];
+
+        for (source, message) in cases.iter() {
+            let err = source.parse::<BreakCondition>().unwrap_err();
+            assert_eq!(err.to_string(), *message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn holds() {
+        let mut device = Device::new(Cartridge::from_bytes(vec![0; 0x8000]));
+        device.cpu_mut().a = 0x12;
+        device.cpu_mut().h = 0xc0;
+        device.cpu_mut().l = 0x01;
+
+        let holds = |source: &str| source.parse::<BreakCondition>().unwrap().holds(&device);
+        assert!(holds("a == 12"));
+        assert!(!holds("a != 12"));
+        assert!(holds("a < 13"));
+        assert!(holds("hl > c000"));
+        assert!(!holds("hl <= c000"));
+        assert!(holds("[0000] == 31"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::memory::Memory;
+
+/// When a [`Counters`] entry counts up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// The value changed.
+    Changed,
+    /// The value went up, like a badge count.
+    Increased,
+    /// The value went down, like a health bar.
+    Decreased,
+    /// The value became the given one.
+    Reached(u8),
+}
+
+impl Trigger {
+    fn fires(&self, previous: u8, value: u8) -> bool {
+        match *self {
+            Trigger::Changed => value != previous,
+            Trigger::Increased => value > previous,
+            Trigger::Decreased => value < previous,
+            Trigger::Reached(target) => value == target && previous != target,
+        }
+    }
+}
+
+struct Counter {
+    address: u16,
+    trigger: Trigger,
+    value: Option<u8>,
+}
+
+/// Counts events in the game's memory, such as a value going up, so an agent
+/// learning to play can be rewarded without reading all of memory every frame.
+///
+/// Memory is checked after every instruction, and the counts are collected
+/// per frame.
+#[derive(Default)]
+pub struct Counters {
+    counters: Vec<Counter>,
+    counts: Vec<u32>,
+    last_frame: Vec<u32>,
+}
+
+impl Counters {
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Adds a counter, returning its index in [`Counters::last_frame`].
+    pub fn add(&mut self, address: u16, trigger: Trigger) -> usize {
+        self.counters.push(Counter {
+            address,
+            trigger,
+            value: None,
+        });
+        self.counts.push(0);
+        self.last_frame.push(0);
+
+        self.counters.len() - 1
+    }
+
+    pub fn clear(&mut self) {
+        *self = Counters::default();
+    }
+
+    /// How often every counter triggered during the last finished frame.
+    pub fn last_frame(&self) -> &[u32] {
+        &self.last_frame
+    }
+
+    pub(crate) fn update<M: Memory>(&mut self, mem: &M) {
+        for (counter, count) in self.counters.iter_mut().zip(self.counts.iter_mut()) {
+            let value = match mem.read(counter.address) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+
+            if let Some(previous) = counter.value {
+                if counter.trigger.fires(previous, value) {
+                    *count += 1;
+                }
+            }
+
+            counter.value = Some(value);
+        }
+    }
+
+    pub(crate) fn finish_frame(&mut self) {
+        self.last_frame.copy_from_slice(&self.counts);
+        self.counts.iter_mut().for_each(|count| *count = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counters, Trigger};
+    use crate::memory::{Memory, MemoryError};
+
+    struct Cell(u8);
+
+    impl Memory for Cell {
+        fn read(&self, _address: u16) -> Result<u8, MemoryError> {
+            Ok(self.0)
+        }
+
+        fn write(&mut self, _address: u16, value: u8) -> Result<(), MemoryError> {
+            self.0 = value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn triggers() {
+        let mut counters = Counters::default();
+        for trigger in [
+            Trigger::Changed,
+            Trigger::Increased,
+            Trigger::Decreased,
+            Trigger::Reached(2),
+        ]
+        .iter()
+        {
+            counters.add(0xd000, *trigger);
+        }
+
+        let mut mem = Cell(2);
+        for value in [2, 3, 1, 2, 2, 0].iter() {
+            mem.0 = *value;
+            counters.update(&mem);
+        }
+        counters.finish_frame();
+        assert_eq!(counters.last_frame(), &[4, 2, 2, 1]);
+
+        counters.update(&mem);
+        counters.finish_frame();
+        assert_eq!(counters.last_frame(), &[0, 0, 0, 0]);
+    }
+}
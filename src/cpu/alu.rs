@@ -0,0 +1,435 @@
+use super::{Cpu, CpuError, CpuFlag};
+use crate::{instruction::Instruction, memory::Memory};
+
+impl Cpu {
+    /// Executes arithmetic and logic, increments and decrements, and the
+    /// instructions that only change A or the flags.
+    pub(super) fn exec_alu<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::And(from) => {
+                self.a &= self.get_u8(mem, from)?;
+                self.set_flags(self.a == 0, false, true, false);
+            }
+            Instruction::Or(from) => {
+                self.a |= self.get_u8(mem, from)?;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+            Instruction::Xor(from) => {
+                self.a ^= self.get_u8(mem, from)?;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+            Instruction::Compare(to) => {
+                let value = self.get_u8(mem, to)?;
+                self.subtract_a(value, false);
+            }
+            Instruction::Subtract(from, use_carry) => {
+                let value = self.get_u8(mem, from)?;
+                let carry = use_carry && self.get_flag(CpuFlag::Carry);
+                self.a = self.subtract_a(value, carry);
+            }
+            Instruction::Add8(to, from, use_carry) => {
+                let carry = (use_carry && self.get_flag(CpuFlag::Carry)) as u8;
+
+                let value = self.get_reg_u8(to)?;
+                let right = self.get_u8(mem, from)?;
+                let result = value.wrapping_add(right).wrapping_add(carry);
+
+                self.set_reg_u8(to, result)?;
+
+                self.set_flags(
+                    result == 0,
+                    false,
+                    (value & 0xf) + (right & 0xf) + carry > 0xf,
+                    value as u16 + right as u16 + carry as u16 > 0xff,
+                );
+            }
+            Instruction::Add16(to, from) => {
+                let value = self.get_reg_u16(to)?;
+                let right = self.get_u16(mem, from)?;
+                let result = value.wrapping_add(right);
+
+                self.set_reg_u16(to, result)?;
+
+                self.set_flag(CpuFlag::Subtraction, false);
+                self.set_flag(
+                    CpuFlag::HalfCarry,
+                    (value & 0xfff) + (right & 0xfff) > 0xfff,
+                );
+                self.set_flag(CpuFlag::Carry, result < value);
+            }
+            Instruction::Increment(to) => {
+                if to.is_16bit() {
+                    let value = self.get_u16(mem, to)?;
+                    mem.drive_address(value);
+                    self.set_u16(mem, to, value.wrapping_add(1))?;
+                } else {
+                    let ov = self.get_u8(mem, to)?;
+                    let val = ov.wrapping_add(1);
+                    self.set_u8(mem, to, val)?;
+
+                    self.set_flag(CpuFlag::Zero, val == 0);
+                    self.set_flag(CpuFlag::Subtraction, false);
+                    self.set_flag(CpuFlag::HalfCarry, ov & 0xf == 0xf);
+                }
+            }
+            Instruction::Decrement(to) => {
+                if to.is_16bit() {
+                    let value = self.get_u16(mem, to)?;
+                    mem.drive_address(value);
+                    self.set_u16(mem, to, value.wrapping_sub(1))?;
+                } else {
+                    let ov = self.get_u8(mem, to)?;
+                    let val = ov.wrapping_sub(1);
+                    self.set_u8(mem, to, val)?;
+
+                    self.set_flag(CpuFlag::Zero, val == 0);
+                    self.set_flag(CpuFlag::Subtraction, true);
+                    self.set_flag(CpuFlag::HalfCarry, ov & 0xf == 0);
+                }
+            }
+            Instruction::Complement => {
+                self.a = !self.a;
+
+                self.set_flag(CpuFlag::Subtraction, true);
+                self.set_flag(CpuFlag::HalfCarry, true);
+            }
+            Instruction::DAA => {
+                let mut correction = 0i8;
+
+                if self.get_flag(CpuFlag::HalfCarry)
+                    || (!self.get_flag(CpuFlag::Subtraction) && (self.a & 0xf > 9))
+                {
+                    correction += 6;
+                }
+
+                if self.get_flag(CpuFlag::Carry)
+                    || (!self.get_flag(CpuFlag::Subtraction) && (self.a > 0x99))
+                {
+                    correction += 0x60;
+                    self.set_flag(CpuFlag::Carry, true);
+                }
+
+                self.a = self.a.wrapping_add(if self.get_flag(CpuFlag::Subtraction) {
+                    -correction as u8
+                } else {
+                    correction as u8
+                });
+
+                self.set_flag(CpuFlag::Zero, self.a == 0);
+                self.set_flag(CpuFlag::HalfCarry, false);
+            }
+            Instruction::SetCarryFlag(toggle) => {
+                let carry = !toggle || !self.get_flag(CpuFlag::Carry);
+
+                self.set_flag(CpuFlag::Carry, carry);
+                self.set_flag(CpuFlag::Subtraction, false);
+                self.set_flag(CpuFlag::HalfCarry, false);
+            }
+            _ => unreachable!("{} is not an ALU instruction", instruction),
+        }
+
+        Ok(())
+    }
+
+    fn subtract_a(&mut self, value: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+
+        let result = self.a.wrapping_sub(value).wrapping_sub(carry);
+
+        self.set_flags(
+            result == 0,
+            true,
+            (self.a & 0xf) < (value & 0xf) + carry,
+            (self.a as u16) < (value as u16 + carry as u16),
+        );
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{flags, TestMemory};
+    use crate::{
+        cpu::{Cpu, CpuFlag},
+        instruction::{
+            CpuRegister, Instruction, InstructionOperand,
+            InstructionOperand::{Immediate8, Register},
+        },
+    };
+
+    #[test]
+    fn add_with_carry() {
+        // (a, operand, carry in, result, (Z, N, H, C))
+        let cases = [
+            (0x00, 0x00, true, 0x01, (false, false, false, false)),
+            (0x0f, 0x00, true, 0x10, (false, false, true, false)),
+            (0x00, 0xff, true, 0x00, (true, false, true, true)),
+            (0xf0, 0x0f, true, 0x00, (true, false, true, true)),
+            (0xff, 0xff, true, 0xff, (false, false, true, true)),
+            (0x80, 0x80, false, 0x00, (true, false, false, true)),
+        ];
+
+        let mut mem = TestMemory::new();
+        for (a, operand, carry, result, expected) in cases.iter().copied() {
+            let mut cpu = Cpu::new();
+            cpu.a = a;
+            cpu.set_flag(CpuFlag::Carry, carry);
+
+            cpu.exec_instruction(
+                &mut mem,
+                Instruction::Add8(
+                    CpuRegister::A,
+                    InstructionOperand::Immediate8(operand),
+                    true,
+                ),
+            )
+            .unwrap();
+
+            assert_eq!(
+                cpu.a, result,
+                "adc {:#04x}, {:#04x} (c={})",
+                a, operand, carry
+            );
+            assert_eq!(flags(&cpu), expected, "adc {:#04x}, {:#04x}", a, operand);
+        }
+    }
+
+    #[test]
+    fn subtract_with_carry() {
+        // (a, operand, carry in, result, (Z, N, H, C))
+        let cases = [
+            (0x01, 0x00, true, 0x00, (true, true, false, false)),
+            (0x10, 0x0f, true, 0x00, (true, true, true, false)),
+            (0x00, 0xff, true, 0x00, (true, true, true, true)),
+            (0x00, 0x00, true, 0xff, (false, true, true, true)),
+            (0x3b, 0x4f, true, 0xeb, (false, true, true, true)),
+            (0x3b, 0x2a, false, 0x11, (false, true, false, false)),
+        ];
+
+        let mut mem = TestMemory::new();
+        for (a, operand, carry, result, expected) in cases.iter().copied() {
+            let mut cpu = Cpu::new();
+            cpu.a = a;
+            cpu.set_flag(CpuFlag::Carry, carry);
+
+            cpu.exec_instruction(
+                &mut mem,
+                Instruction::Subtract(InstructionOperand::Immediate8(operand), true),
+            )
+            .unwrap();
+
+            assert_eq!(
+                cpu.a, result,
+                "sbc {:#04x}, {:#04x} (c={})",
+                a, operand, carry
+            );
+            assert_eq!(flags(&cpu), expected, "sbc {:#04x}, {:#04x}", a, operand);
+        }
+    }
+
+    #[test]
+    fn decimal_adjust() {
+        let bcd = |x: u8| (x / 10) << 4 | (x % 10);
+        let mut mem = TestMemory::new();
+
+        for x in 0..100u8 {
+            for y in 0..100u8 {
+                for subtract in [false, true].iter().copied() {
+                    let mut cpu = Cpu::new();
+                    cpu.a = bcd(x);
+
+                    let operand = InstructionOperand::Immediate8(bcd(y));
+                    let instruction = if subtract {
+                        Instruction::Subtract(operand, false)
+                    } else {
+                        Instruction::Add8(CpuRegister::A, operand, false)
+                    };
+
+                    cpu.exec_instruction(&mut mem, instruction).unwrap();
+                    cpu.exec_instruction(&mut mem, Instruction::DAA).unwrap();
+
+                    let (expected, carry) = if subtract {
+                        ((100 + x - y) % 100, x < y)
+                    } else {
+                        ((x + y) % 100, x + y >= 100)
+                    };
+
+                    let op = if subtract { "-" } else { "+" };
+                    assert_eq!(cpu.a, bcd(expected), "{} {} {}", x, op, y);
+                    assert_eq!(
+                        flags(&cpu),
+                        (expected == 0, subtract, false, carry),
+                        "{} {} {}",
+                        x,
+                        op,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_operand() {
+        // Checks each 8-bit operation against the same arithmetic done wide.
+        let mut mem = TestMemory::new();
+        for a in 0..=255u8 {
+            for operand in 0..=255u8 {
+                for carry in [false, true].iter().copied() {
+                    let c = carry as u16;
+                    let (a16, b16) = (a as u16, operand as u16);
+                    let cases = [
+                        (
+                            Instruction::Add8(CpuRegister::A, Immediate8(operand), true),
+                            a16 + b16 + c,
+                            (a & 0xf) as u16 + (operand & 0xf) as u16 + c > 0xf,
+                            false,
+                        ),
+                        (
+                            Instruction::Subtract(Immediate8(operand), true),
+                            a16.wrapping_sub(b16 + c),
+                            (a & 0xf) < (operand & 0xf) + carry as u8,
+                            true,
+                        ),
+                    ];
+
+                    for (instruction, wide, half_carry, subtraction) in cases.iter().copied() {
+                        let mut cpu = Cpu::new();
+                        cpu.a = a;
+                        cpu.set_flag(CpuFlag::Carry, carry);
+                        cpu.exec_instruction(&mut mem, instruction).unwrap();
+
+                        let name = format!("{} with a={:#04x} c={}", instruction, a, carry);
+                        assert_eq!(cpu.a, wide as u8, "{}", name);
+                        assert_eq!(
+                            flags(&cpu),
+                            (wide as u8 == 0, subtraction, half_carry, wide > 0xff),
+                            "{}",
+                            name
+                        );
+                    }
+                }
+
+                // Compare is a subtraction that leaves A alone.
+                let mut cpu = Cpu::new();
+                cpu.a = a;
+                cpu.exec_instruction(&mut mem, Instruction::Compare(Immediate8(operand)))
+                    .unwrap();
+                assert_eq!(cpu.a, a);
+                assert_eq!(
+                    flags(&cpu),
+                    (a == operand, true, a & 0xf < operand & 0xf, a < operand),
+                    "cp {:#04x}, {:#04x}",
+                    a,
+                    operand
+                );
+
+                // (instruction, result, half carry)
+                let logic = [
+                    (Instruction::And(Immediate8(operand)), a & operand, true),
+                    (Instruction::Or(Immediate8(operand)), a | operand, false),
+                    (Instruction::Xor(Immediate8(operand)), a ^ operand, false),
+                ];
+                for (instruction, result, half_carry) in logic.iter().copied() {
+                    let mut cpu = Cpu::new();
+                    cpu.a = a;
+                    cpu.f = 0xf0;
+                    cpu.exec_instruction(&mut mem, instruction).unwrap();
+
+                    assert_eq!(cpu.a, result, "{}", instruction);
+                    assert_eq!(flags(&cpu), (result == 0, false, half_carry, false));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn increment_and_decrement() {
+        let mut mem = TestMemory::new();
+        for value in 0..=255u8 {
+            for carry in [false, true].iter().copied() {
+                let mut cpu = Cpu::new();
+                cpu.b = value;
+                cpu.set_flag(CpuFlag::Carry, carry);
+
+                // The carry flag is left alone by both.
+                let inc = value.wrapping_add(1);
+                cpu.exec_instruction(&mut mem, Instruction::Increment(Register(CpuRegister::B)))
+                    .unwrap();
+                assert_eq!(cpu.b, inc);
+                assert_eq!(flags(&cpu), (inc == 0, false, value & 0xf == 0xf, carry));
+
+                let dec = inc.wrapping_sub(1);
+                cpu.exec_instruction(&mut mem, Instruction::Decrement(Register(CpuRegister::B)))
+                    .unwrap();
+                assert_eq!(cpu.b, dec);
+                assert_eq!(flags(&cpu), (dec == 0, true, inc & 0xf == 0, carry));
+            }
+        }
+
+        // The 16-bit versions wrap around and don't touch the flags at all.
+        let mut cpu = Cpu::new();
+        cpu.f = 0xa0;
+        cpu.set_hl(0xffff);
+        cpu.exec_instruction(&mut mem, Instruction::Increment(Register(CpuRegister::HL)))
+            .unwrap();
+        assert_eq!((cpu.hl(), cpu.f), (0x0000, 0xa0));
+        cpu.exec_instruction(&mut mem, Instruction::Decrement(Register(CpuRegister::HL)))
+            .unwrap();
+        assert_eq!((cpu.hl(), cpu.f), (0xffff, 0xa0));
+    }
+
+    #[test]
+    fn add_16bit() {
+        // (hl, operand, result, (H, C))
+        let cases = [
+            (0x0fff, 0x0001, 0x1000, (true, false)),
+            (0xffff, 0x0001, 0x0000, (true, true)),
+            (0x8000, 0x8000, 0x0000, (false, true)),
+            (0x1234, 0x0101, 0x1335, (false, false)),
+        ];
+
+        let mut mem = TestMemory::new();
+        for (hl, operand, result, (half_carry, carry)) in cases.iter().copied() {
+            let mut cpu = Cpu::new();
+            cpu.set_hl(hl);
+            cpu.set_de(operand);
+            // Z is kept.
+            cpu.set_flag(CpuFlag::Zero, true);
+
+            cpu.exec_instruction(
+                &mut mem,
+                Instruction::Add16(CpuRegister::HL, Register(CpuRegister::DE)),
+            )
+            .unwrap();
+            assert_eq!(cpu.hl(), result);
+            assert_eq!(flags(&cpu), (true, false, half_carry, carry));
+        }
+    }
+
+    #[test]
+    fn flag_instructions() {
+        let mut mem = TestMemory::new();
+        // (instruction, flags before, a after, flags after)
+        let cases = [
+            (Instruction::SetCarryFlag(false), 0x80, 0x35, 0x90),
+            (Instruction::SetCarryFlag(true), 0x70, 0x35, 0x00),
+            (Instruction::SetCarryFlag(true), 0x80, 0x35, 0x90),
+            (Instruction::Complement, 0x90, 0xca, 0xf0),
+        ];
+
+        for (instruction, before, a, after) in cases.iter().copied() {
+            let mut cpu = Cpu::new();
+            cpu.a = 0x35;
+            cpu.f = before;
+
+            cpu.exec_instruction(&mut mem, instruction).unwrap();
+            assert_eq!((cpu.a, cpu.f), (a, after), "{}", instruction);
+        }
+    }
+}
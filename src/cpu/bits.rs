@@ -0,0 +1,253 @@
+use super::{Cpu, CpuError, CpuFlag};
+use crate::{instruction::Instruction, memory::Memory};
+
+impl Cpu {
+    /// Executes rotates, shifts and the instructions on single bits.
+    pub(super) fn exec_bits<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Bit(bit, from) => {
+                let set = self.get_u8(mem, from)? & (1 << bit) != 0;
+
+                self.set_flag(CpuFlag::Zero, !set);
+                self.set_flag(CpuFlag::Subtraction, false);
+                self.set_flag(CpuFlag::HalfCarry, true);
+            }
+            Instruction::SetBit(bit, to, set) => {
+                let mut value = self.get_u8(mem, to)?;
+
+                if set {
+                    value |= 1 << bit;
+                } else {
+                    value &= !(1 << bit);
+                }
+
+                self.set_u8(mem, to, value)?;
+            }
+            Instruction::RotateLeft(to, use_carry) => {
+                let previous = self.get_u8(mem, to)?;
+                let value = self.rotate_left(previous, use_carry);
+                self.set_u8(mem, to, value)?;
+
+                self.set_flag(CpuFlag::Zero, value == 0);
+            }
+            Instruction::RotateRight(to, use_carry) => {
+                let previous = self.get_u8(mem, to)?;
+                let value = self.rotate_right(previous, use_carry);
+                self.set_u8(mem, to, value)?;
+
+                self.set_flag(CpuFlag::Zero, value == 0);
+            }
+            // The short forms on A always clear Z, unlike the CB prefixed ones.
+            Instruction::RotateLeftA(use_carry) => {
+                self.a = self.rotate_left(self.a, use_carry);
+            }
+            Instruction::RotateRightA(use_carry) => {
+                self.a = self.rotate_right(self.a, use_carry);
+            }
+            Instruction::ShiftRight(to, zero) => {
+                let value = self.get_u8(mem, to)?;
+
+                let last_bit = if zero { 0 } else { value & (1 << 7) };
+                let result = value >> 1 | last_bit;
+
+                self.set_u8(mem, to, result)?;
+
+                self.set_flags(result == 0, false, false, value & 1 != 0);
+            }
+            Instruction::ShiftLeft(to) => {
+                let value = self.get_u8(mem, to)?;
+                let result = value << 1;
+
+                self.set_u8(mem, to, result)?;
+
+                self.set_flags(result == 0, false, false, value & 0x80 != 0);
+            }
+            Instruction::Swap(to) => {
+                let value = self.get_u8(mem, to)?;
+                let result = value >> 4 | (value & 0xf) << 4;
+
+                self.set_u8(mem, to, result)?;
+
+                self.set_flags(result == 0, false, false, false);
+            }
+            _ => unreachable!("{} is not a bit instruction", instruction),
+        }
+
+        Ok(())
+    }
+
+    /// Rotates left through the carry flag, or around into bit 0 otherwise.
+    /// Sets all flags as if the result wasn't zero.
+    fn rotate_left(&mut self, value: u8, through_carry: bool) -> u8 {
+        let bit = if through_carry {
+            self.get_flag(CpuFlag::Carry) as u8
+        } else {
+            value >> 7
+        };
+
+        self.set_flags(false, false, false, value & 0x80 != 0);
+        value << 1 | bit
+    }
+
+    /// Rotates right through the carry flag, or around into bit 7 otherwise.
+    /// Sets all flags as if the result wasn't zero.
+    fn rotate_right(&mut self, value: u8, through_carry: bool) -> u8 {
+        let bit = if through_carry {
+            (self.get_flag(CpuFlag::Carry) as u8) << 7
+        } else {
+            value << 7
+        };
+
+        self.set_flags(false, false, false, value & 1 != 0);
+        value >> 1 | bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{flags, TestMemory};
+    use crate::{
+        cpu::{Cpu, CpuFlag},
+        instruction::{
+            CpuRegister, Instruction,
+            InstructionOperand::{MemoryLocationRegister, Register},
+        },
+    };
+
+    #[test]
+    fn every_rotate_and_shift() {
+        let b = Register(CpuRegister::B);
+        let mut mem = TestMemory::new();
+
+        for value in 0..=255u8 {
+            for carry in [false, true].iter().copied() {
+                let c = carry as u8;
+                // (instruction, result, carry out)
+                let cases = [
+                    (
+                        Instruction::RotateLeft(b, false),
+                        value.rotate_left(1),
+                        value >> 7,
+                    ),
+                    (Instruction::RotateLeft(b, true), value << 1 | c, value >> 7),
+                    (
+                        Instruction::RotateRight(b, false),
+                        value.rotate_right(1),
+                        value & 1,
+                    ),
+                    (
+                        Instruction::RotateRight(b, true),
+                        value >> 1 | c << 7,
+                        value & 1,
+                    ),
+                    (Instruction::ShiftLeft(b), value << 1, value >> 7),
+                    (
+                        Instruction::ShiftRight(b, false),
+                        (value as i8 >> 1) as u8,
+                        value & 1,
+                    ),
+                    (Instruction::ShiftRight(b, true), value >> 1, value & 1),
+                    (Instruction::Swap(b), value.rotate_left(4), 0),
+                ];
+
+                for (instruction, result, carry_out) in cases.iter().copied() {
+                    let mut cpu = Cpu::new();
+                    cpu.b = value;
+                    cpu.set_flag(CpuFlag::Carry, carry);
+                    cpu.exec_instruction(&mut mem, instruction).unwrap();
+
+                    let name = format!("{} with b={:#04x} c={}", instruction, value, carry);
+                    assert_eq!(cpu.b, result, "{}", name);
+                    assert_eq!(
+                        flags(&cpu),
+                        (result == 0, false, false, carry_out != 0),
+                        "{}",
+                        name
+                    );
+                }
+
+                // The short forms on A never set Z.
+                let cases = [
+                    (
+                        Instruction::RotateLeftA(false),
+                        value.rotate_left(1),
+                        value >> 7,
+                    ),
+                    (Instruction::RotateLeftA(true), value << 1 | c, value >> 7),
+                    (
+                        Instruction::RotateRightA(false),
+                        value.rotate_right(1),
+                        value & 1,
+                    ),
+                    (
+                        Instruction::RotateRightA(true),
+                        value >> 1 | c << 7,
+                        value & 1,
+                    ),
+                ];
+
+                for (instruction, result, carry_out) in cases.iter().copied() {
+                    let mut cpu = Cpu::new();
+                    cpu.a = value;
+                    cpu.set_flag(CpuFlag::Carry, carry);
+                    cpu.exec_instruction(&mut mem, instruction).unwrap();
+
+                    let name = format!("{} with a={:#04x} c={}", instruction, value, carry);
+                    assert_eq!(cpu.a, result, "{}", name);
+                    assert_eq!(
+                        flags(&cpu),
+                        (false, false, false, carry_out != 0),
+                        "{}",
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_bit() {
+        let mut mem = TestMemory::new();
+        mem.0[0xc000] = 0b1010_0101;
+
+        for bit in 0..8 {
+            let set = 0b1010_0101 & (1 << bit) != 0;
+            for register in [
+                Register(CpuRegister::B),
+                MemoryLocationRegister(CpuRegister::HL),
+            ]
+            .iter()
+            .copied()
+            {
+                let mut cpu = Cpu::new();
+                cpu.b = 0b1010_0101;
+                cpu.set_hl(0xc000);
+                cpu.set_flag(CpuFlag::Carry, true);
+
+                cpu.exec_instruction(&mut mem, Instruction::Bit(bit, register))
+                    .unwrap();
+                assert_eq!(flags(&cpu), (!set, false, true, true), "bit {}", bit);
+            }
+
+            let mut cpu = Cpu::new();
+            cpu.b = 0b1010_0101;
+            cpu.exec_instruction(
+                &mut mem,
+                Instruction::SetBit(bit, Register(CpuRegister::B), true),
+            )
+            .unwrap();
+            assert_eq!(cpu.b, 0b1010_0101 | 1 << bit);
+            cpu.exec_instruction(
+                &mut mem,
+                Instruction::SetBit(bit, Register(CpuRegister::B), false),
+            )
+            .unwrap();
+            assert_eq!(cpu.b, 0b1010_0101 & !(1 << bit));
+            assert_eq!(cpu.f, 0);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use super::{Cpu, CpuError};
+use crate::{instruction::Instruction, memory::Memory};
+
+impl Cpu {
+    /// Executes jumps, calls and returns, returning the extra M-cycles taken
+    /// when a conditional one is taken.
+    pub(super) fn exec_jump<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<usize, CpuError> {
+        match instruction {
+            Instruction::Jump(to) => {
+                self.pc = self.get_u16(mem, to)?;
+            }
+            Instruction::JumpIf(flag, expected, address) => {
+                if self.get_flag(flag) == expected {
+                    self.pc = address;
+                    return Ok(1);
+                }
+            }
+            Instruction::JumpRelative(offset) => {
+                self.pc = self.pc.wrapping_add(offset as u16);
+            }
+            Instruction::JumpRelativeIf(flag, expected, offset) => {
+                if self.get_flag(flag) == expected {
+                    self.pc = self.pc.wrapping_add(offset as u16);
+                    return Ok(1);
+                }
+            }
+            Instruction::Call(address) => {
+                self.push_u16(mem, self.pc)?;
+                self.pc = address;
+            }
+            Instruction::CallIf(flag, expected, address) => {
+                if self.get_flag(flag) == expected {
+                    self.push_u16(mem, self.pc)?;
+                    self.pc = address;
+                    return Ok(3);
+                }
+            }
+            Instruction::Return => self.pc = self.pop_u16(mem)?,
+            Instruction::ReturnIf(flag, expected) => {
+                // Checking the condition takes an M-cycle of its own.
+                self.tick(mem);
+                if self.get_flag(flag) == expected {
+                    self.pc = self.pop_u16(mem)?;
+                    return Ok(3);
+                }
+            }
+            Instruction::ReturnInterrupt => {
+                self.ime = true;
+                self.pc = self.pop_u16(mem)?
+            }
+            Instruction::Rst(address) => {
+                self.push_u16(mem, self.pc)?;
+                self.pc = address as u16 * 8;
+            }
+            _ => unreachable!("{} is not a jump", instruction),
+        }
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::TestMemory;
+    use crate::{
+        cpu::{Cpu, CpuFlag},
+        instruction::Instruction,
+    };
+
+    #[test]
+    fn conditional_cycles() {
+        for taken in [true, false].iter().copied() {
+            // (instruction, cycles when taken, cycles when not taken)
+            let cases = vec![
+                (Instruction::JumpIf(CpuFlag::Zero, true, 0x1234), 4, 3),
+                (Instruction::JumpRelativeIf(CpuFlag::Zero, true, -2), 3, 2),
+                (Instruction::CallIf(CpuFlag::Zero, true, 0x1234), 6, 3),
+                (Instruction::ReturnIf(CpuFlag::Zero, true), 5, 2),
+            ];
+
+            for (instruction, taken_cycles, not_taken_cycles) in cases {
+                let name = instruction.to_string();
+                let mut mem = TestMemory::new();
+                let mut cpu = Cpu::new();
+                cpu.pc = 0x0100;
+                cpu.sp = 0xfffe;
+                cpu.set_flag(CpuFlag::Zero, taken);
+
+                let cycles = cpu.exec_instruction(&mut mem, instruction).unwrap();
+                let expected = if taken {
+                    taken_cycles
+                } else {
+                    not_taken_cycles
+                };
+                assert_eq!(cycles, expected, "{} (taken={})", name, taken);
+            }
+        }
+    }
+
+    #[test]
+    fn jump_hl() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0100] = 0xe9;
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.set_hl(0x1234);
+
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 1);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn return_from_interrupt() {
+        let mut mem = TestMemory::new();
+        mem.0[0] = 0xd9; // reti
+        mem.0[0xfffc] = 0x34;
+        mem.0[0xfffd] = 0x12;
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xfffc;
+        cpu.exec_next_instruction(&mut mem).unwrap();
+
+        assert!(cpu.ime);
+        assert_eq!(cpu.pc, 0x1234);
+    }
+
+    #[test]
+    fn every_condition() {
+        let conditions = [
+            (CpuFlag::Zero, false),
+            (CpuFlag::Zero, true),
+            (CpuFlag::Carry, false),
+            (CpuFlag::Carry, true),
+        ];
+
+        for (flag, expected) in conditions.iter().copied() {
+            for set in [false, true].iter().copied() {
+                let taken = set == expected;
+                let instructions = [
+                    (Instruction::JumpIf(flag, expected, 0x1234), 0x1234),
+                    (Instruction::JumpRelativeIf(flag, expected, -0x10), 0x00f0),
+                    (Instruction::CallIf(flag, expected, 0x1234), 0x1234),
+                    (Instruction::ReturnIf(flag, expected), 0x5678),
+                ];
+
+                for (instruction, target) in instructions.iter().copied() {
+                    let mut mem = TestMemory::new();
+                    mem.0[0xfffc..0xfffe].copy_from_slice(&[0x78, 0x56]);
+
+                    let mut cpu = Cpu::new();
+                    cpu.pc = 0x0100;
+                    cpu.sp = 0xfffc;
+                    cpu.set_flag(flag, set);
+                    cpu.exec_instruction(&mut mem, instruction).unwrap();
+
+                    let pc = if taken { target } else { 0x0100 };
+                    assert_eq!(cpu.pc, pc, "{} with {} set to {}", instruction, flag, set);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn call_and_restart() {
+        let mut mem = TestMemory::new();
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0103;
+        cpu.sp = 0xfffe;
+
+        assert_eq!(
+            cpu.exec_instruction(&mut mem, Instruction::Call(0x1234))
+                .unwrap(),
+            6
+        );
+        assert_eq!((cpu.pc, cpu.sp), (0x1234, 0xfffc));
+        assert_eq!(&mem.0[0xfffc..0xfffe], &[0x03, 0x01]);
+
+        for vector in 0..8 {
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0150;
+            cpu.sp = 0xfffe;
+
+            assert_eq!(
+                cpu.exec_instruction(&mut mem, Instruction::Rst(vector))
+                    .unwrap(),
+                4
+            );
+            assert_eq!(cpu.pc, vector as u16 * 8);
+            assert_eq!(&mem.0[0xfffc..0xfffe], &[0x50, 0x01]);
+
+            assert_eq!(
+                cpu.exec_instruction(&mut mem, Instruction::Return).unwrap(),
+                4
+            );
+            assert_eq!((cpu.pc, cpu.sp), (0x0150, 0xfffe));
+        }
+    }
+}
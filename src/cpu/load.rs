@@ -0,0 +1,138 @@
+use super::{Cpu, CpuError};
+use crate::{instruction::Instruction, memory::Memory};
+
+impl Cpu {
+    /// Executes loads between registers, immediates and memory.
+    pub(super) fn exec_load<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Load(to, from) => {
+                if to.is_16bit() {
+                    let val = self.get_u16(mem, from)?;
+                    self.set_u16(mem, to, val)?;
+                } else {
+                    let val = self.get_u8(mem, from)?;
+                    self.set_u8(mem, to, val)?;
+                }
+            }
+            _ => unreachable!("{} is not a load", instruction),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::TestMemory;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn store_stack_pointer() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0100..0x0103].copy_from_slice(&[0x08, 0x34, 0x12]);
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.sp = 0xbeef;
+
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 5);
+        assert_eq!(&mem.0[0x1234..0x1236], &[0xef, 0xbe]);
+        assert_eq!(cpu.pc, 0x0103);
+    }
+
+    #[test]
+    fn load_every_register_pair() {
+        let registers = |cpu: &Cpu| [cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, 0, cpu.a];
+
+        // The 0x40-0x7f block loads into the register in bits 3-5 from the one
+        // in bits 0-2, where 6 is (HL) and 0x76 is HALT instead.
+        for opcode in (0x40..0x80u8).filter(|opcode| *opcode != 0x76) {
+            let (to, from) = ((opcode >> 3 & 7) as usize, (opcode & 7) as usize);
+
+            let mut mem = TestMemory::new();
+            mem.0[0x0100] = opcode;
+            mem.0[0xc123] = 0x99;
+
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0100;
+            cpu.a = 0x11;
+            cpu.set_bc(0x2233);
+            cpu.set_de(0x4455);
+            cpu.set_hl(0xc123);
+            let before = registers(&cpu);
+
+            let cycles = cpu.exec_next_instruction(&mut mem).unwrap();
+            let expected_cycles = if to == 6 || from == 6 { 2 } else { 1 };
+            assert_eq!(cycles, expected_cycles, "{:#04x}", opcode);
+
+            let value = if from == 6 { 0x99 } else { before[from] };
+            if to == 6 {
+                assert_eq!(mem.0[0xc123], value, "{:#04x}", opcode);
+            } else {
+                let mut expected = before;
+                expected[to] = value;
+                assert_eq!(registers(&cpu), expected, "{:#04x}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn load_with_increment_and_decrement() {
+        // (opcode, whether it stores A, HL afterwards)
+        let cases = [
+            (0x22, true, 0xc124),
+            (0x2a, false, 0xc124),
+            (0x32, true, 0xc122),
+            (0x3a, false, 0xc122),
+        ];
+
+        for (opcode, store, hl) in cases.iter().copied() {
+            let mut mem = TestMemory::new();
+            mem.0[0x0100] = opcode;
+            mem.0[0xc123] = 0x99;
+
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0100;
+            cpu.a = 0x11;
+            cpu.set_hl(0xc123);
+
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 2);
+            assert_eq!(cpu.hl(), hl, "{:#04x}", opcode);
+            let expected = if store { (0x11, 0x11) } else { (0x99, 0x99) };
+            assert_eq!((cpu.a, mem.0[0xc123]), expected, "{:#04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn load_high_memory() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0100..0x0106].copy_from_slice(&[
+            0xe0, 0x80, // ldh (0x80), a
+            0xe2, // ld (c), a
+            0xf0, 0x81, // ldh a, (0x81)
+            0xf2, // ld a, (c)
+        ]);
+        mem.0[0xff81] = 0x77;
+        mem.0[0xff42] = 0x66;
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.a = 0x55;
+        cpu.c = 0x42;
+
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 3);
+        assert_eq!(mem.0[0xff80], 0x55);
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 2);
+        assert_eq!(mem.0[0xff42], 0x55);
+
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 3);
+        assert_eq!(cpu.a, 0x77);
+        mem.0[0xff42] = 0x66;
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.a, 0x66);
+    }
+}
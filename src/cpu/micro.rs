@@ -0,0 +1,332 @@
+use super::{Cpu, CpuError, CpuFlag};
+use crate::{
+    instruction::{CpuRegister, InstructionOperand},
+    memory::{Memory, MemoryError, MemoryOperation},
+};
+
+// The building blocks instructions are executed with: bus accesses that each
+// take an M-cycle, and reading and writing registers and operands.
+impl Cpu {
+    pub fn pop_u16<M: Memory>(&mut self, mem: &mut M) -> Result<u16, MemoryError> {
+        let lo = self.read_cycle(mem, self.sp)?;
+        mem.drive_address(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        let hi = self.read_cycle(mem, self.sp)?;
+        mem.drive_address(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+
+        Ok((hi as u16) << 8 | (lo as u16))
+    }
+
+    /// Pushes a value onto the stack, after the internal cycle that PUSH, CALL
+    /// and RST spend decrementing SP.
+    pub fn push_u16<M: Memory>(&mut self, mem: &mut M, value: u16) -> Result<(), MemoryError> {
+        let hi = (value >> 8) as u8;
+        let lo = value as u8;
+
+        self.tick(mem);
+        mem.drive_address(self.sp);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(mem, self.sp, hi)?;
+        mem.drive_address(self.sp);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(mem, self.sp, lo)?;
+
+        Ok(())
+    }
+
+    /// Reads from memory, taking a single M-cycle.
+    pub(super) fn read_cycle<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        address: u16,
+    ) -> Result<u8, MemoryError> {
+        let value = mem.read(address)?;
+        self.tick(mem);
+        Ok(value)
+    }
+
+    /// Writes to memory, taking a single M-cycle.
+    pub(super) fn write_cycle<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        address: u16,
+        value: u8,
+    ) -> Result<(), MemoryError> {
+        mem.write(address, value)?;
+        self.tick(mem);
+        Ok(())
+    }
+
+    /// Lets a single M-cycle pass on the bus.
+    pub(super) fn tick<M: Memory>(&mut self, mem: &mut M) {
+        mem.tick();
+        self.ticks += 1;
+        self.cycles += 4;
+    }
+
+    /// Lets a single M-cycle pass without executing anything, while halted.
+    pub fn idle<M: Memory>(&mut self, mem: &mut M) {
+        self.tick(mem);
+    }
+
+    /// The amount of T-cycles that passed since power on. This keeps counting
+    /// across resets.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub(super) fn get_reg_u8(&mut self, reg: CpuRegister) -> Result<u8, CpuError> {
+        match reg {
+            CpuRegister::A => Ok(self.a),
+            CpuRegister::B => Ok(self.b),
+            CpuRegister::C => Ok(self.c),
+            CpuRegister::D => Ok(self.d),
+            CpuRegister::E => Ok(self.e),
+            CpuRegister::H => Ok(self.h),
+            CpuRegister::L => Ok(self.l),
+            CpuRegister::F => Ok(self.f()),
+            _ => Err(CpuError::OperandSizeMismatch {
+                operand: InstructionOperand::Register(reg),
+                op: MemoryOperation::Read,
+            }),
+        }
+    }
+
+    pub(super) fn set_reg_u8(&mut self, reg: CpuRegister, value: u8) -> Result<(), CpuError> {
+        match reg {
+            CpuRegister::A => self.a = value,
+            CpuRegister::B => self.b = value,
+            CpuRegister::C => self.c = value,
+            CpuRegister::D => self.d = value,
+            CpuRegister::E => self.e = value,
+            CpuRegister::H => self.h = value,
+            CpuRegister::L => self.l = value,
+            CpuRegister::F => self.f = value & 0xf0,
+            CpuRegister::AF => self.set_af(value as u16),
+            CpuRegister::BC => self.set_bc(value as u16),
+            CpuRegister::DE => self.set_de(value as u16),
+            CpuRegister::HL => self.set_hl(value as u16),
+            CpuRegister::SP => self.sp = value as u16,
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn get_reg_u16(&mut self, reg: CpuRegister) -> Result<u16, CpuError> {
+        match reg {
+            CpuRegister::A => Ok(self.a as u16),
+            CpuRegister::B => Ok(self.b as u16),
+            CpuRegister::C => Ok(self.c as u16),
+            CpuRegister::D => Ok(self.d as u16),
+            CpuRegister::E => Ok(self.e as u16),
+            CpuRegister::H => Ok(self.h as u16),
+            CpuRegister::L => Ok(self.l as u16),
+            CpuRegister::F => Ok(self.f() as u16),
+            CpuRegister::AF => Ok(self.af()),
+            CpuRegister::BC => Ok(self.bc()),
+            CpuRegister::DE => Ok(self.de()),
+            CpuRegister::HL => Ok(self.hl()),
+            CpuRegister::SP => Ok(self.sp),
+        }
+    }
+
+    pub(super) fn set_reg_u16(&mut self, reg: CpuRegister, value: u16) -> Result<(), CpuError> {
+        match reg {
+            CpuRegister::AF => self.set_af(value),
+            CpuRegister::BC => self.set_bc(value),
+            CpuRegister::DE => self.set_de(value),
+            CpuRegister::HL => self.set_hl(value),
+            CpuRegister::SP => self.sp = value,
+            _ => {
+                return Err(CpuError::OperandSizeMismatch {
+                    operand: InstructionOperand::Register(reg),
+                    op: MemoryOperation::Write,
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn get_u8<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        operand: InstructionOperand,
+    ) -> Result<u8, CpuError> {
+        match operand {
+            InstructionOperand::Register(reg) => self.get_reg_u8(reg),
+            InstructionOperand::Immediate8(val) => Ok(val),
+            InstructionOperand::Immediate16(_) => Err(CpuError::ImmediateSizeMismatch),
+            InstructionOperand::OffsetMemoryLocationRegister(offset, reg) => {
+                let address = self.get_reg_u16(reg)?.wrapping_add(offset);
+                Ok(self.read_cycle(mem, address)?)
+            }
+            InstructionOperand::MemoryLocationRegister(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                Ok(self.read_cycle(mem, address)?)
+            }
+            InstructionOperand::MemoryLocationRegisterDecrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                let value = self.read_cycle(mem, address)?;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_sub(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(value)
+            }
+            InstructionOperand::MemoryLocationRegisterIncrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                let value = self.read_cycle(mem, address)?;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_add(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(value)
+            }
+            InstructionOperand::OffsetMemoryLocationImmediate8(offset, address) => {
+                Ok(self.read_cycle(mem, offset + address as u16)?)
+            }
+            InstructionOperand::MemoryLocationImmediate16(address) => {
+                Ok(self.read_cycle(mem, address)?)
+            }
+            InstructionOperand::DoubleMemoryLocationImmediate16(_) => {
+                Err(CpuError::OperandSizeMismatch {
+                    op: MemoryOperation::Read,
+                    operand,
+                })
+            }
+        }
+    }
+
+    pub(super) fn set_u8<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        operand: InstructionOperand,
+        value: u8,
+    ) -> Result<(), CpuError> {
+        match operand {
+            InstructionOperand::Register(reg) => self.set_reg_u8(reg, value),
+            InstructionOperand::OffsetMemoryLocationRegister(offset, reg) => {
+                let address = self.get_reg_u16(reg)?.wrapping_add(offset);
+                Ok(self.write_cycle(mem, address, value)?)
+            }
+            InstructionOperand::MemoryLocationRegister(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                Ok(self.write_cycle(mem, address, value)?)
+            }
+            InstructionOperand::MemoryLocationRegisterDecrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                self.write_cycle(mem, address, value)?;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_sub(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(())
+            }
+            InstructionOperand::MemoryLocationRegisterIncrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                self.write_cycle(mem, address, value)?;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_add(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(())
+            }
+            InstructionOperand::OffsetMemoryLocationImmediate8(offset, address) => {
+                Ok(self.write_cycle(mem, offset + address as u16, value)?)
+            }
+            InstructionOperand::MemoryLocationImmediate16(address) => {
+                Ok(self.write_cycle(mem, address, value)?)
+            }
+            InstructionOperand::DoubleMemoryLocationImmediate16(_) => {
+                Err(CpuError::OperandSizeMismatch {
+                    op: MemoryOperation::Write,
+                    operand,
+                })
+            }
+            InstructionOperand::Immediate8(_) => Err(CpuError::ImmediateWrite),
+            InstructionOperand::Immediate16(_) => Err(CpuError::ImmediateWrite),
+        }
+    }
+
+    pub(super) fn get_u16<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        operand: InstructionOperand,
+    ) -> Result<u16, CpuError> {
+        match operand {
+            InstructionOperand::Register(reg) => self.get_reg_u16(reg),
+            InstructionOperand::Immediate8(val) => Ok(val as u16),
+            InstructionOperand::Immediate16(val) => Ok(val),
+            InstructionOperand::OffsetMemoryLocationRegister(offset, reg) => {
+                let address = self.get_reg_u16(reg)?.wrapping_add(offset);
+                Ok(self.read_cycle(mem, address)? as u16)
+            }
+            InstructionOperand::MemoryLocationRegister(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                Ok(self.read_cycle(mem, address)? as u16)
+            }
+            InstructionOperand::MemoryLocationRegisterDecrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                let value = self.read_cycle(mem, address)? as u16;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_sub(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(value)
+            }
+            InstructionOperand::MemoryLocationRegisterIncrement(reg) => {
+                let address = self.get_reg_u16(reg)?;
+                let value = self.read_cycle(mem, address)? as u16;
+                mem.drive_address(address);
+                let reg_value = self.get_reg_u16(reg)?.wrapping_add(1);
+                self.set_reg_u16(reg, reg_value)?;
+                Ok(value)
+            }
+            InstructionOperand::OffsetMemoryLocationImmediate8(offset, address) => {
+                Ok(self.read_cycle(mem, offset + address as u16)? as u16)
+            }
+            InstructionOperand::MemoryLocationImmediate16(address) => {
+                Ok(self.read_cycle(mem, address)? as u16)
+            }
+            InstructionOperand::DoubleMemoryLocationImmediate16(address) => {
+                let lo = self.read_cycle(mem, address)? as u16;
+                let hi = self.read_cycle(mem, address + 1)? as u16;
+                Ok(hi << 8 | lo)
+            }
+        }
+    }
+
+    pub(super) fn set_u16<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        operand: InstructionOperand,
+        value: u16,
+    ) -> Result<(), CpuError> {
+        match operand {
+            InstructionOperand::Register(reg) => self.set_reg_u16(reg, value),
+            InstructionOperand::DoubleMemoryLocationImmediate16(address) => {
+                self.write_cycle(mem, address, value as u8)?;
+                self.write_cycle(mem, address + 1, (value >> 8) as u8)?;
+                Ok(())
+            }
+            InstructionOperand::Immediate8(_) => Err(CpuError::ImmediateWrite),
+            InstructionOperand::Immediate16(_) => Err(CpuError::ImmediateWrite),
+            _ => Err(CpuError::OperandSizeMismatch {
+                operand,
+                op: MemoryOperation::Write,
+            }),
+        }
+    }
+
+    /// Sets all four flags at once, in the order they are usually listed:
+    /// Z, N, H and C.
+    pub(super) fn set_flags(
+        &mut self,
+        zero: bool,
+        subtraction: bool,
+        half_carry: bool,
+        carry: bool,
+    ) {
+        self.set_flag(CpuFlag::Zero, zero);
+        self.set_flag(CpuFlag::Subtraction, subtraction);
+        self.set_flag(CpuFlag::HalfCarry, half_carry);
+        self.set_flag(CpuFlag::Carry, carry);
+    }
+}
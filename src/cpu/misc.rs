@@ -0,0 +1,118 @@
+use super::{pending_interrupts, Cpu, CpuError};
+use crate::{instruction::Instruction, memory::Memory};
+
+/// The M-cycles the CPU is stopped for while switching its speed.
+const SPEED_SWITCH_CYCLES: usize = 2050;
+
+impl Cpu {
+    /// Executes the instructions controlling the CPU itself.
+    pub(super) fn exec_misc<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Noop => {}
+            // Only speed switches are supported, not the low-power mode.
+            Instruction::Stop => {
+                if !mem.switch_speed() {
+                    return Err(CpuError::Unimplemented(instruction));
+                }
+
+                for _ in 0..SPEED_SWITCH_CYCLES {
+                    self.tick(mem);
+                }
+            }
+            Instruction::Halt => {
+                if !self.ime && !pending_interrupts(mem)?.is_empty() {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            Instruction::DisableInterrupts => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::EnableInterrupts => self.ime_scheduled = true,
+            _ => unreachable!("{} is not a control instruction", instruction),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::TestMemory;
+    use crate::cpu::{Cpu, Interrupts};
+
+    #[test]
+    fn halt() {
+        let mut mem = TestMemory::new();
+        mem.0[0] = 0x76; // halt
+
+        let mut cpu = Cpu::new();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+
+        assert!(cpu.halted);
+        assert!(!cpu.halt_bug);
+    }
+
+    #[test]
+    fn halt_bug() {
+        let mut mem = TestMemory::new();
+        mem.0[0] = 0x76; // halt
+        mem.0[1] = 0x3c; // inc a
+        mem.0[0xff0f] = 0x04;
+        mem.0[0xffff] = 0x04;
+
+        let mut cpu = Cpu::new();
+        for _ in 0..3 {
+            cpu.exec_next_instruction(&mut mem).unwrap();
+        }
+
+        assert!(!cpu.halted);
+        assert_eq!(cpu.a, 2);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn enable_interrupts_delay() {
+        let mut mem = TestMemory::new();
+        mem.0[0] = 0xfb; // ei
+        mem.0[1] = 0x00; // nop
+
+        mem.0[0xff0f] = Interrupts::VBLANK.bits();
+        mem.0[0xffff] = Interrupts::VBLANK.bits();
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xfffe;
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.process_interrupts(&mut mem).unwrap(), 0);
+
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert!(cpu.ime);
+        assert_eq!(cpu.process_interrupts(&mut mem).unwrap(), 5);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0x40);
+        assert_eq!(mem.0[0xff0f], 0);
+    }
+
+    #[test]
+    fn disable_interrupts() {
+        let mut mem = TestMemory::new();
+        mem.0[0] = 0xfb; // ei
+        mem.0[1] = 0xf3; // di
+        mem.0[2] = 0x00; // nop
+
+        let mut cpu = Cpu::new();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert!(!cpu.ime);
+
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert!(!cpu.ime);
+    }
+}
@@ -0,0 +1,902 @@
+use bitflags::bitflags;
+use std::{collections::BTreeMap, fmt, u8};
+use thiserror::Error;
+
+use crate::{
+    decode::{CB_OPCODES, OPCODES},
+    instruction::{Instruction, InstructionOperand},
+    memory::{Memory, MemoryError, MemoryOperation, Untimed},
+};
+
+mod alu;
+mod bits;
+mod jumps;
+mod load;
+mod micro;
+mod misc;
+mod stack;
+
+bitflags! {
+    pub struct Interrupts: u8 {
+        const VBLANK = 1 << 0;
+        const LCD_STAT = 1 << 1;
+        const TIMER = 1 << 2;
+        const SERIAL = 1 << 3;
+        const JOYPAD = 1 << 4;
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy)]
+pub enum InstructionError {
+    #[error("invalid opcode {opcode:#04x}")]
+    InvalidOpcode { opcode: u16 },
+    #[error("memory error")]
+    MemoryError(#[from] MemoryError),
+}
+
+#[derive(Error, Debug, Clone, Copy)]
+pub enum CpuError {
+    #[error("{op} to {operand} with mismatched argument size")]
+    OperandSizeMismatch {
+        operand: InstructionOperand,
+        op: MemoryOperation,
+    },
+    #[error("access to immediate operand with mismatched argument size")]
+    ImmediateSizeMismatch,
+    #[error("write to immediate operand")]
+    ImmediateWrite,
+    #[error("memory error")]
+    MemoryError(#[from] MemoryError),
+    #[error("instruction error")]
+    InstructionError(#[from] InstructionError),
+    #[error("unimplemented instruction {0}")]
+    Unimplemented(Instruction),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CpuFlag {
+    Zero,
+    Subtraction,
+    HalfCarry,
+    Carry,
+}
+
+impl CpuFlag {
+    pub fn bit(&self) -> u8 {
+        match self {
+            CpuFlag::Zero => 1 << 7,
+            CpuFlag::Subtraction => 1 << 6,
+            CpuFlag::HalfCarry => 1 << 5,
+            CpuFlag::Carry => 1 << 4,
+        }
+    }
+}
+
+impl fmt::Display for CpuFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuFlag::Zero => write!(f, "Z"),
+            CpuFlag::Subtraction => write!(f, "S"),
+            CpuFlag::HalfCarry => write!(f, "H"),
+            CpuFlag::Carry => write!(f, "C"),
+        }
+    }
+}
+
+/// Every register and bit of execution state of the [`Cpu`], to be taken and
+/// restored at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub f: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub ime_scheduled: bool,
+    pub halted: bool,
+    pub halt_bug: bool,
+    pub locked: bool,
+}
+
+/// The CPU registers and execution state. With the `serde` feature enabled,
+/// this can be serialized to snapshot and restore it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cpu {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub f: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// The interrupt master enable flag.
+    pub ime: bool,
+    /// Set by EI, since IME only gets enabled after the following instruction.
+    pub ime_scheduled: bool,
+    pub halted: bool,
+    /// Set when HALT is executed with interrupts disabled while one is pending,
+    /// causing the next opcode fetch to not increment PC.
+    pub halt_bug: bool,
+    /// Set when one of the illegal opcodes is executed, which hangs the CPU
+    /// until it is reset.
+    pub locked: bool,
+
+    /// The M-cycles ticked during the current instruction.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ticks: usize,
+    /// The T-cycles passed since power on.
+    cycles: u64,
+}
+
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            f: 0,
+            sp: 0,
+            pc: 0,
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            locked: false,
+            ticks: 0,
+            cycles: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.b = 0;
+        self.c = 0;
+        self.d = 0;
+        self.e = 0;
+        self.h = 0;
+        self.l = 0;
+        self.f = 0;
+        self.pc = 0;
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.halted = false;
+        self.halt_bug = false;
+        self.locked = false;
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            f: self.f(),
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            ime_scheduled: self.ime_scheduled,
+            halted: self.halted,
+            halt_bug: self.halt_bug,
+            locked: self.locked,
+        }
+    }
+
+    /// Restores a snapshot, leaving the cycle count as it is.
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.a = snapshot.a;
+        self.b = snapshot.b;
+        self.c = snapshot.c;
+        self.d = snapshot.d;
+        self.e = snapshot.e;
+        self.h = snapshot.h;
+        self.l = snapshot.l;
+        self.f = snapshot.f & 0xf0;
+        self.sp = snapshot.sp;
+        self.pc = snapshot.pc;
+        self.ime = snapshot.ime;
+        self.ime_scheduled = snapshot.ime_scheduled;
+        self.halted = snapshot.halted;
+        self.halt_bug = snapshot.halt_bug;
+        self.locked = snapshot.locked;
+    }
+
+    pub fn af(&self) -> u16 {
+        (self.a as u16) << 8 | (self.f() as u16)
+    }
+
+    /// The flags register. Its lower nibble always reads as zero, even when
+    /// `f` was written directly.
+    pub fn f(&self) -> u8 {
+        self.f & 0xf0
+    }
+
+    pub fn set_af(&mut self, value: u16) {
+        self.a = (value >> 8) as u8;
+        self.f = value as u8 & 0xf0;
+    }
+
+    pub fn bc(&self) -> u16 {
+        (self.b as u16) << 8 | (self.c as u16)
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        self.b = (value >> 8) as u8;
+        self.c = value as u8;
+    }
+
+    pub fn de(&self) -> u16 {
+        (self.d as u16) << 8 | (self.e as u16)
+    }
+
+    pub fn set_de(&mut self, value: u16) {
+        self.d = (value >> 8) as u8;
+        self.e = value as u8;
+    }
+
+    pub fn hl(&self) -> u16 {
+        (self.h as u16) << 8 | (self.l as u16)
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        self.h = (value >> 8) as u8;
+        self.l = value as u8;
+    }
+
+    pub fn get_flag(&self, flag: CpuFlag) -> bool {
+        self.f & flag.bit() != 0
+    }
+
+    pub fn set_flag(&mut self, flag: CpuFlag, value: bool) {
+        if value {
+            self.f |= flag.bit()
+        } else {
+            self.f &= !flag.bit()
+        }
+    }
+}
+
+/// Returns the interrupts that are both requested (IF) and enabled (IE).
+pub fn pending_interrupts<M: Memory>(mem: &M) -> Result<Interrupts, MemoryError> {
+    let enabled = Interrupts::from_bits_truncate(mem.read(0xffff)?);
+    let requested = Interrupts::from_bits_truncate(mem.read(0xff0f)?);
+    Ok(enabled & requested)
+}
+
+impl Cpu {
+    /// Executes the next instruction, ticking the memory for every M-cycle it
+    /// takes. Returns the amount of M-cycles taken.
+    pub fn exec_next_instruction<M: Memory>(&mut self, mem: &mut M) -> Result<usize, CpuError> {
+        self.ticks = 0;
+        let cycles = self.fetch_and_exec(mem)?;
+
+        // Whatever is left are internal cycles without a bus access.
+        while self.ticks < cycles {
+            self.tick(mem);
+        }
+
+        Ok(cycles)
+    }
+
+    fn fetch_and_exec<M: Memory>(&mut self, mem: &mut M) -> Result<usize, CpuError> {
+        if self.locked {
+            return Ok(1);
+        }
+
+        let pc = self.pc;
+        let instruction = match self.fetch_timed(mem) {
+            Err(InstructionError::InvalidOpcode { .. }) => {
+                self.pc = pc;
+                self.locked = true;
+                return Ok(1);
+            }
+            result => result?,
+        };
+
+        let result = self.exec_instruction(mem, instruction);
+        if result.is_err() {
+            self.pc = pc;
+        }
+
+        result
+    }
+
+    /// Executes a decoded instruction, returning the amount of M-cycles it
+    /// took. The work is handed to the module for its category.
+    pub fn exec_instruction<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<usize, CpuError> {
+        if self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.ime = true;
+        }
+
+        let mut cycles = instruction.cycles();
+
+        match instruction {
+            Instruction::Load(..) => self.exec_load(mem, instruction)?,
+            Instruction::And(_)
+            | Instruction::Or(_)
+            | Instruction::Xor(_)
+            | Instruction::Compare(_)
+            | Instruction::Add8(..)
+            | Instruction::Add16(..)
+            | Instruction::Subtract(..)
+            | Instruction::Increment(_)
+            | Instruction::Decrement(_)
+            | Instruction::Complement
+            | Instruction::DAA
+            | Instruction::SetCarryFlag(_) => self.exec_alu(mem, instruction)?,
+            Instruction::Bit(..)
+            | Instruction::SetBit(..)
+            | Instruction::RotateLeftA(_)
+            | Instruction::RotateLeft(..)
+            | Instruction::RotateRightA(_)
+            | Instruction::RotateRight(..)
+            | Instruction::ShiftLeft(_)
+            | Instruction::ShiftRight(..)
+            | Instruction::Swap(_) => self.exec_bits(mem, instruction)?,
+            Instruction::Jump(_)
+            | Instruction::JumpIf(..)
+            | Instruction::JumpRelative(_)
+            | Instruction::JumpRelativeIf(..)
+            | Instruction::Call(_)
+            | Instruction::CallIf(..)
+            | Instruction::Return
+            | Instruction::ReturnIf(..)
+            | Instruction::ReturnInterrupt
+            | Instruction::Rst(_) => cycles += self.exec_jump(mem, instruction)?,
+            Instruction::Push(_) | Instruction::Pop(_) | Instruction::SPOps(_) => {
+                self.exec_stack(mem, instruction)?
+            }
+            Instruction::Noop
+            | Instruction::Stop
+            | Instruction::Halt
+            | Instruction::DisableInterrupts
+            | Instruction::EnableInterrupts => self.exec_misc(mem, instruction)?,
+        }
+
+        Ok(cycles)
+    }
+}
+
+impl Cpu {
+    /// Decodes the instruction at PC and moves PC past it, without letting any
+    /// time pass.
+    pub fn fetch_instruction<M: Memory>(
+        &mut self,
+        mem: &mut M,
+    ) -> Result<Instruction, InstructionError> {
+        let (ticks, cycles) = (self.ticks, self.cycles);
+        let instruction = self.fetch_timed(&mut Untimed(mem));
+        self.ticks = ticks;
+        self.cycles = cycles;
+
+        instruction
+    }
+
+    /// Decodes the instruction at PC, taking an M-cycle for every byte read.
+    fn fetch_timed<M: Memory>(&mut self, mem: &mut M) -> Result<Instruction, InstructionError> {
+        let opcode = self.fetch_u8(mem)?;
+
+        let entry = if opcode == 0xcb {
+            CB_OPCODES[self.fetch_u8(mem)? as usize]
+        } else {
+            OPCODES[opcode as usize].ok_or(InstructionError::InvalidOpcode {
+                opcode: opcode as u16,
+            })?
+        };
+
+        let immediate = match entry.immediate_len {
+            0 => 0,
+            1 => self.fetch_u8(mem)? as u16,
+            _ => self.fetch_u16(mem)?,
+        };
+
+        Ok((entry.build)(immediate))
+    }
+
+    fn fetch_u8<M: Memory>(&mut self, mem: &mut M) -> Result<u8, MemoryError> {
+        let ret = self.read_cycle(mem, self.pc)?;
+
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+
+        Ok(ret)
+    }
+
+    fn fetch_u16<M: Memory>(&mut self, mem: &mut M) -> Result<u16, MemoryError> {
+        let lo = self.read_cycle(mem, self.pc)? as u16;
+        let hi = self.read_cycle(mem, self.pc.wrapping_add(1))? as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(hi << 8 | lo)
+    }
+
+    /// Dispatches the highest priority pending interrupt if IME is set, which
+    /// takes 5 M-cycles. Returns the amount of cycles taken.
+    ///
+    /// The vector is only chosen after the upper byte of PC has been pushed, so
+    /// if that push overwrites IE the dispatch can be redirected to another
+    /// interrupt, or cancelled entirely in which case execution continues at
+    /// 0x0000.
+    pub fn process_interrupts<M: Memory>(&mut self, mem: &mut M) -> Result<usize, MemoryError> {
+        if !self.ime || self.locked || pending_interrupts(mem)?.is_empty() {
+            return Ok(0);
+        }
+
+        self.ime = false;
+
+        self.tick(mem);
+        self.tick(mem);
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(mem, self.sp, (self.pc >> 8) as u8)?;
+
+        let pending = pending_interrupts(mem)?;
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(mem, self.sp, self.pc as u8)?;
+
+        let vectors = [
+            (Interrupts::VBLANK, 0x40),
+            (Interrupts::LCD_STAT, 0x48),
+            (Interrupts::TIMER, 0x50),
+            (Interrupts::SERIAL, 0x58),
+            (Interrupts::JOYPAD, 0x60),
+        ];
+
+        self.pc = 0x0000;
+        for (interrupt, address) in vectors.iter() {
+            if pending.contains(*interrupt) {
+                let requested = Interrupts::from_bits_truncate(mem.read(0xff0f)?);
+                mem.write(0xff0f, (requested - *interrupt).bits())?;
+                self.pc = *address;
+                break;
+            }
+        }
+
+        self.tick(mem);
+
+        Ok(5)
+    }
+
+    pub fn disassemble<M: Memory>(&mut self, mem: &mut M, max: u16) -> BTreeMap<u16, String> {
+        let old_pc = self.pc;
+        let old_halt_bug = self.halt_bug;
+        self.halt_bug = false;
+
+        let mut res = BTreeMap::new();
+
+        self.pc = 0;
+        let mut pc = 0;
+        while !res.contains_key(&pc) && pc < max {
+            let instruction = self.fetch_instruction(mem);
+            if let Ok(instruction) = instruction {
+                res.insert(pc, format!("{:#06x}: {}", pc, instruction));
+            } else {
+                res.insert(pc, format!("{:#06x}: <unknown>", pc));
+            }
+            pc = self.pc;
+        }
+
+        self.pc = old_pc;
+        self.halt_bug = old_halt_bug;
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::{Cpu, CpuError, CpuFlag, CpuSnapshot, Interrupts};
+    use crate::{
+        instruction::Instruction,
+        memory::{Memory, MemoryError},
+    };
+
+    pub(super) struct TestMemory(pub(super) Box<[u8; 0x10000]>);
+
+    impl TestMemory {
+        pub(super) fn new() -> TestMemory {
+            TestMemory(Box::new([0; 0x10000]))
+        }
+    }
+
+    impl Memory for TestMemory {
+        fn read(&self, address: u16) -> Result<u8, MemoryError> {
+            Ok(self.0[address as usize])
+        }
+
+        fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+            self.0[address as usize] = value;
+            Ok(())
+        }
+    }
+
+    pub(super) fn flags(cpu: &Cpu) -> (bool, bool, bool, bool) {
+        (
+            cpu.get_flag(CpuFlag::Zero),
+            cpu.get_flag(CpuFlag::Subtraction),
+            cpu.get_flag(CpuFlag::HalfCarry),
+            cpu.get_flag(CpuFlag::Carry),
+        )
+    }
+
+    #[test]
+    fn interrupt_priority() {
+        // (requested interrupts, expected order of serviced vectors)
+        let cases = [
+            (Interrupts::all(), vec![0x40, 0x48, 0x50, 0x58, 0x60]),
+            (Interrupts::JOYPAD | Interrupts::TIMER, vec![0x50, 0x60]),
+            (Interrupts::SERIAL | Interrupts::LCD_STAT, vec![0x48, 0x58]),
+            (Interrupts::JOYPAD | Interrupts::VBLANK, vec![0x40, 0x60]),
+            (Interrupts::TIMER, vec![0x50]),
+            (Interrupts::empty(), vec![]),
+        ];
+
+        for (requested, expected) in cases.iter() {
+            let mut mem = TestMemory::new();
+            mem.0[0xff0f] = requested.bits();
+            mem.0[0xffff] = Interrupts::all().bits();
+
+            let mut cpu = Cpu::new();
+            cpu.sp = 0xfffe;
+
+            let mut serviced = Vec::new();
+
+            loop {
+                let before = mem.0[0xff0f];
+
+                cpu.ime = true;
+                let cycles = cpu.process_interrupts(&mut mem).unwrap();
+
+                if cycles == 0 {
+                    assert_eq!(before, 0);
+                    break;
+                }
+
+                assert_eq!(cycles, 5);
+                assert_eq!(
+                    (before ^ mem.0[0xff0f]).count_ones(),
+                    1,
+                    "more than one dispatch"
+                );
+                assert!(!cpu.ime);
+
+                serviced.push(cpu.pc);
+            }
+
+            assert_eq!(&serviced, expected, "requested {:?}", requested);
+        }
+    }
+
+    #[test]
+    fn interrupt_dispatch_ie_push() {
+        // (pc, requested, enabled, expected vector, expected IF afterwards)
+        let cases = [
+            (0x0012, 0x01, 0x01, 0x0000, 0x01),
+            (0x0412, 0x05, 0x05, 0x0050, 0x01),
+            (0x0112, 0x05, 0x05, 0x0040, 0x04),
+            (0x1f12, 0x10, 0x10, 0x0060, 0x00),
+        ];
+
+        for (pc, requested, enabled, vector, remaining) in cases.iter().copied() {
+            let mut mem = TestMemory::new();
+            mem.0[0xff0f] = requested;
+            mem.0[0xffff] = enabled;
+
+            let mut cpu = Cpu::new();
+            cpu.pc = pc;
+            cpu.sp = 0x0000;
+            cpu.ime = true;
+
+            assert_eq!(cpu.process_interrupts(&mut mem).unwrap(), 5);
+            assert_eq!(cpu.pc, vector, "pc {:#06x}", pc);
+            assert_eq!(mem.0[0xff0f], remaining, "pc {:#06x}", pc);
+            assert_eq!(mem.0[0xfffe], pc as u8);
+            assert_eq!(cpu.sp, 0xfffe);
+        }
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let snapshot = CpuSnapshot {
+            a: 0x01,
+            f: 0xb0,
+            h: 0x01,
+            l: 0x4d,
+            sp: 0xfffe,
+            pc: 0x0100,
+            ime: true,
+            halted: true,
+            ..CpuSnapshot::default()
+        };
+
+        let mut cpu = Cpu::new();
+        cpu.restore(&snapshot);
+        assert_eq!(cpu.hl(), 0x014d);
+        assert_eq!(cpu.snapshot(), snapshot);
+
+        cpu.restore(&CpuSnapshot {
+            f: 0xff,
+            ..snapshot
+        });
+        assert_eq!(cpu.snapshot().f, 0xf0);
+    }
+
+    #[test]
+    fn cycle_counter() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0000] = 0x00; // nop
+        mem.0[0x0001] = 0xc3; // jp $0000
+        mem.0[0x0004] = 0x76; // halt
+
+        let mut cpu = Cpu::new();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert_eq!(cpu.cycles(), 20);
+
+        cpu.disassemble(&mut mem, 0x10);
+        cpu.idle(&mut mem);
+        cpu.reset();
+        assert_eq!(cpu.cycles(), 24);
+    }
+
+    #[test]
+    fn illegal_opcode_lock() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0100] = 0xd3;
+        mem.0[0xff0f] = Interrupts::VBLANK.bits();
+        mem.0[0xffff] = Interrupts::VBLANK.bits();
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.ime = true;
+
+        for _ in 0..3 {
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 1);
+            assert_eq!(cpu.process_interrupts(&mut mem).unwrap(), 0);
+            assert!(cpu.locked);
+            assert_eq!(cpu.pc, 0x0100);
+        }
+
+        cpu.reset();
+        assert!(!cpu.locked);
+    }
+
+    #[test]
+    fn unimplemented_instruction() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0100] = 0x10;
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+
+        assert!(matches!(
+            cpu.exec_next_instruction(&mut mem),
+            Err(CpuError::Unimplemented(Instruction::Stop))
+        ));
+        assert_eq!(cpu.pc, 0x0100);
+    }
+
+    /// A bus access as the M-cycle it happened in, the operation and address.
+    type Access = (usize, &'static str, u16);
+
+    /// Records every bus access along with the M-cycle it happened in.
+    struct TimedMemory {
+        mem: TestMemory,
+        cycle: usize,
+        accesses: RefCell<Vec<Access>>,
+    }
+
+    impl Memory for TimedMemory {
+        fn read(&self, address: u16) -> Result<u8, MemoryError> {
+            self.accesses
+                .borrow_mut()
+                .push((self.cycle, "read", address));
+            self.mem.read(address)
+        }
+
+        fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+            self.accesses
+                .borrow_mut()
+                .push((self.cycle, "write", address));
+            self.mem.write(address, value)
+        }
+
+        fn tick(&mut self) {
+            self.cycle += 1;
+        }
+    }
+
+    #[test]
+    fn memory_access_timing() {
+        let cases: [(&[u8], &[Access], usize); 5] = [
+            // inc (hl)
+            (
+                &[0x34],
+                &[
+                    (0, "read", 0x0000),
+                    (1, "read", 0xc000),
+                    (2, "write", 0xc000),
+                ],
+                3,
+            ),
+            // push bc
+            (
+                &[0xc5],
+                &[
+                    (0, "read", 0x0000),
+                    (2, "write", 0xfffd),
+                    (3, "write", 0xfffc),
+                ],
+                4,
+            ),
+            // call $2000
+            (
+                &[0xcd, 0x00, 0x20],
+                &[
+                    (0, "read", 0x0000),
+                    (1, "read", 0x0001),
+                    (2, "read", 0x0002),
+                    (4, "write", 0xfffd),
+                    (5, "write", 0xfffc),
+                ],
+                6,
+            ),
+            // ret z
+            (
+                &[0xc8],
+                &[
+                    (0, "read", 0x0000),
+                    (2, "read", 0xfffe),
+                    (3, "read", 0xffff),
+                ],
+                5,
+            ),
+            // ld ($c000), sp
+            (
+                &[0x08, 0x00, 0xc0],
+                &[
+                    (0, "read", 0x0000),
+                    (1, "read", 0x0001),
+                    (2, "read", 0x0002),
+                    (3, "write", 0xc000),
+                    (4, "write", 0xc001),
+                ],
+                5,
+            ),
+        ];
+
+        for (code, expected, cycles) in cases.iter() {
+            let mut mem = TimedMemory {
+                mem: TestMemory::new(),
+                cycle: 0,
+                accesses: RefCell::new(Vec::new()),
+            };
+            mem.mem.0[..code.len()].copy_from_slice(code);
+
+            let mut cpu = Cpu::new();
+            cpu.set_hl(0xc000);
+            cpu.sp = 0xfffe;
+            cpu.set_flag(CpuFlag::Zero, true);
+
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), *cycles);
+            assert_eq!(mem.cycle, *cycles, "{:02x?}", code);
+            assert_eq!(mem.accesses.into_inner(), *expected, "{:02x?}", code);
+        }
+    }
+
+    #[test]
+    fn interrupt_dispatch_timing() {
+        let mut mem = TimedMemory {
+            mem: TestMemory::new(),
+            cycle: 0,
+            accesses: RefCell::new(Vec::new()),
+        };
+        mem.mem.0[0xff0f] = Interrupts::VBLANK.bits();
+        mem.mem.0[0xffff] = Interrupts::VBLANK.bits();
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x1234;
+        cpu.sp = 0xfffe;
+        cpu.ime = true;
+
+        assert_eq!(cpu.process_interrupts(&mut mem).unwrap(), 5);
+        assert_eq!(mem.cycle, 5);
+
+        let writes: Vec<_> = mem
+            .accesses
+            .into_inner()
+            .into_iter()
+            .filter(|(_, op, address)| *op == "write" && *address != 0xff0f)
+            .collect();
+        assert_eq!(writes, vec![(2, "write", 0xfffd), (3, "write", 0xfffc)]);
+    }
+
+    /// Records the addresses the CPU drives onto the bus when incrementing or
+    /// decrementing a 16-bit register.
+    struct DrivenMemory {
+        mem: TestMemory,
+        driven: Vec<u16>,
+    }
+
+    impl Memory for DrivenMemory {
+        fn read(&self, address: u16) -> Result<u8, MemoryError> {
+            self.mem.read(address)
+        }
+
+        fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+            self.mem.write(address, value)
+        }
+
+        fn drive_address(&mut self, address: u16) {
+            self.driven.push(address);
+        }
+    }
+
+    #[test]
+    fn driven_addresses() {
+        let cases: [(&[u8], &[u16]); 11] = [
+            // inc bc, dec de, inc hl, dec sp
+            (&[0x03], &[0xc100]),
+            (&[0x1b], &[0xc200]),
+            (&[0x23], &[0xfe10]),
+            (&[0x3b], &[0xfffe]),
+            // ld a, (hl+) and ld a, (hl-)
+            (&[0x2a], &[0xfe10]),
+            (&[0x3a], &[0xfe10]),
+            // ld (hl+), a and ld (hl-), a
+            (&[0x22], &[0xfe10]),
+            (&[0x32], &[0xfe10]),
+            // push bc, pop bc
+            (&[0xc5], &[0xfffe, 0xfffd]),
+            (&[0xc1], &[0xfffe, 0xffff]),
+            // inc b and ld a, (hl) leave the bus alone
+            (&[0x04, 0x7e], &[]),
+        ];
+
+        for (code, expected) in cases.iter() {
+            let mut mem = DrivenMemory {
+                mem: TestMemory::new(),
+                driven: Vec::new(),
+            };
+            mem.mem.0[..code.len()].copy_from_slice(code);
+
+            let mut cpu = Cpu::new();
+            cpu.set_bc(0xc100);
+            cpu.set_de(0xc200);
+            cpu.set_hl(0xfe10);
+            cpu.sp = 0xfffe;
+
+            for _ in 0..code.len() {
+                cpu.exec_next_instruction(&mut mem).unwrap();
+            }
+            assert_eq!(mem.driven, *expected, "{:02x?}", code);
+        }
+    }
+}
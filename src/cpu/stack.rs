@@ -0,0 +1,184 @@
+use super::{Cpu, CpuError};
+use crate::{
+    instruction::{Instruction, SPOps},
+    memory::Memory,
+};
+
+impl Cpu {
+    /// Executes pushes, pops and the arithmetic on SP.
+    pub(super) fn exec_stack<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        instruction: Instruction,
+    ) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Push(reg) => {
+                let value = self.get_reg_u16(reg)?;
+                self.push_u16(mem, value)?
+            }
+            Instruction::Pop(reg) => {
+                let value = self.pop_u16(mem)?;
+                self.set_reg_u16(reg, value)?;
+            }
+            Instruction::SPOps(op) => match op {
+                SPOps::AddOffset(offset) => {
+                    self.sp = self.offset_sp(offset);
+                }
+                SPOps::LoadIntoHL(offset) => {
+                    let value = self.offset_sp(offset);
+                    self.set_hl(value);
+                }
+                SPOps::LoadFromHL => {
+                    self.sp = self.hl();
+                }
+            },
+            _ => unreachable!("{} is not a stack instruction", instruction),
+        }
+
+        Ok(())
+    }
+
+    /// Adds a signed offset to SP. The flags are set by the unsigned addition
+    /// of the offset to the low byte of SP.
+    fn offset_sp(&mut self, offset: i8) -> u16 {
+        let offset = offset as i16 as u16;
+        let carries = self.sp ^ offset ^ self.sp.wrapping_add(offset);
+
+        self.set_flags(false, false, carries & 0x10 != 0, carries & 0x100 != 0);
+
+        self.sp.wrapping_add(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{flags, TestMemory};
+    use crate::{cpu::Cpu, instruction::CpuRegister};
+
+    #[test]
+    fn stack_pointer_offset() {
+        // (opcode, sp, offset, result, (Z, N, H, C), cycles)
+        let cases = [
+            (0xe8, 0xfff8, 0x08, 0x0000, (false, false, true, true), 4),
+            (0xe8, 0x000f, 0x01, 0x0010, (false, false, true, false), 4),
+            (0xe8, 0x00ff, 0x01, 0x0100, (false, false, true, true), 4),
+            (0xe8, 0x1000, 0xff, 0x0fff, (false, false, false, false), 4),
+            (0xe8, 0x10ff, 0xff, 0x10fe, (false, false, true, true), 4),
+            (0xf8, 0xfff8, 0x08, 0x0000, (false, false, true, true), 3),
+            (0xf8, 0x0001, 0xfe, 0xffff, (false, false, false, false), 3),
+            (0xf8, 0x0002, 0xfe, 0x0000, (false, false, true, true), 3),
+        ];
+
+        for (opcode, sp, offset, result, expected, cycles) in cases.iter().copied() {
+            let mut mem = TestMemory::new();
+            mem.0[0x0100..0x0102].copy_from_slice(&[opcode, offset]);
+
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0100;
+            cpu.sp = sp;
+            cpu.f = 0xf0;
+
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), cycles);
+
+            let actual = if opcode == 0xe8 { cpu.sp } else { cpu.hl() };
+            assert_eq!(
+                actual, result,
+                "{:#04x} {:#06x}, {}",
+                opcode, sp, offset as i8
+            );
+            assert_eq!(
+                flags(&cpu),
+                expected,
+                "{:#04x} {:#06x}, {}",
+                opcode,
+                sp,
+                offset as i8
+            );
+        }
+
+        let mut mem = TestMemory::new();
+        mem.0[0x0100] = 0xf9;
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0100;
+        cpu.set_hl(0xc123);
+
+        assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 2);
+        assert_eq!(cpu.sp, 0xc123);
+    }
+
+    #[test]
+    fn flags_low_nibble() {
+        let mut mem = TestMemory::new();
+        mem.0[0x0000] = 0xf5; // push af
+        mem.0[0x0001] = 0xd1; // pop de
+        mem.0[0x0002] = 0xd5; // push de
+        mem.0[0x0003] = 0xf1; // pop af
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xfffe;
+        cpu.a = 0x12;
+        cpu.f = 0xff;
+        assert_eq!(cpu.af(), 0x12f0);
+
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert_eq!(cpu.de(), 0x12f0);
+
+        cpu.set_de(0x34ff);
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        cpu.exec_next_instruction(&mut mem).unwrap();
+        assert_eq!(cpu.af(), 0x34f0);
+        assert_eq!(cpu.f, 0xf0);
+    }
+
+    #[test]
+    fn push_pop_every_pair() {
+        // (push opcode, pop opcode, register pair)
+        let pairs = [
+            (0xc5, 0xc1, CpuRegister::BC),
+            (0xd5, 0xd1, CpuRegister::DE),
+            (0xe5, 0xe1, CpuRegister::HL),
+            (0xf5, 0xf1, CpuRegister::AF),
+        ];
+
+        for (push, pop, reg) in pairs.iter().copied() {
+            let mut mem = TestMemory::new();
+            mem.0[0x0100..0x0102].copy_from_slice(&[push, pop]);
+
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0100;
+            cpu.sp = 0xd000;
+            cpu.a = 0x12;
+            cpu.f = 0x30;
+            cpu.set_bc(0x3456);
+            cpu.set_de(0x789a);
+            cpu.set_hl(0xbcde);
+            let value = match reg {
+                CpuRegister::BC => cpu.bc(),
+                CpuRegister::DE => cpu.de(),
+                CpuRegister::HL => cpu.hl(),
+                _ => cpu.af(),
+            };
+
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 4);
+            assert_eq!(cpu.sp, 0xcffe, "{:?}", reg);
+            assert_eq!(&mem.0[0xcffe..0xd000], &value.to_le_bytes(), "{:?}", reg);
+
+            // Popping gets back the same value, after clobbering the pair.
+            cpu.set_bc(0);
+            cpu.set_de(0);
+            cpu.set_hl(0);
+            cpu.set_af(0);
+            assert_eq!(cpu.exec_next_instruction(&mut mem).unwrap(), 3);
+            assert_eq!(cpu.sp, 0xd000, "{:?}", reg);
+            let popped = match reg {
+                CpuRegister::BC => cpu.bc(),
+                CpuRegister::DE => cpu.de(),
+                CpuRegister::HL => cpu.hl(),
+                _ => cpu.af(),
+            };
+            assert_eq!(popped, value, "{:?}", reg);
+        }
+    }
+}
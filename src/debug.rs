@@ -1,358 +1,1438 @@
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Range,
+    path::{Path, PathBuf},
     rc::Rc,
-    time::{Duration, Instant},
 };
 
-use gameboy::{cpu::CpuFlag, device::Device};
+use gameboy::{
+    apu::ChannelState,
+    cpu::CpuFlag,
+    decode::OPCODES,
+    device::{Device, ReturnTarget, StopReason},
+    gpu::{LayerVisibility, SpriteFlags, Tilemap},
+    memory::watch::{WatchKind, Watchpoint},
+    model::{io_addresses, IO_REGISTERS},
+    palette::PALETTES,
+};
 use glium::{
     glutin::{
         dpi::LogicalSize,
-        event::{Event, WindowEvent},
-        event_loop::{ControlFlow, EventLoop},
-        window::WindowBuilder,
-        ContextBuilder,
+        event::{Event, VirtualKeyCode},
     },
     texture::{ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat},
     uniforms::{MagnifySamplerFilter, SamplerBehavior},
-    Display, Rect, Surface, Texture2d,
+    Display, Frame, Rect, Surface, Texture2d,
 };
 use imgui::{
     im_str,
-    sys::{igBeginPopupContextItem, igEndPopup},
-    ChildWindow, Condition, Context, FontConfig, FontSource, ImString, Image, MenuItem, Selectable,
-    Window,
+    sys::{igBeginPopupContextItem, igEndPopup, igIsKeyPressed},
+    ChildWindow, Condition, FocusedWidget, FontConfig, FontSource, ImString, Image, MenuItem,
+    Selectable, TextureId, Ui, Window,
 };
 use imgui_glium_renderer::{Renderer, Texture};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 
+use crate::{
+    condition::BreakCondition,
+    dirs::DataDirs,
+    frontend::{self, Frontend, Runtime, Trace},
+    screenshot::ScreenshotMode,
+    script::{self, Command, Script},
+    session::{watch_kind_name, Session},
+    template::Templates,
+    view::draw_viewport,
+};
+
 enum RunStatus {
     Running,
     RunningUntil(u16),
+    SteppingOver(ReturnTarget),
+    SteppingOut(ReturnTarget),
+    RunningFrames(usize),
     Paused,
 }
 
-pub fn start_debug_view(mut device: Device) {
-    let disassembly = device.disassemble(0x8000);
+/// Runs the device until the end of the current frame, or until `should_stop`
+/// returns true after an instruction, the CPU locks up or the device stops.
+/// Returns whether execution was stopped.
+fn run_frame_until<F: Fn(&Device) -> bool>(device: &mut Device, should_stop: F) -> bool {
+    loop {
+        let frame = device.step();
+
+        if should_stop(device) || device.is_locked() || device.stop_reason().is_some() {
+            return true;
+        }
 
-    let event_loop = EventLoop::new();
-    let context = ContextBuilder::new().with_vsync(true);
-    let builder = WindowBuilder::new()
-        .with_title(device.cart().title().unwrap_or("gameboy"))
-        .with_inner_size(LogicalSize::new(874, 473));
-    let display = Display::new(builder, context, &event_loop).expect("failed to create display");
+        if frame {
+            return false;
+        }
+    }
+}
 
-    let mut imgui = Context::create();
-    imgui.set_ini_filename(None);
+/// Like [`run_frame_until`], but steps towards a return target and also
+/// stops once it is reached.
+fn run_frame_towards<F: Fn(&Device) -> bool>(
+    device: &mut Device,
+    target: &mut ReturnTarget,
+    should_stop: F,
+) -> bool {
+    loop {
+        let frame = device.step_towards(target);
 
-    let mut platform = WinitPlatform::init(&mut imgui);
-    {
-        let gl_window = display.gl_window();
-        let window = gl_window.window();
-        platform.attach_window(imgui.io_mut(), window, HiDpiMode::Default);
+        if target.is_reached()
+            || should_stop(device)
+            || device.is_locked()
+            || device.stop_reason().is_some()
+        {
+            return true;
+        }
+
+        if frame {
+            return false;
+        }
     }
+}
 
-    let hidpi_factor = platform.hidpi_factor();
-    let font_size = hidpi_factor * 13.0;
-    imgui.fonts().add_font(&[FontSource::DefaultFontData {
-        config: Some(FontConfig {
-            size_pixels: font_size as f32,
-            ..FontConfig::default()
-        }),
-    }]);
-
-    imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
-
-    let mut renderer =
-        Renderer::init(&mut imgui, &display).expect("failed to create imgui glium renderer");
-
-    let display_texture = Rc::new(
-        Texture2d::empty_with_format(
-            &display,
-            UncompressedFloatFormat::U8U8U8,
-            MipmapsOption::NoMipmap,
-            160,
-            144,
-        )
-        .expect("failed to create display texture"),
-    );
-    let display_texture_id = renderer.textures().insert(Texture {
-        texture: display_texture.clone(),
-        sampler: SamplerBehavior {
-            magnify_filter: MagnifySamplerFilter::Nearest,
-            ..SamplerBehavior::default()
-        },
-    });
-
-    let tile_texture = Rc::new(
-        Texture2d::empty_with_format(
-            &display,
-            UncompressedFloatFormat::U8U8U8,
-            MipmapsOption::NoMipmap,
-            8 * 16,
-            8 * 24,
-        )
-        .expect("failed to create tile texture"),
-    );
-    let tile_texture_id = renderer.textures().insert(Texture {
-        texture: tile_texture.clone(),
-        sampler: SamplerBehavior {
-            magnify_filter: MagnifySamplerFilter::Nearest,
-            ..SamplerBehavior::default()
-        },
-    });
-
-    let mut display_scale = 3;
-    let mut follow_execution = true;
-    let mut run_status = RunStatus::Paused;
-    let mut emulation_speed = 4194304.0 / 70224.0;
-    let mut last_frame = Instant::now();
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::MainEventsCleared => {
+/// Steps over calls and restarts by running until they return, and steps a
+/// single instruction otherwise.
+fn step_over(device: &mut Device) -> RunStatus {
+    match device.step_over() {
+        Some(target) => RunStatus::SteppingOver(target),
+        None => RunStatus::Paused,
+    }
+}
+
+const AUDIO_HISTORY_FRAMES: usize = 256;
+/// Where sessions are exported to unless `--debug-session` says otherwise.
+const DEFAULT_SESSION: &str = "session.json";
+/// The range of MIDI note numbers shown in the piano roll, C1 up to C8.
+const PIANO_ROLL_NOTES: Range<i32> = 24..108;
+const CHANNEL_NAMES: [&str; 4] = ["Square 1", "Square 2", "Wave", "Noise"];
+const CHANNEL_COLORS: [[f32; 4]; 4] = [
+    [1.0, 0.3, 0.3, 1.0],
+    [1.0, 0.9, 0.3, 1.0],
+    [0.3, 0.9, 1.0, 1.0],
+    [0.4, 1.0, 0.4, 1.0],
+];
+
+/// Converts a frequency to a (fractional) MIDI note number, where 69 is A4.
+fn note_number(frequency: f32) -> f32 {
+    69.0 + 12.0 * (frequency / 440.0).log2()
+}
+
+fn note_name(frequency: f32) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    let note = note_number(frequency).round() as i32;
+    format!(
+        "{}{}",
+        NAMES[note.rem_euclid(12) as usize],
+        note.div_euclid(12) - 1
+    )
+}
+
+/// The mnemonic of an unprefixed opcode, like `rst` for 0xff.
+fn opcode_name(opcode: u8) -> String {
+    match OPCODES[opcode as usize] {
+        Some(entry) => (entry.build)(0)
+            .to_string()
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+        None if opcode == 0xcb => "prefix".to_owned(),
+        None => "illegal".to_owned(),
+    }
+}
+
+fn key_pressed(key: VirtualKeyCode) -> bool {
+    unsafe { igIsKeyPressed(key as i32, false) }
+}
+
+struct DebugView {
+    imgui: imgui::Context,
+    platform: WinitPlatform,
+    renderer: Renderer,
+    disassembly: BTreeMap<u16, String>,
+    display_texture: Rc<Texture2d>,
+    display_texture_id: TextureId,
+    tile_texture: Rc<Texture2d>,
+    tile_texture_id: TextureId,
+    map_texture: Rc<Texture2d>,
+    map_texture_id: TextureId,
+    map_pixels: Vec<u8>,
+    /// Whether the Background window shows the tile map the background
+    /// doesn't use, such as the window's.
+    show_other_map: bool,
+    sprite_texture: Rc<Texture2d>,
+    sprite_texture_id: TextureId,
+    /// Every sprite as it looks, in a grid of 10 by 4 cells of 8x16 RGBA
+    /// pixels.
+    sprite_pixels: Vec<u8>,
+    display_scale: i32,
+    follow_execution: bool,
+    run_status: RunStatus,
+    /// Breakpoint addresses, with the condition each one only stops on.
+    breakpoints: BTreeMap<u16, Option<BreakCondition>>,
+    /// The condition for a breakpoint on the selected line, like `a == 12`.
+    condition_input: ImString,
+    /// Opcodes to pause on, such as 0xff to catch runaway execution through
+    /// memory filled with 0xff bytes.
+    opcode_breakpoints: BTreeSet<u8>,
+    opcode_input: ImString,
+    /// The addresses to watch, as a pattern like `NR1x`.
+    watch_input: ImString,
+    /// An address to freeze and its value, like `c0a0=05`.
+    freeze_input: ImString,
+    /// Where the breakpoints are imported from and exported to.
+    session_path: ImString,
+    selected_line: Option<u16>,
+    scroll_to: Option<u16>,
+    goto_address: ImString,
+    audio_history: VecDeque<[ChannelState; 4]>,
+    script: Option<Script>,
+    /// The instructions leading up to a crash, when opened from the game view.
+    trace: Option<Trace>,
+}
+
+impl DebugView {
+    fn new(
+        display: &Display,
+        disassembly: BTreeMap<u16, String>,
+        script: Option<Script>,
+        trace: Option<Trace>,
+        session: Session,
+        session_path: Option<PathBuf>,
+    ) -> DebugView {
+        let mut imgui = imgui::Context::create();
+        imgui.set_ini_filename(None);
+
+        let mut platform = WinitPlatform::init(&mut imgui);
+        {
             let gl_window = display.gl_window();
-            platform
-                .prepare_frame(imgui.io_mut(), gl_window.window())
-                .expect("failed to prepare imgui frame");
-            gl_window.window().request_redraw();
+            let window = gl_window.window();
+            platform.attach_window(imgui.io_mut(), window, HiDpiMode::Default);
         }
-        Event::RedrawRequested(_) => {
-            if last_frame.elapsed().as_secs_f32() >= 1.0 / emulation_speed {
-                last_frame += Duration::from_secs_f32(1.0 / emulation_speed);
 
-                match run_status {
-                    RunStatus::Running => device.step_frame(),
-                    RunStatus::RunningUntil(address) => {
-                        device.step_frame_until_pc(address);
-                        if device.cpu().pc == address {
-                            run_status = RunStatus::Paused;
-                        }
+        let hidpi_factor = platform.hidpi_factor();
+        let font_size = hidpi_factor * 13.0;
+        imgui.fonts().add_font(&[FontSource::DefaultFontData {
+            config: Some(FontConfig {
+                size_pixels: font_size as f32,
+                ..FontConfig::default()
+            }),
+        }]);
+
+        imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
+
+        let mut renderer =
+            Renderer::init(&mut imgui, display).expect("failed to create imgui glium renderer");
+
+        let display_texture = Rc::new(
+            Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::U8U8U8,
+                MipmapsOption::NoMipmap,
+                160,
+                144,
+            )
+            .expect("failed to create display texture"),
+        );
+        let display_texture_id = renderer.textures().insert(Texture {
+            texture: display_texture.clone(),
+            sampler: SamplerBehavior {
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..SamplerBehavior::default()
+            },
+        });
+
+        let tile_texture = Rc::new(
+            Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::U8U8U8,
+                MipmapsOption::NoMipmap,
+                8 * 16,
+                8 * 24,
+            )
+            .expect("failed to create tile texture"),
+        );
+        let tile_texture_id = renderer.textures().insert(Texture {
+            texture: tile_texture.clone(),
+            sampler: SamplerBehavior {
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..SamplerBehavior::default()
+            },
+        });
+
+        let map_texture = Rc::new(
+            Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::U8U8U8,
+                MipmapsOption::NoMipmap,
+                256,
+                256,
+            )
+            .expect("failed to create tile map texture"),
+        );
+        let map_texture_id = renderer.textures().insert(Texture {
+            texture: map_texture.clone(),
+            sampler: SamplerBehavior {
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..SamplerBehavior::default()
+            },
+        });
+
+        let sprite_texture = Rc::new(
+            Texture2d::empty_with_format(
+                display,
+                UncompressedFloatFormat::U8U8U8U8,
+                MipmapsOption::NoMipmap,
+                10 * 8,
+                4 * 16,
+            )
+            .expect("failed to create sprite texture"),
+        );
+        let sprite_texture_id = renderer.textures().insert(Texture {
+            texture: sprite_texture.clone(),
+            sampler: SamplerBehavior {
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                ..SamplerBehavior::default()
+            },
+        });
+
+        let session_path = session_path.unwrap_or_else(|| DEFAULT_SESSION.into());
+        let mut session_path = ImString::new(session_path.to_string_lossy());
+        session_path.reserve(256);
+
+        DebugView {
+            imgui,
+            platform,
+            renderer,
+            disassembly,
+            display_texture,
+            display_texture_id,
+            tile_texture,
+            tile_texture_id,
+            map_texture,
+            map_texture_id,
+            map_pixels: vec![0; 3 * 256 * 256],
+            show_other_map: false,
+            sprite_texture,
+            sprite_texture_id,
+            sprite_pixels: vec![0; 4 * 10 * 8 * 4 * 16],
+            display_scale: 3,
+            follow_execution: true,
+            run_status: RunStatus::Paused,
+            breakpoints: session.breakpoints,
+            condition_input: ImString::with_capacity(32),
+            opcode_breakpoints: session.opcode_breakpoints,
+            opcode_input: ImString::with_capacity(4),
+            watch_input: ImString::with_capacity(16),
+            freeze_input: ImString::with_capacity(16),
+            session_path,
+            selected_line: None,
+            scroll_to: None,
+            goto_address: ImString::with_capacity(8),
+            audio_history: VecDeque::with_capacity(AUDIO_HISTORY_FRAMES),
+            script,
+            trace,
+        }
+    }
+
+    /// Executes script commands until one of them resumes execution.
+    fn run_script(&mut self, context: &mut frontend::Context) {
+        let script = match (&self.run_status, self.script.as_mut()) {
+            (RunStatus::Paused, Some(script)) => script,
+            _ => return,
+        };
+
+        while let Some(command) = script.next_command() {
+            match command {
+                Command::Break(address) => {
+                    self.breakpoints.entry(address).or_insert(None);
+                }
+                Command::Run(frames) => {
+                    self.run_status = match frames {
+                        Some(frames) => RunStatus::RunningFrames(frames),
+                        None => RunStatus::Running,
+                    };
+                    break;
+                }
+                Command::Step(count) => {
+                    for _ in 0..count {
+                        context.device.step();
+                    }
+                }
+                Command::Quit => {
+                    context.exit();
+                    break;
+                }
+                command => {
+                    if let Err(err) = script::execute(context.device, &command) {
+                        println!("script command failed: {:?}", err);
                     }
-                    RunStatus::Paused => {}
                 }
             }
+        }
+    }
+}
 
-            let ui = imgui.frame();
+impl Frontend for DebugView {
+    fn handle_event(&mut self, display: &Display, event: &Event<()>) {
+        self.platform
+            .handle_event(self.imgui.io_mut(), display.gl_window().window(), event);
+    }
 
-            Window::new(im_str!("CPU State"))
-                .position([206.0, 265.0], Condition::FirstUseEver)
-                .size([166.0, 0.0], Condition::FirstUseEver)
-                .build(&ui, || {
-                    let flag_color = |set| {
-                        if set {
-                            [0.0, 1.0, 0.0, 1.0]
-                        } else {
-                            [1.0, 0.0, 0.0, 1.0]
-                        }
-                    };
+    fn prepare(&mut self, display: &Display) {
+        self.platform
+            .prepare_frame(self.imgui.io_mut(), display.gl_window().window())
+            .expect("failed to prepare imgui frame");
+    }
 
-                    ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Zero)), "Z");
-                    ui.same_line_with_spacing(0.0, 8.0);
-                    ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Subtraction)), "S");
-                    ui.same_line_with_spacing(0.0, 8.0);
-                    ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::HalfCarry)), "H");
-                    ui.same_line_with_spacing(0.0, 8.0);
-                    ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Carry)), "C");
+    fn wants_joypad_input(&self) -> bool {
+        !self.imgui.io().want_text_input
+    }
 
-                    ui.separator();
+    fn emulate_frame(&mut self, device: &mut Device) {
+        if let RunStatus::Paused = self.run_status {
+            return;
+        }
 
-                    ui.text(format!("PC: {:#06x}", device.cpu().pc));
-                    ui.text(format!("SP: {:#06x}", device.cpu().sp));
-                    ui.spacing();
-                    ui.text(format!("Scanline: {}", device.gpu().scanline()));
-                    ui.text(format!(
-                        "Scroll: {}, {}",
-                        device.gpu().scroll_x,
-                        device.gpu().scroll_y
-                    ));
-                    ui.spacing();
-                    ui.text(format!("AF: {0:#06x} ({0})", device.cpu().af()));
-                    ui.text(format!("BC: {0:#06x} ({0})", device.cpu().bc()));
-                    ui.text(format!("DE: {0:#06x} ({0})", device.cpu().de()));
-                    ui.text(format!("HL: {0:#06x} ({0})", device.cpu().hl()));
-                });
+        let breakpoints = &self.breakpoints;
+        let opcode_breakpoints = &self.opcode_breakpoints;
+        let at_breakpoint = |d: &Device| {
+            let at_address = match breakpoints.get(&d.cpu().pc) {
+                Some(Some(condition)) => condition.holds(d),
+                Some(None) => true,
+                None => false,
+            };
 
-            Window::new(im_str!("Device Controls"))
-                .position([206.0, 3.0], Condition::FirstUseEver)
-                .resizable(false)
-                .build(&ui, || {
-                    if ui.button(
-                        if let RunStatus::Paused = run_status {
-                            im_str!("Run")
-                        } else {
-                            im_str!("Pause")
-                        },
-                        [150.0, 0.0],
-                    ) {
-                        if let RunStatus::Paused = run_status {
-                            run_status = RunStatus::Running;
-                        } else {
-                            run_status = RunStatus::Paused;
-                        }
-                    }
+            at_address
+                || d.read(d.cpu().pc)
+                    .map_or(false, |opcode| opcode_breakpoints.contains(&opcode))
+        };
 
-                    ui.text(match run_status {
-                        RunStatus::Running => "Status: Running".to_owned(),
-                        RunStatus::RunningUntil(address) => {
-                            format!("Status: Run to {:#06x}", address)
-                        }
-                        RunStatus::Paused => "Status: Paused".to_owned(),
-                    });
+        let stopped = match self.run_status {
+            RunStatus::Running => run_frame_until(device, at_breakpoint),
+            RunStatus::RunningUntil(address) => {
+                run_frame_until(device, |d| d.cpu().pc == address || at_breakpoint(d))
+            }
+            RunStatus::SteppingOver(ref mut target) | RunStatus::SteppingOut(ref mut target) => {
+                run_frame_towards(device, target, at_breakpoint)
+            }
+            RunStatus::RunningFrames(frames) => {
+                let stopped = run_frame_until(device, at_breakpoint);
+                self.run_status = RunStatus::RunningFrames(frames.saturating_sub(1));
+                stopped || frames <= 1
+            }
+            RunStatus::Paused => false,
+        };
+
+        if stopped {
+            self.run_status = RunStatus::Paused;
+        }
+
+        if self.audio_history.len() == AUDIO_HISTORY_FRAMES {
+            self.audio_history.pop_front();
+        }
+
+        let apu = device.apu();
+        self.audio_history.push_back([
+            apu.channel(0),
+            apu.channel(1),
+            apu.channel(2),
+            apu.channel(3),
+        ]);
+    }
+
+    fn draw(&mut self, target: &mut Frame, context: &mut frontend::Context) {
+        self.run_script(context);
+
+        let DebugView {
+            imgui,
+            platform,
+            renderer,
+            disassembly,
+            display_texture,
+            display_texture_id,
+            tile_texture,
+            tile_texture_id,
+            map_texture,
+            map_texture_id,
+            map_pixels,
+            show_other_map,
+            sprite_texture,
+            sprite_texture_id,
+            sprite_pixels,
+            display_scale,
+            follow_execution,
+            run_status,
+            breakpoints,
+            condition_input,
+            opcode_breakpoints,
+            opcode_input,
+            watch_input,
+            freeze_input,
+            session_path,
+            selected_line,
+            scroll_to,
+            goto_address,
+            audio_history,
+            trace,
+            ..
+        } = self;
+        let display_texture_id = *display_texture_id;
+        let tile_texture_id = *tile_texture_id;
+        let map_texture_id = *map_texture_id;
+        let sprite_texture_id = *sprite_texture_id;
+        let device = &mut *context.device;
+        let emulation_speed = &mut *context.emulation_speed;
+
+        let ui = imgui.frame();
+
+        if !ui.io().want_text_input {
+            if key_pressed(VirtualKeyCode::F5) {
+                if let RunStatus::Paused = run_status {
+                    *run_status = RunStatus::Running;
+                } else {
+                    *run_status = RunStatus::Paused;
+                }
+            }
 
-                    ui.separator();
+            if key_pressed(VirtualKeyCode::F10) {
+                *run_status = step_over(device);
+            }
 
-                    if ui.button(im_str!("Step instruction"), [150.0, 0.0]) {
-                        device.step();
+            if key_pressed(VirtualKeyCode::F11) {
+                if ui.io().key_shift {
+                    *run_status = RunStatus::SteppingOut(device.step_out());
+                } else {
+                    device.step();
+                    *run_status = RunStatus::Paused;
+                }
+            }
+
+            if key_pressed(VirtualKeyCode::F9) {
+                if let Some(address) = *selected_line {
+                    if breakpoints.remove(&address).is_none() {
+                        breakpoints.insert(address, None);
                     }
+                }
+            }
+
+            if ui.io().key_ctrl && key_pressed(VirtualKeyCode::G) {
+                goto_address.clear();
+                ui.open_popup(im_str!("Go to address"));
+            }
+        }
+
+        ui.popup_modal(im_str!("Go to address"))
+            .always_auto_resize(true)
+            .build(|| {
+                if ui.is_window_appearing() {
+                    ui.set_keyboard_focus_here(FocusedWidget::Next);
+                }
+
+                let entered = ui
+                    .input_text(im_str!("##goto_address"), goto_address)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .build();
 
-                    if ui.button(im_str!("Step frame"), [150.0, 0.0]) {
-                        device.step_frame();
+                if entered {
+                    if let Ok(address) = u16::from_str_radix(goto_address.to_str(), 16) {
+                        let line = disassembly
+                            .range(..=address)
+                            .next_back()
+                            .map(|(addr, _)| *addr);
+                        *selected_line = line;
+                        *scroll_to = line;
                     }
 
-                    if ui.button(im_str!("Skip instruction"), [150.0, 0.0]) {
-                        device.skip();
+                    ui.close_current_popup();
+                }
+
+                if ui.is_key_pressed(imgui::Key::Escape) {
+                    ui.close_current_popup();
+                }
+            });
+
+        Window::new(im_str!("CPU State"))
+            .position([206.0, 265.0], Condition::FirstUseEver)
+            .size([166.0, 0.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let flag_color = |set| {
+                    if set {
+                        [0.0, 1.0, 0.0, 1.0]
+                    } else {
+                        [1.0, 0.0, 0.0, 1.0]
                     }
+                };
+
+                ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Zero)), "Z");
+                ui.same_line_with_spacing(0.0, 8.0);
+                ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Subtraction)), "S");
+                ui.same_line_with_spacing(0.0, 8.0);
+                ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::HalfCarry)), "H");
+                ui.same_line_with_spacing(0.0, 8.0);
+                ui.text_colored(flag_color(device.cpu().get_flag(CpuFlag::Carry)), "C");
 
-                    ui.separator();
+                ui.separator();
 
-                    ui.text(im_str!("Emulation speed:"));
-                    ui.set_next_item_width(150.0);
-                    ui.input_float(im_str!("##emulation_speed"), &mut emulation_speed)
-                        .build();
+                ui.text(format!(
+                    "IME: {}{}",
+                    device.interrupt_master_enable(),
+                    if device.is_halted() { " (halted)" } else { "" }
+                ));
+                ui.text(format!(
+                    "IF: {:05b} IE: {:05b}",
+                    device.requested_interrupts().bits(),
+                    device.enabled_interrupts().bits()
+                ));
+                ui.spacing();
+                ui.text(format!("PC: {:#06x}", device.cpu().pc));
+                ui.text(format!("SP: {:#06x}", device.cpu().sp));
+                ui.spacing();
+                ui.text(format!("Cycles: {}", device.cycles()));
+                ui.text(format!("Scanline: {}", device.gpu().scanline()));
+                let viewport = device.gpu().viewport();
+                ui.text(format!("Scroll: {}, {}", viewport.x, viewport.y));
+                ui.text(if device.serial().transferring {
+                    format!("Serial: {} bits left", device.serial().bits_left())
+                } else {
+                    "Serial: idle".to_owned()
+                });
+                ui.spacing();
+                ui.text(format!("AF: {0:#06x} ({0})", device.cpu().af()));
+                ui.text(format!("BC: {0:#06x} ({0})", device.cpu().bc()));
+                ui.text(format!("DE: {0:#06x} ({0})", device.cpu().de()));
+                ui.text(format!("HL: {0:#06x} ({0})", device.cpu().hl()));
+            });
 
-                    ui.separator();
+        Window::new(im_str!("Device Controls"))
+            .position([206.0, 3.0], Condition::FirstUseEver)
+            .resizable(false)
+            .build(&ui, || {
+                if ui.button(
+                    if let RunStatus::Paused = run_status {
+                        im_str!("Run")
+                    } else {
+                        im_str!("Pause")
+                    },
+                    [150.0, 0.0],
+                ) {
+                    if let RunStatus::Paused = run_status {
+                        *run_status = RunStatus::Running;
+                    } else {
+                        *run_status = RunStatus::Paused;
+                    }
+                }
 
-                    ui.text(im_str!("Display scale:"));
-                    ui.set_next_item_width(150.0);
-                    ui.input_int(im_str!("##display_scale"), &mut display_scale)
-                        .build();
+                if let Some(reason) = device.stop_reason() {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], format!("Stopped: {}", reason));
+
+                    if let StopReason::UninitializedRead { .. } = reason {
+                        if ui.button(im_str!("Resume"), [150.0, 0.0]) {
+                            device.resume();
+                        }
+                    }
+                }
 
-                    ui.separator();
+                for lint in device.lints() {
+                    ui.text_colored([1.0, 0.8, 0.0, 1.0], lint.to_string());
+                }
 
-                    if ui.button(im_str!("Reset"), [150.0, 0.0]) {
-                        device.reset();
+                ui.text(match run_status {
+                    _ if device.is_locked() => {
+                        format!("Status: Locked up at {:#06x}", device.cpu().pc)
+                    }
+                    RunStatus::Running => "Status: Running".to_owned(),
+                    RunStatus::RunningUntil(address) => {
+                        format!("Status: Run to {:#06x}", address)
                     }
+                    RunStatus::SteppingOver(_) => "Status: Step over".to_owned(),
+                    RunStatus::SteppingOut(_) => "Status: Step out".to_owned(),
+                    RunStatus::RunningFrames(frames) => {
+                        format!("Status: Run {} frames", frames)
+                    }
+                    RunStatus::Paused => "Status: Paused".to_owned(),
                 });
 
-            Window::new(im_str!("Disassembly"))
-                .position([3.0, 3.0], Condition::FirstUseEver)
-                .size([200.0, 467.0], Condition::FirstUseEver)
-                .build(&ui, || {
-                    ui.checkbox(im_str!("Follow execution"), &mut follow_execution);
-
-                    ChildWindow::new(im_str!("Instruction list")).build(&ui, || {
-                        disassembly
-                            .iter()
-                            .take(0x500)
-                            .for_each(|(addr, instruction)| {
-                                Selectable::new(&ImString::new(instruction))
-                                    .selected(&device.cpu().pc == addr)
-                                    .build(&ui);
-
-                                if follow_execution && &device.cpu().pc == addr {
-                                    ui.set_scroll_here_y()
-                                }
+                ui.text(format!(
+                    "Frame: {} ({} dropped)",
+                    device.frame_count(),
+                    device.dropped_frames()
+                ));
 
-                                if unsafe { igBeginPopupContextItem(std::ptr::null(), 0) } {
-                                    if MenuItem::new(im_str!("Jump to here")).build(&ui) {
-                                        device.cpu_mut().pc = *addr;
-                                    }
+                ui.separator();
+
+                if ui.button(im_str!("Step instruction"), [150.0, 0.0]) {
+                    device.step();
+                }
+
+                if ui.button(im_str!("Step over"), [150.0, 0.0]) {
+                    *run_status = step_over(device);
+                }
+
+                if ui.button(im_str!("Step out"), [150.0, 0.0]) {
+                    *run_status = RunStatus::SteppingOut(device.step_out());
+                }
+
+                if ui.button(im_str!("Step frame"), [150.0, 0.0]) {
+                    device.step_frame();
+                }
+
+                if ui.button(im_str!("Skip instruction"), [150.0, 0.0]) {
+                    if let Err(err) = device.skip() {
+                        println!("warning: can't skip the instruction, {}", err);
+                    }
+                }
+
+                ui.separator();
+
+                ui.text(im_str!("Emulation speed:"));
+                ui.set_next_item_width(150.0);
+                ui.input_float(im_str!("##emulation_speed"), emulation_speed)
+                    .build();
 
-                                    if MenuItem::new(im_str!("Run to here")).build(&ui) {
-                                        run_status = RunStatus::RunningUntil(*addr);
+                ui.separator();
+
+                ui.text(im_str!("Display scale:"));
+                ui.set_next_item_width(150.0);
+                ui.input_int(im_str!("##display_scale"), display_scale)
+                    .build();
+
+                ui.separator();
+
+                if ui.button(im_str!("Reset"), [150.0, 0.0]) {
+                    device.reset();
+                }
+            });
+
+        Window::new(im_str!("Disassembly"))
+            .position([3.0, 3.0], Condition::FirstUseEver)
+            .size([200.0, 467.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.checkbox(im_str!("Follow execution"), follow_execution);
+
+                ChildWindow::new(im_str!("Instruction list")).build(&ui, || {
+                    disassembly
+                        .iter()
+                        .take(0x500)
+                        .for_each(|(addr, instruction)| {
+                            let label = format!(
+                                "{}{} {}##{}",
+                                if breakpoints.contains_key(addr) {
+                                    "*"
+                                } else {
+                                    " "
+                                },
+                                if &device.cpu().pc == addr { ">" } else { " " },
+                                instruction,
+                                addr
+                            );
+
+                            if Selectable::new(&ImString::new(label))
+                                .selected(*selected_line == Some(*addr))
+                                .build(&ui)
+                            {
+                                *selected_line = Some(*addr);
+                            }
+
+                            if *follow_execution && &device.cpu().pc == addr {
+                                ui.set_scroll_here_y()
+                            }
+
+                            if *scroll_to == Some(*addr) {
+                                ui.set_scroll_here_y();
+                                *scroll_to = None;
+                            }
+
+                            if unsafe { igBeginPopupContextItem(std::ptr::null(), 0) } {
+                                if MenuItem::new(im_str!("Toggle breakpoint")).build(&ui) {
+                                    if breakpoints.remove(addr).is_none() {
+                                        breakpoints.insert(*addr, None);
                                     }
+                                }
+
+                                if MenuItem::new(im_str!("Jump to here")).build(&ui) {
+                                    device.cpu_mut().pc = *addr;
+                                }
 
-                                    unsafe { igEndPopup() };
+                                if MenuItem::new(im_str!("Run to here")).build(&ui) {
+                                    *run_status = RunStatus::RunningUntil(*addr);
                                 }
-                            });
-                    });
+
+                                unsafe { igEndPopup() };
+                            }
+                        });
                 });
+            });
 
-            Window::new(im_str!("Display"))
-                .position([375.0, 3.0], Condition::FirstUseEver)
-                .always_auto_resize(true)
-                .scroll_bar(false)
-                .resizable(false)
-                .build(&ui, || {
-                    let display_framebuffer = device.display_framebuffer();
-                    let raw_image = RawImage2d {
-                        data: Cow::Borrowed(display_framebuffer),
+        Window::new(im_str!("Display"))
+            .position([375.0, 3.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .scroll_bar(false)
+            .resizable(false)
+            .build(&ui, || {
+                let raw_image = RawImage2d {
+                    data: device.overlaid_framebuffer(),
+                    width: 160,
+                    height: 144,
+                    format: ClientFormat::U8U8U8,
+                };
+
+                display_texture.write(
+                    Rect {
+                        bottom: 0,
+                        left: 0,
                         width: 160,
                         height: 144,
+                    },
+                    raw_image,
+                );
+
+                Image::new(
+                    display_texture_id,
+                    [
+                        160.0 * (*display_scale as f32),
+                        144.0 * (*display_scale as f32),
+                    ],
+                )
+                .build(&ui);
+
+                // These only change what is drawn from the next frame on.
+                let toggles = &mut device.gpu_mut().layer_toggles;
+                layer_visibility(&ui, "Background", &mut toggles.background);
+                layer_visibility(&ui, "Window", &mut toggles.window);
+                layer_visibility(&ui, "Sprites", &mut toggles.sprites);
+                ui.checkbox(
+                    im_str!("Highlight the window"),
+                    &mut toggles.highlight_window,
+                );
+            });
+
+        Window::new(im_str!("Tileset"))
+            .always_auto_resize(true)
+            .scroll_bar(false)
+            .resizable(false)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 33.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let tile_framebuffer = device.tile_framebuffer();
+                let raw_image = RawImage2d {
+                    data: Cow::Borrowed(tile_framebuffer),
+                    width: 8 * 16,
+                    height: 8 * 24,
+                    format: ClientFormat::U8U8U8,
+                };
+
+                tile_texture.write(
+                    Rect {
+                        bottom: 0,
+                        left: 0,
+                        width: 16 * 8,
+                        height: 24 * 8,
+                    },
+                    raw_image,
+                );
+
+                Image::new(tile_texture_id, [16.0 * 8.0, 24.0 * 8.0]).build(&ui);
+            });
+
+        Window::new(im_str!("Background"))
+            .always_auto_resize(true)
+            .scroll_bar(false)
+            .resizable(false)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 163.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.checkbox(im_str!("Show the other map"), show_other_map);
+
+                let tilemap = match (device.gpu().background_tilemap(), *show_other_map) {
+                    (tilemap, false) => tilemap,
+                    (Tilemap::Low, true) => Tilemap::High,
+                    (Tilemap::High, true) => Tilemap::Low,
+                };
+                let viewport = device.gpu().viewport();
+                ui.text(format!(
+                    "{} (SCX {}, SCY {})",
+                    match tilemap {
+                        Tilemap::Low => "0x9800",
+                        Tilemap::High => "0x9c00",
+                    },
+                    viewport.x,
+                    viewport.y
+                ));
+
+                device.render_tilemap(tilemap, map_pixels);
+                if !*show_other_map {
+                    draw_viewport(map_pixels, viewport);
+                }
+
+                map_texture.write(
+                    Rect {
+                        bottom: 0,
+                        left: 0,
+                        width: 256,
+                        height: 256,
+                    },
+                    RawImage2d {
+                        data: Cow::Borrowed(map_pixels),
+                        width: 256,
+                        height: 256,
                         format: ClientFormat::U8U8U8,
-                    };
+                    },
+                );
+
+                Image::new(map_texture_id, [256.0, 256.0]).build(&ui);
+            });
+
+        Window::new(im_str!("Sprites"))
+            .size([260.0, 400.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 183.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                let height = device.gpu().sprite_height();
+                let mut sprite = vec![0; 4 * 8 * height];
+                for pixel in sprite_pixels.iter_mut() {
+                    *pixel = 0;
+                }
+
+                for i in 0..40 {
+                    device.render_sprite(i, &mut sprite);
+                    let (left, top) = (i % 10 * 8, i / 10 * 16);
+                    for (y, row) in sprite.chunks_exact(4 * 8).enumerate() {
+                        let start = 4 * ((top + y) * 10 * 8 + left);
+                        sprite_pixels[start..start + 4 * 8].copy_from_slice(row);
+                    }
+                }
+
+                sprite_texture.write(
+                    Rect {
+                        bottom: 0,
+                        left: 0,
+                        width: 10 * 8,
+                        height: 4 * 16,
+                    },
+                    RawImage2d {
+                        data: Cow::Borrowed(sprite_pixels),
+                        width: 10 * 8,
+                        height: 4 * 16,
+                        format: ClientFormat::U8U8U8U8,
+                    },
+                );
 
-                    display_texture.write(
-                        Rect {
-                            bottom: 0,
-                            left: 0,
-                            width: 160,
-                            height: 144,
+                Image::new(sprite_texture_id, [3.0 * 10.0 * 8.0, 3.0 * 4.0 * 16.0]).build(&ui);
+                ui.separator();
+
+                for (i, sprite) in device.sprites().iter().enumerate() {
+                    let on_screen = (-7..160).contains(&sprite.screen_x())
+                        && (1 - height as i16..144).contains(&sprite.screen_y());
+                    let flag = |flag, name| {
+                        if sprite.flags.contains(flag) {
+                            name
+                        } else {
+                            "-"
+                        }
+                    };
+                    ui.text(format!(
+                        "{}{:2} {:4},{:4} tile {:02x} {}{}{}{}",
+                        if on_screen { "*" } else { " " },
+                        i,
+                        sprite.screen_x(),
+                        sprite.screen_y(),
+                        sprite.tile,
+                        flag(SpriteFlags::FLIP_X, "x"),
+                        flag(SpriteFlags::FLIP_Y, "y"),
+                        flag(SpriteFlags::BEHIND_BACKGROUND, "b"),
+                        if device.gpu().cgb_mode() {
+                            format!(" pal {}", sprite.cgb_palette)
+                        } else {
+                            format!(" obp{}", sprite.layer() as usize - 1)
                         },
-                        raw_image,
-                    );
+                    ));
+                }
+            });
 
-                    Image::new(
-                        display_texture_id,
-                        [
-                            160.0 * (display_scale as f32),
-                            144.0 * (display_scale as f32),
-                        ],
-                    )
-                    .build(&ui);
-                });
+        Window::new(im_str!("Audio"))
+            .always_auto_resize(true)
+            .resizable(false)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 63.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                if let Some(channels) = audio_history.back() {
+                    for (i, channel) in channels.iter().enumerate() {
+                        let state = if channel.enabled {
+                            format!(
+                                "{} ({:.1} Hz), volume {}",
+                                note_name(channel.frequency),
+                                channel.frequency,
+                                channel.volume
+                            )
+                        } else {
+                            "off".to_owned()
+                        };
 
-            Window::new(im_str!("Tileset"))
-                .always_auto_resize(true)
-                .scroll_bar(false)
-                .resizable(false)
-                .collapsed(true, Condition::FirstUseEver)
-                .position([716.0, 33.0], Condition::FirstUseEver)
-                .build(&ui, || {
-                    let tile_framebuffer = device.tile_framebuffer();
-                    let raw_image = RawImage2d {
-                        data: Cow::Borrowed(tile_framebuffer),
-                        width: 8 * 16,
-                        height: 8 * 24,
-                        format: ClientFormat::U8U8U8,
+                        ui.text_colored(
+                            CHANNEL_COLORS[i],
+                            format!("{}: {}", CHANNEL_NAMES[i], state),
+                        );
+                    }
+                }
+
+                let [x, y] = ui.cursor_screen_pos();
+                let size = [
+                    AUDIO_HISTORY_FRAMES as f32 * 2.0,
+                    (PIANO_ROLL_NOTES.end - PIANO_ROLL_NOTES.start) as f32 * 2.0,
+                ];
+
+                let draw_list = ui.get_window_draw_list();
+                draw_list
+                    .add_rect([x, y], [x + size[0], y + size[1]], [0.1, 0.1, 0.1, 1.0])
+                    .filled(true)
+                    .build();
+
+                for (frame, channels) in audio_history.iter().enumerate() {
+                    let left = x + frame as f32 * 2.0;
+                    let previous = frame.checked_sub(1).map(|frame| &audio_history[frame]);
+
+                    for (i, channel) in channels.iter().enumerate() {
+                        let triggered = previous
+                            .map(|previous| previous[i].triggers != channel.triggers)
+                            .unwrap_or(false);
+
+                        if triggered {
+                            draw_list
+                                .add_line([left, y], [left, y + size[1]], CHANNEL_COLORS[i])
+                                .build();
+                        }
+
+                        let note = note_number(channel.frequency).round() as i32;
+                        if !channel.enabled || !PIANO_ROLL_NOTES.contains(&note) {
+                            continue;
+                        }
+
+                        let top = y + (PIANO_ROLL_NOTES.end - note) as f32 * 2.0;
+                        let [r, g, b, _] = CHANNEL_COLORS[i];
+                        let alpha = 0.2 + 0.8 * channel.volume as f32 / 15.0;
+
+                        draw_list
+                            .add_rect([left, top - 1.0], [left + 2.0, top + 1.0], [r, g, b, alpha])
+                            .filled(true)
+                            .build();
+                    }
+                }
+
+                ui.dummy(size);
+            });
+
+        Window::new(im_str!("Breakpoints"))
+            .always_auto_resize(true)
+            .resizable(false)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 103.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                ui.text("Addresses:");
+                for (address, condition) in breakpoints.clone().iter() {
+                    match condition {
+                        Some(condition) => ui.text(format!("{:#06x} if {}", address, condition)),
+                        None => ui.text(format!("{:#06x}", address)),
+                    }
+                    ui.same_line_with_spacing(0.0, 8.0);
+                    if ui.small_button(&ImString::new(format!("Remove##address{}", address))) {
+                        breakpoints.remove(address);
+                    }
+                }
+
+                let entered = ui.button(im_str!("Break on selected line if"), [0.0, 0.0]);
+                ui.same_line_with_spacing(0.0, 8.0);
+                ui.set_next_item_width(100.0);
+                let entered = ui
+                    .input_text(im_str!("##condition"), condition_input)
+                    .enter_returns_true(true)
+                    .build()
+                    || entered;
+                if entered {
+                    match (*selected_line, condition_input.to_str().parse()) {
+                        (Some(address), Ok(condition)) => {
+                            breakpoints.insert(address, Some(condition));
+                            condition_input.clear();
+                        }
+                        (None, _) => println!("warning: no line is selected"),
+                        (_, Err(err)) => println!("warning: {:?}", err),
+                    }
+                }
+
+                ui.separator();
+
+                ui.text("Opcodes:");
+                for opcode in opcode_breakpoints.clone().iter() {
+                    ui.text(format!("{:#04x} ({})", opcode, opcode_name(*opcode)));
+                    ui.same_line_with_spacing(0.0, 8.0);
+                    if ui.small_button(&ImString::new(format!("Remove##opcode{}", opcode))) {
+                        opcode_breakpoints.remove(opcode);
+                    }
+                }
+
+                ui.set_next_item_width(60.0);
+                let entered = ui
+                    .input_text(im_str!("##opcode"), opcode_input)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .build();
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Break on opcode"), [0.0, 0.0]) || entered {
+                    if let Ok(opcode) = u8::from_str_radix(opcode_input.to_str(), 16) {
+                        opcode_breakpoints.insert(opcode);
+                    }
+
+                    opcode_input.clear();
+                }
+
+                let mut rst_38 = opcode_breakpoints.contains(&0xff);
+                if ui.checkbox(im_str!("Break on rst $38"), &mut rst_38) {
+                    if rst_38 {
+                        opcode_breakpoints.insert(0xff);
+                    } else {
+                        opcode_breakpoints.remove(&0xff);
+                    }
+                }
+
+                ui.separator();
+
+                ui.text("Watchpoints:");
+                let mut removed = None;
+                for (i, watchpoint) in device.watchpoints().iter().enumerate() {
+                    ui.text(format!(
+                        "{} {}",
+                        watch_kind_name(watchpoint.kind),
+                        watchpoint.label
+                    ));
+                    ui.same_line_with_spacing(0.0, 8.0);
+                    if ui.small_button(&ImString::new(format!("Remove##watchpoint{}", i))) {
+                        removed = Some(i);
+                    }
+                }
+
+                if let Some(i) = removed {
+                    device.watchpoints_mut().remove(i);
+                }
+
+                ui.set_next_item_width(80.0);
+                ui.input_text(im_str!("##watch"), watch_input).build();
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Break on write"), [0.0, 0.0]) {
+                    watch(device, watch_input.to_str(), WatchKind::Write);
+                    watch_input.clear();
+                }
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Break on read"), [0.0, 0.0]) {
+                    watch(device, watch_input.to_str(), WatchKind::Read);
+                    watch_input.clear();
+                }
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Break on change"), [0.0, 0.0]) {
+                    watch(device, watch_input.to_str(), WatchKind::Change);
+                    watch_input.clear();
+                }
+
+                ui.separator();
+
+                ui.text("Frozen:");
+                for (address, value) in device.frozen().clone().iter() {
+                    ui.text(format!("{:#06x} = {:#04x}", address, value));
+                    ui.same_line_with_spacing(0.0, 8.0);
+                    if ui.small_button(&ImString::new(format!("Unfreeze##frozen{}", address))) {
+                        device.unfreeze(*address);
+                    }
+                }
+
+                ui.set_next_item_width(80.0);
+                let entered = ui
+                    .input_text(im_str!("##freeze"), freeze_input)
+                    .enter_returns_true(true)
+                    .build();
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Freeze"), [0.0, 0.0]) || entered {
+                    match parse_freeze(freeze_input.to_str()) {
+                        Some((address, value)) => device.freeze(address, value),
+                        None => println!("warning: expected an address and value like c0a0=05"),
+                    }
+
+                    freeze_input.clear();
+                }
+
+                ui.separator();
+
+                ui.set_next_item_width(160.0);
+                ui.input_text(im_str!("##session"), session_path).build();
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Import"), [0.0, 0.0]) {
+                    if let Some(session) = import_session(Path::new(session_path.to_str())) {
+                        *breakpoints = session.breakpoints;
+                        *opcode_breakpoints = session.opcode_breakpoints;
+                        apply_session(device, &session);
+                    }
+                }
+                ui.same_line_with_spacing(0.0, 8.0);
+                if ui.button(im_str!("Export"), [0.0, 0.0]) {
+                    let session = Session {
+                        breakpoints: breakpoints.clone(),
+                        opcode_breakpoints: opcode_breakpoints.clone(),
+                        watchpoints: device.watchpoints().iter().cloned().collect(),
+                        frozen: device.frozen().clone(),
                     };
 
-                    tile_texture.write(
-                        Rect {
-                            bottom: 0,
-                            left: 0,
-                            width: 16 * 8,
-                            height: 24 * 8,
-                        },
-                        raw_image,
+                    match session.save(session_path.to_str()) {
+                        Ok(()) => println!("exported session to {}", session_path.to_str()),
+                        Err(err) => println!("warning: {:?}", err),
+                    }
+                }
+            });
+
+        Window::new(im_str!("Palettes"))
+            .always_auto_resize(true)
+            .resizable(false)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 83.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                for palette in PALETTES.iter() {
+                    let [x, y] = ui.cursor_screen_pos();
+                    let draw_list = ui.get_window_draw_list();
+                    for (i, [r, g, b]) in palette.colors.iter().enumerate() {
+                        let left = x + i as f32 * 16.0;
+                        let color = [*r as f32 / 255.0, *g as f32 / 255.0, *b as f32 / 255.0, 1.0];
+
+                        draw_list
+                            .add_rect([left, y], [left + 14.0, y + 14.0], color)
+                            .filled(true)
+                            .build();
+                    }
+
+                    ui.dummy([4.0 * 16.0, 14.0]);
+                    ui.same_line_with_spacing(0.0, 8.0);
+
+                    if Selectable::new(&ImString::new(palette.name))
+                        .selected(device.palette() == *palette)
+                        .build(&ui)
+                    {
+                        device.set_palette(*palette);
+                    }
+                }
+            });
+
+        Window::new(im_str!("IO Registers"))
+            .size([180.0, 300.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .position([716.0, 143.0], Condition::FirstUseEver)
+            .build(&ui, || {
+                for register in IO_REGISTERS.iter() {
+                    let watched = device
+                        .watchpoints()
+                        .iter()
+                        .any(|watchpoint| watchpoint.addresses.contains(&register.address));
+                    let value = device
+                        .read(register.address)
+                        .map_or("--".to_string(), |value| format!("{:02x}", value));
+                    let label = format!(
+                        "{}{:#06x} {:<5} {}##io{}",
+                        if watched { "*" } else { " " },
+                        register.address,
+                        register.name,
+                        value,
+                        register.address
                     );
+                    Selectable::new(&ImString::new(label)).build(&ui);
 
-                    Image::new(tile_texture_id, [16.0 * 8.0, 24.0 * 8.0]).build(&ui);
-                });
+                    if unsafe { igBeginPopupContextItem(std::ptr::null(), 0) } {
+                        if MenuItem::new(im_str!("Break on write")).build(&ui) {
+                            watch(device, register.name, WatchKind::Write);
+                        }
 
-            let gl_window = display.gl_window();
-            let mut target = display.draw();
+                        if MenuItem::new(im_str!("Break on read")).build(&ui) {
+                            watch(device, register.name, WatchKind::Read);
+                        }
 
-            target.clear_color_srgb(1.0, 1.0, 1.0, 1.0);
+                        if MenuItem::new(im_str!("Break on change")).build(&ui) {
+                            watch(device, register.name, WatchKind::Change);
+                        }
 
-            platform.prepare_render(&ui, gl_window.window());
-            let draw_data = ui.render();
-            renderer
-                .render(&mut target, draw_data)
-                .expect("failed to render imgui frame");
+                        if let Some(group) = register_group(register.name) {
+                            let label = ImString::new(format!("Break on any write to {}", group));
+                            if MenuItem::new(&label).build(&ui) {
+                                watch(device, &group, WatchKind::Write);
+                            }
+                        }
 
-            target.finish().expect("failed to finish frame");
+                        unsafe { igEndPopup() };
+                    }
+                }
+            });
+
+        if let Some(trace) = trace {
+            Window::new(im_str!("Trace"))
+                .position([716.0, 123.0], Condition::FirstUseEver)
+                .size([220.0, 300.0], Condition::FirstUseEver)
+                .build(&ui, || {
+                    for info in trace.borrow().iter() {
+                        ui.text(format!("{:#06x}: {}", info.pc, info.instruction));
+                    }
+
+                    if ui.is_window_appearing() {
+                        ui.set_scroll_here_y_with_ratio(1.0);
+                    }
+                });
         }
-        Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-        } => {
-            if let Err(err) = device.cart().save() {
-                println!("failed to save game: {:?}", err)
-            }
 
-            *control_flow = ControlFlow::Exit
+        target.clear_color_srgb(1.0, 1.0, 1.0, 1.0);
+
+        platform.prepare_render(&ui, context.display.gl_window().window());
+        let draw_data = ui.render();
+        renderer
+            .render(target, draw_data)
+            .expect("failed to render imgui frame");
+    }
+}
+
+/// Radio buttons for drawing a layer as LCDC says, or hiding or showing it
+/// regardless.
+fn layer_visibility(ui: &Ui, name: &str, visibility: &mut LayerVisibility) {
+    ui.text(format!("{:<10}", name));
+
+    for (label, value) in [
+        ("Auto", LayerVisibility::Auto),
+        ("Hide", LayerVisibility::Hidden),
+        ("Show", LayerVisibility::Shown),
+    ]
+    .iter()
+    {
+        ui.same_line_with_spacing(0.0, 8.0);
+        ui.radio_button(
+            &ImString::new(format!("{}##{}", label, name)),
+            visibility,
+            *value,
+        );
+    }
+}
+
+/// Stops on accesses to the addresses a pattern like `NR1x` names, see
+/// [`io_addresses`].
+fn watch(device: &mut Device, pattern: &str, kind: WatchKind) {
+    match io_addresses(pattern) {
+        Some(addresses) => device.watchpoints_mut().add(Watchpoint {
+            label: pattern.trim().to_string(),
+            addresses,
+            kind,
+        }),
+        None => println!("warning: {:?} names no registers or addresses", pattern),
+    }
+}
+
+/// The pattern for a numbered register and its siblings, like `NR1x` for
+/// `NR12`, if it has any.
+fn register_group(name: &str) -> Option<String> {
+    if !name.ends_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let group = format!("{}x", &name[..name.len() - 1]);
+    match io_addresses(&group) {
+        Some(addresses) if addresses.len() > 1 => Some(group),
+        _ => None,
+    }
+}
+
+/// Loads a session, printing why if that fails.
+fn import_session(path: &Path) -> Option<Session> {
+    match Session::load(path) {
+        Ok(session) => {
+            println!("imported session from {}", path.display());
+            Some(session)
+        }
+        Err(err) => {
+            println!("warning: {:?}", err);
+            None
         }
-        event => platform.handle_event(imgui.io_mut(), display.gl_window().window(), &event),
-    });
+    }
+}
+
+/// Replaces the watchpoints and frozen addresses of the device with the
+/// session's.
+fn apply_session(device: &mut Device, session: &Session) {
+    device.watchpoints_mut().clear();
+    for watchpoint in &session.watchpoints {
+        device.watchpoints_mut().add(watchpoint.clone());
+    }
+
+    let current: Vec<u16> = device.frozen().keys().copied().collect();
+    for address in current {
+        device.unfreeze(address);
+    }
+
+    for (&address, &value) in &session.frozen {
+        device.freeze(address, value);
+    }
+}
+
+/// An address and the value to freeze it at, written like `c0a0=05`.
+fn parse_freeze(input: &str) -> Option<(u16, u8)> {
+    let split = input.find('=')?;
+    let address = input[..split].trim().trim_start_matches("0x");
+    let value = input[split + 1..].trim().trim_start_matches("0x");
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u8::from_str_radix(value, 16).ok()?,
+    ))
+}
+
+pub fn start_debug_view(
+    mut device: Device,
+    dirs: DataDirs,
+    templates: Templates,
+    script: Option<Script>,
+    session_path: Option<PathBuf>,
+    screenshot_mode: ScreenshotMode,
+) {
+    let session = match &session_path {
+        Some(path) if path.exists() => import_session(path).unwrap_or_default(),
+        _ => Session::default(),
+    };
+    apply_session(&mut device, &session);
+
+    let disassembly = device.disassemble(0x8000);
+    let size = LogicalSize::new(874, 473);
+    let runtime = Runtime::new(device, dirs, templates, screenshot_mode, size);
+    let view = DebugView::new(
+        runtime.display(),
+        disassembly,
+        script,
+        None,
+        session,
+        session_path,
+    );
+    runtime.run(view);
+}
+
+/// Opens the debugger on a device that stopped while running in the game view,
+/// showing the instructions that led up to it.
+pub fn crash_debugger(display: &Display, device: &mut Device, trace: Trace) -> Box<dyn Frontend> {
+    display
+        .gl_window()
+        .window()
+        .set_inner_size(LogicalSize::new(874, 473));
+
+    let disassembly = device.disassemble(0x8000);
+    Box::new(DebugView::new(
+        display,
+        disassembly,
+        None,
+        Some(trace),
+        Session::default(),
+        None,
+    ))
 }
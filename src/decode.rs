@@ -0,0 +1,673 @@
+use crate::{
+    cpu::CpuFlag,
+    instruction::{CpuRegister, Instruction, InstructionOperand, SPOps},
+};
+
+/// How to decode a single opcode: the amount of immediate bytes following it,
+/// and how to build the instruction from them.
+#[derive(Clone, Copy)]
+pub struct Opcode {
+    pub immediate_len: u8,
+    /// Builds the instruction from the little endian immediate value.
+    pub build: fn(u16) -> Instruction,
+}
+
+macro_rules! operand_len {
+    (( @@IMM16 )) => {
+        2
+    };
+    (( @IMM16 )) => {
+        2
+    };
+    (( @IMM8 $offset:expr )) => {
+        1
+    };
+    (( SP $op:ident )) => {
+        1
+    };
+    ( REL8 ) => {
+        1
+    };
+    ( ABS16 ) => {
+        2
+    };
+    ( IMM8 ) => {
+        1
+    };
+    ( IMM16 ) => {
+        2
+    };
+    ( $other:tt ) => {
+        0
+    };
+}
+
+macro_rules! operand {
+    ($imm:ident ( R $reg:ident )) => {
+        CpuRegister::$reg
+    };
+    ($imm:ident ( :R $reg:ident )) => {
+        InstructionOperand::Register(CpuRegister::$reg)
+    };
+    ($imm:ident ( @R $reg:ident )) => {
+        InstructionOperand::MemoryLocationRegister(CpuRegister::$reg)
+    };
+    ($imm:ident ( @R $reg:ident $offset:expr )) => {
+        InstructionOperand::OffsetMemoryLocationRegister($offset, CpuRegister::$reg)
+    };
+    ($imm:ident ( @R+ $reg:ident )) => {
+        InstructionOperand::MemoryLocationRegisterIncrement(CpuRegister::$reg)
+    };
+    ($imm:ident ( @R- $reg:ident )) => {
+        InstructionOperand::MemoryLocationRegisterDecrement(CpuRegister::$reg)
+    };
+    ($imm:ident ( @@IMM16 )) => {
+        InstructionOperand::DoubleMemoryLocationImmediate16($imm)
+    };
+    ($imm:ident ( @IMM16 )) => {
+        InstructionOperand::MemoryLocationImmediate16($imm)
+    };
+    ($imm:ident ( @IMM8 $offset:expr )) => {
+        InstructionOperand::OffsetMemoryLocationImmediate8($offset, $imm as u8)
+    };
+    ($imm:ident ( SP $op:ident )) => {
+        SPOps::$op($imm as u8 as i8)
+    };
+    ($imm:ident ( F $flag:ident )) => {
+        CpuFlag::$flag
+    };
+    ($imm:ident REL8) => {
+        $imm as u8 as i8
+    };
+    ($imm:ident ABS16) => {
+        $imm
+    };
+    ($imm:ident IMM8) => {
+        InstructionOperand::Immediate8($imm as u8)
+    };
+    ($imm:ident IMM16) => {
+        InstructionOperand::Immediate16($imm)
+    };
+    ($imm:ident ( = $e:expr )) => {
+        $e
+    };
+}
+
+macro_rules! op {
+    ( $op:ident ) => {
+        Opcode {
+            immediate_len: 0,
+            build: |_| Instruction::$op,
+        }
+    };
+    ( $op:ident $($b:tt)* ) => {
+        Opcode {
+            immediate_len: 0 $(+ operand_len!($b))*,
+            build: |_imm| Instruction::$op($(operand!(_imm $b)),*),
+        }
+    };
+}
+
+/// The unprefixed opcodes. Illegal opcodes, which lock up the CPU, are `None`.
+pub static OPCODES: [Option<Opcode>; 256] = [
+    Some(op!(Noop)),                                    // 0x00
+    Some(op!(Load (:R BC) IMM16)),                      // 0x01
+    Some(op!(Load (@R BC) (:R A))),                     // 0x02
+    Some(op!(Increment (:R BC))),                       // 0x03
+    Some(op!(Increment (:R B))),                        // 0x04
+    Some(op!(Decrement (:R B))),                        // 0x05
+    Some(op!(Load (:R B) IMM8)),                        // 0x06
+    Some(op!(RotateLeftA (= false))),                   // 0x07
+    Some(op!(Load (@@IMM16) (:R SP))),                  // 0x08
+    Some(op!(Add16 (R HL) (:R BC))),                    // 0x09
+    Some(op!(Load (:R A) (@R BC))),                     // 0x0a
+    Some(op!(Decrement (:R BC))),                       // 0x0b
+    Some(op!(Increment (:R C))),                        // 0x0c
+    Some(op!(Decrement (:R C))),                        // 0x0d
+    Some(op!(Load (:R C) IMM8)),                        // 0x0e
+    Some(op!(RotateRightA (= false))),                  // 0x0f
+    Some(op!(Stop)),                                    // 0x10
+    Some(op!(Load (:R DE) IMM16)),                      // 0x11
+    Some(op!(Load (@R DE) (:R A))),                     // 0x12
+    Some(op!(Increment (:R DE))),                       // 0x13
+    Some(op!(Increment (:R D))),                        // 0x14
+    Some(op!(Decrement (:R D))),                        // 0x15
+    Some(op!(Load (:R D) IMM8)),                        // 0x16
+    Some(op!(RotateLeftA (= true))),                    // 0x17
+    Some(op!(JumpRelative REL8)),                       // 0x18
+    Some(op!(Add16 (R HL) (:R DE))),                    // 0x19
+    Some(op!(Load (:R A) (@R DE))),                     // 0x1a
+    Some(op!(Decrement (:R DE))),                       // 0x1b
+    Some(op!(Increment (:R E))),                        // 0x1c
+    Some(op!(Decrement (:R E))),                        // 0x1d
+    Some(op!(Load (:R E) IMM8)),                        // 0x1e
+    Some(op!(RotateRightA (= true))),                   // 0x1f
+    Some(op!(JumpRelativeIf (F Zero) (= false) REL8)),  // 0x20
+    Some(op!(Load (:R HL) IMM16)),                      // 0x21
+    Some(op!(Load (@R+ HL) (:R A))),                    // 0x22
+    Some(op!(Increment (:R HL))),                       // 0x23
+    Some(op!(Increment (:R H))),                        // 0x24
+    Some(op!(Decrement (:R H))),                        // 0x25
+    Some(op!(Load (:R H) IMM8)),                        // 0x26
+    Some(op!(DAA)),                                     // 0x27
+    Some(op!(JumpRelativeIf (F Zero) (= true) REL8)),   // 0x28
+    Some(op!(Add16 (R HL) (:R HL))),                    // 0x29
+    Some(op!(Load (:R A) (@R+ HL))),                    // 0x2a
+    Some(op!(Decrement (:R HL))),                       // 0x2b
+    Some(op!(Increment (:R L))),                        // 0x2c
+    Some(op!(Decrement (:R L))),                        // 0x2d
+    Some(op!(Load (:R L) IMM8)),                        // 0x2e
+    Some(op!(Complement)),                              // 0x2f
+    Some(op!(JumpRelativeIf (F Carry) (= false) REL8)), // 0x30
+    Some(op!(Load (:R SP) IMM16)),                      // 0x31
+    Some(op!(Load (@R- HL) (:R A))),                    // 0x32
+    Some(op!(Increment (:R SP))),                       // 0x33
+    Some(op!(Increment (@R HL))),                       // 0x34
+    Some(op!(Decrement (@R HL))),                       // 0x35
+    Some(op!(Load (@R HL) IMM8)),                       // 0x36
+    Some(op!(SetCarryFlag (= false))),                  // 0x37
+    Some(op!(JumpRelativeIf (F Carry) (= true) REL8)),  // 0x38
+    Some(op!(Add16 (R HL) (:R SP))),                    // 0x39
+    Some(op!(Load (:R A) (@R- HL))),                    // 0x3a
+    Some(op!(Decrement (:R SP))),                       // 0x3b
+    Some(op!(Increment (:R A))),                        // 0x3c
+    Some(op!(Decrement (:R A))),                        // 0x3d
+    Some(op!(Load (:R A) IMM8)),                        // 0x3e
+    Some(op!(SetCarryFlag (= true))),                   // 0x3f
+    Some(op!(Load (:R B) (:R B))),                      // 0x40
+    Some(op!(Load (:R B) (:R C))),                      // 0x41
+    Some(op!(Load (:R B) (:R D))),                      // 0x42
+    Some(op!(Load (:R B) (:R E))),                      // 0x43
+    Some(op!(Load (:R B) (:R H))),                      // 0x44
+    Some(op!(Load (:R B) (:R L))),                      // 0x45
+    Some(op!(Load (:R B) (@R HL))),                     // 0x46
+    Some(op!(Load (:R B) (:R A))),                      // 0x47
+    Some(op!(Load (:R C) (:R B))),                      // 0x48
+    Some(op!(Load (:R C) (:R C))),                      // 0x49
+    Some(op!(Load (:R C) (:R D))),                      // 0x4a
+    Some(op!(Load (:R C) (:R E))),                      // 0x4b
+    Some(op!(Load (:R C) (:R H))),                      // 0x4c
+    Some(op!(Load (:R C) (:R L))),                      // 0x4d
+    Some(op!(Load (:R C) (@R HL))),                     // 0x4e
+    Some(op!(Load (:R C) (:R A))),                      // 0x4f
+    Some(op!(Load (:R D) (:R B))),                      // 0x50
+    Some(op!(Load (:R D) (:R C))),                      // 0x51
+    Some(op!(Load (:R D) (:R D))),                      // 0x52
+    Some(op!(Load (:R D) (:R E))),                      // 0x53
+    Some(op!(Load (:R D) (:R H))),                      // 0x54
+    Some(op!(Load (:R D) (:R L))),                      // 0x55
+    Some(op!(Load (:R D) (@R HL))),                     // 0x56
+    Some(op!(Load (:R D) (:R A))),                      // 0x57
+    Some(op!(Load (:R E) (:R B))),                      // 0x58
+    Some(op!(Load (:R E) (:R C))),                      // 0x59
+    Some(op!(Load (:R E) (:R D))),                      // 0x5a
+    Some(op!(Load (:R E) (:R E))),                      // 0x5b
+    Some(op!(Load (:R E) (:R H))),                      // 0x5c
+    Some(op!(Load (:R E) (:R L))),                      // 0x5d
+    Some(op!(Load (:R E) (@R HL))),                     // 0x5e
+    Some(op!(Load (:R E) (:R A))),                      // 0x5f
+    Some(op!(Load (:R H) (:R B))),                      // 0x60
+    Some(op!(Load (:R H) (:R C))),                      // 0x61
+    Some(op!(Load (:R H) (:R D))),                      // 0x62
+    Some(op!(Load (:R H) (:R E))),                      // 0x63
+    Some(op!(Load (:R H) (:R H))),                      // 0x64
+    Some(op!(Load (:R H) (:R L))),                      // 0x65
+    Some(op!(Load (:R H) (@R HL))),                     // 0x66
+    Some(op!(Load (:R H) (:R A))),                      // 0x67
+    Some(op!(Load (:R L) (:R B))),                      // 0x68
+    Some(op!(Load (:R L) (:R C))),                      // 0x69
+    Some(op!(Load (:R L) (:R D))),                      // 0x6a
+    Some(op!(Load (:R L) (:R E))),                      // 0x6b
+    Some(op!(Load (:R L) (:R H))),                      // 0x6c
+    Some(op!(Load (:R L) (:R L))),                      // 0x6d
+    Some(op!(Load (:R L) (@R HL))),                     // 0x6e
+    Some(op!(Load (:R L) (:R A))),                      // 0x6f
+    Some(op!(Load (@R HL) (:R B))),                     // 0x70
+    Some(op!(Load (@R HL) (:R C))),                     // 0x71
+    Some(op!(Load (@R HL) (:R D))),                     // 0x72
+    Some(op!(Load (@R HL) (:R E))),                     // 0x73
+    Some(op!(Load (@R HL) (:R H))),                     // 0x74
+    Some(op!(Load (@R HL) (:R L))),                     // 0x75
+    Some(op!(Halt)),                                    // 0x76
+    Some(op!(Load (@R HL) (:R A))),                     // 0x77
+    Some(op!(Load (:R A) (:R B))),                      // 0x78
+    Some(op!(Load (:R A) (:R C))),                      // 0x79
+    Some(op!(Load (:R A) (:R D))),                      // 0x7a
+    Some(op!(Load (:R A) (:R E))),                      // 0x7b
+    Some(op!(Load (:R A) (:R H))),                      // 0x7c
+    Some(op!(Load (:R A) (:R L))),                      // 0x7d
+    Some(op!(Load (:R A) (@R HL))),                     // 0x7e
+    Some(op!(Load (:R A) (:R A))),                      // 0x7f
+    Some(op!(Add8 (R A) (:R B) (= false))),             // 0x80
+    Some(op!(Add8 (R A) (:R C) (= false))),             // 0x81
+    Some(op!(Add8 (R A) (:R D) (= false))),             // 0x82
+    Some(op!(Add8 (R A) (:R E) (= false))),             // 0x83
+    Some(op!(Add8 (R A) (:R H) (= false))),             // 0x84
+    Some(op!(Add8 (R A) (:R L) (= false))),             // 0x85
+    Some(op!(Add8 (R A) (@R HL) (= false))),            // 0x86
+    Some(op!(Add8 (R A) (:R A) (= false))),             // 0x87
+    Some(op!(Add8 (R A) (:R B) (= true))),              // 0x88
+    Some(op!(Add8 (R A) (:R C) (= true))),              // 0x89
+    Some(op!(Add8 (R A) (:R D) (= true))),              // 0x8a
+    Some(op!(Add8 (R A) (:R E) (= true))),              // 0x8b
+    Some(op!(Add8 (R A) (:R H) (= true))),              // 0x8c
+    Some(op!(Add8 (R A) (:R L) (= true))),              // 0x8d
+    Some(op!(Add8 (R A) (@R HL) (= true))),             // 0x8e
+    Some(op!(Add8 (R A) (:R A) (= true))),              // 0x8f
+    Some(op!(Subtract (:R B) (= false))),               // 0x90
+    Some(op!(Subtract (:R C) (= false))),               // 0x91
+    Some(op!(Subtract (:R D) (= false))),               // 0x92
+    Some(op!(Subtract (:R E) (= false))),               // 0x93
+    Some(op!(Subtract (:R H) (= false))),               // 0x94
+    Some(op!(Subtract (:R L) (= false))),               // 0x95
+    Some(op!(Subtract (@R HL) (= false))),              // 0x96
+    Some(op!(Subtract (:R A) (= false))),               // 0x97
+    Some(op!(Subtract (:R B) (= true))),                // 0x98
+    Some(op!(Subtract (:R C) (= true))),                // 0x99
+    Some(op!(Subtract (:R D) (= true))),                // 0x9a
+    Some(op!(Subtract (:R E) (= true))),                // 0x9b
+    Some(op!(Subtract (:R H) (= true))),                // 0x9c
+    Some(op!(Subtract (:R L) (= true))),                // 0x9d
+    Some(op!(Subtract (@R HL) (= true))),               // 0x9e
+    Some(op!(Subtract (:R A) (= true))),                // 0x9f
+    Some(op!(And (:R B))),                              // 0xa0
+    Some(op!(And (:R C))),                              // 0xa1
+    Some(op!(And (:R D))),                              // 0xa2
+    Some(op!(And (:R E))),                              // 0xa3
+    Some(op!(And (:R H))),                              // 0xa4
+    Some(op!(And (:R L))),                              // 0xa5
+    Some(op!(And (@R HL))),                             // 0xa6
+    Some(op!(And (:R A))),                              // 0xa7
+    Some(op!(Xor (:R B))),                              // 0xa8
+    Some(op!(Xor (:R C))),                              // 0xa9
+    Some(op!(Xor (:R D))),                              // 0xaa
+    Some(op!(Xor (:R E))),                              // 0xab
+    Some(op!(Xor (:R H))),                              // 0xac
+    Some(op!(Xor (:R L))),                              // 0xad
+    Some(op!(Xor (@R HL))),                             // 0xae
+    Some(op!(Xor (:R A))),                              // 0xaf
+    Some(op!(Or (:R B))),                               // 0xb0
+    Some(op!(Or (:R C))),                               // 0xb1
+    Some(op!(Or (:R D))),                               // 0xb2
+    Some(op!(Or (:R E))),                               // 0xb3
+    Some(op!(Or (:R H))),                               // 0xb4
+    Some(op!(Or (:R L))),                               // 0xb5
+    Some(op!(Or (@R HL))),                              // 0xb6
+    Some(op!(Or (:R A))),                               // 0xb7
+    Some(op!(Compare (:R B))),                          // 0xb8
+    Some(op!(Compare (:R C))),                          // 0xb9
+    Some(op!(Compare (:R D))),                          // 0xba
+    Some(op!(Compare (:R E))),                          // 0xbb
+    Some(op!(Compare (:R H))),                          // 0xbc
+    Some(op!(Compare (:R L))),                          // 0xbd
+    Some(op!(Compare (@R HL))),                         // 0xbe
+    Some(op!(Compare (:R A))),                          // 0xbf
+    Some(op!(ReturnIf (F Zero) (= false))),             // 0xc0
+    Some(op!(Pop (R BC))),                              // 0xc1
+    Some(op!(JumpIf (F Zero) (= false) ABS16)),         // 0xc2
+    Some(op!(Jump IMM16)),                              // 0xc3
+    Some(op!(CallIf (F Zero) (= false) ABS16)),         // 0xc4
+    Some(op!(Push (R BC))),                             // 0xc5
+    Some(op!(Add8 (R A) IMM8 (= false))),               // 0xc6
+    Some(op!(Rst (= 0))),                               // 0xc7
+    Some(op!(ReturnIf (F Zero) (= true))),              // 0xc8
+    Some(op!(Return)),                                  // 0xc9
+    Some(op!(JumpIf (F Zero) (= true) ABS16)),          // 0xca
+    None,                                               // 0xcb, prefix for CB_OPCODES
+    Some(op!(CallIf (F Zero) (= true) ABS16)),          // 0xcc
+    Some(op!(Call ABS16)),                              // 0xcd
+    Some(op!(Add8 (R A) IMM8 (= true))),                // 0xce
+    Some(op!(Rst (= 1))),                               // 0xcf
+    Some(op!(ReturnIf (F Carry) (= false))),            // 0xd0
+    Some(op!(Pop (R DE))),                              // 0xd1
+    Some(op!(JumpIf (F Carry) (= false) ABS16)),        // 0xd2
+    None,                                               // 0xd3
+    Some(op!(CallIf (F Carry) (= false) ABS16)),        // 0xd4
+    Some(op!(Push (R DE))),                             // 0xd5
+    Some(op!(Subtract IMM8 (= false))),                 // 0xd6
+    Some(op!(Rst (= 2))),                               // 0xd7
+    Some(op!(ReturnIf (F Carry) (= true))),             // 0xd8
+    Some(op!(ReturnInterrupt)),                         // 0xd9
+    Some(op!(JumpIf (F Carry) (= true) ABS16)),         // 0xda
+    None,                                               // 0xdb
+    Some(op!(CallIf (F Carry) (= true) ABS16)),         // 0xdc
+    None,                                               // 0xdd
+    Some(op!(Subtract IMM8 (= true))),                  // 0xde
+    Some(op!(Rst (= 3))),                               // 0xdf
+    Some(op!(Load (@IMM8 0xff00) (:R A))),              // 0xe0
+    Some(op!(Pop (R HL))),                              // 0xe1
+    Some(op!(Load (@R C 0xff00) (:R A))),               // 0xe2
+    None,                                               // 0xe3
+    None,                                               // 0xe4
+    Some(op!(Push (R HL))),                             // 0xe5
+    Some(op!(And IMM8)),                                // 0xe6
+    Some(op!(Rst (= 4))),                               // 0xe7
+    Some(op!(SPOps (SP AddOffset))),                    // 0xe8
+    Some(op!(Jump (:R HL))),                            // 0xe9
+    Some(op!(Load (@IMM16) (:R A))),                    // 0xea
+    None,                                               // 0xeb
+    None,                                               // 0xec
+    None,                                               // 0xed
+    Some(op!(Xor IMM8)),                                // 0xee
+    Some(op!(Rst (= 5))),                               // 0xef
+    Some(op!(Load (:R A) (@IMM8 0xff00))),              // 0xf0
+    Some(op!(Pop (R AF))),                              // 0xf1
+    Some(op!(Load (:R A) (@R C 0xff00))),               // 0xf2
+    Some(op!(DisableInterrupts)),                       // 0xf3
+    None,                                               // 0xf4
+    Some(op!(Push (R AF))),                             // 0xf5
+    Some(op!(Or IMM8)),                                 // 0xf6
+    Some(op!(Rst (= 6))),                               // 0xf7
+    Some(op!(SPOps (SP LoadIntoHL))),                   // 0xf8
+    Some(op!(SPOps (= SPOps::LoadFromHL))),             // 0xf9
+    Some(op!(Load (:R A) (@IMM16))),                    // 0xfa
+    Some(op!(EnableInterrupts)),                        // 0xfb
+    None,                                               // 0xfc
+    None,                                               // 0xfd
+    Some(op!(Compare IMM8)),                            // 0xfe
+    Some(op!(Rst (= 7))),                               // 0xff
+];
+
+/// The opcodes following a 0xcb prefix.
+pub static CB_OPCODES: [Opcode; 256] = [
+    op!(RotateLeft (:R B) (= false)),    // 0x00
+    op!(RotateLeft (:R C) (= false)),    // 0x01
+    op!(RotateLeft (:R D) (= false)),    // 0x02
+    op!(RotateLeft (:R E) (= false)),    // 0x03
+    op!(RotateLeft (:R H) (= false)),    // 0x04
+    op!(RotateLeft (:R L) (= false)),    // 0x05
+    op!(RotateLeft (@R HL) (= false)),   // 0x06
+    op!(RotateLeft (:R A) (= false)),    // 0x07
+    op!(RotateRight (:R B) (= false)),   // 0x08
+    op!(RotateRight (:R C) (= false)),   // 0x09
+    op!(RotateRight (:R D) (= false)),   // 0x0a
+    op!(RotateRight (:R E) (= false)),   // 0x0b
+    op!(RotateRight (:R H) (= false)),   // 0x0c
+    op!(RotateRight (:R L) (= false)),   // 0x0d
+    op!(RotateRight (@R HL) (= false)),  // 0x0e
+    op!(RotateRight (:R A) (= false)),   // 0x0f
+    op!(RotateLeft (:R B) (= true)),     // 0x10
+    op!(RotateLeft (:R C) (= true)),     // 0x11
+    op!(RotateLeft (:R D) (= true)),     // 0x12
+    op!(RotateLeft (:R E) (= true)),     // 0x13
+    op!(RotateLeft (:R H) (= true)),     // 0x14
+    op!(RotateLeft (:R L) (= true)),     // 0x15
+    op!(RotateLeft (@R HL) (= true)),    // 0x16
+    op!(RotateLeft (:R A) (= true)),     // 0x17
+    op!(RotateRight (:R B) (= true)),    // 0x18
+    op!(RotateRight (:R C) (= true)),    // 0x19
+    op!(RotateRight (:R D) (= true)),    // 0x1a
+    op!(RotateRight (:R E) (= true)),    // 0x1b
+    op!(RotateRight (:R H) (= true)),    // 0x1c
+    op!(RotateRight (:R L) (= true)),    // 0x1d
+    op!(RotateRight (@R HL) (= true)),   // 0x1e
+    op!(RotateRight (:R A) (= true)),    // 0x1f
+    op!(ShiftLeft (:R B)),               // 0x20
+    op!(ShiftLeft (:R C)),               // 0x21
+    op!(ShiftLeft (:R D)),               // 0x22
+    op!(ShiftLeft (:R E)),               // 0x23
+    op!(ShiftLeft (:R H)),               // 0x24
+    op!(ShiftLeft (:R L)),               // 0x25
+    op!(ShiftLeft (@R HL)),              // 0x26
+    op!(ShiftLeft (:R A)),               // 0x27
+    op!(ShiftRight (:R B) (= false)),    // 0x28
+    op!(ShiftRight (:R C) (= false)),    // 0x29
+    op!(ShiftRight (:R D) (= false)),    // 0x2a
+    op!(ShiftRight (:R E) (= false)),    // 0x2b
+    op!(ShiftRight (:R H) (= false)),    // 0x2c
+    op!(ShiftRight (:R L) (= false)),    // 0x2d
+    op!(ShiftRight (@R HL) (= false)),   // 0x2e
+    op!(ShiftRight (:R A) (= false)),    // 0x2f
+    op!(Swap (:R B)),                    // 0x30
+    op!(Swap (:R C)),                    // 0x31
+    op!(Swap (:R D)),                    // 0x32
+    op!(Swap (:R E)),                    // 0x33
+    op!(Swap (:R H)),                    // 0x34
+    op!(Swap (:R L)),                    // 0x35
+    op!(Swap (@R HL)),                   // 0x36
+    op!(Swap (:R A)),                    // 0x37
+    op!(ShiftRight (:R B) (= true)),     // 0x38
+    op!(ShiftRight (:R C) (= true)),     // 0x39
+    op!(ShiftRight (:R D) (= true)),     // 0x3a
+    op!(ShiftRight (:R E) (= true)),     // 0x3b
+    op!(ShiftRight (:R H) (= true)),     // 0x3c
+    op!(ShiftRight (:R L) (= true)),     // 0x3d
+    op!(ShiftRight (@R HL) (= true)),    // 0x3e
+    op!(ShiftRight (:R A) (= true)),     // 0x3f
+    op!(Bit (= 0) (:R B)),               // 0x40
+    op!(Bit (= 0) (:R C)),               // 0x41
+    op!(Bit (= 0) (:R D)),               // 0x42
+    op!(Bit (= 0) (:R E)),               // 0x43
+    op!(Bit (= 0) (:R H)),               // 0x44
+    op!(Bit (= 0) (:R L)),               // 0x45
+    op!(Bit (= 0) (@R HL)),              // 0x46
+    op!(Bit (= 0) (:R A)),               // 0x47
+    op!(Bit (= 1) (:R B)),               // 0x48
+    op!(Bit (= 1) (:R C)),               // 0x49
+    op!(Bit (= 1) (:R D)),               // 0x4a
+    op!(Bit (= 1) (:R E)),               // 0x4b
+    op!(Bit (= 1) (:R H)),               // 0x4c
+    op!(Bit (= 1) (:R L)),               // 0x4d
+    op!(Bit (= 1) (@R HL)),              // 0x4e
+    op!(Bit (= 1) (:R A)),               // 0x4f
+    op!(Bit (= 2) (:R B)),               // 0x50
+    op!(Bit (= 2) (:R C)),               // 0x51
+    op!(Bit (= 2) (:R D)),               // 0x52
+    op!(Bit (= 2) (:R E)),               // 0x53
+    op!(Bit (= 2) (:R H)),               // 0x54
+    op!(Bit (= 2) (:R L)),               // 0x55
+    op!(Bit (= 2) (@R HL)),              // 0x56
+    op!(Bit (= 2) (:R A)),               // 0x57
+    op!(Bit (= 3) (:R B)),               // 0x58
+    op!(Bit (= 3) (:R C)),               // 0x59
+    op!(Bit (= 3) (:R D)),               // 0x5a
+    op!(Bit (= 3) (:R E)),               // 0x5b
+    op!(Bit (= 3) (:R H)),               // 0x5c
+    op!(Bit (= 3) (:R L)),               // 0x5d
+    op!(Bit (= 3) (@R HL)),              // 0x5e
+    op!(Bit (= 3) (:R A)),               // 0x5f
+    op!(Bit (= 4) (:R B)),               // 0x60
+    op!(Bit (= 4) (:R C)),               // 0x61
+    op!(Bit (= 4) (:R D)),               // 0x62
+    op!(Bit (= 4) (:R E)),               // 0x63
+    op!(Bit (= 4) (:R H)),               // 0x64
+    op!(Bit (= 4) (:R L)),               // 0x65
+    op!(Bit (= 4) (@R HL)),              // 0x66
+    op!(Bit (= 4) (:R A)),               // 0x67
+    op!(Bit (= 5) (:R B)),               // 0x68
+    op!(Bit (= 5) (:R C)),               // 0x69
+    op!(Bit (= 5) (:R D)),               // 0x6a
+    op!(Bit (= 5) (:R E)),               // 0x6b
+    op!(Bit (= 5) (:R H)),               // 0x6c
+    op!(Bit (= 5) (:R L)),               // 0x6d
+    op!(Bit (= 5) (@R HL)),              // 0x6e
+    op!(Bit (= 5) (:R A)),               // 0x6f
+    op!(Bit (= 6) (:R B)),               // 0x70
+    op!(Bit (= 6) (:R C)),               // 0x71
+    op!(Bit (= 6) (:R D)),               // 0x72
+    op!(Bit (= 6) (:R E)),               // 0x73
+    op!(Bit (= 6) (:R H)),               // 0x74
+    op!(Bit (= 6) (:R L)),               // 0x75
+    op!(Bit (= 6) (@R HL)),              // 0x76
+    op!(Bit (= 6) (:R A)),               // 0x77
+    op!(Bit (= 7) (:R B)),               // 0x78
+    op!(Bit (= 7) (:R C)),               // 0x79
+    op!(Bit (= 7) (:R D)),               // 0x7a
+    op!(Bit (= 7) (:R E)),               // 0x7b
+    op!(Bit (= 7) (:R H)),               // 0x7c
+    op!(Bit (= 7) (:R L)),               // 0x7d
+    op!(Bit (= 7) (@R HL)),              // 0x7e
+    op!(Bit (= 7) (:R A)),               // 0x7f
+    op!(SetBit (= 0) (:R B) (= false)),  // 0x80
+    op!(SetBit (= 0) (:R C) (= false)),  // 0x81
+    op!(SetBit (= 0) (:R D) (= false)),  // 0x82
+    op!(SetBit (= 0) (:R E) (= false)),  // 0x83
+    op!(SetBit (= 0) (:R H) (= false)),  // 0x84
+    op!(SetBit (= 0) (:R L) (= false)),  // 0x85
+    op!(SetBit (= 0) (@R HL) (= false)), // 0x86
+    op!(SetBit (= 0) (:R A) (= false)),  // 0x87
+    op!(SetBit (= 1) (:R B) (= false)),  // 0x88
+    op!(SetBit (= 1) (:R C) (= false)),  // 0x89
+    op!(SetBit (= 1) (:R D) (= false)),  // 0x8a
+    op!(SetBit (= 1) (:R E) (= false)),  // 0x8b
+    op!(SetBit (= 1) (:R H) (= false)),  // 0x8c
+    op!(SetBit (= 1) (:R L) (= false)),  // 0x8d
+    op!(SetBit (= 1) (@R HL) (= false)), // 0x8e
+    op!(SetBit (= 1) (:R A) (= false)),  // 0x8f
+    op!(SetBit (= 2) (:R B) (= false)),  // 0x90
+    op!(SetBit (= 2) (:R C) (= false)),  // 0x91
+    op!(SetBit (= 2) (:R D) (= false)),  // 0x92
+    op!(SetBit (= 2) (:R E) (= false)),  // 0x93
+    op!(SetBit (= 2) (:R H) (= false)),  // 0x94
+    op!(SetBit (= 2) (:R L) (= false)),  // 0x95
+    op!(SetBit (= 2) (@R HL) (= false)), // 0x96
+    op!(SetBit (= 2) (:R A) (= false)),  // 0x97
+    op!(SetBit (= 3) (:R B) (= false)),  // 0x98
+    op!(SetBit (= 3) (:R C) (= false)),  // 0x99
+    op!(SetBit (= 3) (:R D) (= false)),  // 0x9a
+    op!(SetBit (= 3) (:R E) (= false)),  // 0x9b
+    op!(SetBit (= 3) (:R H) (= false)),  // 0x9c
+    op!(SetBit (= 3) (:R L) (= false)),  // 0x9d
+    op!(SetBit (= 3) (@R HL) (= false)), // 0x9e
+    op!(SetBit (= 3) (:R A) (= false)),  // 0x9f
+    op!(SetBit (= 4) (:R B) (= false)),  // 0xa0
+    op!(SetBit (= 4) (:R C) (= false)),  // 0xa1
+    op!(SetBit (= 4) (:R D) (= false)),  // 0xa2
+    op!(SetBit (= 4) (:R E) (= false)),  // 0xa3
+    op!(SetBit (= 4) (:R H) (= false)),  // 0xa4
+    op!(SetBit (= 4) (:R L) (= false)),  // 0xa5
+    op!(SetBit (= 4) (@R HL) (= false)), // 0xa6
+    op!(SetBit (= 4) (:R A) (= false)),  // 0xa7
+    op!(SetBit (= 5) (:R B) (= false)),  // 0xa8
+    op!(SetBit (= 5) (:R C) (= false)),  // 0xa9
+    op!(SetBit (= 5) (:R D) (= false)),  // 0xaa
+    op!(SetBit (= 5) (:R E) (= false)),  // 0xab
+    op!(SetBit (= 5) (:R H) (= false)),  // 0xac
+    op!(SetBit (= 5) (:R L) (= false)),  // 0xad
+    op!(SetBit (= 5) (@R HL) (= false)), // 0xae
+    op!(SetBit (= 5) (:R A) (= false)),  // 0xaf
+    op!(SetBit (= 6) (:R B) (= false)),  // 0xb0
+    op!(SetBit (= 6) (:R C) (= false)),  // 0xb1
+    op!(SetBit (= 6) (:R D) (= false)),  // 0xb2
+    op!(SetBit (= 6) (:R E) (= false)),  // 0xb3
+    op!(SetBit (= 6) (:R H) (= false)),  // 0xb4
+    op!(SetBit (= 6) (:R L) (= false)),  // 0xb5
+    op!(SetBit (= 6) (@R HL) (= false)), // 0xb6
+    op!(SetBit (= 6) (:R A) (= false)),  // 0xb7
+    op!(SetBit (= 7) (:R B) (= false)),  // 0xb8
+    op!(SetBit (= 7) (:R C) (= false)),  // 0xb9
+    op!(SetBit (= 7) (:R D) (= false)),  // 0xba
+    op!(SetBit (= 7) (:R E) (= false)),  // 0xbb
+    op!(SetBit (= 7) (:R H) (= false)),  // 0xbc
+    op!(SetBit (= 7) (:R L) (= false)),  // 0xbd
+    op!(SetBit (= 7) (@R HL) (= false)), // 0xbe
+    op!(SetBit (= 7) (:R A) (= false)),  // 0xbf
+    op!(SetBit (= 0) (:R B) (= true)),   // 0xc0
+    op!(SetBit (= 0) (:R C) (= true)),   // 0xc1
+    op!(SetBit (= 0) (:R D) (= true)),   // 0xc2
+    op!(SetBit (= 0) (:R E) (= true)),   // 0xc3
+    op!(SetBit (= 0) (:R H) (= true)),   // 0xc4
+    op!(SetBit (= 0) (:R L) (= true)),   // 0xc5
+    op!(SetBit (= 0) (@R HL) (= true)),  // 0xc6
+    op!(SetBit (= 0) (:R A) (= true)),   // 0xc7
+    op!(SetBit (= 1) (:R B) (= true)),   // 0xc8
+    op!(SetBit (= 1) (:R C) (= true)),   // 0xc9
+    op!(SetBit (= 1) (:R D) (= true)),   // 0xca
+    op!(SetBit (= 1) (:R E) (= true)),   // 0xcb
+    op!(SetBit (= 1) (:R H) (= true)),   // 0xcc
+    op!(SetBit (= 1) (:R L) (= true)),   // 0xcd
+    op!(SetBit (= 1) (@R HL) (= true)),  // 0xce
+    op!(SetBit (= 1) (:R A) (= true)),   // 0xcf
+    op!(SetBit (= 2) (:R B) (= true)),   // 0xd0
+    op!(SetBit (= 2) (:R C) (= true)),   // 0xd1
+    op!(SetBit (= 2) (:R D) (= true)),   // 0xd2
+    op!(SetBit (= 2) (:R E) (= true)),   // 0xd3
+    op!(SetBit (= 2) (:R H) (= true)),   // 0xd4
+    op!(SetBit (= 2) (:R L) (= true)),   // 0xd5
+    op!(SetBit (= 2) (@R HL) (= true)),  // 0xd6
+    op!(SetBit (= 2) (:R A) (= true)),   // 0xd7
+    op!(SetBit (= 3) (:R B) (= true)),   // 0xd8
+    op!(SetBit (= 3) (:R C) (= true)),   // 0xd9
+    op!(SetBit (= 3) (:R D) (= true)),   // 0xda
+    op!(SetBit (= 3) (:R E) (= true)),   // 0xdb
+    op!(SetBit (= 3) (:R H) (= true)),   // 0xdc
+    op!(SetBit (= 3) (:R L) (= true)),   // 0xdd
+    op!(SetBit (= 3) (@R HL) (= true)),  // 0xde
+    op!(SetBit (= 3) (:R A) (= true)),   // 0xdf
+    op!(SetBit (= 4) (:R B) (= true)),   // 0xe0
+    op!(SetBit (= 4) (:R C) (= true)),   // 0xe1
+    op!(SetBit (= 4) (:R D) (= true)),   // 0xe2
+    op!(SetBit (= 4) (:R E) (= true)),   // 0xe3
+    op!(SetBit (= 4) (:R H) (= true)),   // 0xe4
+    op!(SetBit (= 4) (:R L) (= true)),   // 0xe5
+    op!(SetBit (= 4) (@R HL) (= true)),  // 0xe6
+    op!(SetBit (= 4) (:R A) (= true)),   // 0xe7
+    op!(SetBit (= 5) (:R B) (= true)),   // 0xe8
+    op!(SetBit (= 5) (:R C) (= true)),   // 0xe9
+    op!(SetBit (= 5) (:R D) (= true)),   // 0xea
+    op!(SetBit (= 5) (:R E) (= true)),   // 0xeb
+    op!(SetBit (= 5) (:R H) (= true)),   // 0xec
+    op!(SetBit (= 5) (:R L) (= true)),   // 0xed
+    op!(SetBit (= 5) (@R HL) (= true)),  // 0xee
+    op!(SetBit (= 5) (:R A) (= true)),   // 0xef
+    op!(SetBit (= 6) (:R B) (= true)),   // 0xf0
+    op!(SetBit (= 6) (:R C) (= true)),   // 0xf1
+    op!(SetBit (= 6) (:R D) (= true)),   // 0xf2
+    op!(SetBit (= 6) (:R E) (= true)),   // 0xf3
+    op!(SetBit (= 6) (:R H) (= true)),   // 0xf4
+    op!(SetBit (= 6) (:R L) (= true)),   // 0xf5
+    op!(SetBit (= 6) (@R HL) (= true)),  // 0xf6
+    op!(SetBit (= 6) (:R A) (= true)),   // 0xf7
+    op!(SetBit (= 7) (:R B) (= true)),   // 0xf8
+    op!(SetBit (= 7) (:R C) (= true)),   // 0xf9
+    op!(SetBit (= 7) (:R D) (= true)),   // 0xfa
+    op!(SetBit (= 7) (:R E) (= true)),   // 0xfb
+    op!(SetBit (= 7) (:R H) (= true)),   // 0xfc
+    op!(SetBit (= 7) (:R L) (= true)),   // 0xfd
+    op!(SetBit (= 7) (@R HL) (= true)),  // 0xfe
+    op!(SetBit (= 7) (:R A) (= true)),   // 0xff
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{CB_OPCODES, OPCODES};
+
+    #[test]
+    fn illegal_opcodes() {
+        let missing: Vec<_> = (0..=0xff)
+            .filter(|opcode| OPCODES[*opcode as usize].is_none())
+            .collect();
+
+        assert_eq!(
+            missing,
+            vec![0xcb, 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd]
+        );
+    }
+
+    #[test]
+    fn immediates() {
+        let cases = [
+            (0x01, 2, 0x1234, "ld BC, 0x1234"),
+            (0x08, 2, 0xc000, "ld (0xc000), SP"),
+            (0x18, 1, 0x00fe, "jr -2"),
+            (0x3e, 1, 0x0042, "ld A, 0x42"),
+            (0xc4, 2, 0x2000, "call NZ, 8192"),
+            (0xe0, 1, 0x0044, "ld (0xff00+0x44), A"),
+            (0xe8, 1, 0x00ff, "add SP, -1"),
+        ];
+
+        for (opcode, len, immediate, expected) in cases.iter() {
+            let entry = OPCODES[*opcode as usize].unwrap();
+            assert_eq!(entry.immediate_len, *len, "{:#04x}", opcode);
+            assert_eq!(
+                (entry.build)(*immediate).to_string(),
+                *expected,
+                "{:#04x}",
+                opcode
+            );
+        }
+
+        assert!(CB_OPCODES.iter().all(|entry| entry.immediate_len == 0));
+        assert_eq!((CB_OPCODES[0x7e].build)(0).to_string(), "bit 7, (HL)");
+    }
+}
@@ -1,154 +1,2202 @@
-use std::collections::BTreeMap;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt, io,
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
 
-use anyhow::Context;
+use thiserror::Error;
 
 use crate::{
-    bios::DMG_BIOS,
+    apu::Apu,
+    bios::{CGB_BIOS, DMG_BIOS},
     cartridge::Cartridge,
-    cpu::Cpu,
-    gpu::Gpu,
-    memory::mmu::{JoypadButton, Mmu},
+    counter::{Counters, Trigger},
+    cpu::{Cpu, CpuError, CpuFlag, InstructionError, Interrupts},
+    gpu::{Gpu, GpuMode, Layer, LcdControl, SpriteEntry, Tilemap},
+    input::InputSequence,
+    instruction::Instruction,
+    journal::SaveJournal,
+    lint::{Lint, Lints},
+    memory::{
+        mmu::{JoypadButton, Mmu},
+        watch::Watchpoints,
+        BusSnooper, Memory, MemoryError, MemoryOperation,
+    },
+    model::{Model, IO_REGISTERS},
+    observation::Observation,
+    overlay::Overlay,
+    palette::{ColorMapper, Palette, Rgb888, Rgba8888},
+    profile::{ExecutionProfile, Location, SubsystemTimes},
+    rng::Rng,
+    serial::Serial,
+    sink::{AudioSink, FrameSink},
+    trace::{Trace, Tracer},
 };
 
-#[cfg(feature = "dump-log")]
-use crate::memory::Memory;
-#[cfg(feature = "dump-log")]
-use std::{fs::File, io::Write};
+/// Address of the boot ROM loop that is entered when the cartridge logo does
+/// not match.
+const BOOT_LOGO_LOCK: u16 = 0x00e9;
+/// Address of the boot ROM loop that is entered when the header checksum is
+/// wrong.
+const BOOT_CHECKSUM_LOCK: u16 = 0x00fa;
+
+#[derive(Debug, Clone, Copy)]
+pub enum StopReason {
+    Error(CpuError),
+    /// The instruction at `pc` read WRAM or HRAM that was never written, see
+    /// [`UninitializedReads::Trap`].
+    UninitializedRead {
+        pc: u16,
+        address: u16,
+    },
+    /// The instruction at `pc` accessed an address a watchpoint covers, see
+    /// [`Device::watchpoints_mut`]. `old` is the value before the access and
+    /// `new` the value read or written, which are the same for reads.
+    Watchpoint {
+        pc: u16,
+        address: u16,
+        op: MemoryOperation,
+        old: u8,
+        new: u8,
+    },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Error(err) => write!(f, "{}", err),
+            StopReason::UninitializedRead { pc, address } => write!(
+                f,
+                "instruction at {:#06x} read uninitialized RAM at {:#06x}",
+                pc, address
+            ),
+            StopReason::Watchpoint {
+                pc,
+                address,
+                op: MemoryOperation::Read,
+                new,
+                ..
+            } => write!(
+                f,
+                "instruction at {:#06x} read {:#04x} from watched {:#06x}",
+                pc, new, address
+            ),
+            StopReason::Watchpoint {
+                pc,
+                address,
+                op: MemoryOperation::Write,
+                old,
+                new,
+            } if old == new => write!(
+                f,
+                "instruction at {:#06x} wrote {:#04x} to watched {:#06x}",
+                pc, new, address
+            ),
+            StopReason::Watchpoint {
+                pc,
+                address,
+                op: MemoryOperation::Write,
+                old,
+                new,
+            } => write!(
+                f,
+                "instruction at {:#06x} changed watched {:#06x} from {:#04x} to {:#04x}",
+                pc, address, old, new
+            ),
+        }
+    }
+}
+
+/// What happens when the game reads WRAM or HRAM it never wrote. That memory
+/// holds random values on hardware, so a game that works here because it
+/// reads 0 can break there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UninitializedReads {
+    Ignore,
+    /// Reports a [`Lint::UninitializedRead`] for the instruction.
+    Log,
+    /// Also stops right after the instruction, see [`Device::resume`].
+    Trap,
+}
+
+/// Where stepping over or out of a routine stops, see [`Device::step_over`]
+/// and [`Device::step_out`].
+///
+/// Calls and returns are recognized by how they move the stack pointer, so a
+/// call that isn't taken, a recursive call passing the same address or an
+/// interrupt handler returning in between doesn't stop early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReturnTarget {
+    /// The number of calls and interrupts still to return from, negative once
+    /// the routine returned.
+    depth: i32,
+}
+
+impl ReturnTarget {
+    pub fn is_reached(&self) -> bool {
+        self.depth < 0
+    }
+}
+
+/// An instruction as passed to instruction hooks.
+#[derive(Debug, Clone, Copy)]
+pub struct InstructionInfo {
+    pub instruction: Instruction,
+    /// The address of the instruction.
+    pub pc: u16,
+    /// The T-cycle count at the time the hook is called, see [`Device::cycles`].
+    /// For post-instruction hooks, this includes any interrupt dispatched
+    /// right after the instruction.
+    pub cycles: u64,
+}
+
+/// A callback invoked around every executed instruction, along with the CPU
+/// state at that time.
+pub type InstructionHook = Box<dyn FnMut(&InstructionInfo, &Cpu)>;
+
+/// A callback drawing on the [`Overlay`] when the GPU finishes a line, which
+/// is passed the line's number.
+pub type HBlankHook = Box<dyn FnMut(u8, &Mmu, &mut Overlay)>;
+
+/// A callback taking every line the GPU finishes, as its number and its 160
+/// RGB pixels, for renderers and filters that work a line at a time.
+pub type ScanlineHook = Box<dyn FnMut(u8, &[u8])>;
+
+/// A callback drawing on the [`Overlay`] when the GPU finishes a frame.
+pub type VBlankHook = Box<dyn FnMut(&Mmu, &mut Overlay)>;
+
+#[derive(Error, Debug)]
+pub enum BootError {
+    #[error("the boot ROM rejected the cartridge logo")]
+    LogoMismatch,
+    #[error("the boot ROM rejected the header checksum")]
+    HeaderChecksum,
+    #[error("boot ROM did not finish within {frames} frames, stalled at {pc:#06x}")]
+    Timeout { frames: usize, pc: u16 },
+    #[error("execution stopped at {pc:#06x}: {reason}")]
+    Stopped { reason: StopReason, pc: u16 },
+    #[error("boot ROM handed control to {pc:#06x} instead of 0x0100")]
+    WrongEntryPoint { pc: u16 },
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BootRomError {
+    #[error(
+        "the boot ROM is {0} bytes, expected {} for the DMG or {} for the CGB",
+        DMG_BIOS.len(),
+        CGB_BIOS.len()
+    )]
+    Size(usize),
+}
+
+/// Sets up a [`Device`] with more than a cartridge, such as
+/// `DeviceBuilder::new(cart).model(Model::Mgb).build()`.
+pub struct DeviceBuilder {
+    cart: Cartridge,
+    model: Model,
+    boot_rom: Option<Vec<u8>>,
+    skip_boot: bool,
+}
+
+impl DeviceBuilder {
+    /// Starts out with a DMG and the built-in boot ROM of the model.
+    pub fn new(cart: Cartridge) -> DeviceBuilder {
+        DeviceBuilder {
+            cart,
+            model: Model::Dmg,
+            boot_rom: None,
+            skip_boot: false,
+        }
+    }
+
+    /// See [`Device::with_model`].
+    pub fn model(mut self, model: Model) -> DeviceBuilder {
+        self.model = model;
+        self
+    }
+
+    /// Boots with a dump of a boot ROM instead of the built-in one, which
+    /// has to be exactly as large as the DMG's or the CGB's. The CGB's is
+    /// mapped around the cartridge header, with 0x0100-0x01ff left unused.
+    pub fn boot_rom(mut self, rom: Vec<u8>) -> Result<DeviceBuilder, BootRomError> {
+        if rom.len() != DMG_BIOS.len() && rom.len() != CGB_BIOS.len() {
+            return Err(BootRomError::Size(rom.len()));
+        }
+
+        self.boot_rom = Some(rom);
+        Ok(self)
+    }
+
+    /// Starts at the cartridge's entry point, see [`Device::skip_boot`].
+    pub fn skip_boot(mut self) -> DeviceBuilder {
+        self.skip_boot = true;
+        self
+    }
+
+    pub fn build(self) -> Device {
+        let boot_rom = match self.boot_rom {
+            Some(rom) => Cow::Owned(rom),
+            None if self.model.is_cgb() => Cow::Borrowed(CGB_BIOS),
+            None => Cow::Borrowed(DMG_BIOS),
+        };
+
+        let mut device = Device::assemble(self.cart, self.model, boot_rom);
+        if self.skip_boot {
+            device.skip_boot();
+        }
 
-const PALETTE: [[u8; 3]; 4] = [[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]];
+        device
+    }
+}
 
 pub struct Device {
     cpu: Cpu,
     mmu: Mmu,
+    model: Model,
 
     tile_framebuffer: Box<[u8; 3 * 16 * 24 * 8 * 8]>,
     display_framebuffer: Box<[u8; 3 * 160 * 144]>,
+    color_mapper: Option<Box<dyn ColorMapper>>,
+    mapped_framebuffer: Vec<u8>,
+
+    input: Option<InputSequence>,
+    /// Bytes kept at a value, see [`Device::freeze`].
+    frozen: BTreeMap<u16, u8>,
+    rng_seed: u64,
+    stop_reason: Option<StopReason>,
+
+    presented_frame: usize,
+    dropped_frames: usize,
 
-    #[cfg(feature = "dump-log")]
-    log: File,
+    /// The palette of every [`Layer`], indexed by the layer.
+    palettes: [Palette; 3],
+
+    pre_instruction_hooks: Vec<InstructionHook>,
+    post_instruction_hooks: Vec<InstructionHook>,
+    hblank_hooks: Vec<HBlankHook>,
+    vblank_hooks: Vec<VBlankHook>,
+    scanline_hooks: Vec<ScanlineHook>,
+    /// The last line the HBlank and scanline hooks were called for.
+    hblank_line: Option<u8>,
+    overlay: Overlay,
+    frame_sinks: Vec<Box<dyn FrameSink>>,
+    audio_sinks: Vec<Box<dyn AudioSink>>,
+
+    tracer: Option<Tracer>,
+    profile: Option<ExecutionProfile>,
+    counters: Counters,
+    journal: Option<SaveJournal>,
+    lints: Lints,
+    uninitialized_reads: UninitializedReads,
+    framebuffer_time: Option<Duration>,
 }
 
 impl Device {
     pub fn new(cart: Cartridge) -> Device {
+        Device::with_model(cart, Model::Dmg)
+    }
+
+    /// Emulates the given model with its boot ROM. [`Model::Cgb`] only runs
+    /// in CGB mode, with colors and the second VRAM bank, for games whose
+    /// header says they support it.
+    pub fn with_model(cart: Cartridge, model: Model) -> Device {
+        DeviceBuilder::new(cart).model(model).build()
+    }
+
+    fn assemble(cart: Cartridge, model: Model, boot_rom: Cow<'static, [u8]>) -> Device {
+        let mut gpu = Gpu::new();
+        gpu.set_cgb_mode(model == Model::Cgb && cart.supports_cgb());
+
         Device {
             cpu: Cpu::new(),
-            mmu: Mmu::new(DMG_BIOS, cart, Gpu::new()),
+            mmu: Mmu::new(boot_rom, cart, gpu),
+            model,
             tile_framebuffer: Box::new([0; 3 * 16 * 24 * 8 * 8]),
             display_framebuffer: Box::new([0; 3 * 160 * 144]),
+            color_mapper: None,
+            mapped_framebuffer: Vec::new(),
+            input: None,
+            frozen: BTreeMap::new(),
+            rng_seed: 0,
+            stop_reason: None,
+
+            presented_frame: 0,
+            dropped_frames: 0,
 
-            #[cfg(feature = "dump-log")]
-            log: File::create("log.txt").expect("cannot create dump log file"),
+            palettes: [Palette::default(); 3],
+
+            pre_instruction_hooks: Vec::new(),
+            post_instruction_hooks: Vec::new(),
+            hblank_hooks: Vec::new(),
+            vblank_hooks: Vec::new(),
+            scanline_hooks: Vec::new(),
+            hblank_line: None,
+            overlay: Overlay::new(),
+            frame_sinks: Vec::new(),
+            audio_sinks: Vec::new(),
+
+            tracer: None,
+            profile: None,
+            counters: Counters::default(),
+            journal: None,
+            lints: Lints::default(),
+            uninitialized_reads: UninitializedReads::Ignore,
+            framebuffer_time: None,
         }
     }
 
     pub fn reset(&mut self) {
         self.cpu.reset();
-        self.mmu.gpu.reset();
+        self.mmu.reset_gpu();
         self.mmu.use_bios = true;
+        self.stop_reason = None;
+    }
+
+    /// Leaves the boot ROM out, starting the cartridge at 0x0100 with the
+    /// CPU and IO registers set like the boot ROM would have left them on
+    /// the model. The logo the boot ROM draws isn't in VRAM, and registers
+    /// that depend on the boot timing, like DIV, start out as after a reset.
+    pub fn skip_boot(&mut self) {
+        let model = match self.model {
+            Model::Cgb if !self.cgb_mode() => Model::CgbDmg,
+            model => model,
+        };
+
+        let checksum = self.mmu.cart.read(0x14d).unwrap_or(0);
+        self.cpu.restore(&model.post_boot_cpu(checksum));
+        self.mmu.use_bios = false;
+
+        // Writing DMA or HDMA5 would start a transfer, HDMA1-HDMA4 are write
+        // only anyway, and the infrared port isn't emulated.
+        let skipped = [
+            0xff04, 0xff41, 0xff44, 0xff46, 0xff51, 0xff52, 0xff53, 0xff54, 0xff55, 0xff56,
+        ];
+        let mut registers: Vec<_> = IO_REGISTERS
+            .iter()
+            .filter(|register| !skipped.contains(&register.address))
+            .collect();
+        // The sound registers ignore writes while the APU is off, so NR52
+        // goes first.
+        registers.sort_by_key(|register| register.address != 0xff26);
+
+        for register in registers {
+            if let Some(value) = register.post_boot_value(model) {
+                // None of the registers left are read-only.
+                self.mmu.write(register.address, value).ok();
+            }
+        }
     }
 
     pub fn step_frame(&mut self) {
-        while !self.step() {}
+        while !self.step() && self.stop_reason.is_none() {}
     }
 
     pub fn step_frame_until_pc(&mut self, pc: u16) {
-        while !self.step() && self.cpu.pc != pc {}
+        while !self.step() && self.stop_reason.is_none() && self.cpu.pc != pc {}
     }
 
+    /// Executes a single instruction, returning whether a frame was finished.
+    /// Does nothing while the device is stopped, see [`Device::stop_reason`].
     pub fn step(&mut self) -> bool {
-        #[cfg(feature = "dump-log")]
-        let Device { cpu, mmu, log, .. } = self;
+        if self.stop_reason.is_some() {
+            return false;
+        }
+
+        let hooked = self.hooked_instruction();
+        if let Some(info) = &hooked {
+            for hook in self.pre_instruction_hooks.iter_mut() {
+                hook(info, &self.cpu);
+            }
+        }
+
+        let frame_count = self.frame_count();
+        let pc = self.cpu.pc;
+        let Device {
+            cpu,
+            mmu,
+            tracer,
+            profile,
+            ..
+        } = self;
 
-        #[cfg(feature = "dump-log")]
-        writeln!(log, "A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: {:02X}:{:04X} ({:02X} {:02X} {:02X} {:02X})",
-            cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, 0, cpu.pc, mmu.read(cpu.pc).unwrap(), mmu.read(cpu.pc + 1).unwrap(), mmu.read(cpu.pc + 2).unwrap(), mmu.read(cpu.pc + 3).unwrap())
-            .unwrap();
+        if !cpu.halted && !cpu.locked && !mmu.in_boot_rom(cpu.pc) {
+            if let Some(tracer) = tracer {
+                tracer.trace(cpu, mmu, frame_count);
+            }
 
-        #[cfg(not(feature = "dump-log"))]
-        let Device { cpu, mmu, .. } = self;
+            if let Some(profile) = profile {
+                profile.record(match cpu.pc {
+                    0x0000..=0x7fff => Location::Rom {
+                        bank: mmu.cart.rom_bank(cpu.pc),
+                        address: cpu.pc,
+                    },
+                    address => Location::Ram(address),
+                });
+            }
+        }
+
+        let frame = match mmu.step(cpu) {
+            Ok(frame) => frame,
+            Err(err) => {
+                self.stop_reason = Some(StopReason::Error(err));
+                return false;
+            }
+        };
+
+        if let Some(address) = self.mmu.take_uninitialized_read() {
+            self.lints.report(Lint::UninitializedRead { pc, address });
+            if self.uninitialized_reads == UninitializedReads::Trap {
+                self.stop_reason = Some(StopReason::UninitializedRead { pc, address });
+            }
+        }
+
+        if let Some(hit) = self.mmu.take_watch_hit() {
+            self.stop_reason = Some(StopReason::Watchpoint {
+                pc,
+                address: hit.address,
+                op: hit.op,
+                old: hit.old,
+                new: hit.new,
+            });
+        }
+
+        if !self.counters.is_empty() {
+            self.counters.update(&self.mmu);
+        }
+
+        for (address, value) in self.mmu.take_rom_writes() {
+            self.lints.report(Lint::RomWrite { pc, address, value });
+        }
+
+        if let Some(mut info) = hooked {
+            info.cycles = self.cycles();
+            for hook in self.post_instruction_hooks.iter_mut() {
+                hook(&info, &self.cpu);
+            }
+        }
+
+        if !self.hblank_hooks.is_empty() || !self.scanline_hooks.is_empty() {
+            self.run_hblank_hooks();
+        }
+
+        if frame {
+            for hook in self.vblank_hooks.iter_mut() {
+                hook(&self.mmu, &mut self.overlay);
+            }
+
+            if let Some(input) = self.input.as_mut() {
+                input.advance(&mut self.mmu);
+            }
+
+            for (address, value) in self.frozen.iter() {
+                let _ = self.mmu.poke(*address, *value);
+            }
 
-        if mmu.step(cpu) {
+            self.counters.finish_frame();
+
+            if let Some(journal) = self.journal.as_mut() {
+                journal.finish_frame(&self.mmu.cart);
+            }
+
+            let start = self.framebuffer_time.map(|_| Instant::now());
             self.update_framebuffers();
-            true
-        } else {
-            false
+            if let (Some(time), Some(start)) = (self.framebuffer_time.as_mut(), start) {
+                *time += start.elapsed();
+            }
+
+            if !self.frame_sinks.is_empty() {
+                self.send_frame();
+            }
+
+            if !self.audio_sinks.is_empty() {
+                self.send_samples();
+            }
         }
+
+        frame
     }
 
-    pub fn skip(&mut self) {
-        let Device { cpu, mmu, .. } = self;
-        cpu.fetch_instruction(mmu)
-            .context("failed to fetch next instruction")
-            .unwrap();
+    /// Executes instructions as they are taken from the returned iterator,
+    /// which yields each one along with the CPU state before it. Time spent
+    /// halted passes while waiting for the next instruction, and the iterator
+    /// ends when the CPU locks up, the device stops, or a halt isn't ended by
+    /// an interrupt within a second.
+    pub fn trace(&mut self) -> Trace<'_> {
+        Trace::new(self)
     }
 
-    pub fn cpu(&self) -> &Cpu {
-        &self.cpu
+    /// Starts writing a trace of every executed instruction, replacing the
+    /// current tracer.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
     }
 
-    pub fn cpu_mut(&mut self) -> &mut Cpu {
-        &mut self.cpu
+    /// Stops tracing, returning the tracer to flush it or check for errors.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
     }
 
-    pub fn gpu(&self) -> &Gpu {
-        &self.mmu.gpu
+    /// Suspicious things the game did so far, which are almost always bugs.
+    pub fn lints(&self) -> &[Lint] {
+        self.lints.all()
     }
 
-    pub fn cart(&self) -> &Cartridge {
-        &self.mmu.cart
+    pub fn clear_lints(&mut self) {
+        self.lints.clear();
     }
 
-    pub fn disassemble(&mut self, max: u16) -> BTreeMap<u16, String> {
-        let Device { cpu, mmu, .. } = self;
-        cpu.disassemble(mmu, max)
+    /// Changes what happens when the game reads RAM it never wrote. Every
+    /// byte is only reported the first time it is read.
+    pub fn set_uninitialized_reads(&mut self, policy: UninitializedReads) {
+        self.uninitialized_reads = policy;
+        self.mmu.check_uninitialized = policy != UninitializedReads::Ignore;
     }
 
-    pub fn tile_framebuffer(&self) -> &[u8] {
-        self.tile_framebuffer.as_ref()
+    /// Starts writing changes to the cartridge's battery-backed RAM through to
+    /// the journal about once a second, replacing the current journal.
+    pub fn set_save_journal(&mut self, journal: SaveJournal) {
+        self.journal = Some(journal);
     }
 
-    pub fn display_framebuffer(&self) -> &[u8] {
-        self.display_framebuffer.as_ref()
+    /// Stops journaling, returning the journal to compact it into a save or
+    /// check for errors.
+    pub fn take_save_journal(&mut self) -> Option<SaveJournal> {
+        self.journal.take()
     }
 
-    pub fn press(&mut self, buttons: &[JoypadButton]) {
-        self.mmu.press(buttons);
+    /// Starts or stops counting how often every instruction is executed,
+    /// outside of the boot ROM. Stopping throws away the counts.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.profile = None;
+        } else if self.profile.is_none() {
+            self.profile = Some(ExecutionProfile::new(self.mmu.cart.rom_banks()));
+        }
     }
 
-    pub fn release(&mut self, buttons: &[JoypadButton]) {
-        self.mmu.release(buttons);
+    pub fn profile(&self) -> Option<&ExecutionProfile> {
+        self.profile.as_ref()
     }
 
-    fn update_framebuffers(&mut self) {
-        for tile_x in 0..16 {
-            for tile_y in 0..24 {
-                let tile = self.gpu().tiles[tile_x + tile_y * 16];
+    pub fn profile_mut(&mut self) -> Option<&mut ExecutionProfile> {
+        self.profile.as_mut()
+    }
 
-                for x in 0..8 {
-                    for y in 0..8 {
-                        let color =
-                            PALETTE[self.gpu().bg_palette[tile.get(x, y) as usize] as usize];
+    /// Starts or stops measuring the time spent in the subsystems next to the
+    /// CPU. Stopping throws away the measurements.
+    pub fn set_timing(&mut self, enabled: bool) {
+        if !enabled {
+            self.mmu.ppu_time = None;
+            self.framebuffer_time = None;
+        } else if self.framebuffer_time.is_none() {
+            self.mmu.ppu_time = Some(Duration::default());
+            self.framebuffer_time = Some(Duration::default());
+        }
+    }
 
-                        let index = 3 * (8 * tile_x + x + 16 * 8 * 8 * tile_y + 16 * 8 * y);
-                        for (i, c) in color.iter().enumerate() {
-                            self.tile_framebuffer[index + i] = *c;
-                        }
-                    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Whether the game runs in CGB mode, see [`Device::with_model`].
+    pub fn cgb_mode(&self) -> bool {
+        self.mmu.gpu.cgb_mode()
+    }
+
+    /// Turns the locking of VRAM and OAM while the GPU uses them on or off.
+    /// With it off, the CPU and debugger always see their contents.
+    pub fn set_access_restrictions(&mut self, enabled: bool) {
+        self.mmu.access_restrictions = enabled;
+    }
+
+    /// Turns the DMG's corruption of OAM on or off, which happens when the
+    /// CPU increments or decrements a pointer into OAM while the GPU searches
+    /// it. Some games and test ROMs depend on it. It is off by default, and
+    /// the CGB doesn't have it.
+    pub fn set_oam_corruption(&mut self, enabled: bool) {
+        self.mmu.oam_corruption = enabled && !self.model.is_cgb();
+    }
+
+    pub fn subsystem_times(&self) -> Option<SubsystemTimes> {
+        Some(SubsystemTimes {
+            ppu: self.mmu.ppu_time?,
+            framebuffer: self.framebuffer_time?,
+        })
+    }
+
+    /// Counts how often a memory event happens per frame, see [`Counters`].
+    /// Returns the counter's index in [`Device::frame_counters`].
+    pub fn add_counter(&mut self, address: u16, trigger: Trigger) -> usize {
+        self.counters.add(address, trigger)
+    }
+
+    /// How often every counter triggered during the last frame.
+    pub fn frame_counters(&self) -> &[u32] {
+        self.counters.last_frame()
+    }
+
+    pub fn clear_counters(&mut self) {
+        self.counters.clear();
+    }
+
+    /// Steps over calls and restarts: executes a single instruction and, if it
+    /// called a routine, returns the target to run towards with
+    /// [`Device::step_towards`] until the routine returned.
+    pub fn step_over(&mut self) -> Option<ReturnTarget> {
+        match self.peek_instruction() {
+            Ok((Instruction::Call(_), _))
+            | Ok((Instruction::CallIf(_, _, _), _))
+            | Ok((Instruction::Rst(_), _)) => {}
+            _ => {
+                self.step();
+                return None;
+            }
+        }
+
+        // The call itself brings the depth to 0 if it is taken.
+        let mut target = ReturnTarget { depth: -1 };
+        self.step_towards(&mut target);
+
+        Some(target).filter(|target| !target.is_reached())
+    }
+
+    /// The target to run towards with [`Device::step_towards`] to finish the
+    /// current routine, which is reached once it executes its return.
+    pub fn step_out(&self) -> ReturnTarget {
+        ReturnTarget { depth: 0 }
+    }
+
+    /// Executes a single instruction like [`Device::step`], keeping track of
+    /// the calls and returns on the way to the target.
+    pub fn step_towards(&mut self, target: &mut ReturnTarget) -> bool {
+        let instruction = self
+            .peek_instruction()
+            .ok()
+            .map(|(instruction, _)| instruction);
+        let (sp, halted) = (self.cpu.sp, self.cpu.halted);
+
+        let frame = self.step();
+
+        // Undo the push of a dispatched interrupt to see what the instruction
+        // itself did to the stack.
+        let interrupted = self.mmu.interrupt_dispatched();
+        let sp_after = if interrupted {
+            self.cpu.sp.wrapping_add(2)
+        } else {
+            self.cpu.sp
+        };
+
+        if !halted {
+            match instruction {
+                Some(Instruction::Call(_))
+                | Some(Instruction::CallIf(_, _, _))
+                | Some(Instruction::Rst(_))
+                    if sp_after == sp.wrapping_sub(2) =>
+                {
+                    target.depth += 1
+                }
+                Some(Instruction::Return)
+                | Some(Instruction::ReturnIf(_, _))
+                | Some(Instruction::ReturnInterrupt)
+                    if sp_after == sp.wrapping_add(2) =>
+                {
+                    target.depth -= 1
                 }
+                _ => {}
+            }
+        }
+
+        if interrupted {
+            target.depth += 1;
+        }
+
+        frame
+    }
+
+    /// Registers a callback that is invoked right before every instruction.
+    pub fn add_pre_instruction_hook(&mut self, hook: InstructionHook) {
+        self.pre_instruction_hooks.push(hook);
+    }
+
+    /// Registers a callback that is invoked right after every instruction.
+    pub fn add_post_instruction_hook(&mut self, hook: InstructionHook) {
+        self.post_instruction_hooks.push(hook);
+    }
+
+    pub fn clear_instruction_hooks(&mut self) {
+        self.pre_instruction_hooks.clear();
+        self.post_instruction_hooks.clear();
+    }
+
+    /// Registers a callback that is invoked once the GPU finished drawing a
+    /// line, while the LCD is on.
+    pub fn add_hblank_hook(&mut self, hook: HBlankHook) {
+        self.hblank_hooks.push(hook);
+    }
+
+    /// Registers a callback that is invoked once the GPU finished a frame.
+    pub fn add_vblank_hook(&mut self, hook: VBlankHook) {
+        self.vblank_hooks.push(hook);
+    }
+
+    pub fn clear_overlay_hooks(&mut self) {
+        self.hblank_hooks.clear();
+        self.vblank_hooks.clear();
+    }
+
+    /// Registers a callback that is handed every line once the GPU finished
+    /// drawing it, while the LCD is on, see [`ScanlineHook`]. The pixels are
+    /// in the colors of the display framebuffer, without the overlay.
+    pub fn add_scanline_hook(&mut self, hook: ScanlineHook) {
+        self.scanline_hooks.push(hook);
+    }
+
+    pub fn clear_scanline_hooks(&mut self) {
+        self.scanline_hooks.clear();
+    }
+
+    /// Hands every finished frame to a sink, see [`FrameSink`].
+    pub fn add_frame_sink(&mut self, sink: Box<dyn FrameSink>) {
+        self.frame_sinks.push(sink);
+    }
+
+    /// Removes the frame sinks after finishing them, returning the first
+    /// error one of them ran into.
+    pub fn finish_frame_sinks(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for mut sink in self.frame_sinks.drain(..) {
+            let finished = sink.finish();
+            if result.is_ok() {
+                result = finished;
+            }
+        }
+
+        result
+    }
+
+    /// Hands the sound to a sink once every frame, see [`AudioSink`]. The APU
+    /// only mixes samples while there are audio sinks.
+    pub fn add_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio_sinks.push(sink);
+        self.mmu.apu.sampling = true;
+    }
+
+    /// Removes the audio sinks after handing them the last samples and
+    /// finishing them, returning the first error one of them ran into.
+    pub fn finish_audio_sinks(&mut self) -> io::Result<()> {
+        self.send_samples();
+        self.mmu.apu.sampling = false;
+
+        let mut result = Ok(());
+        for mut sink in self.audio_sinks.drain(..) {
+            let finished = sink.finish();
+            if result.is_ok() {
+                result = finished;
             }
         }
 
+        result
+    }
+
+    fn send_samples(&mut self) {
+        let samples = self.mmu.apu.take_samples();
+        for sink in self.audio_sinks.iter_mut() {
+            sink.samples(&samples);
+        }
+    }
+
+    fn send_frame(&mut self) {
+        let frame_no = self.frame_count() as u64;
         let Device {
-            mmu,
             display_framebuffer,
+            overlay,
+            frame_sinks,
             ..
         } = self;
 
-        let framebuffer = mmu.gpu.framebuffer.as_ref();
-        for i in 0..framebuffer.len() {
-            for c in 0..3 {
-                display_framebuffer[i * 3 + c] = PALETTE[framebuffer[i] as usize][c];
+        let rgb = overlay.composited(display_framebuffer.as_ref());
+        for sink in frame_sinks.iter_mut() {
+            sink.frame(&rgb, frame_no);
+        }
+    }
+
+    /// Calls the HBlank and scanline hooks if the GPU entered HBlank on a new
+    /// line. An instruction is much shorter than HBlank, so no line is missed.
+    fn run_hblank_hooks(&mut self) {
+        let gpu = &self.mmu.gpu;
+        let line = gpu.scanline();
+        let in_hblank = gpu.lcd_control.contains(LcdControl::LCD_ENABLE)
+            && matches!(gpu.mode(), GpuMode::HBlank);
+
+        if !in_hblank || self.hblank_line == Some(line) {
+            return;
+        }
+
+        self.hblank_line = Some(line);
+        for hook in self.hblank_hooks.iter_mut() {
+            hook(line, &self.mmu, &mut self.overlay);
+        }
+
+        if !self.scanline_hooks.is_empty() {
+            let mut pixels = [0; 3 * 160];
+            let start = line as usize * 160;
+            map_screen(
+                &self.mmu.gpu,
+                &self.palettes,
+                &mut Rgb888,
+                start,
+                &mut pixels,
+            );
+
+            for hook in self.scanline_hooks.iter_mut() {
+                hook(line, &pixels);
+            }
+        }
+    }
+
+    /// The instruction that is about to be executed, if there are any hooks
+    /// interested in it.
+    fn hooked_instruction(&mut self) -> Option<InstructionInfo> {
+        let hooked =
+            !self.pre_instruction_hooks.is_empty() || !self.post_instruction_hooks.is_empty();
+
+        if !hooked || self.cpu.halted || self.cpu.locked {
+            return None;
+        }
+
+        let pc = self.cpu.pc;
+        let (instruction, _) = self.peek_instruction().ok()?;
+
+        Some(InstructionInfo {
+            instruction,
+            pc,
+            cycles: self.cycles(),
+        })
+    }
+
+    /// Why the device stopped executing, if it did. Execution continues once
+    /// the reason is dealt with, by skipping the instruction or a reset.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Runs the boot ROM until it hands control to the cartridge, and checks
+    /// that it does so at the entry point within `max_frames` frames. Returns
+    /// the amount of frames it took.
+    pub fn check_boot(&mut self, max_frames: usize) -> Result<usize, BootError> {
+        let mut frames = 0;
+
+        while self.mmu.use_bios {
+            // Both checks of the DMG boot ROM end in a `jr nz` to itself, so
+            // we can detect the lock before it is taken.
+            if !self.cpu.get_flag(CpuFlag::Zero) && self.mmu.boot_rom().len() == DMG_BIOS.len() {
+                match self.cpu.pc {
+                    BOOT_LOGO_LOCK => return Err(BootError::LogoMismatch),
+                    BOOT_CHECKSUM_LOCK => return Err(BootError::HeaderChecksum),
+                    _ => {}
+                }
+            }
+
+            if frames >= max_frames {
+                return Err(BootError::Timeout {
+                    frames,
+                    pc: self.cpu.pc,
+                });
+            }
+
+            if self.step() {
+                frames += 1;
             }
+
+            if let Some(reason) = self.stop_reason {
+                return Err(BootError::Stopped {
+                    reason,
+                    pc: self.cpu.pc,
+                });
+            }
+        }
+
+        match self.cpu.pc {
+            0x0100 => Ok(frames),
+            pc => Err(BootError::WrongEntryPoint { pc }),
         }
     }
+
+    /// Continues after a trap that stopped the device once an instruction
+    /// finished, which unlike an error doesn't need the instruction skipped.
+    pub fn resume(&mut self) {
+        if let Some(StopReason::UninitializedRead { .. }) | Some(StopReason::Watchpoint { .. }) =
+            self.stop_reason
+        {
+            self.stop_reason = None;
+        }
+    }
+
+    /// Moves PC past the next instruction without executing it and clears
+    /// the stop reason. An illegal opcode, which locks up the CPU, can't be
+    /// skipped, so this leaves everything as is and returns the error.
+    pub fn skip(&mut self) -> Result<(), InstructionError> {
+        let Device { cpu, mmu, .. } = self;
+        let pc = cpu.pc;
+        if let Err(err) = cpu.fetch_instruction(mmu) {
+            cpu.pc = pc;
+            return Err(err);
+        }
+
+        self.stop_reason = None;
+        Ok(())
+    }
+
+    pub fn peek_instruction(&mut self) -> Result<(Instruction, u16), InstructionError> {
+        let Device { cpu, mmu, .. } = self;
+        let (pc, halt_bug) = (cpu.pc, cpu.halt_bug);
+        let instruction = cpu.fetch_instruction(mmu);
+        let next_pc = cpu.pc;
+        cpu.pc = pc;
+        cpu.halt_bug = halt_bug;
+
+        Ok((instruction?, next_pc))
+    }
+
+    /// Whether the CPU hung on an illegal opcode, which only a reset recovers
+    /// from.
+    pub fn is_locked(&self) -> bool {
+        self.cpu.locked
+    }
+
+    /// Whether the CPU is halted, waiting for an interrupt.
+    pub fn is_halted(&self) -> bool {
+        self.cpu.halted
+    }
+
+    /// The interrupt master enable flag, which decides whether pending
+    /// interrupts are dispatched or only wake the CPU from a halt.
+    pub fn interrupt_master_enable(&self) -> bool {
+        self.cpu.ime
+    }
+
+    /// The interrupts requested in IF.
+    pub fn requested_interrupts(&self) -> Interrupts {
+        self.mmu.requested_interrupts()
+    }
+
+    /// The interrupts enabled in IE.
+    pub fn enabled_interrupts(&self) -> Interrupts {
+        self.mmu.enabled_interrupts()
+    }
+
+    /// The interrupts that are both requested and enabled, which are
+    /// dispatched when IME is set.
+    pub fn pending_interrupts(&self) -> Interrupts {
+        self.mmu.requested_interrupts() & self.mmu.enabled_interrupts()
+    }
+
+    /// Requests interrupts as if the hardware raised them. They are handled
+    /// after the next instruction, if enabled.
+    pub fn request_interrupt(&mut self, interrupts: Interrupts) {
+        self.mmu.request_interrupt(interrupts);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.mmu.gpu
+    }
+
+    /// The GPU, to change things like [`Gpu::layer_toggles`] that only affect
+    /// what is drawn.
+    pub fn gpu_mut(&mut self) -> &mut Gpu {
+        &mut self.mmu.gpu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.mmu.apu
+    }
+
+    pub fn serial(&self) -> &Serial {
+        &self.mmu.serial
+    }
+
+    /// Starts recording what the game sends over the serial port, see
+    /// [`Serial::capture_output`].
+    pub fn capture_serial_output(&mut self) {
+        self.mmu.serial.capture_output();
+    }
+
+    pub fn serial_output(&self) -> &[u8] {
+        self.mmu.serial.output()
+    }
+
+    /// Runs until the captured serial output satisfies `done`, checking after
+    /// every frame. Gives up after `max_frames` frames or when the device
+    /// stops, and returns whether `done` was satisfied.
+    pub fn run_until_serial_output<F: Fn(&[u8]) -> bool>(
+        &mut self,
+        max_frames: usize,
+        done: F,
+    ) -> bool {
+        self.capture_serial_output();
+
+        for _ in 0..max_frames {
+            if done(self.serial_output()) {
+                return true;
+            }
+
+            if self.stop_reason.is_some() {
+                return false;
+            }
+
+            self.step_frame();
+        }
+
+        done(self.serial_output())
+    }
+
+    pub fn cart(&self) -> &Cartridge {
+        &self.mmu.cart
+    }
+
+    /// Attaches a component that observes, and can intercept, all bus accesses
+    /// within the given range.
+    pub fn attach_snooper(&mut self, range: RangeInclusive<u16>, snooper: Box<dyn BusSnooper>) {
+        self.mmu.attach_snooper(range, snooper);
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        self.mmu.watchpoints()
+    }
+
+    /// The watchpoints that stop the device right after an instruction
+    /// accesses memory they cover, see [`StopReason::Watchpoint`].
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        self.mmu.watchpoints_mut()
+    }
+
+    pub fn read(&self, address: u16) -> Result<u8, MemoryError> {
+        self.mmu.read(address)
+    }
+
+    /// Keeps the byte at an address at a value, like a cheat device does, by
+    /// writing it at the start of every VBlank.
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.frozen.insert(address, value);
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.frozen.remove(&address);
+    }
+
+    /// The frozen addresses and the values they are kept at.
+    pub fn frozen(&self) -> &BTreeMap<u16, u8> {
+        &self.frozen
+    }
+
+    pub fn disassemble(&mut self, max: u16) -> BTreeMap<u16, String> {
+        let Device { cpu, mmu, .. } = self;
+        cpu.disassemble(mmu, max)
+    }
+
+    pub fn tile_framebuffer(&self) -> &[u8] {
+        self.tile_framebuffer.as_ref()
+    }
+
+    pub fn display_framebuffer(&self) -> &[u8] {
+        self.display_framebuffer.as_ref()
+    }
+
+    /// The display framebuffer with 4 bytes per pixel, with an opaque alpha
+    /// channel. `out` has to be `4 * 160 * 144` bytes long.
+    pub fn display_framebuffer_rgba(&self, out: &mut [u8]) {
+        map_screen(&self.mmu.gpu, &self.palettes, &mut Rgba8888, 0, out);
+    }
+
+    /// The screen as DMG shades from 0 for the lightest to 3, before any
+    /// palette is applied. In CGB mode, these are the color indices within
+    /// the color palettes instead.
+    pub fn indexed_framebuffer(&self) -> &[u8] {
+        self.mmu.gpu.framebuffer.as_ref()
+    }
+
+    /// Converts every frame with `mapper` as well, into the
+    /// [`Device::mapped_framebuffer`].
+    pub fn set_color_mapper(&mut self, mapper: Box<dyn ColorMapper>) {
+        self.mapped_framebuffer = vec![0; mapper.bytes_per_pixel() * 160 * 144];
+        self.color_mapper = Some(mapper);
+        self.update_framebuffers();
+    }
+
+    pub fn clear_color_mapper(&mut self) {
+        self.color_mapper = None;
+        self.mapped_framebuffer = Vec::new();
+    }
+
+    /// The screen in the format of the color mapper, if one is set.
+    pub fn mapped_framebuffer(&self) -> Option<&[u8]> {
+        self.color_mapper
+            .as_ref()
+            .map(|_| self.mapped_framebuffer.as_ref())
+    }
+
+    /// The layer drawn over the game image, see [`Overlay`].
+    pub fn overlay(&self) -> &Overlay {
+        &self.overlay
+    }
+
+    pub fn overlay_mut(&mut self) -> &mut Overlay {
+        &mut self.overlay
+    }
+
+    /// The display framebuffer with the overlay blended over it, as the
+    /// frontends show it.
+    pub fn overlaid_framebuffer(&self) -> Cow<'_, [u8]> {
+        self.overlay.composited(self.display_framebuffer.as_ref())
+    }
+
+    /// Draws a whole tile map as 256x256 RGB pixels in the background palette,
+    /// like the background would show it. `out` has to be `3 * 256 * 256`
+    /// bytes long.
+    pub fn render_tilemap(&self, tilemap: Tilemap, out: &mut [u8]) {
+        let mut shades = vec![0; 256 * 256];
+        self.mmu.gpu.render_tilemap(tilemap, &mut shades);
+
+        for (pixel, shade) in out.chunks_exact_mut(3).zip(shades.iter()) {
+            pixel.copy_from_slice(&self.palette().colors[*shade as usize]);
+        }
+    }
+
+    /// The 40 sprites in OAM, in order, including the ones off screen.
+    pub fn sprites(&self) -> [SpriteEntry; 40] {
+        self.mmu.gpu.sprites()
+    }
+
+    /// Draws a sprite as RGBA pixels in its palette, where color 0 is fully
+    /// transparent. It is 8 pixels wide and 8 or 16 high depending on the
+    /// sprite size in LCDC, see [`Gpu::sprite_height`], so `out` has to be
+    /// `4 * 8 * height` bytes long.
+    pub fn render_sprite(&self, index: usize, out: &mut [u8]) {
+        let gpu = &self.mmu.gpu;
+        let sprite = gpu.sprites()[index];
+        let mut indices = vec![0; 8 * gpu.sprite_height()];
+        gpu.render_sprite(index, &mut indices);
+
+        for (pixel, color) in out.chunks_exact_mut(4).zip(indices.iter()) {
+            let color = *color as usize;
+            if color == 0 {
+                pixel.copy_from_slice(&[0; 4]);
+            } else if gpu.cgb_mode() {
+                let color = gpu.obj_colors.color(sprite.cgb_palette as usize, color);
+                Rgba8888.map_color(color, pixel);
+            } else {
+                let layer = sprite.layer();
+                let shade = gpu.obj_palette[layer as usize - 1][color];
+                Rgba8888.map_shade(shade, &self.palettes[layer as usize], pixel);
+            }
+        }
+    }
+
+    /// Writes the screen to `out` in a compact form for machine learning,
+    /// straight from the shades the GPU draws rather than the colored
+    /// framebuffer. `out` has to be [`Observation::size`] bytes long.
+    pub fn observe(&self, observation: Observation, out: &mut [u8]) {
+        observation.write(self.mmu.gpu.framebuffer.as_ref(), out);
+    }
+
+    pub fn observation(&self, observation: Observation) -> Vec<u8> {
+        let mut out = vec![0; observation.size()];
+        self.observe(observation, &mut out);
+        out
+    }
+
+    /// The amount of T-cycles that passed since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    /// The amount of frames finished since power on.
+    pub fn frame_count(&self) -> usize {
+        self.mmu.gpu.frame_count()
+    }
+
+    /// Marks the last finished frame as shown on screen. Frames finished
+    /// since the previously presented one are counted as dropped.
+    pub fn present_frame(&mut self) {
+        let frames = self.frame_count();
+        if frames > self.presented_frame + 1 {
+            self.dropped_frames += frames - self.presented_frame - 1;
+        }
+
+        self.presented_frame = frames;
+    }
+
+    /// The amount of frames that were finished but never presented.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped_frames
+    }
+
+    /// The palette of the background, which the tile views are also drawn
+    /// with.
+    pub fn palette(&self) -> Palette {
+        self.layer_palette(Layer::Background)
+    }
+
+    /// Changes the colors the framebuffers are drawn with for every layer,
+    /// redrawing them right away.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palettes = [palette; 3];
+        self.update_framebuffers();
+    }
+
+    pub fn layer_palette(&self, layer: Layer) -> Palette {
+        self.palettes[layer as usize]
+    }
+
+    /// Changes the colors of a single layer, such as to tell sprites apart
+    /// from the background like the CGB does for DMG games. Has no effect in
+    /// CGB mode, where games pick their own colors.
+    pub fn set_layer_palette(&mut self, layer: Layer, palette: Palette) {
+        self.palettes[layer as usize] = palette;
+        self.update_framebuffers();
+    }
+
+    /// Plays back the given inputs, starting at the next frame.
+    pub fn set_input_sequence(&mut self, input: InputSequence) {
+        self.input = Some(input);
+    }
+
+    /// The seed of [`Device::frame_rng`], 0 unless set.
+    pub fn rng_seed(&self) -> u64 {
+        self.rng_seed
+    }
+
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng_seed = seed;
+    }
+
+    /// Random numbers for scripts and tools to generate input with, which
+    /// are the same for a frame every run with the same seed. The emulated
+    /// machine never sees them, so they don't affect its determinism.
+    pub fn frame_rng(&self) -> Rng {
+        Rng::for_frame(self.rng_seed, self.frame_count())
+    }
+
+    /// Presses buttons on the first controller, the only one the game reads
+    /// for now.
+    pub fn press(&mut self, buttons: &[JoypadButton]) {
+        self.mmu.press(buttons);
+    }
+
+    pub fn release(&mut self, buttons: &[JoypadButton]) {
+        self.mmu.release(buttons);
+    }
+
+    /// Presses buttons on one of the [`PLAYERS`](crate::memory::mmu::PLAYERS)
+    /// controllers, counting from 0. Only the first one reaches the joypad
+    /// register, so the others are kept for the Super Game Boy's multiplayer
+    /// mode and netplay to take later. Players past the last controller are
+    /// ignored.
+    pub fn press_player(&mut self, player: usize, buttons: &[JoypadButton]) {
+        self.mmu.press_player(player, buttons);
+    }
+
+    pub fn release_player(&mut self, player: usize, buttons: &[JoypadButton]) {
+        self.mmu.release_player(player, buttons);
+    }
+
+    fn update_framebuffers(&mut self) {
+        for tile_x in 0..16 {
+            for tile_y in 0..24 {
+                let tile = self.gpu().tiles[tile_x + tile_y * 16];
+
+                for x in 0..8 {
+                    for y in 0..8 {
+                        let color = self.palette().colors
+                            [self.gpu().bg_palette[tile.get(x, y) as usize] as usize];
+
+                        let index = 3 * (8 * tile_x + x + 16 * 8 * 8 * tile_y + 16 * 8 * y);
+                        for (i, c) in color.iter().enumerate() {
+                            self.tile_framebuffer[index + i] = *c;
+                        }
+                    }
+                }
+            }
+        }
+
+        let Device {
+            mmu,
+            display_framebuffer,
+            color_mapper,
+            mapped_framebuffer,
+            palettes,
+            ..
+        } = self;
+
+        map_screen(
+            &mmu.gpu,
+            palettes,
+            &mut Rgb888,
+            0,
+            display_framebuffer.as_mut(),
+        );
+        if mmu.gpu.layer_toggles.highlight_window {
+            highlight_window(&mmu.gpu, display_framebuffer.as_mut());
+        }
+        if let Some(mapper) = color_mapper {
+            map_screen(&mmu.gpu, palettes, mapper.as_mut(), 0, mapped_framebuffer);
+        }
+    }
+}
+
+/// Converts the screen the GPU drew with `mapper`, showing every DMG shade in
+/// the palette of the layer that drew it. `pixels` is filled from the pixel
+/// at index `start` on, so it can take part of the screen.
+fn map_screen<M: ColorMapper + ?Sized>(
+    gpu: &Gpu,
+    palettes: &[Palette; 3],
+    mapper: &mut M,
+    start: usize,
+    pixels: &mut [u8],
+) {
+    let size = mapper.bytes_per_pixel();
+
+    if gpu.cgb_mode() {
+        for (pixel, color) in pixels
+            .chunks_exact_mut(size)
+            .zip(gpu.color_framebuffer[start..].iter())
+        {
+            mapper.map_color(*color, pixel);
+        }
+
+        return;
+    }
+
+    for (i, pixel) in (start..).zip(pixels.chunks_exact_mut(size)) {
+        let palette = &palettes[gpu.layers[i] as usize];
+        mapper.map_shade(gpu.framebuffer[i], palette, pixel);
+    }
+}
+
+/// Tints the area the window covered red.
+fn highlight_window(gpu: &Gpu, rgb: &mut [u8]) {
+    for (line, start) in gpu.window_area() {
+        let row = 3 * 160 * line as usize;
+        for pixel in rgb[row + 3 * start as usize..row + 3 * 160].chunks_exact_mut(3) {
+            pixel[0] = (pixel[0] / 2) + 128;
+            pixel[1] /= 2;
+            pixel[2] /= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{
+        BootError, BootRomError, Device, DeviceBuilder, InstructionInfo, ReturnTarget, StopReason,
+        UninitializedReads,
+    };
+    use crate::{
+        apu::SAMPLE_RATE,
+        bios::{CGB_BIOS, DMG_BIOS},
+        cartridge::Cartridge,
+        counter::Trigger,
+        cpu::{Cpu, CpuError, Interrupts},
+        gpu::Layer,
+        instruction::Instruction,
+        lint::Lint,
+        memory::{
+            watch::{WatchKind, Watchpoint},
+            Memory, MemoryOperation,
+        },
+        model::{io_addresses, Model},
+        palette::{rgb888_to_rgb565, Palette, Rgb565, GRAYSCALE, GREEN},
+        profile::Location,
+        sink::{AudioRing, FrameSink},
+    };
+
+    fn rom(logo: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(logo);
+        rom[0x14d] = rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+        rom
+    }
+
+    /// A device about to run the boot ROM, with a cartridge that passes its
+    /// checks.
+    fn device() -> Device {
+        Device::new(Cartridge::from_bytes(program_rom(&[])))
+    }
+
+    /// A ROM that passes the boot ROM's checks, with the program at 0x0100.
+    fn program_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = rom(&DMG_BIOS[0xa8..0xd8]);
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    /// Turns the LCD on, as the boot ROM leaves it, and loops forever.
+    const LCD_ON: [u8; 6] = [
+        0x3e, 0x91, // ld a, 0x91
+        0xe0, 0x40, // ldh (LCDC), a
+        0x18, 0xfe, // jr -2
+    ];
+
+    /// A device about to run the program at 0x0100, past the boot ROM.
+    fn device_with_program(program: &[u8]) -> Device {
+        let mut device = Device::new(Cartridge::from_bytes(program_rom(program)));
+        device.cpu_mut().pc = 0x0100;
+        device
+    }
+
+    #[test]
+    fn boot_check() {
+        let mut device = device();
+        device.check_boot(600).unwrap();
+        assert_eq!(device.cpu().pc, 0x0100);
+    }
+
+    #[test]
+    fn boot_check_bad_logo() {
+        let mut device = Device::new(Cartridge::from_bytes(rom(&[0xff; 0x30])));
+        assert!(matches!(
+            device.check_boot(600),
+            Err(BootError::LogoMismatch)
+        ));
+    }
+
+    #[test]
+    fn boot_check_bad_checksum() {
+        let mut rom = program_rom(&[]);
+        rom[0x14d] ^= 0xff;
+
+        let mut device = Device::new(Cartridge::from_bytes(rom));
+        assert!(matches!(
+            device.check_boot(600),
+            Err(BootError::HeaderChecksum)
+        ));
+    }
+
+    #[test]
+    fn stop_on_error() {
+        let mut device = device_with_program(&[0x10]);
+        device.step_frame();
+
+        assert!(matches!(
+            device.stop_reason(),
+            Some(StopReason::Error(CpuError::Unimplemented(
+                Instruction::Stop
+            )))
+        ));
+        assert_eq!(device.cpu().pc, 0x0100);
+
+        device.skip().unwrap();
+        assert!(device.stop_reason().is_none());
+        assert_eq!(device.cpu().pc, 0x0101);
+    }
+
+    #[test]
+    fn skip_illegal_opcode() {
+        let mut device = device_with_program(&[0xd3]);
+        device.step();
+        assert!(device.cpu().locked);
+
+        assert!(device.skip().is_err());
+        assert_eq!(device.cpu().pc, 0x0100);
+        assert!(device.cpu().locked);
+    }
+
+    #[test]
+    fn dropped_frames() {
+        let mut device = device_with_program(&LCD_ON);
+
+        device.step_frame();
+        device.present_frame();
+        device.present_frame();
+        assert_eq!(device.frame_count(), 1);
+        assert_eq!(device.dropped_frames(), 0);
+
+        for _ in 0..3 {
+            device.step_frame();
+        }
+        device.present_frame();
+        assert_eq!(device.frame_count(), 4);
+        assert_eq!(device.dropped_frames(), 2);
+    }
+
+    #[test]
+    fn cycles_per_frame() {
+        let mut device = device_with_program(&LCD_ON);
+        device.step_frame();
+
+        let start = device.cycles();
+        device.step_frame();
+        let frame = device.cycles() - start;
+
+        // A frame ends in the middle of an instruction, so allow for the longest one.
+        assert!((70224 - 24..=70224 + 24).contains(&frame), "{}", frame);
+    }
+
+    #[test]
+    fn inspection_takes_no_time() {
+        let mut device = device_with_program(&LCD_ON);
+        device.step();
+
+        let cycles = device.cycles();
+        let timer = device.read(0xff04).unwrap();
+        let line = device.read(0xff44).unwrap();
+
+        for _ in 0..1000 {
+            device.peek_instruction().unwrap();
+            device.disassemble(0x100);
+        }
+
+        assert_eq!(device.cycles(), cycles);
+        assert_eq!(device.read(0xff04).unwrap(), timer);
+        assert_eq!(device.read(0xff44).unwrap(), line);
+    }
+
+    #[test]
+    fn profiling() {
+        let mut device = device_with_program(&[0x00, 0x18, 0xfd]); // nop; jr -3
+        device.set_profiling(true);
+        for _ in 0..10 {
+            device.step();
+        }
+
+        let profile = device.profile().unwrap();
+        let location = |address| Location::Rom { bank: 0, address };
+        assert_eq!(profile.count(location(0x0100)), 5);
+        assert_eq!(profile.count(location(0x0101)), 5);
+        assert_eq!(profile.hot_spots(10).len(), 2);
+    }
+
+    #[test]
+    fn counters() {
+        let program = [
+            0x21, 0x00, 0xc0, // ld hl, 0xc000
+            0x34, // inc (hl)
+            0x18, 0xfd, // jr -3
+        ];
+
+        let mut device = device_with_program(&program);
+        let wrapped = device.add_counter(0xc000, Trigger::Reached(0));
+        let increased = device.add_counter(0xc000, Trigger::Increased);
+        device.step_frame();
+        device.step_frame();
+
+        // A frame fits about 17556 / 6 = 2926 loops, so the byte wraps around
+        // 11 or 12 times.
+        let counts = device.frame_counters();
+        assert!((11..=12).contains(&counts[wrapped]), "{:?}", counts);
+        assert!(counts[increased] > 2900, "{:?}", counts);
+    }
+
+    #[test]
+    fn trace() {
+        let program = [
+            0x3e, 0x12, // ld a, 0x12
+            0x21, 0x00, 0xc0, // ld hl, 0xc000
+            0x77, // ld (hl), a
+            0xdd, // illegal
+        ];
+
+        let mut device = device_with_program(&program);
+
+        let entries: Vec<_> = device
+            .trace()
+            .map(|entry| {
+                (
+                    entry.pc,
+                    entry.bytes,
+                    entry.instruction.to_string(),
+                    entry.cpu.a,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (0x0100, vec![0x3e, 0x12], "ld A, 0x12".to_owned(), 0x00),
+                (
+                    0x0102,
+                    vec![0x21, 0x00, 0xc0],
+                    "ld HL, 0xc000".to_owned(),
+                    0x12
+                ),
+                (0x0105, vec![0x77], "ld (HL), A".to_owned(), 0x12),
+            ]
+        );
+        assert_eq!(device.read(0xc000).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn trace_halt() {
+        // Nothing ends a halt with interrupts disabled in IE.
+        let mut device = device_with_program(&[0xf3, 0x76, 0x00]); // di; halt; nop
+        let pcs: Vec<_> = device.trace().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, vec![0x0100, 0x0101]);
+
+        // The timer is stopped, so its interrupt never fires either.
+        let program = [
+            0x3e, 0x04, // 0x0100: ld a, TIMER
+            0xe0, 0xff, // 0x0102: ldh (IE), a
+            0xf3, // 0x0104: di
+            0x76, // 0x0105: halt
+        ];
+        let mut device = device_with_program(&program);
+        assert_eq!(device.trace().count(), 4);
+        assert!(device.is_halted());
+
+        // VBlank does, which resumes after the halt without IME.
+        let program = [
+            0x3e, 0x91, // 0x0100: ld a, 0x91
+            0xe0, 0x40, // 0x0102: ldh (LCDC), a
+            0x3e, 0x01, // 0x0104: ld a, VBLANK
+            0xe0, 0xff, // 0x0106: ldh (IE), a
+            0xf3, // 0x0108: di
+            0x76, // 0x0109: halt
+            0x18, 0xfe, // 0x010a: jr -2
+        ];
+        let mut device = device_with_program(&program);
+        let pcs: Vec<_> = device.trace().take(7).map(|entry| entry.pc).collect();
+        assert_eq!(
+            pcs,
+            vec![0x0100, 0x0102, 0x0104, 0x0106, 0x0108, 0x0109, 0x010a]
+        );
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let program = [
+            0x3e, 0x02, // 0x0100: ld a, 2
+            0xcd, 0x06, 0x01, // 0x0102: call 0x0106
+            0x76, // 0x0105: halt
+            0xc5, // 0x0106: push bc
+            0xc1, // 0x0107: pop bc
+            0x3d, // 0x0108: dec a
+            0xc4, 0x06, 0x01, // 0x0109: call nz, 0x0106
+            0xc9, // 0x010c: ret
+        ];
+
+        let run = |device: &mut Device, mut target: ReturnTarget| {
+            while !target.is_reached() {
+                device.step_towards(&mut target);
+            }
+        };
+
+        let mut device = device_with_program(&program);
+        device.cpu_mut().sp = 0xfffe;
+
+        assert_eq!(device.step_over(), None);
+        let target = device.step_over().unwrap();
+        run(&mut device, target);
+        assert_eq!((device.cpu().pc, device.cpu().sp), (0x0105, 0xfffe));
+        assert_eq!(device.cpu().a, 0);
+
+        // Stepping out after the `push bc` of the outer call returns from it,
+        // not from the recursive call that returns with SP above the start.
+        let mut device = device_with_program(&program);
+        device.cpu_mut().sp = 0xfffe;
+        while device.cpu().pc != 0x0107 {
+            device.step();
+        }
+
+        let target = device.step_out();
+        run(&mut device, target);
+        assert_eq!((device.cpu().pc, device.cpu().sp), (0x0105, 0xfffe));
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut device = device_with_program(&[0x76]); // halt
+        device.cpu_mut().sp = 0xfffe;
+        device.mmu.write(0xffff, Interrupts::TIMER.bits()).unwrap();
+
+        device.step();
+        assert!(device.is_halted());
+        assert!(device.pending_interrupts().is_empty());
+
+        device.request_interrupt(Interrupts::TIMER);
+        assert!(device.requested_interrupts().contains(Interrupts::TIMER));
+        assert_eq!(device.enabled_interrupts(), Interrupts::TIMER);
+        assert_eq!(device.pending_interrupts(), Interrupts::TIMER);
+
+        // Without IME the interrupt only ends the halt.
+        device.step();
+        assert!(!device.is_halted());
+        assert_eq!(device.cpu().pc, 0x0101);
+
+        device.cpu_mut().ime = true;
+        device.step();
+        assert_eq!(device.cpu().pc, 0x0050);
+        assert!(!device.interrupt_master_enable());
+        assert!(device.pending_interrupts().is_empty());
+    }
+
+    #[test]
+    fn rom_write_lints() {
+        let program = [
+            0x3e, 0x01, // 0x0100: ld a, 1
+            0xea, 0x00, 0x20, // 0x0102: ld (0x2000), a
+            0x18, 0xf9, // 0x0105: jr 0x0100
+        ];
+
+        let mut device = device_with_program(&program);
+        for _ in 0..9 {
+            device.step();
+        }
+        assert_eq!(
+            device.lints(),
+            &[Lint::RomWrite {
+                pc: 0x0102,
+                address: 0x2000,
+                value: 0x01
+            }]
+        );
+
+        // With a memory bank controller, that selects a ROM bank.
+        let mut rom = program_rom(&program);
+        rom[0x147] = 0x01;
+        let mut device = Device::new(Cartridge::from_bytes(rom));
+        device.cpu_mut().pc = 0x0100;
+        for _ in 0..3 {
+            device.step();
+        }
+        assert!(device.lints().is_empty());
+    }
+
+    #[test]
+    fn uninitialized_reads() {
+        let program = [
+            0xea, 0x00, 0xc0, // 0x0100: ld (0xc000), a
+            0xfa, 0x00, 0xc0, // 0x0103: ld a, (0xc000)
+            0xfa, 0x01, 0xe0, // 0x0106: ld a, (0xe001)
+            0xfa, 0x01, 0xc0, // 0x0109: ld a, (0xc001)
+            0xf0, 0x80, // 0x010c: ldh a, (0xff80)
+        ];
+
+        let mut device = device_with_program(&program);
+        device.set_uninitialized_reads(UninitializedReads::Trap);
+        for _ in 0..5 {
+            device.step();
+        }
+
+        // Echo RAM reads count against WRAM, and reporting a byte initializes
+        // it.
+        assert!(matches!(
+            device.stop_reason(),
+            Some(StopReason::UninitializedRead {
+                pc: 0x0106,
+                address: 0xe001
+            })
+        ));
+        assert_eq!(device.cpu().pc, 0x0109);
+        device.resume();
+        device.step();
+        device.set_uninitialized_reads(UninitializedReads::Log);
+        device.step();
+
+        assert!(device.stop_reason().is_none());
+        assert_eq!(
+            device.lints(),
+            &[
+                Lint::UninitializedRead {
+                    pc: 0x0106,
+                    address: 0xe001
+                },
+                Lint::UninitializedRead {
+                    pc: 0x010c,
+                    address: 0xff80
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let program = [
+            0x3e, 0x91, // 0x0100: ld a, 0x91
+            0xe0, 0x40, // 0x0102: ldh (LCDC), a
+            0xf0, 0x11, // 0x0104: ldh a, (NR11)
+            0xe0, 0x12, // 0x0106: ldh (NR12), a
+            0x18, 0xfe, // 0x0108: jr -2
+        ];
+
+        let mut device = device_with_program(&program);
+        for (pattern, kind) in [("LCDC", WatchKind::Write), ("NR1x", WatchKind::Read)].iter() {
+            device.watchpoints_mut().add(Watchpoint {
+                label: pattern.to_string(),
+                addresses: io_addresses(pattern).unwrap(),
+                kind: *kind,
+            });
+        }
+
+        for _ in 0..3 {
+            device.step();
+        }
+        assert!(matches!(
+            device.stop_reason(),
+            Some(StopReason::Watchpoint {
+                pc: 0x0102,
+                address: 0xff40,
+                op: MemoryOperation::Write,
+                old: 0x00,
+                new: 0x91
+            })
+        ));
+        assert_eq!(device.cpu().pc, 0x0104);
+
+        device.resume();
+        device.step();
+        assert!(matches!(
+            device.stop_reason(),
+            Some(StopReason::Watchpoint {
+                pc: 0x0104,
+                address: 0xff11,
+                op: MemoryOperation::Read,
+                ..
+            })
+        ));
+
+        device.resume();
+        for _ in 0..10 {
+            device.step();
+        }
+        assert!(device.stop_reason().is_none());
+    }
+
+    #[test]
+    fn frozen_addresses() {
+        let program = [
+            0x3e, 0x91, // 0x0100: ld a, 0x91
+            0xe0, 0x40, // 0x0102: ldh (LCDC), a
+            0xaf, // 0x0104: xor a
+            0xea, 0x00, 0xc0, // 0x0105: ld (0xc000), a
+            0x18, 0xfb, // 0x0108: jr 0x0105
+        ];
+
+        // The program keeps clearing the byte, which is put back at the
+        // start of every frame.
+        let mut device = device_with_program(&program);
+        device.freeze(0xc000, 0x42);
+        for _ in 0..2 {
+            device.step_frame();
+            assert_eq!(device.read(0xc000).unwrap(), 0x42);
+            device.step();
+            device.step();
+            assert_eq!(device.read(0xc000).unwrap(), 0x00);
+        }
+        assert_eq!(device.frozen().len(), 1);
+
+        device.unfreeze(0xc000);
+        device.step_frame();
+        assert_eq!(device.read(0xc000).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn instruction_hooks() {
+        let mut device = device();
+
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        for (name, pre) in [("pre", true), ("post", false)].iter().copied() {
+            let calls = calls.clone();
+            let hook = Box::new(move |info: &InstructionInfo, cpu: &Cpu| {
+                calls
+                    .borrow_mut()
+                    .push((name, info.pc, info.cycles, cpu.pc));
+            });
+
+            if pre {
+                device.add_pre_instruction_hook(hook);
+            } else {
+                device.add_post_instruction_hook(hook);
+            }
+        }
+
+        // ld sp, $fffe; xor a
+        device.step();
+        device.step();
+
+        assert_eq!(
+            *calls.borrow(),
+            vec![
+                ("pre", 0x0000, 0, 0x0000),
+                ("post", 0x0000, 12, 0x0003),
+                ("pre", 0x0003, 12, 0x0003),
+                ("post", 0x0003, 16, 0x0004),
+            ]
+        );
+
+        device.clear_instruction_hooks();
+        device.step();
+        assert_eq!(calls.borrow().len(), 4);
+    }
+
+    #[test]
+    fn overlay_hooks() {
+        let mut device = device();
+        device.check_boot(600).unwrap();
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let recorded = lines.clone();
+        device.add_hblank_hook(Box::new(move |line, _, _| {
+            recorded.borrow_mut().push(line);
+        }));
+        device.add_vblank_hook(Box::new(|mmu, overlay| {
+            let scroll_y = mmu.read(0xff42).unwrap();
+            overlay.draw_text(0, 0, &format!("{:02x}", scroll_y), [255, 0, 0, 255]);
+        }));
+
+        device.step_frame();
+        lines.borrow_mut().clear();
+        device.step_frame();
+        assert_eq!(*lines.borrow(), (0..144).collect::<Vec<u8>>());
+
+        assert!(!device.overlay().is_empty());
+        let overlaid = device.overlaid_framebuffer();
+        assert_eq!(&overlaid[0..3], &[255, 0, 0]);
+        // Below the text, the game image shows through.
+        let below = 3 * 160 * 5;
+        assert_eq!(&overlaid[below..], &device.display_framebuffer()[below..]);
+
+        device.clear_overlay_hooks();
+        device.overlay_mut().clear();
+        device.step_frame();
+        assert_eq!(lines.borrow().len(), 144);
+        assert!(device.overlay().is_empty());
+    }
+
+    #[test]
+    fn scanline_hooks() {
+        let mut device = device();
+        device.check_boot(600).unwrap();
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let recorded = lines.clone();
+        device.add_scanline_hook(Box::new(move |line, rgb| {
+            recorded.borrow_mut().push((line, rgb.to_vec()));
+        }));
+
+        device.step_frame();
+        lines.borrow_mut().clear();
+        device.step_frame();
+
+        // The lines put together are the frame, as the boot ROM leaves the
+        // logo standing still.
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 144);
+        for (i, (line, rgb)) in lines.iter().enumerate() {
+            let start = 3 * 160 * i;
+            assert_eq!(*line as usize, i);
+            assert_eq!(
+                &rgb[..],
+                &device.display_framebuffer()[start..start + 3 * 160]
+            );
+        }
+
+        device.clear_scanline_hooks();
+        device.step_frame();
+        assert_eq!(lines.len(), 144);
+    }
+
+    #[test]
+    fn layer_palettes() {
+        let mut device = device_with_program(&LCD_ON);
+        device.step_frame();
+        device.step_frame();
+
+        // Nothing but the background is on screen, so changing the sprite
+        // palettes keeps it the way it is.
+        let red = Palette::custom([[255, 0, 0]; 4]);
+        device.set_layer_palette(Layer::Object0, red);
+        device.set_layer_palette(Layer::Object1, red);
+        assert_eq!(&device.display_framebuffer()[0..3], &GRAYSCALE.colors[0]);
+        assert_eq!(device.palette(), GRAYSCALE);
+
+        device.set_layer_palette(Layer::Background, red);
+        assert_eq!(&device.display_framebuffer()[0..3], &[255, 0, 0]);
+
+        device.set_palette(GREEN);
+        for layer in [Layer::Background, Layer::Object0, Layer::Object1].iter() {
+            assert_eq!(device.layer_palette(*layer), GREEN);
+        }
+    }
+
+    struct FrameLog(Rc<RefCell<Vec<(u64, usize)>>>);
+
+    impl FrameSink for FrameLog {
+        fn frame(&mut self, rgb: &[u8], frame_no: u64) {
+            self.0.borrow_mut().push((frame_no, rgb.len()));
+        }
+    }
+
+    #[test]
+    fn color_mapper() {
+        let mut device = device();
+        assert_eq!(device.mapped_framebuffer(), None);
+
+        device.set_color_mapper(Box::new(Rgb565));
+        device.check_boot(600).unwrap();
+
+        let mapped = device.mapped_framebuffer().unwrap();
+        assert_eq!(mapped.len(), 2 * 160 * 144);
+        for ((pixel, rgb), shade) in mapped
+            .chunks_exact(2)
+            .zip(device.display_framebuffer().chunks_exact(3))
+            .zip(device.indexed_framebuffer())
+        {
+            let rgb = [rgb[0], rgb[1], rgb[2]];
+            assert_eq!(rgb, GRAYSCALE.colors[*shade as usize]);
+            assert_eq!(pixel, rgb888_to_rgb565(rgb).to_le_bytes());
+        }
+        // The logo is on screen, so not every pixel is the same.
+        assert!(device.indexed_framebuffer().contains(&3));
+
+        let mut rgba = vec![0; 4 * 160 * 144];
+        device.display_framebuffer_rgba(&mut rgba);
+        for (rgba, rgb) in rgba
+            .chunks_exact(4)
+            .zip(device.display_framebuffer().chunks_exact(3))
+        {
+            assert_eq!(&rgba[..3], rgb);
+            assert_eq!(rgba[3], 255);
+        }
+
+        device.clear_color_mapper();
+        assert_eq!(device.mapped_framebuffer(), None);
+    }
+
+    #[test]
+    fn sprite_thumbnails() {
+        let mut device = device_with_program(&LCD_ON);
+        device.set_layer_palette(Layer::Object1, GREEN);
+        let gpu = &mut device.mmu.gpu;
+        gpu.oam[8..12].copy_from_slice(&[16, 8, 1, 0b0001_0000]);
+        gpu.obj_palette[1] = [0, 3, 2, 1];
+        gpu.tiles[1].set(0, 0, 1);
+        gpu.tiles[1].set(1, 0, 2);
+
+        let sprite = device.sprites()[2];
+        assert_eq!((sprite.screen_x(), sprite.screen_y()), (0, 0));
+        assert_eq!(sprite.layer(), Layer::Object1);
+
+        let mut rgba = vec![0xff; 4 * 8 * 8];
+        device.render_sprite(2, &mut rgba);
+        assert_eq!(rgba[..3], GREEN.colors[3]);
+        assert_eq!(rgba[4..7], GREEN.colors[2]);
+        assert_eq!((rgba[3], rgba[7]), (255, 255));
+        assert_eq!(rgba[8..12], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn frame_sinks() {
+        let mut device = device_with_program(&LCD_ON);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        device.add_frame_sink(Box::new(FrameLog(log.clone())));
+
+        device.step_frame();
+        device.step_frame();
+        let first = device.frame_count() as u64 - 1;
+        assert_eq!(
+            *log.borrow(),
+            [(first, 3 * 160 * 144), (first + 1, 3 * 160 * 144)]
+        );
+
+        device.finish_frame_sinks().unwrap();
+        device.step_frame();
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn audio_sinks() {
+        let mut device = device();
+        let ring = AudioRing::new(1 << 20);
+        device.add_audio_sink(Box::new(ring.clone()));
+
+        // The boot ROM plays its chime, which every sample is kept of.
+        device.check_boot(600).unwrap();
+        device.finish_audio_sinks().unwrap();
+        let m_cycles = device.cycles() as usize / 4;
+        assert_eq!(
+            ring.len(),
+            2 * (m_cycles * SAMPLE_RATE as usize / 1_048_576)
+        );
+
+        let mut samples = vec![0; ring.len()];
+        ring.pop(&mut samples);
+        assert!(samples.iter().any(|sample| *sample != 0));
+
+        device.step_frame();
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn serial_output() {
+        let program = [
+            0x3e, b'o', 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02, // send 'o'
+            0x3e, b'k', 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02, // send 'k'
+            0x18, 0xfe, // jr -2
+        ];
+
+        let mut device = device_with_program(&program);
+
+        assert!(device.run_until_serial_output(10, |output| output == b"ok"));
+        assert!(!device.run_until_serial_output(10, |output| output.ends_with(b"!")));
+    }
+
+    #[test]
+    fn custom_boot_rom() {
+        let cart = || Cartridge::from_bytes(program_rom(&[]));
+
+        assert_eq!(
+            DeviceBuilder::new(cart()).boot_rom(vec![0; 3]).err(),
+            Some(BootRomError::Size(3))
+        );
+
+        // A boot ROM that jumps straight to the cartridge.
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..0x03].copy_from_slice(&[0xc3, 0xfc, 0x00]);
+        boot_rom[0xfc..].copy_from_slice(&[0x3e, 0x01, 0xe0, 0x50]);
+        let mut device = DeviceBuilder::new(cart())
+            .boot_rom(boot_rom)
+            .unwrap()
+            .build();
+
+        assert_eq!(device.read(0x0001).unwrap(), 0xfc);
+        assert!(matches!(device.check_boot(1), Ok(0)));
+        assert_eq!(device.cpu().pc, 0x0100);
+    }
+
+    #[test]
+    fn cgb_boot_rom() {
+        let mut cart = program_rom(&[]);
+        cart[0x143] = 0x80;
+        cart[0x14d] = cart[0x134..0x14d]
+            .iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+
+        let mut device = Device::with_model(Cartridge::from_bytes(cart), Model::Cgb);
+        assert_eq!(device.mmu.boot_rom().len(), CGB_BIOS.len());
+        assert_eq!(device.read(0x0134).unwrap(), 0);
+        assert_eq!(device.read(0x0200).unwrap(), CGB_BIOS[0x200]);
+        assert!(device.mmu.in_boot_rom(0x0200));
+        assert!(!device.mmu.in_boot_rom(0x0150));
+
+        assert!(device.check_boot(600).is_ok());
+        assert!(!device.mmu.in_boot_rom(0x0200));
+        assert_eq!(device.cpu().pc, 0x0100);
+        assert_eq!(device.cpu().a, 0x11);
+        assert!(device.cgb_mode());
+        assert_eq!(device.read(0x0200).unwrap(), 0);
+    }
 }
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+use gameboy::{
+    cartridge::{Cartridge, SaveError},
+    device::Device,
+};
+
+/// Where older versions kept save games, relative to the working directory.
+const LEGACY_SAVE_DIR: &str = "saves";
+
+/// The directories the emulator writes its files to. These follow the
+/// platform's conventions, such as `~/.local/share/gameboy-rs` on Linux or
+/// `AppData\Roaming\gameboy-rs` on Windows, unless overridden.
+pub struct DataDirs {
+    pub saves: PathBuf,
+    /// Where saves are looked for when there is none in `saves`, and written
+    /// to when `saves` can't be written to.
+    pub fallback_saves: PathBuf,
+    pub screenshots: PathBuf,
+    /// Where the play statistics of every game are kept.
+    pub stats: PathBuf,
+}
+
+impl DataDirs {
+    pub fn new() -> DataDirs {
+        let data_dir = ProjectDirs::from("", "", "gameboy-rs")
+            .map(|dirs| dirs.data_dir().to_owned())
+            .unwrap_or_default();
+
+        DataDirs {
+            saves: data_dir.join("saves"),
+            fallback_saves: LEGACY_SAVE_DIR.into(),
+            screenshots: data_dir.join("screenshots"),
+            stats: data_dir.join("stats"),
+        }
+    }
+
+    /// Loads the cartridge's save from the save directory, or from the
+    /// fallback directory if there is none.
+    pub fn load_game(&self, cart: &mut Cartridge) -> Result<Option<PathBuf>, SaveError> {
+        match cart.try_load(&self.saves) {
+            Ok(None) => cart.try_load(&self.fallback_saves),
+            result => result,
+        }
+    }
+
+    /// Writes the game's save when the emulator exits, folding in the save
+    /// journal if there is one. Falls back to the fallback directory if the
+    /// save can't be written, returning the first error if that fails too.
+    pub fn save_game(&self, device: &mut Device) -> Result<PathBuf, SaveError> {
+        let saved = match device.take_save_journal() {
+            Some(mut journal) => {
+                if let Some(err) = journal.error() {
+                    println!("warning: journaling stopped early: {}", err);
+                }
+                journal.compact(device.cart())
+            }
+            None => device.cart().save(&self.saves),
+        };
+
+        saved.or_else(|err| device.cart().save(&self.fallback_saves).map_err(|_| err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use gameboy::{
+        cartridge::{Cartridge, SaveError},
+        device::Device,
+    };
+
+    use super::DataDirs;
+
+    #[test]
+    fn save_fallback() {
+        let root = env::temp_dir().join(format!("gameboy-rs-dirs-{}", process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        // A directory can't be created inside a file, not even by root.
+        let blocker = root.join("file");
+        fs::write(&blocker, b"").unwrap();
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13a].copy_from_slice(b"TETRIS");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        let mut device = Device::new(Cartridge::from_bytes(rom.clone()));
+
+        let mut dirs = DataDirs::new();
+        dirs.saves = blocker.join("saves");
+        dirs.fallback_saves = root.join("fallback");
+        let path = dirs.save_game(&mut device).unwrap();
+        assert_eq!(path, root.join("fallback").join("TETRIS.sav"));
+
+        let mut loaded = Cartridge::from_bytes(rom);
+        assert_eq!(dirs.load_game(&mut loaded).unwrap(), Some(path));
+
+        dirs.fallback_saves = blocker.join("fallback");
+        match dirs.save_game(&mut device) {
+            Err(SaveError::Write { path, .. }) => {
+                assert_eq!(path, blocker.join("saves").join("TETRIS.sav"))
+            }
+            result => panic!("expected a write error, got {:?}", result),
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
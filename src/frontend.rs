@@ -0,0 +1,437 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use gameboy::{
+    device::{Device, InstructionInfo},
+    memory::mmu::JoypadButton,
+};
+use glium::{
+    glutin::{
+        dpi::LogicalSize,
+        event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+        event_loop::{ControlFlow, EventLoop},
+        window::WindowBuilder,
+        ContextBuilder,
+    },
+    texture::RawImage2d,
+    Display, Frame,
+};
+
+use crate::{
+    dirs::DataDirs,
+    screenshot::{save_png, save_screenshot, ScreenshotMode},
+    stats::StatsTracker,
+    template::{Templates, Values},
+};
+
+pub const EMULATION_SPEED: f32 = 4194304.0 / 70224.0;
+
+/// The most frames emulated for a single redraw when emulation falls behind.
+/// Those are dropped, any lag beyond that slows emulation down instead.
+const MAX_FRAMES_PER_REDRAW: usize = 4;
+
+/// State shared with a [`Frontend`] while it draws.
+pub struct Context<'a> {
+    pub display: &'a Display,
+    pub device: &'a mut Device,
+    pub emulation_speed: &'a mut f32,
+    exit: &'a mut bool,
+    next_frontend: &'a mut Option<Box<dyn Frontend>>,
+}
+
+impl<'a> Context<'a> {
+    /// Shuts down the runtime after the current frame.
+    pub fn exit(&mut self) {
+        *self.exit = true;
+    }
+
+    /// Replaces the current frontend after this frame, leaving the device as
+    /// it is.
+    pub fn switch_to(&mut self, frontend: Box<dyn Frontend>) {
+        *self.next_frontend = Some(frontend);
+    }
+}
+
+/// The most recently executed instructions, oldest first.
+pub type Trace = Rc<RefCell<VecDeque<InstructionInfo>>>;
+
+/// Keeps the last `length` instructions the device executes in a [`Trace`].
+pub fn record_trace(device: &mut Device, length: usize) -> Trace {
+    let trace = Trace::default();
+    let recorded = trace.clone();
+
+    device.add_post_instruction_hook(Box::new(move |info, _| {
+        let mut recorded = recorded.borrow_mut();
+        if recorded.len() == length {
+            recorded.pop_front();
+        }
+
+        recorded.push_back(*info);
+    }));
+
+    trace
+}
+
+/// A set of views drawn by the [`Runtime`], such as the plain game view or the
+/// debugger.
+pub trait Frontend {
+    /// Called for every event the window receives.
+    fn handle_event(&mut self, _display: &Display, _event: &Event<()>) {}
+
+    /// Called before a redraw is requested.
+    fn prepare(&mut self, _display: &Display) {}
+
+    /// Something to point out in the window title, after the title template.
+    fn status(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether keyboard input should currently be routed to the joypad.
+    fn wants_joypad_input(&self) -> bool {
+        true
+    }
+
+    /// Called whenever the pacing allows the next frame to be emulated.
+    fn emulate_frame(&mut self, device: &mut Device) {
+        device.step_frame();
+    }
+
+    fn draw(&mut self, target: &mut Frame, context: &mut Context);
+}
+
+/// Owns the window, the event loop and the emulated device, and takes care of
+/// frame pacing, joypad input and saving on exit for every frontend.
+pub struct Runtime {
+    event_loop: EventLoop<()>,
+    display: Display,
+    device: Device,
+    dirs: DataDirs,
+    templates: Templates,
+    screenshot_mode: ScreenshotMode,
+}
+
+impl Runtime {
+    pub fn new(
+        device: Device,
+        dirs: DataDirs,
+        templates: Templates,
+        screenshot_mode: ScreenshotMode,
+        size: LogicalSize<u32>,
+    ) -> Runtime {
+        let event_loop = EventLoop::new();
+        let context = ContextBuilder::new().with_vsync(true);
+        let builder = WindowBuilder::new()
+            .with_title(templates.title.render(&Values::new(&device, 0.0)))
+            .with_inner_size(size);
+        let display =
+            Display::new(builder, context, &event_loop).expect("failed to create display");
+
+        Runtime {
+            event_loop,
+            display,
+            device,
+            dirs,
+            templates,
+            screenshot_mode,
+        }
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn run<F: Frontend + 'static>(self, frontend: F) -> ! {
+        let Runtime {
+            event_loop,
+            display,
+            mut device,
+            dirs,
+            templates,
+            screenshot_mode,
+        } = self;
+
+        let mut emulation_speed = EMULATION_SPEED;
+        let mut fps = FpsCounter::new(&device);
+        let mut stats = StatsTracker::start(&dirs.stats, &device);
+        let mut title = String::new();
+        let mut last_frame = Instant::now();
+        let mut input = InputQueue::default();
+        // A screenshot of the window can only be taken once it's drawn.
+        let mut screenshot_requested = false;
+        let mut frontend: Box<dyn Frontend> = Box::new(frontend);
+
+        event_loop.run(move |event, _, control_flow| {
+            match &event {
+                Event::MainEventsCleared => {
+                    frontend.prepare(&display);
+                    display.gl_window().window().request_redraw();
+                }
+                Event::RedrawRequested(_) => {
+                    let frame_time = Duration::from_secs_f32(1.0 / emulation_speed);
+                    let mut frames = 0;
+                    while last_frame.elapsed() >= frame_time {
+                        if frames == MAX_FRAMES_PER_REDRAW {
+                            last_frame = Instant::now();
+                            break;
+                        }
+
+                        last_frame += frame_time;
+                        input.apply(&mut device);
+                        frontend.emulate_frame(&mut device);
+                        frames += 1;
+                    }
+
+                    let mut exit = false;
+                    let mut next_frontend = None;
+                    let mut target = display.draw();
+                    frontend.draw(
+                        &mut target,
+                        &mut Context {
+                            display: &display,
+                            device: &mut device,
+                            emulation_speed: &mut emulation_speed,
+                            exit: &mut exit,
+                            next_frontend: &mut next_frontend,
+                        },
+                    );
+                    target.finish().expect("failed to finish frame");
+                    device.present_frame();
+
+                    if screenshot_requested {
+                        take_displayed_screenshot(&display, &device, &dirs, &templates, fps.fps);
+                        screenshot_requested = false;
+                    }
+
+                    if let Some(next) = next_frontend {
+                        frontend = next;
+                    }
+
+                    fps.update(&device);
+                    stats.update(&device);
+                    let mut next_title = templates.title.render(&Values::new(&device, fps.fps));
+                    if let Some(status) = frontend.status() {
+                        next_title = format!("{} ({})", next_title, status);
+                    }
+                    if next_title != title {
+                        display.gl_window().window().set_title(&next_title);
+                        title = next_title;
+                    }
+
+                    if exit {
+                        shutdown(&mut device, &dirs, &mut stats);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => {
+                    shutdown(&mut device, &dirs, &mut stats);
+                    *control_flow = ControlFlow::Exit;
+                }
+                Event::WindowEvent {
+                    event:
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::F12),
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => match screenshot_mode {
+                    ScreenshotMode::Native => take_screenshot(&device, &dirs, &templates, fps.fps),
+                    ScreenshotMode::Displayed => screenshot_requested = true,
+                },
+                Event::WindowEvent {
+                    event: WindowEvent::KeyboardInput { input: key, .. },
+                    ..
+                } if frontend.wants_joypad_input() => input.push(key),
+                _ => {}
+            }
+
+            frontend.handle_event(&display, &event);
+        })
+    }
+}
+
+/// Joypad input received from the window, waiting for the next emulated frame.
+///
+/// Input is applied right before a frame is emulated rather than whenever the
+/// window delivers it, so every frame sees a consistent state from its first
+/// cycle. A key press therefore reaches the game at the start of the next
+/// frame, which is at most one frame (about 17 ms) of added latency.
+#[derive(Default)]
+struct InputQueue {
+    events: Vec<(JoypadButton, ElementState)>,
+}
+
+impl InputQueue {
+    fn push(&mut self, input: &KeyboardInput) {
+        let button = match input.virtual_keycode {
+            Some(VirtualKeyCode::Left) => JoypadButton::Left,
+            Some(VirtualKeyCode::Right) => JoypadButton::Right,
+            Some(VirtualKeyCode::Up) => JoypadButton::Up,
+            Some(VirtualKeyCode::Down) => JoypadButton::Down,
+            Some(VirtualKeyCode::Z) => JoypadButton::B,
+            Some(VirtualKeyCode::X) => JoypadButton::A,
+            Some(VirtualKeyCode::LControl) => JoypadButton::Start,
+            Some(VirtualKeyCode::LShift) => JoypadButton::Select,
+            _ => return,
+        };
+
+        self.events.push((button, input.state));
+    }
+
+    /// Applies the queued input. A button that is pressed and released before
+    /// the frame starts is released a frame later, so short taps aren't lost.
+    fn apply(&mut self, device: &mut Device) {
+        let mut pressed = Vec::new();
+        let mut deferred = Vec::new();
+
+        for (button, state) in self.events.drain(..) {
+            let tapped = state == ElementState::Released && pressed.contains(&button);
+            if tapped || deferred.iter().any(|(deferred, _)| *deferred == button) {
+                deferred.push((button, state));
+                continue;
+            }
+
+            match state {
+                ElementState::Pressed => {
+                    device.press(&[button]);
+                    pressed.push(button);
+                }
+                ElementState::Released => device.release(&[button]),
+            }
+        }
+
+        self.events = deferred;
+    }
+}
+
+/// Measures how many frames are emulated per second, averaged over a second.
+struct FpsCounter {
+    fps: f32,
+    since: Instant,
+    frame_count: usize,
+}
+
+impl FpsCounter {
+    fn new(device: &Device) -> FpsCounter {
+        FpsCounter {
+            fps: 0.0,
+            since: Instant::now(),
+            frame_count: device.frame_count(),
+        }
+    }
+
+    fn update(&mut self, device: &Device) {
+        let elapsed = self.since.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            self.fps = device.frame_count().saturating_sub(self.frame_count) as f32 / elapsed;
+            self.since = Instant::now();
+            self.frame_count = device.frame_count();
+        }
+    }
+}
+
+fn take_screenshot(device: &Device, dirs: &DataDirs, templates: &Templates, fps: f32) {
+    save_to_screenshots(device, dirs, templates, fps, |path| {
+        save_screenshot(device, path, false)
+    });
+}
+
+/// Saves what was just drawn to the window, read back from the front buffer.
+fn take_displayed_screenshot(
+    display: &Display,
+    device: &Device,
+    dirs: &DataDirs,
+    templates: &Templates,
+    fps: f32,
+) {
+    save_to_screenshots(device, dirs, templates, fps, |path| {
+        let image: RawImage2d<u8> = display.read_front_buffer()?;
+
+        // OpenGL's rows go from the bottom up.
+        let row = 4 * image.width as usize;
+        let pixels = image
+            .data
+            .chunks_exact(row)
+            .rev()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+
+        save_png(
+            path,
+            image.width,
+            image.height,
+            png::ColorType::RGBA,
+            &pixels,
+        )
+    });
+}
+
+fn save_to_screenshots<F>(
+    device: &Device,
+    dirs: &DataDirs,
+    templates: &Templates,
+    fps: f32,
+    save: F,
+) where
+    F: FnOnce(&Path) -> anyhow::Result<()>,
+{
+    let path = dirs
+        .screenshots
+        .join(templates.screenshot.render(&Values::new(device, fps)));
+
+    let result = fs::create_dir_all(&dirs.screenshots)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| save(&path));
+
+    match result {
+        Ok(()) => println!("saved screenshot to {}", path.display()),
+        Err(err) => println!("warning: failed to save screenshot: {}", err),
+    }
+}
+
+fn shutdown(device: &mut Device, dirs: &DataDirs, stats: &mut StatsTracker) {
+    for lint in device.lints() {
+        println!("warning: {}", lint);
+    }
+
+    match dirs.save_game(device) {
+        Ok(path) => {
+            println!("saved game to {}", path.display());
+            stats.record_save();
+        }
+        Err(err) => println!("warning: failed to save game: {}", err),
+    }
+
+    stats.update(device);
+    stats.flush();
+
+    if let Err(err) = device.finish_frame_sinks() {
+        println!("warning: failed to record video: {}", err);
+    }
+
+    if let Err(err) = device.finish_audio_sinks() {
+        println!("warning: failed to record sound: {}", err);
+    }
+
+    if let Some(mut tracer) = device.take_tracer() {
+        if let Some(err) = tracer.error() {
+            println!("warning: tracing stopped early: {}", err);
+        } else if let Err(err) = tracer.flush() {
+            println!("warning: failed to write trace: {}", err);
+        }
+    }
+}
@@ -1,7 +1,10 @@
+use std::{iter, ops::Range};
+
 use crate::cpu::Interrupts;
 use bitflags::bitflags;
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct LcdControl: u8 {
         const BG_WINDOW_ENABLE = 1 << 0;
         const OBJ_ENABLE = 1 << 1;
@@ -15,6 +18,7 @@ bitflags! {
 }
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct StatInterruptSource: u8 {
         const HBLANK = 1 << 3;
         const VBLANK = 1 << 4;
@@ -23,7 +27,24 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// The attribute bits of an OAM entry. Bits 0 to 2 hold the CGB palette,
+    /// which [`SpriteEntry::cgb_palette`] has instead.
+    pub struct SpriteFlags: u8 {
+        /// The VRAM bank the tile is in, in CGB mode.
+        const BANK = 1 << 3;
+        /// Whether the sprite uses OBP1 rather than OBP0.
+        const PALETTE = 1 << 4;
+        const FLIP_X = 1 << 5;
+        const FLIP_Y = 1 << 6;
+        /// Whether the background shows over the sprite, except where it has
+        /// color 0.
+        const BEHIND_BACKGROUND = 1 << 7;
+    }
+}
+
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum GpuMode {
     HBlank = 0,
@@ -32,6 +53,154 @@ pub enum GpuMode {
     VramRead = 3,
 }
 
+/// The length of OAM search (mode 2) in dots.
+const OAM_SEARCH_CYCLES: usize = 80;
+/// The shortest mode 3, without sprites or fine scrolling.
+const MIN_TRANSFER_CYCLES: usize = 172;
+/// Modes 2, 3 and 0 together always take a whole line, so a longer mode 3
+/// makes for a shorter HBlank.
+const LINE_CYCLES: usize = 456;
+/// The length of a frame of 144 visible lines and 10 lines of VBlank, which
+/// the screen also keeps to while the LCD is off.
+const FRAME_CYCLES: usize = 154 * LINE_CYCLES;
+/// The dots at the start of mode 3 before the first pixel is pushed, spent
+/// fetching a tile that is thrown away.
+const FIRST_PIXEL_DELAY: usize = 12;
+/// RGB555 white, which the screen shows while the LCD is off in CGB mode.
+const WHITE: u16 = 0x7fff;
+
+/// What drew a pixel on screen, which picks the palette its shade is shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Layer {
+    /// The background or the window.
+    Background = 0,
+    /// A sprite using OBP0.
+    Object0 = 1,
+    /// A sprite using OBP1.
+    Object1 = 2,
+}
+
+/// Whether the GPU draws a layer as LCDC says, or hides or shows it
+/// regardless, for debugging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LayerVisibility {
+    Auto,
+    Hidden,
+    Shown,
+}
+
+// Deriving this needs `#[default]` on the variant, which is too new for the
+// Rust versions supported.
+#[allow(clippy::derivable_impls)]
+impl Default for LayerVisibility {
+    fn default() -> LayerVisibility {
+        LayerVisibility::Auto
+    }
+}
+
+impl LayerVisibility {
+    fn resolve(self, enabled: bool) -> bool {
+        match self {
+            LayerVisibility::Auto => enabled,
+            LayerVisibility::Hidden => false,
+            LayerVisibility::Shown => true,
+        }
+    }
+}
+
+/// Debugging overrides of what the GPU draws. They only change the picture,
+/// so the game still reads LCDC as it wrote it and the timing of a line
+/// doesn't change.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerToggles {
+    /// Hiding the background leaves color 0 behind the window and sprites.
+    pub background: LayerVisibility,
+    pub window: LayerVisibility,
+    pub sprites: LayerVisibility,
+    /// Tints the area the window covers in the display framebuffer.
+    pub highlight_window: bool,
+}
+
+/// One of the two 32x32 tile maps in VRAM, which the background and window
+/// each pick from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tilemap {
+    /// The map at 0x9800.
+    Low,
+    /// The map at 0x9c00.
+    High,
+}
+
+impl Tilemap {
+    fn vram_offset(&self) -> usize {
+        match self {
+            Tilemap::Low => 0x1800,
+            Tilemap::High => 0x1c00,
+        }
+    }
+}
+
+/// The 160x144 area of the 256x256 background tile map the screen shows, set
+/// by SCX and SCY. It wraps around the edges of the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl Viewport {
+    /// Whether a pixel of the tile map is shown on screen.
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        x.wrapping_sub(self.x) < 160 && y.wrapping_sub(self.y) < 144
+    }
+}
+
+/// A decoded OAM entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteEntry {
+    /// The X coordinate as stored in OAM, which is 8 more than the screen
+    /// coordinate of the left edge.
+    pub x: u8,
+    /// The Y coordinate as stored in OAM, which is 16 more than the screen
+    /// coordinate of the top edge.
+    pub y: u8,
+    pub tile: u8,
+    pub flags: SpriteFlags,
+    pub cgb_palette: u8,
+}
+
+impl SpriteEntry {
+    fn decode(bytes: &[u8]) -> SpriteEntry {
+        SpriteEntry {
+            y: bytes[0],
+            x: bytes[1],
+            tile: bytes[2],
+            flags: SpriteFlags::from_bits_truncate(bytes[3]),
+            cgb_palette: bytes[3] & 0b111,
+        }
+    }
+
+    pub fn screen_x(&self) -> i16 {
+        self.x as i16 - 8
+    }
+
+    pub fn screen_y(&self) -> i16 {
+        self.y as i16 - 16
+    }
+
+    /// The palette the sprite is drawn in on the DMG.
+    pub fn layer(&self) -> Layer {
+        if self.flags.contains(SpriteFlags::PALETTE) {
+            Layer::Object1
+        } else {
+            Layer::Object0
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Tile {
     pixels: [u8; 64],
@@ -53,48 +222,227 @@ impl Tile {
     pub fn get_x_flipped(&self, x: usize, y: usize) -> u8 {
         self.pixels[(7 - x) + y * 8]
     }
+
+    /// A pixel of the tile flipped as the CGB attribute bits 5 (horizontal)
+    /// and 6 (vertical) say.
+    fn get_flipped(&self, x: usize, y: usize, attributes: u8) -> u8 {
+        let y = if attributes & (1 << 6) != 0 { 7 - y } else { y };
+
+        if attributes & (1 << 5) != 0 {
+            self.get_x_flipped(x, y)
+        } else {
+            self.get(x, y)
+        }
+    }
+}
+
+fn blank_tiles() -> Box<[Tile; 768]> {
+    Box::new([Tile::new(); 768])
+}
+
+/// The 8 palettes of 4 colors the CGB has for the background and for sprites
+/// each, written through an index register (BCPS or OCPS) and a data register
+/// (BCPD or OCPD).
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorPalettes {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::inline"))]
+    data: [u8; 64],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColorPalettes {
+    pub fn new() -> ColorPalettes {
+        ColorPalettes {
+            data: [0xff; 64],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// The value of BCPS or OCPS, with the unused bit 6 set.
+    pub fn specification(&self) -> u8 {
+        0x40 | (self.auto_increment as u8) << 7 | self.index
+    }
+
+    pub fn set_specification(&mut self, value: u8) {
+        self.index = value & 0x3f;
+        self.auto_increment = value & 0x80 != 0;
+    }
+
+    /// The byte at the index, which BCPD or OCPD reads.
+    pub fn data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    /// Writes the byte at the index, moving on to the next one if the index
+    /// auto-increments.
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        self.increment();
+    }
+
+    /// Moves the index on like a write would without writing, for writes the
+    /// GPU blocks during mode 3.
+    pub fn increment(&mut self) {
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3f;
+        }
+    }
+
+    /// A color as RGB555, with red in the lowest 5 bits.
+    pub fn color(&self, palette: usize, color: usize) -> u16 {
+        let i = palette * 8 + color * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]]) & 0x7fff
+    }
 }
 
+/// The registers games change in the middle of a line for raster effects.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct LineRegisters {
+    lcd_control: LcdControl,
+    scroll_x: u8,
+    scroll_y: u8,
+    bg_palette: [u8; 4],
+}
+
+/// The video hardware. With the `serde` feature enabled, this can be
+/// serialized for save states. The decoded tiles are left out and decoded
+/// from VRAM again on load, as are the debugging [`LayerToggles`], which
+/// stay at their defaults.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct Gpu {
-    pub vram: Box<[u8; 0x2000]>,
-    pub oam: Box<[u8; 0xa0]>,
+    /// Both VRAM banks, with the second one at 0x2000 only used in CGB mode.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    pub(crate) vram: Box<[u8; 0x4000]>,
+    /// The VRAM bank the CPU sees, selected through VBK.
+    vram_bank: usize,
+    /// Whether the GPU works like on a CGB running a CGB game, with the second
+    /// VRAM bank, tile map attributes and color palettes.
+    cgb: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    pub(crate) oam: Box<[u8; 0xa0]>,
     mode_cycles: usize,
+    /// The length of mode 3 on the current line, which sprites and fine
+    /// scrolling stretch out.
+    transfer_cycles: usize,
     line: u8,
-    pub lyc: u8,
+    lyc: u8,
     mode: GpuMode,
-    pub scroll_x: u8,
-    pub scroll_y: u8,
-    pub tiles: Box<[Tile; 384]>,
-    pub framebuffer: Box<[u8; 160 * 144]>,
-    pub lcd_control: LcdControl,
+    pub(crate) scroll_x: u8,
+    pub(crate) scroll_y: u8,
+    /// The tiles in both VRAM banks, with those in the second bank from 384
+    /// on.
+    #[cfg_attr(feature = "serde", serde(skip, default = "blank_tiles"))]
+    pub(crate) tiles: Box<[Tile; 768]>,
+    /// The screen as DMG shades. In CGB mode, these are the color indices
+    /// before the color palettes are applied.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    pub(crate) framebuffer: Box<[u8; 160 * 144]>,
+    /// The layer each pixel of the framebuffer was drawn by.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    pub(crate) layers: Box<[Layer; 160 * 144]>,
+    /// The screen as RGB555 colors, only drawn in CGB mode.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    pub(crate) color_framebuffer: Box<[u16; 160 * 144]>,
+    /// The background and window color indices of the current line, before
+    /// the palette is applied, which decide whether sprites behind the
+    /// background show.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::inline"))]
+    bg_indices: [u8; 160],
+    /// The background and window pixels of the current line whose tile map
+    /// attributes put them in front of sprites, in CGB mode.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::inline"))]
+    bg_priority: [bool; 160],
+    /// Changing the LCD enable bit here skips turning the LCD on or off, see
+    /// [`Gpu::set_lcd_control`].
+    pub(crate) lcd_control: LcdControl,
+    /// The first line after turning the LCD on has no OAM search, reporting
+    /// HBlank in its place.
+    first_line: bool,
+    /// The first frame after turning the LCD on isn't shown.
+    first_frame: bool,
     stat_interrupt_source: StatInterruptSource,
-    pub bg_palette: [u8; 4],
-    pub obj_palette: [[u8; 4]; 2],
-    pub window_coords: (u8, u8),
+    /// The single line all STAT interrupt sources are ORed into. An interrupt
+    /// is only requested when it goes high, so a source becoming active while
+    /// another one already is gets blocked.
+    stat_line: bool,
+    pub(crate) bg_palette: [u8; 4],
+    pub(crate) obj_palette: [[u8; 4]; 2],
+    pub(crate) bg_colors: ColorPalettes,
+    pub(crate) obj_colors: ColorPalettes,
+    pub(crate) window_coords: (u8, u8),
     window_drawing: bool,
     window_line: usize,
+    /// The first pixel the window covered on every line, if it showed.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::boxed"))]
+    window_starts: Box<[Option<u8>; 144]>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub layer_toggles: LayerToggles,
+    /// The line registers before each write to them during mode 3, along with
+    /// the pixel that was being drawn at the time.
+    line_writes: Vec<(usize, LineRegisters)>,
+    frames: usize,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Gpu {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Gpu::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Gpu {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Gpu, D::Error> {
+        let mut gpu = Gpu::deserialize(deserializer)?;
+        gpu.decode_tiles();
+        Ok(gpu)
+    }
 }
 
 impl Gpu {
     pub fn new() -> Gpu {
         Gpu {
-            vram: Box::new([0; 0x2000]),
+            vram: Box::new([0; 0x4000]),
+            vram_bank: 0,
+            cgb: false,
             oam: Box::new([0; 0xa0]),
             mode: GpuMode::HBlank,
             mode_cycles: 0,
+            transfer_cycles: MIN_TRANSFER_CYCLES,
             line: 0,
             lyc: 0,
             scroll_x: 0,
             scroll_y: 0,
-            tiles: Box::new([Tile::new(); 384]),
+            tiles: blank_tiles(),
             framebuffer: Box::new([0; 160 * 144]),
+            layers: Box::new([Layer::Background; 160 * 144]),
+            color_framebuffer: Box::new([WHITE; 160 * 144]),
+            bg_indices: [0; 160],
+            bg_priority: [false; 160],
             lcd_control: LcdControl::empty(),
+            first_line: false,
+            first_frame: false,
             stat_interrupt_source: StatInterruptSource::empty(),
+            stat_line: false,
             bg_palette: [0; 4],
             obj_palette: [[0; 4], [0; 4]],
+            bg_colors: ColorPalettes::new(),
+            obj_colors: ColorPalettes::new(),
             window_coords: (0, 0),
             window_drawing: false,
             window_line: 0,
+            window_starts: Box::new([None; 144]),
+            layer_toggles: LayerToggles::default(),
+            line_writes: Vec::new(),
+            frames: 0,
         }
     }
 
@@ -104,104 +452,490 @@ impl Gpu {
         self.line = 0;
         self.mode = GpuMode::HBlank;
         self.mode_cycles = 0;
+        self.transfer_cycles = MIN_TRANSFER_CYCLES;
+        self.line_writes.clear();
+        self.first_line = false;
+        self.first_frame = false;
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb
+    }
+
+    /// Switches CGB mode on or off, which is decided once before booting.
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb = enabled;
+        self.vram_bank = 0;
+    }
+
+    /// The value of VBK, which only has the bank in bit 0 in CGB mode.
+    pub fn vram_bank(&self) -> u8 {
+        if self.cgb {
+            0xfe | self.vram_bank as u8
+        } else {
+            0xff
+        }
+    }
+
+    pub fn set_vram_bank(&mut self, value: u8) {
+        if self.cgb {
+            self.vram_bank = (value & 1) as usize;
+        }
+    }
+
+    /// Reads from the VRAM bank the CPU sees, at an address from 0 to 0x1fff.
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[self.vram_bank * 0x2000 + address as usize]
+    }
+
+    /// Writes to the VRAM bank the CPU sees, at an address from 0 to 0x1fff.
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        let address = self.vram_bank * 0x2000 + address as usize;
+        self.vram[address] = value;
+        self.update_tile(address as u16);
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcd_control.contains(LcdControl::LCD_ENABLE)
+    }
+
+    /// Whether the LCD is on and the GPU is in H-blank, which is when the
+    /// H-blank DMA of the CGB copies.
+    pub fn in_hblank(&self) -> bool {
+        self.lcd_enabled() && matches!(self.mode, GpuMode::HBlank)
+    }
+
+    /// Writes LCDC. Turning the LCD off resets LY to 0, stops the GPU and
+    /// blanks the screen, and turning it on starts over at the top of a frame
+    /// that is only shown once it finished. The GPU has to be caught up first.
+    pub fn set_lcd_control(&mut self, value: LcdControl) -> Interrupts {
+        let was_enabled = self.lcd_enabled();
+        self.lcd_control = value;
+
+        match (was_enabled, self.lcd_enabled()) {
+            (true, false) => {
+                self.line = 0;
+                self.mode = GpuMode::HBlank;
+                self.mode_cycles = 0;
+                self.line_writes.clear();
+                self.framebuffer.fill(0);
+                self.layers.fill(Layer::Background);
+                self.color_framebuffer.fill(WHITE);
+                self.window_starts.fill(None);
+            }
+            (false, true) => {
+                self.line = 0;
+                self.mode = GpuMode::OamRead;
+                self.mode_cycles = 0;
+                self.first_line = true;
+                self.first_frame = true;
+                self.window_drawing = false;
+            }
+            _ => {}
+        }
+
+        self.update_stat_line()
+    }
+
+    /// The mode as the CPU sees it, which is always HBlank while the LCD is
+    /// off.
+    fn visible_mode(&self) -> GpuMode {
+        if !self.lcd_enabled() || self.first_line && matches!(self.mode, GpuMode::OamRead) {
+            GpuMode::HBlank
+        } else {
+            self.mode
+        }
     }
 
     pub fn stat(&self) -> u8 {
-        let mut value = self.stat_interrupt_source.bits();
-        value |= self.mode as u8;
+        let mut value = 0b1000_0000 | self.stat_interrupt_source.bits();
+        value |= self.visible_mode() as u8;
 
-        if self.line == self.lyc {
+        if self.coincidence() {
             value |= 1 << 2;
         }
 
         value
     }
 
-    pub fn set_stat(&mut self, value: u8) {
+    /// Whether the CPU can access VRAM, which it can't while the GPU draws
+    /// from it in mode 3.
+    pub fn vram_accessible(&self) -> bool {
+        !matches!(self.visible_mode(), GpuMode::VramRead)
+    }
+
+    /// Whether the CPU can access OAM, which it can't while the GPU searches
+    /// it in mode 2 or draws sprites from it in mode 3.
+    pub fn oam_accessible(&self) -> bool {
+        !matches!(self.visible_mode(), GpuMode::OamRead | GpuMode::VramRead)
+    }
+
+    /// Remembers the registers used for rendering before one of them is
+    /// written, so the pixels drawn so far on the line keep the old values.
+    /// Only does something in mode 3, so the GPU has to be caught up first.
+    pub fn latch_line_registers(&mut self) {
+        if let GpuMode::VramRead = self.mode {
+            let pixel = self.transfer_pixel();
+            let registers = self.line_registers();
+            self.line_writes.push((pixel, registers));
+        }
+    }
+
+    /// The pixel on the line the GPU is drawing in mode 3. Pauses for sprites
+    /// aren't taken into account, so this can be a few pixels ahead.
+    fn transfer_pixel(&self) -> usize {
+        let delay = FIRST_PIXEL_DELAY + (self.scroll_x % 8) as usize;
+        self.mode_cycles.saturating_sub(delay).min(160)
+    }
+
+    fn line_registers(&self) -> LineRegisters {
+        LineRegisters {
+            lcd_control: self.lcd_control,
+            scroll_x: self.scroll_x,
+            scroll_y: self.scroll_y,
+            bg_palette: self.bg_palette,
+        }
+    }
+
+    fn set_line_registers(&mut self, registers: LineRegisters) {
+        self.lcd_control = registers.lcd_control;
+        self.scroll_x = registers.scroll_x;
+        self.scroll_y = registers.scroll_y;
+        self.bg_palette = registers.bg_palette;
+    }
+
+    /// Changes the STAT interrupt sources, which requests an interrupt if an
+    /// enabled one is already active. The GPU has to be caught up first.
+    pub fn set_stat(&mut self, value: u8) -> Interrupts {
         self.stat_interrupt_source = StatInterruptSource::from_bits_truncate(value);
+        self.update_stat_line()
+    }
+
+    pub fn lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    /// Changes the line compared against LY, which requests an interrupt if
+    /// that makes the STAT line go high. The GPU has to be caught up first.
+    pub fn set_lyc(&mut self, value: u8) -> Interrupts {
+        self.lyc = value;
+        self.update_stat_line()
+    }
+
+    /// Whether any enabled STAT interrupt source is active, which none are
+    /// while the LCD is off.
+    fn stat_line_level(&self) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+
+        let sources = self.stat_interrupt_source;
+        let mode = match self.mode {
+            GpuMode::HBlank => StatInterruptSource::HBLANK,
+            GpuMode::VBlank => StatInterruptSource::VBLANK,
+            GpuMode::OamRead if self.first_line => StatInterruptSource::empty(),
+            GpuMode::OamRead => StatInterruptSource::OAM,
+            GpuMode::VramRead => StatInterruptSource::empty(),
+        };
+
+        sources.intersects(mode)
+            || (sources.contains(StatInterruptSource::LYC_LY) && self.coincidence())
+    }
+
+    /// The LY value LYC is compared against, which follows LY an M-cycle
+    /// late. In that M-cycle after LY changes, nothing is compared.
+    ///
+    /// On line 153, LY only reads 153 for the first M-cycle before wrapping
+    /// around to 0 early, so the comparison sees 153 for an M-cycle, then
+    /// nothing, and then 0 for the rest of the line and all of line 0.
+    fn compared_line(&self) -> Option<u8> {
+        let dots = match self.mode {
+            GpuMode::OamRead | GpuMode::VBlank => self.mode_cycles,
+            GpuMode::VramRead | GpuMode::HBlank => return Some(self.line),
+        };
+
+        match (self.line, dots) {
+            (0, _) => Some(0),
+            (_, 0..=3) => None,
+            (153, 4..=7) => Some(153),
+            (153, 8..=11) => None,
+            (153, _) => Some(0),
+            (line, _) => Some(line),
+        }
+    }
+
+    fn coincidence(&self) -> bool {
+        self.compared_line() == Some(self.lyc)
+    }
+
+    /// Requests a STAT interrupt if the STAT line went from low to high.
+    fn update_stat_line(&mut self) -> Interrupts {
+        let level = self.stat_line_level();
+        let rising = level && !self.stat_line;
+        self.stat_line = level;
+
+        if rising {
+            Interrupts::LCD_STAT
+        } else {
+            Interrupts::empty()
+        }
     }
 
+    /// LY, the line being drawn, which already reads 0 for most of line 153.
     pub fn scanline(&self) -> u8 {
-        self.line
+        if self.line == 153 && self.mode_cycles >= 4 {
+            0
+        } else {
+            self.line
+        }
+    }
+
+    pub fn mode(&self) -> GpuMode {
+        self.mode
+    }
+
+    /// The tile map the background is currently drawn from.
+    pub fn background_tilemap(&self) -> Tilemap {
+        if self.lcd_control.contains(LcdControl::BG_TILEMAP_AREA) {
+            Tilemap::High
+        } else {
+            Tilemap::Low
+        }
+    }
+
+    /// The tile map the window is currently drawn from.
+    pub fn window_tilemap(&self) -> Tilemap {
+        if self.lcd_control.contains(LcdControl::WINDOW_TILEMAP_AREA) {
+            Tilemap::High
+        } else {
+            Tilemap::Low
+        }
+    }
+
+    /// The part of the background tile map the screen currently shows.
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            x: self.scroll_x,
+            y: self.scroll_y,
+        }
+    }
+
+    /// Draws a whole tile map as 256x256 shades, one byte per pixel, with the
+    /// tile data and palette the background currently uses.
+    pub fn render_tilemap(&self, tilemap: Tilemap, out: &mut [u8]) {
+        assert_eq!(out.len(), 256 * 256, "tile map buffer has the wrong size");
+
+        let signed = !self
+            .lcd_control
+            .contains(LcdControl::BG_WINDOW_TILEDATA_AREA);
+
+        for (i, entry) in self.vram[tilemap.vram_offset()..tilemap.vram_offset() + 0x400]
+            .iter()
+            .enumerate()
+        {
+            let mut tile = *entry as usize;
+            if signed && tile < 128 {
+                tile += 256;
+            }
+
+            let (tile_x, tile_y) = (i % 32 * 8, i / 32 * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    out[(tile_y + y) * 256 + tile_x + x] =
+                        self.bg_palette[self.tiles[tile].get(x, y) as usize];
+                }
+            }
+        }
+    }
+
+    /// The 40 entries in OAM, in order.
+    pub fn sprites(&self) -> [SpriteEntry; 40] {
+        let mut sprites = [SpriteEntry::decode(&[0; 4]); 40];
+        for (sprite, bytes) in sprites.iter_mut().zip(self.oam.chunks_exact(4)) {
+            *sprite = SpriteEntry::decode(bytes);
+        }
+
+        sprites
+    }
+
+    /// The height of every sprite in pixels, 8 or 16 as LCDC says.
+    pub fn sprite_height(&self) -> usize {
+        if self.lcd_control.contains(LcdControl::OBJ_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Draws a sprite as it currently looks, flipped and with the tiles it
+    /// currently uses, as 8 pixels wide and [`Gpu::sprite_height`] pixels
+    /// high color indices, one byte per pixel. Color 0 is transparent.
+    pub fn render_sprite(&self, index: usize, out: &mut [u8]) {
+        let height = self.sprite_height();
+        assert_eq!(out.len(), 8 * height, "sprite buffer has the wrong size");
+
+        let sprite = SpriteEntry::decode(&self.oam[index * 4..index * 4 + 4]);
+        let bank = if self.cgb && sprite.flags.contains(SpriteFlags::BANK) {
+            384
+        } else {
+            0
+        };
+        let first_tile = if height == 16 {
+            sprite.tile & 0xfe
+        } else {
+            sprite.tile
+        } as usize;
+
+        for y in 0..height {
+            let row = if sprite.flags.contains(SpriteFlags::FLIP_Y) {
+                height - 1 - y
+            } else {
+                y
+            };
+            let tile = &self.tiles[bank + first_tile + row / 8];
+
+            for x in 0..8 {
+                out[y * 8 + x] = if sprite.flags.contains(SpriteFlags::FLIP_X) {
+                    tile.get_x_flipped(x, row % 8)
+                } else {
+                    tile.get(x, row % 8)
+                };
+            }
+        }
+    }
+
+    /// The pixels the window covered on the last frame, as the first pixel
+    /// of every line it showed on.
+    pub fn window_area(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.window_starts
+            .iter()
+            .enumerate()
+            .filter_map(|(line, start)| start.map(|start| (line as u8, start)))
+    }
+
+    /// Corrupts OAM like the DMG does when the CPU puts an address in OAM on
+    /// the bus while the GPU searches it, which happens when games increment
+    /// or decrement a pointer into OAM. The GPU reads a row of 8 bytes every
+    /// M-cycle, and the row it is reading gets mixed with the one before it.
+    /// The first row is never corrupted.
+    pub(crate) fn corrupt_oam(&mut self) {
+        if !self.lcd_enabled() || !matches!(self.visible_mode(), GpuMode::OamRead) {
+            return;
+        }
+
+        let row = self.mode_cycles / 4;
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let (current, previous) = (row * 8, row * 8 - 8);
+        let word = |oam: &[u8; 0xa0], i: usize| u16::from_le_bytes([oam[i], oam[i + 1]]);
+        let a = word(&self.oam, current);
+        let b = word(&self.oam, previous);
+        let c = word(&self.oam, previous + 4);
+
+        let corrupted = ((a ^ c) & (b ^ c)) ^ c;
+        self.oam[current..current + 2].copy_from_slice(&corrupted.to_le_bytes());
+        self.oam
+            .copy_within(previous + 2..previous + 8, current + 2);
+    }
+
+    /// The amount of frames finished since power on.
+    pub fn frame_count(&self) -> usize {
+        self.frames
+    }
+
+    /// The number of cycles until the mode changes or the line LYC is compared
+    /// against does, before which [`Gpu::cycle`] does nothing observable.
+    pub fn cycles_until_event(&self) -> usize {
+        if !self.lcd_enabled() {
+            return FRAME_CYCLES.saturating_sub(self.mode_cycles);
+        }
+
+        let comparisons: &[usize] = match (self.mode, self.line) {
+            (GpuMode::VBlank, 153) => &[4, 8, 12],
+            (GpuMode::OamRead, _) | (GpuMode::VBlank, _) => &[4],
+            _ => &[],
+        };
+
+        let next = comparisons
+            .iter()
+            .copied()
+            .find(|dots| *dots > self.mode_cycles)
+            .unwrap_or_else(|| self.mode_duration());
+        next.saturating_sub(self.mode_cycles)
+    }
+
+    fn mode_duration(&self) -> usize {
+        match self.mode {
+            GpuMode::HBlank => LINE_CYCLES - OAM_SEARCH_CYCLES - self.transfer_cycles,
+            GpuMode::VBlank => LINE_CYCLES,
+            GpuMode::OamRead => OAM_SEARCH_CYCLES,
+            GpuMode::VramRead => self.transfer_cycles,
+        }
     }
 
     pub fn cycle(&mut self, cycles: usize) -> (bool, Interrupts) {
         self.mode_cycles += cycles;
 
         let mut new_interrupts = Interrupts::empty();
+        let mut frame = false;
+
+        // With the LCD off, the GPU stops, but blank frames still finish at
+        // the usual rate so the screen keeps being shown.
+        if !self.lcd_enabled() {
+            if self.mode_cycles >= FRAME_CYCLES {
+                self.mode_cycles -= FRAME_CYCLES;
+                self.frames += 1;
+                frame = true;
+            }
+
+            return (frame, new_interrupts);
+        }
 
         match self.mode {
             GpuMode::HBlank => {
-                if self.mode_cycles >= 204 {
-                    self.mode_cycles -= 204;
+                let duration = self.mode_duration();
+                if self.mode_cycles >= duration {
+                    self.mode_cycles -= duration;
                     self.line += 1;
 
-                    if self
-                        .stat_interrupt_source
-                        .contains(StatInterruptSource::LYC_LY)
-                        && self.lyc == self.line
-                    {
-                        new_interrupts.insert(Interrupts::LCD_STAT);
-                    }
-
                     if self.line > 143 {
                         self.mode = GpuMode::VBlank;
 
-                        if self
-                            .stat_interrupt_source
-                            .contains(StatInterruptSource::VBLANK)
-                        {
-                            new_interrupts.insert(Interrupts::LCD_STAT);
-                        }
-
                         new_interrupts.insert(Interrupts::VBLANK);
 
                         self.window_drawing = false;
-
-                        return (true, new_interrupts);
+                        self.first_frame = false;
+                        self.frames += 1;
+                        frame = true;
                     } else {
                         self.mode = GpuMode::OamRead;
-
-                        if self
-                            .stat_interrupt_source
-                            .contains(StatInterruptSource::OAM)
-                        {
-                            new_interrupts.insert(Interrupts::LCD_STAT);
-                        }
                     }
                 }
             }
             GpuMode::VBlank => {
-                if self.mode_cycles >= 456 {
-                    self.mode_cycles -= 456;
+                if self.mode_cycles >= LINE_CYCLES {
+                    self.mode_cycles -= LINE_CYCLES;
                     self.line += 1;
 
                     if self.line > 153 {
                         self.mode = GpuMode::OamRead;
                         self.line = 0;
-
-                        if (self
-                            .stat_interrupt_source
-                            .contains(StatInterruptSource::LYC_LY)
-                            && self.lyc == self.line)
-                            || self
-                                .stat_interrupt_source
-                                .contains(StatInterruptSource::OAM)
-                        {
-                            new_interrupts.insert(Interrupts::LCD_STAT);
-                        }
                     }
                 }
             }
             GpuMode::OamRead => {
-                if self.mode_cycles >= 80 {
-                    self.mode_cycles -= 80;
+                if self.mode_cycles >= OAM_SEARCH_CYCLES {
+                    self.mode_cycles -= OAM_SEARCH_CYCLES;
                     self.mode = GpuMode::VramRead;
+                    self.first_line = false;
+                    self.transfer_cycles = self.transfer_duration();
                 }
             }
             GpuMode::VramRead => {
-                if self.mode_cycles >= 172 {
-                    self.mode_cycles -= 172;
+                if self.mode_cycles >= self.transfer_cycles {
+                    self.mode_cycles -= self.transfer_cycles;
                     self.mode = GpuMode::HBlank;
 
                     if self.window_coords.1 == self.line {
@@ -210,29 +944,25 @@ impl Gpu {
                     }
 
                     self.render_scanline();
-
-                    if self
-                        .stat_interrupt_source
-                        .contains(StatInterruptSource::HBLANK)
-                    {
-                        new_interrupts.insert(Interrupts::LCD_STAT);
-                    }
                 }
             }
         }
 
-        (false, new_interrupts)
+        new_interrupts.insert(self.update_stat_line());
+        (frame, new_interrupts)
     }
 
+    /// Decodes the row of the tile at an address in either VRAM bank again,
+    /// after it was written.
     pub fn update_tile(&mut self, vram_address: u16) {
         let vram_address = vram_address & !1;
 
-        let tile = vram_address / 16;
-
-        if tile >= 384 {
+        let bank_offset = vram_address % 0x2000;
+        if bank_offset >= 0x1800 {
             return;
         }
 
+        let tile = vram_address / 0x2000 * 384 + bank_offset / 16;
         let y = vram_address % 16 / 2;
 
         for x in 0..8 {
@@ -252,26 +982,128 @@ impl Gpu {
         }
     }
 
+    /// Decodes every tile in VRAM again, such as after it was restored.
+    #[cfg(feature = "serde")]
+    fn decode_tiles(&mut self) {
+        for address in (0..self.vram.len() as u16).step_by(2) {
+            self.update_tile(address);
+        }
+    }
+
+    /// Draws the current line. Pixels drawn before a register was written in
+    /// mode 3 are drawn with the old value, for raster effects.
     fn render_scanline(&mut self) {
-        if !self.lcd_control.contains(LcdControl::LCD_ENABLE) {
-            self.framebuffer.fill(0);
+        let writes = std::mem::take(&mut self.line_writes);
+
+        // The first frame after turning the LCD on stays blank.
+        if self.first_frame {
             return;
         }
 
-        if self.lcd_control.contains(LcdControl::BG_WINDOW_ENABLE) {
-            self.render_background_scanline();
+        self.bg_indices = [0; 160];
+        self.bg_priority = [false; 160];
+        self.window_starts[self.line as usize] = None;
+        let mut objects = [false; 160];
+        let mut window_drawn = false;
+        let toggles = self.layer_toggles;
+
+        let current = self.line_registers();
+        let mut start = 0;
+        for (end, registers) in writes.into_iter().chain(iter::once((160, current))) {
+            if end <= start {
+                continue;
+            }
+
+            self.set_line_registers(registers);
+            let pixels = start..end;
+            start = end;
+
+            let bg_enabled = self.lcd_control.contains(LcdControl::BG_WINDOW_ENABLE);
+            if toggles.background.resolve(bg_enabled || self.cgb) {
+                self.render_background_scanline(pixels.clone());
+            } else if toggles.background == LayerVisibility::Hidden {
+                self.clear_background_scanline(pixels.clone());
+            }
+
+            let window_enabled = self.lcd_control.contains(LcdControl::WINDOW_ENABLE);
+            if toggles.window.resolve(window_enabled) {
+                window_drawn |= self.render_window_scanline(pixels.clone());
+            }
+
+            // In CGB mode, LCDC bit 0 doesn't hide the background, but puts
+            // sprites in front of it everywhere instead.
+            if self.cgb && !bg_enabled {
+                self.bg_indices[pixels.clone()].fill(0);
+            }
+
+            if toggles
+                .sprites
+                .resolve(self.lcd_control.contains(LcdControl::OBJ_ENABLE))
+            {
+                objects[pixels].fill(true);
+            }
+        }
+        self.set_line_registers(current);
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+
+        if objects.contains(&true) {
+            self.render_sprite_scanline(&objects);
+        }
+    }
+
+    fn tile_data_index(&self, tile: u8) -> usize {
+        if !self
+            .lcd_control
+            .contains(LcdControl::BG_WINDOW_TILEDATA_AREA)
+            && tile < 128
+        {
+            tile as usize + 256
+        } else {
+            tile as usize
         }
+    }
+
+    /// The tile a tile map entry points to, along with its attributes from
+    /// the second VRAM bank in CGB mode.
+    fn map_entry(&self, address: usize) -> (usize, u8) {
+        let tile = self.tile_data_index(self.vram[address]);
 
-        if self.lcd_control.contains(LcdControl::WINDOW_ENABLE) {
-            self.render_window_scanline();
+        if self.cgb {
+            let attributes = self.vram[0x2000 + address];
+            (tile + (attributes >> 3 & 1) as usize * 384, attributes)
+        } else {
+            (tile, 0)
         }
+    }
 
-        if self.lcd_control.contains(LcdControl::OBJ_ENABLE) {
-            self.render_sprite_scanline();
+    /// Draws a background or window pixel in a color index of the tile.
+    fn draw_background_pixel(&mut self, x: usize, color: u8, attributes: u8) {
+        let index = x + 160 * self.line as usize;
+        self.bg_indices[x] = color;
+        self.bg_priority[x] = attributes & (1 << 7) != 0;
+        self.layers[index] = Layer::Background;
+
+        if self.cgb {
+            let palette = (attributes & 0b111) as usize;
+            self.framebuffer[index] = color;
+            self.color_framebuffer[index] = self.bg_colors.color(palette, color as usize);
+        } else {
+            self.framebuffer[index] = self.bg_palette[color as usize];
         }
     }
 
-    fn render_background_scanline(&mut self) {
+    /// Draws white where the background would be, when it is hidden.
+    fn clear_background_scanline(&mut self, pixels: Range<usize>) {
+        let line = 160 * self.line as usize;
+        self.framebuffer[line + pixels.start..line + pixels.end].fill(0);
+        self.color_framebuffer[line + pixels.start..line + pixels.end].fill(WHITE);
+        self.layers[line + pixels.start..line + pixels.end].fill(Layer::Background);
+    }
+
+    fn render_background_scanline(&mut self, pixels: Range<usize>) {
         let mut address = if self.lcd_control.contains(LcdControl::BG_TILEMAP_AREA) {
             0x1c00
         } else {
@@ -279,56 +1111,44 @@ impl Gpu {
         };
 
         address += (self.line.wrapping_add(self.scroll_y) as usize) / 8 * 32;
-        let mut line_offset = (self.scroll_x / 8) as usize;
+        let map_x = pixels.start + self.scroll_x as usize;
+        let mut line_offset = map_x / 8 % 32;
 
-        let tile_y = self.line.wrapping_add(self.scroll_y) % 8;
+        let tile_y = (self.line.wrapping_add(self.scroll_y) % 8) as usize;
 
-        let mut tile = self.vram[address + line_offset] as usize;
+        let (mut tile, mut attributes) = self.map_entry(address + line_offset);
         line_offset = (line_offset + 1) % 32;
 
-        if !self
-            .lcd_control
-            .contains(LcdControl::BG_WINDOW_TILEDATA_AREA)
-            && tile < 128
-        {
-            tile += 256;
-        }
-
-        let mut tile_x = self.scroll_x % 8;
-        for x in 0..160 {
-            let index = x + 160 * self.line as usize;
-            self.framebuffer[index] =
-                self.bg_palette[self.tiles[tile].get(tile_x as usize, tile_y as usize) as usize];
+        let mut tile_x = map_x % 8;
+        for x in pixels {
+            let color = self.tiles[tile].get_flipped(tile_x, tile_y, attributes);
+            self.draw_background_pixel(x, color, attributes);
 
             tile_x += 1;
             if tile_x == 8 {
                 tile_x = 0;
-                tile = self.vram[address + line_offset] as usize;
+                let entry = self.map_entry(address + line_offset);
+                tile = entry.0;
+                attributes = entry.1;
                 line_offset = (line_offset + 1) % 32;
-
-                if !self
-                    .lcd_control
-                    .contains(LcdControl::BG_WINDOW_TILEDATA_AREA)
-                    && tile < 128
-                {
-                    tile += 256;
-                }
             }
         }
     }
 
-    fn render_window_scanline(&mut self) {
+    /// Draws the part of the window within `pixels`, returning whether the
+    /// window is showing on this line.
+    fn render_window_scanline(&mut self, pixels: Range<usize>) -> bool {
         if self.line < self.window_coords.1 {
-            return;
+            return false;
         }
 
         if !self.window_drawing {
-            return;
+            return false;
         }
 
         if !(0..=166).contains(&self.window_coords.0) || !(0..=143).contains(&self.window_coords.1)
         {
-            return;
+            return false;
         }
 
         let mut address = if self.lcd_control.contains(LcdControl::WINDOW_TILEMAP_AREA) {
@@ -341,58 +1161,102 @@ impl Gpu {
 
         let tile_y = self.window_line % 8;
 
-        let mut tile = self.vram[address] as usize;
+        let real_x = self.window_coords.0.saturating_sub(7) as usize;
+        let start = pixels.start.max(real_x);
+        address += (start - real_x) / 8;
+
+        let (mut tile, mut attributes) = self.map_entry(address);
         address += 1;
 
-        if !self
-            .lcd_control
-            .contains(LcdControl::BG_WINDOW_TILEDATA_AREA)
-            && tile < 128
-        {
-            tile += 256;
-        }
+        let first = &mut self.window_starts[self.line as usize];
+        *first = Some(first.map_or(start as u8, |first| first.min(start as u8)));
 
-        let mut tile_x = 0;
-        let real_x = self.window_coords.0.saturating_sub(7) as usize;
-        for x in 0..160 - real_x {
-            let index = x + real_x + 160 * self.line as usize;
-            self.framebuffer[index] =
-                self.bg_palette[self.tiles[tile].get(tile_x as usize, tile_y as usize) as usize];
+        let mut tile_x = (start - real_x) % 8;
+        for x in start..pixels.end {
+            let color = self.tiles[tile].get_flipped(tile_x, tile_y, attributes);
+            self.draw_background_pixel(x, color, attributes);
 
             tile_x += 1;
             if tile_x == 8 {
                 tile_x = 0;
-                tile = self.vram[address] as usize;
+                let entry = self.map_entry(address);
+                tile = entry.0;
+                attributes = entry.1;
                 address += 1;
-
-                if !self
-                    .lcd_control
-                    .contains(LcdControl::BG_WINDOW_TILEDATA_AREA)
-                    && tile < 128
-                {
-                    tile += 256;
-                }
             }
         }
 
-        self.window_line += 1;
+        true
     }
 
-    fn render_sprite_scanline(&mut self) {
-        let large_sprites = self.lcd_control.contains(LcdControl::OBJ_SIZE);
-        let sprite_height = if large_sprites { 16 } else { 8 };
+    /// The sprites on the current line, the first 10 in OAM whose rows cover
+    /// it. Sprites are picked by their Y coordinate alone, so ones that are
+    /// off screen horizontally still count towards the limit.
+    fn scan_oam(&self) -> Vec<usize> {
+        let sprite_height = self.sprite_height() as isize;
 
-        let mut indices = (0..40)
+        (0..40)
             .filter(|i| {
-                self.line as isize >= self.oam[i * 4] as isize - 16
-                    && (self.line as isize) < (self.oam[i * 4] + sprite_height) as isize - 16
+                let top = self.oam[i * 4] as isize - 16;
+                (top..top + sprite_height).contains(&(self.line as isize))
             })
             .take(10)
-            .collect::<Vec<usize>>();
+            .collect()
+    }
+
+    /// The length of mode 3 on the current line. Fine scrolling discards the
+    /// first pixels of the line, and every sprite pauses the background
+    /// fetcher for 6 dots, plus up to 5 more to finish fetching the
+    /// background tile the sprite starts in if no earlier sprite did.
+    fn transfer_duration(&self) -> usize {
+        let fine_scroll = (self.scroll_x % 8) as usize;
+        let mut cycles = MIN_TRANSFER_CYCLES + fine_scroll;
+
+        if !self.lcd_control.contains(LcdControl::OBJ_ENABLE) {
+            return cycles;
+        }
+
+        let mut sprites: Vec<usize> = self
+            .scan_oam()
+            .iter()
+            .map(|i| self.oam[i * 4 + 1] as usize)
+            .filter(|x| *x < 168)
+            .collect();
+        sprites.sort_unstable();
+
+        let mut fetched_tile = None;
+        for x in sprites {
+            // Sprites at X = 0 start in the tile left of the screen.
+            let position = x + fine_scroll;
+            let tile = position / 8;
+
+            if fetched_tile != Some(tile) {
+                cycles += 5usize.saturating_sub(position % 8);
+                fetched_tile = Some(tile);
+            }
+
+            cycles += 6;
+        }
+
+        cycles
+    }
 
-        indices.sort_by_key(|i| self.oam[i * 4 + 1]);
+    /// Draws the sprites on the current line, only over the pixels where
+    /// `visible` says they were enabled.
+    fn render_sprite_scanline(&mut self, visible: &[bool; 160]) {
+        let large_sprites = self.lcd_control.contains(LcdControl::OBJ_SIZE);
+        let mut indices = self.scan_oam();
+
+        // The sprite with the lowest X coordinate, or the one first in OAM if
+        // those are equal, wins a pixel. The CGB only goes by the order in
+        // OAM. The winner's pixel hides the ones of the other sprites even
+        // when it is hidden behind the background itself.
+        if !self.cgb {
+            indices.sort_by_key(|i| (self.oam[i * 4 + 1], *i));
+        }
+        let mut claimed = [false; 160];
 
-        for i in indices.iter().rev() {
+        for i in indices.iter() {
             let tile_index = self.oam[i * 4 + 2] as usize;
             let sprite_y = self.oam[i * 4] as isize - 16;
             let sprite_x = self.oam[i * 4 + 1] as isize - 8;
@@ -408,16 +1272,23 @@ impl Gpu {
                 }
             }
 
-            let tile = self.tiles[if large_sprites {
-                if y >= 8 {
-                    y -= 8;
-                    (tile_index & 0xfe) + 1
-                } else {
-                    tile_index & 0xfe
-                }
+            let bank = if self.cgb {
+                (attributes >> 3 & 1) as usize * 384
             } else {
-                tile_index
-            }];
+                0
+            };
+
+            let tile = self.tiles[bank
+                + if large_sprites {
+                    if y >= 8 {
+                        y -= 8;
+                        (tile_index & 0xfe) + 1
+                    } else {
+                        tile_index & 0xfe
+                    }
+                } else {
+                    tile_index
+                }];
 
             let bg_priority = attributes & (1 << 7) != 0;
             let palette = ((attributes & (1 << 4)) >> 4) as usize;
@@ -441,11 +1312,485 @@ impl Gpu {
                     break;
                 }
 
-                let index = self.line as usize * 160 + (sprite_x + x as isize) as usize;
-                if !bg_priority || self.framebuffer[index] == 0 {
-                    self.framebuffer[index] = self.obj_palette[palette][pixel];
+                let screen_x = (sprite_x + x as isize) as usize;
+                if !visible[screen_x] || claimed[screen_x] {
+                    continue;
+                }
+                claimed[screen_x] = true;
+
+                // Sprites behind the background only show through color 0,
+                // whatever shade the palette gives it. In CGB mode, the tile
+                // map can also put the background in front.
+                let behind = bg_priority || self.bg_priority[screen_x];
+                if !behind || self.bg_indices[screen_x] == 0 {
+                    let index = self.line as usize * 160 + screen_x;
+                    self.layers[index] = if palette == 0 {
+                        Layer::Object0
+                    } else {
+                        Layer::Object1
+                    };
+
+                    if self.cgb {
+                        let palette = (attributes & 0b111) as usize;
+                        self.framebuffer[index] = pixel as u8;
+                        self.color_framebuffer[index] = self.obj_colors.color(palette, pixel);
+                    } else {
+                        self.framebuffer[index] = self.obj_palette[palette][pixel];
+                    }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Gpu, GpuMode, Layer, LayerToggles, LayerVisibility, LcdControl, SpriteFlags, Tilemap,
+        Viewport, FRAME_CYCLES, OAM_SEARCH_CYCLES,
+    };
+    use crate::cpu::Interrupts;
+
+    #[test]
+    fn render_tilemap() {
+        let mut gpu = Gpu::new();
+        gpu.bg_palette = [0, 1, 2, 3];
+        gpu.tiles[256].set(0, 0, 3);
+        gpu.tiles[5].set(7, 7, 2);
+        gpu.vram[0x1c01] = 5;
+
+        // Tile 0 is at 0x9000 with the signed tile data area.
+        let mut out = vec![0; 256 * 256];
+        gpu.render_tilemap(Tilemap::Low, &mut out);
+        assert_eq!((out[0], out[8 * 256 + 8], out[1]), (3, 3, 0));
+
+        gpu.lcd_control = LcdControl::BG_WINDOW_TILEDATA_AREA | LcdControl::BG_TILEMAP_AREA;
+        assert_eq!(gpu.background_tilemap(), Tilemap::High);
+        gpu.render_tilemap(Tilemap::High, &mut out);
+        assert_eq!((out[0], out[7 * 256 + 15]), (0, 2));
+    }
+
+    #[test]
+    fn sprites() {
+        let mut gpu = Gpu::new();
+        gpu.oam[4..8].copy_from_slice(&[20, 12, 0x43, 0b1011_0101]);
+        gpu.tiles[0x42].set(1, 7, 2);
+        gpu.tiles[0x43].set(0, 0, 3);
+
+        let sprite = gpu.sprites()[1];
+        assert_eq!(
+            (sprite.screen_x(), sprite.screen_y(), sprite.tile),
+            (4, 4, 0x43)
+        );
+        assert_eq!(
+            sprite.flags,
+            SpriteFlags::BEHIND_BACKGROUND | SpriteFlags::FLIP_X | SpriteFlags::PALETTE
+        );
+        assert_eq!((sprite.cgb_palette, sprite.layer()), (5, Layer::Object1));
+
+        let mut out = vec![0; 8 * 8];
+        gpu.render_sprite(1, &mut out);
+        assert_eq!(&out[..8], [0, 0, 0, 0, 0, 0, 0, 3]);
+
+        // Tall sprites start at the even tile, and flip as a whole.
+        gpu.lcd_control = LcdControl::OBJ_SIZE;
+        gpu.oam[7] = 0b0100_0000;
+        let mut out = vec![0; 8 * 16];
+        gpu.render_sprite(1, &mut out);
+        assert_eq!(&out[7 * 8..8 * 8], [3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&out[8 * 8..9 * 8], [0, 2, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn layer_toggles() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE
+            | LcdControl::BG_WINDOW_ENABLE
+            | LcdControl::BG_WINDOW_TILEDATA_AREA
+            | LcdControl::WINDOW_TILEMAP_AREA;
+        gpu.bg_palette = [0, 1, 2, 3];
+        gpu.obj_palette[0] = [0, 1, 2, 3];
+        for x in 0..8 {
+            gpu.tiles[1].set(x, 0, 1);
+            gpu.tiles[2].set(x, 0, 2);
+            gpu.tiles[3].set(x, 0, 3);
+        }
+        gpu.vram[0x1800..0x1c00].fill(1);
+        gpu.vram[0x1c00..0x2000].fill(2);
+        gpu.window_coords = (7 + 80, 0);
+        gpu.window_drawing = true;
+        gpu.oam[0..4].copy_from_slice(&[16, 8 + 76, 3, 0]);
+
+        // The window and sprites are off in LCDC.
+        gpu.render_scanline();
+        assert_eq!((gpu.framebuffer[0], gpu.framebuffer[100]), (1, 1));
+        assert_eq!(gpu.window_area().count(), 0);
+
+        gpu.layer_toggles.window = LayerVisibility::Shown;
+        gpu.layer_toggles.sprites = LayerVisibility::Shown;
+        gpu.render_scanline();
+        assert_eq!(
+            &gpu.framebuffer[74..90],
+            &[1, 1, 3, 3, 3, 3, 3, 3, 3, 3, 2, 2, 2, 2, 2, 2]
+        );
+        assert_eq!(gpu.window_area().collect::<Vec<_>>(), [(0, 80)]);
+
+        // Hiding the background leaves color 0, which sprites show over.
+        gpu.lcd_control |= LcdControl::WINDOW_ENABLE | LcdControl::OBJ_ENABLE;
+        gpu.layer_toggles = LayerToggles {
+            background: LayerVisibility::Hidden,
+            window: LayerVisibility::Hidden,
+            ..LayerToggles::default()
+        };
+        gpu.render_scanline();
+        assert_eq!(
+            &gpu.framebuffer[74..86],
+            &[0, 0, 3, 3, 3, 3, 3, 3, 3, 3, 0, 0]
+        );
+    }
+
+    #[test]
+    fn oam_corruption() {
+        let mut gpu = Gpu::new();
+        gpu.set_lcd_control(LcdControl::LCD_ENABLE);
+        for (i, byte) in gpu.oam.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let original = gpu.oam.clone();
+        let run = |gpu: &mut Gpu, dots: usize| {
+            for _ in 0..dots / 4 {
+                gpu.cycle(4);
+            }
+        };
+
+        // The first line after turning the LCD on has no OAM search, so this
+        // is the third row on the second line.
+        run(&mut gpu, 456 + 8);
+        gpu.corrupt_oam();
+        assert_eq!(
+            gpu.oam[16..24],
+            [0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]
+        );
+        assert_eq!(gpu.oam[..16], original[..16]);
+        assert_eq!(gpu.oam[24..], original[24..]);
+
+        // Outside of mode 2 and in the first row, nothing happens.
+        let corrupted = gpu.oam.clone();
+        run(&mut gpu, 80);
+        gpu.corrupt_oam();
+        run(&mut gpu, 456 - 88);
+        gpu.corrupt_oam();
+        assert_eq!(gpu.oam, corrupted);
+    }
+
+    #[test]
+    fn viewport_wraps() {
+        let mut gpu = Gpu::new();
+        gpu.scroll_x = 200;
+        gpu.scroll_y = 16;
+
+        let viewport = gpu.viewport();
+        assert_eq!(viewport, Viewport { x: 200, y: 16 });
+        assert!(viewport.contains(200, 16));
+        assert!(viewport.contains(255, 159));
+        assert!(viewport.contains(103, 100));
+        assert!(!viewport.contains(104, 100));
+        assert!(!viewport.contains(199, 100));
+        assert!(!viewport.contains(0, 160));
+        assert!(!viewport.contains(0, 15));
+    }
+
+    #[test]
+    fn sprites_behind_background() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE
+            | LcdControl::BG_WINDOW_ENABLE
+            | LcdControl::OBJ_ENABLE
+            | LcdControl::BG_WINDOW_TILEDATA_AREA;
+        // Color 0 of the background is black, but sprites still show through.
+        gpu.bg_palette = [3, 2, 1, 0];
+        gpu.obj_palette[1] = [0, 1, 0, 0];
+        gpu.tiles[0].set(1, 0, 1);
+        gpu.tiles[1].set(0, 0, 1);
+        gpu.tiles[1].set(1, 0, 1);
+        gpu.oam[0..4].copy_from_slice(&[16, 8, 1, 0x80 | 0x10]);
+
+        gpu.render_scanline();
+        assert_eq!(&gpu.framebuffer[0..3], &[1, 2, 3]);
+        assert_eq!(
+            &gpu.layers[0..3],
+            &[Layer::Object1, Layer::Background, Layer::Background]
+        );
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE | LcdControl::OBJ_ENABLE;
+        gpu.obj_palette = [[0, 1, 2, 3], [0, 1, 2, 3]];
+        for (tile, color) in [(1, 1), (2, 2), (3, 3)].iter() {
+            for x in 0..8 {
+                gpu.tiles[*tile].set(x, 0, *color);
+            }
+        }
+
+        // Sprite 1 is further left, so it is on top of sprite 0. Sprite 2 is at
+        // the same position as sprite 1, but comes later in OAM.
+        gpu.oam[0..4].copy_from_slice(&[16, 12, 1, 0]);
+        gpu.oam[4..8].copy_from_slice(&[16, 8, 2, 0]);
+        gpu.oam[8..12].copy_from_slice(&[16, 8, 3, 0]);
+        gpu.render_scanline();
+        assert_eq!(&gpu.framebuffer[0..10], &[2, 2, 2, 2, 2, 2, 2, 2, 1, 1]);
+
+        // A winning sprite behind the background still hides the others.
+        gpu.lcd_control |= LcdControl::BG_WINDOW_ENABLE;
+        gpu.bg_palette = [0, 3, 3, 3];
+        gpu.tiles[256].set(0, 0, 1);
+        gpu.oam[7] = 0x80;
+        gpu.render_scanline();
+        assert_eq!(&gpu.framebuffer[0..2], &[3, 2]);
+    }
+
+    #[test]
+    fn cgb_attributes() {
+        let mut gpu = Gpu::new();
+        gpu.set_cgb_mode(true);
+        gpu.lcd_control = LcdControl::LCD_ENABLE
+            | LcdControl::BG_WINDOW_ENABLE
+            | LcdControl::OBJ_ENABLE
+            | LcdControl::BG_WINDOW_TILEDATA_AREA;
+        gpu.bg_colors.set_specification(0x80 | (3 * 8 + 2));
+        gpu.bg_colors.write_data(0x1f);
+        gpu.bg_colors.write_data(0x00);
+        gpu.obj_colors.set_specification(0x80 | (2 * 8 + 6));
+        gpu.obj_colors.write_data(0xe0);
+        gpu.obj_colors.write_data(0x03);
+
+        // The first tile comes from the second bank, flipped horizontally and
+        // in palette 3. The second one is in front of sprites.
+        gpu.tiles[384].set(7, 0, 1);
+        gpu.vram[0x3800] = 0b0010_1011;
+        gpu.tiles[0].set(0, 0, 2);
+        gpu.vram[0x3801] = 0x80;
+        gpu.vram[0x1801] = 0;
+        for x in 0..8 {
+            gpu.tiles[1].set(x, 0, 3);
+        }
+        gpu.oam[0..4].copy_from_slice(&[16, 16, 1, 2]);
+
+        gpu.render_scanline();
+        assert_eq!(&gpu.framebuffer[0..2], &[1, 0]);
+        assert_eq!(&gpu.color_framebuffer[0..2], &[0x001f, 0x7fff]);
+        assert_eq!(&gpu.color_framebuffer[8..10], &[0x7fff, 0x03e0]);
+
+        // Without LCDC bit 0, sprites are in front of everything.
+        gpu.lcd_control.remove(LcdControl::BG_WINDOW_ENABLE);
+        gpu.render_scanline();
+        assert_eq!(gpu.color_framebuffer[0], 0x001f);
+        assert_eq!(gpu.color_framebuffer[8], 0x03e0);
+    }
+
+    #[test]
+    fn raster_effects() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE
+            | LcdControl::BG_WINDOW_ENABLE
+            | LcdControl::BG_WINDOW_TILEDATA_AREA;
+        gpu.bg_palette = [0, 1, 2, 3];
+        for x in 0..8 {
+            gpu.tiles[0].set(x, 0, 1);
+            gpu.tiles[1].set(x, 0, 2);
+        }
+        gpu.vram[0x1800 + 20] = 1;
+
+        gpu.mode = GpuMode::OamRead;
+        gpu.cycle(80);
+
+        // Halfway through drawing pixel 40, the palette changes.
+        gpu.cycle(12 + 40);
+        gpu.latch_line_registers();
+        gpu.bg_palette = [0, 3, 3, 3];
+
+        // Scrolling at pixel 100 brings tile 20 of the map into view there.
+        gpu.cycle(60);
+        gpu.latch_line_registers();
+        gpu.scroll_x = 60;
+
+        gpu.cycle(gpu.cycles_until_event());
+        assert_eq!(&gpu.framebuffer[38..42], &[1, 1, 3, 3]);
+        assert_eq!(&gpu.framebuffer[98..102], &[3, 3, 3, 3]);
+        assert_eq!(gpu.bg_indices[99..109], [1, 2, 2, 2, 2, 2, 2, 2, 2, 1]);
+    }
+
+    #[test]
+    fn transfer_duration() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE | LcdControl::OBJ_ENABLE;
+        gpu.scroll_x = 3;
+        assert_eq!(gpu.transfer_duration(), 175);
+
+        // The first sprite finishes the fetch of the tile it starts in, the
+        // second one in the same tile doesn't have to.
+        gpu.oam[0..2].copy_from_slice(&[16, 0]);
+        assert_eq!(gpu.transfer_duration(), 175 + 2 + 6);
+        gpu.oam[4..6].copy_from_slice(&[16, 1]);
+        assert_eq!(gpu.transfer_duration(), 175 + 2 + 6 + 6);
+
+        // Only the first 10 sprites on the line count, even off screen ones.
+        for i in 2..12 {
+            gpu.oam[i * 4..i * 4 + 2].copy_from_slice(&[16, 200]);
+        }
+        assert_eq!(gpu.scan_oam(), (0..10).collect::<Vec<_>>());
+        assert_eq!(gpu.transfer_duration(), 175 + 2 + 6 + 6);
+
+        // Mode 3 taking longer makes HBlank shorter.
+        gpu.mode = GpuMode::OamRead;
+        gpu.cycle(80);
+        assert_eq!(gpu.cycles_until_event(), 189);
+        gpu.cycle(189);
+        assert_eq!(gpu.cycles_until_event(), 456 - 80 - 189);
+    }
+
+    #[test]
+    fn stat_blocking() {
+        let mut gpu = Gpu::new();
+        gpu.lcd_control = LcdControl::LCD_ENABLE;
+        gpu.mode = GpuMode::VramRead;
+
+        // Going from HBlank straight into OAM search keeps the line high, so
+        // only HBlank requests an interrupt.
+        assert!(gpu.set_stat(0x28).is_empty());
+        let mut requested = Vec::new();
+        for _ in 0..3 {
+            let (_, interrupts) = gpu.cycle(gpu.cycles_until_event());
+            requested.push(interrupts.contains(Interrupts::LCD_STAT));
+        }
+        assert_eq!(requested, [true, false, false]);
+        assert_eq!(gpu.scanline(), 1);
+
+        // A coincidence during HBlank is blocked too.
+        while !matches!(gpu.mode, GpuMode::HBlank) {
+            gpu.cycle(gpu.cycles_until_event());
+        }
+        assert!(gpu.set_stat(0x48).is_empty());
+        assert!(gpu.set_lyc(1).is_empty());
+        assert!(gpu.set_stat(0x40).is_empty());
+
+        // Once the line went low, the next coincidence gets through.
+        assert!(gpu.set_lyc(2).is_empty());
+        assert_eq!(gpu.set_lyc(1), Interrupts::LCD_STAT);
+    }
+
+    #[test]
+    fn lcd_off_and_on() {
+        let mut gpu = Gpu::new();
+        gpu.bg_palette = [3, 3, 3, 3];
+        let enabled = LcdControl::LCD_ENABLE | LcdControl::BG_WINDOW_ENABLE;
+        gpu.set_lcd_control(enabled);
+
+        // The first line has no OAM search, and the first frame stays blank.
+        assert_eq!(gpu.stat() & 0b11, 0);
+        assert!(gpu.oam_accessible());
+        gpu.cycle(OAM_SEARCH_CYCLES);
+        assert_eq!(gpu.stat() & 0b11, 3);
+
+        while !gpu.cycle(gpu.cycles_until_event()).0 {}
+        assert!(gpu.framebuffer.iter().all(|shade| *shade == 0));
+        while !gpu.cycle(gpu.cycles_until_event()).0 {}
+        assert!(gpu.framebuffer.iter().all(|shade| *shade == 3));
+
+        // Turning it off blanks the screen and stops the GPU on line 0, but
+        // blank frames keep coming.
+        while gpu.scanline() != 5 {
+            gpu.cycle(gpu.cycles_until_event());
+        }
+        gpu.set_stat(0x08);
+        gpu.set_lcd_control(LcdControl::empty());
+        assert_eq!((gpu.scanline(), gpu.stat() & 0b11), (0, 0));
+        assert!(gpu.framebuffer.iter().all(|shade| *shade == 0));
+
+        assert_eq!(gpu.cycles_until_event(), FRAME_CYCLES);
+        assert_eq!(gpu.cycle(FRAME_CYCLES - 4), (false, Interrupts::empty()));
+        assert_eq!(gpu.cycle(4), (true, Interrupts::empty()));
+        assert_eq!(gpu.scanline(), 0);
+    }
+
+    #[test]
+    fn line_153() {
+        let mut gpu = Gpu::new();
+        gpu.set_lcd_control(LcdControl::LCD_ENABLE);
+        gpu.set_stat(0x40);
+        gpu.set_lyc(153);
+        while gpu.line != 153 {
+            gpu.cycle(gpu.cycles_until_event());
+        }
+
+        // (LY, coincidence flag, STAT interrupt requested) after every
+        // M-cycle at the start of line 153 and line 0.
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            let (_, interrupts) = gpu.cycle(4);
+            states.push((
+                gpu.scanline(),
+                gpu.stat() & 0b100 != 0,
+                !interrupts.is_empty(),
+            ));
+        }
+        assert_eq!(
+            states,
+            [
+                (0, true, true),
+                (0, false, false),
+                (0, false, false),
+                (0, false, false),
+            ]
+        );
+
+        // LYC=0 matches from the middle of line 153 on, so line 0 doesn't
+        // raise another interrupt.
+        assert_eq!(gpu.set_lyc(0), Interrupts::LCD_STAT);
+        while gpu.line != 0 {
+            assert!(gpu.cycle(gpu.cycles_until_event()).1.is_empty());
+        }
+        assert_eq!(gpu.stat() & 0b100, 0b100);
+
+        // Other lines compare nothing for an M-cycle after LY changes.
+        while gpu.line != 1 {
+            gpu.cycle(gpu.cycles_until_event());
+        }
+        gpu.set_lyc(1);
+        assert_eq!(gpu.stat() & 0b100, 0);
+        gpu.cycle(4);
+        assert_eq!(gpu.stat() & 0b100, 0b100);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut gpu = Gpu::new();
+        gpu.set_cgb_mode(true);
+        gpu.set_lcd_control(LcdControl::LCD_ENABLE | LcdControl::BG_WINDOW_ENABLE);
+        gpu.set_vram_bank(1);
+        gpu.write_vram(0x0010, 0b1000_0001);
+        gpu.write_vram(0x0011, 0b1000_0000);
+        gpu.oam[3] = 0x42;
+        gpu.scroll_x = 7;
+        for _ in 0..100 {
+            gpu.cycle(4);
+        }
+
+        let json = serde_json::to_string(&gpu).unwrap();
+        let restored: Gpu = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(&restored.vram[..], &gpu.vram[..]);
+        assert_eq!(&restored.oam[..], &gpu.oam[..]);
+        assert_eq!(restored.scanline(), gpu.scanline());
+        assert_eq!(restored.mode_cycles, gpu.mode_cycles);
+        assert_eq!((restored.scroll_x, restored.vram_bank()), (7, 0xff));
+
+        // The first tile of the second bank is decoded from VRAM again.
+        assert_eq!(restored.tiles[385].get(0, 0), 3);
+        assert_eq!(restored.tiles[385].get(7, 0), 1);
+        assert_eq!(restored.tiles[385].get(1, 0), 0);
+    }
+}
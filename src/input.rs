@@ -0,0 +1,237 @@
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::memory::mmu::{JoypadButton, Mmu};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InputParseError {
+    #[error("expected `frame <n>: <action> <buttons>` in {0:?}")]
+    Syntax(String),
+    #[error("invalid frame number {0:?}")]
+    Frame(String),
+    #[error("unknown action {0:?}, expected press, hold or release")]
+    Action(String),
+    #[error("unknown button {0:?}")]
+    Button(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    /// Presses the buttons for a single frame.
+    Press,
+    /// Presses the buttons until they are released.
+    Hold,
+    Release,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: usize,
+    pub action: InputAction,
+    pub buttons: Vec<JoypadButton>,
+}
+
+/// A list of joypad inputs to apply at given frames, used to drive ROMs
+/// without anyone at the controls.
+///
+/// Sequences are written as `;` or newline separated events, such as
+/// `frame 600: press start; frame 700: hold a, b; frame 760: release a, b`.
+#[derive(Debug, Clone, Default)]
+pub struct InputSequence {
+    events: Vec<InputEvent>,
+    next: usize,
+    frame: usize,
+    pressed: Vec<JoypadButton>,
+}
+
+impl InputSequence {
+    pub fn new(mut events: Vec<InputEvent>) -> InputSequence {
+        events.sort_by_key(|event| event.frame);
+
+        InputSequence {
+            events,
+            ..InputSequence::default()
+        }
+    }
+
+    /// The events, ordered by frame.
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len() && self.pressed.is_empty()
+    }
+
+    /// Applies the inputs for the frame that just finished.
+    pub(crate) fn advance(&mut self, mmu: &mut Mmu) {
+        if !self.pressed.is_empty() {
+            mmu.release(&self.pressed);
+            self.pressed.clear();
+        }
+
+        while let Some(event) = self.events.get(self.next) {
+            if event.frame > self.frame {
+                break;
+            }
+
+            match event.action {
+                InputAction::Press => {
+                    mmu.press(&event.buttons);
+                    self.pressed.extend_from_slice(&event.buttons);
+                }
+                InputAction::Hold => mmu.press(&event.buttons),
+                InputAction::Release => mmu.release(&event.buttons),
+            }
+
+            self.next += 1;
+        }
+
+        self.frame += 1;
+    }
+}
+
+impl FromStr for InputSequence {
+    type Err = InputParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let events = s
+            .split(&[';', '\n'][..])
+            .map(str::trim)
+            .filter(|event| !event.is_empty())
+            .map(parse_event)
+            .collect::<Result<_, _>>()?;
+
+        Ok(InputSequence::new(events))
+    }
+}
+
+fn parse_event(event: &str) -> Result<InputEvent, InputParseError> {
+    let syntax_error = || InputParseError::Syntax(event.to_owned());
+
+    let mut parts = event.splitn(2, ':');
+    let frame = parts
+        .next()
+        .and_then(|frame| frame.trim().strip_prefix("frame"))
+        .ok_or_else(syntax_error)?
+        .trim();
+    let frame = frame
+        .parse()
+        .map_err(|_| InputParseError::Frame(frame.to_owned()))?;
+
+    let mut parts = parts.next().ok_or_else(syntax_error)?.trim().splitn(2, ' ');
+    let action = match parts.next().ok_or_else(syntax_error)? {
+        "press" => InputAction::Press,
+        "hold" => InputAction::Hold,
+        "release" => InputAction::Release,
+        action => return Err(InputParseError::Action(action.to_owned())),
+    };
+
+    let buttons = parts
+        .next()
+        .ok_or_else(syntax_error)?
+        .split(',')
+        .map(|button| parse_button(button.trim()))
+        .collect::<Result<_, _>>()?;
+
+    Ok(InputEvent {
+        frame,
+        action,
+        buttons,
+    })
+}
+
+fn parse_button(button: &str) -> Result<JoypadButton, InputParseError> {
+    match button.to_ascii_lowercase().as_str() {
+        "up" => Ok(JoypadButton::Up),
+        "down" => Ok(JoypadButton::Down),
+        "left" => Ok(JoypadButton::Left),
+        "right" => Ok(JoypadButton::Right),
+        "start" => Ok(JoypadButton::Start),
+        "select" => Ok(JoypadButton::Select),
+        "b" => Ok(JoypadButton::B),
+        "a" => Ok(JoypadButton::A),
+        _ => Err(InputParseError::Button(button.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputAction, InputEvent, InputParseError, InputSequence};
+    use crate::{
+        bios::DMG_BIOS,
+        cartridge::Cartridge,
+        gpu::Gpu,
+        memory::{
+            mmu::{JoypadButton, Mmu},
+            Memory,
+        },
+    };
+
+    #[test]
+    fn parse() {
+        let sequence: InputSequence = "frame 700: press A;\nframe 600: hold start, Select"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            sequence.events,
+            vec![
+                InputEvent {
+                    frame: 600,
+                    action: InputAction::Hold,
+                    buttons: vec![JoypadButton::Start, JoypadButton::Select],
+                },
+                InputEvent {
+                    frame: 700,
+                    action: InputAction::Press,
+                    buttons: vec![JoypadButton::A],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            (
+                "600: press a",
+                InputParseError::Syntax("600: press a".to_owned()),
+            ),
+            ("frame x: press a", InputParseError::Frame("x".to_owned())),
+            (
+                "frame 1: push a",
+                InputParseError::Action("push".to_owned()),
+            ),
+            ("frame 1: press c", InputParseError::Button("c".to_owned())),
+        ];
+
+        for (source, expected) in cases.iter() {
+            assert_eq!(
+                source.parse::<InputSequence>().unwrap_err(),
+                *expected,
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn advance() {
+        let mut mmu = Mmu::new(DMG_BIOS, Cartridge::from_bytes(vec![0; 0x8000]), Gpu::new());
+        mmu.write(0xff00, 0x10).unwrap();
+
+        let mut sequence: InputSequence = "frame 1: press a; frame 2: hold b; frame 4: release b"
+            .parse()
+            .unwrap();
+
+        let mut buttons = Vec::new();
+        while !sequence.is_finished() {
+            sequence.advance(&mut mmu);
+            buttons.push(mmu.read(0xff00).unwrap() & 0xf);
+        }
+
+        assert_eq!(buttons, vec![0xf, 0xe, 0xd, 0xd, 0xf]);
+    }
+}
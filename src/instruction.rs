@@ -39,22 +39,30 @@ impl CpuRegister {
     }
 }
 
+/// Shows the register in uppercase, or in lowercase as RGBDS writes it with
+/// `{:#}`.
 impl fmt::Display for CpuRegister {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CpuRegister::A => write!(f, "A"),
-            CpuRegister::B => write!(f, "B"),
-            CpuRegister::C => write!(f, "C"),
-            CpuRegister::D => write!(f, "D"),
-            CpuRegister::E => write!(f, "E"),
-            CpuRegister::H => write!(f, "H"),
-            CpuRegister::L => write!(f, "L"),
-            CpuRegister::F => write!(f, "F"),
-            CpuRegister::AF => write!(f, "AF"),
-            CpuRegister::BC => write!(f, "BC"),
-            CpuRegister::DE => write!(f, "DE"),
-            CpuRegister::HL => write!(f, "HL"),
-            CpuRegister::SP => write!(f, "SP"),
+        let name = match self {
+            CpuRegister::A => "A",
+            CpuRegister::B => "B",
+            CpuRegister::C => "C",
+            CpuRegister::D => "D",
+            CpuRegister::E => "E",
+            CpuRegister::H => "H",
+            CpuRegister::L => "L",
+            CpuRegister::F => "F",
+            CpuRegister::AF => "AF",
+            CpuRegister::BC => "BC",
+            CpuRegister::DE => "DE",
+            CpuRegister::HL => "HL",
+            CpuRegister::SP => "SP",
+        };
+
+        if f.alternate() {
+            write!(f, "{}", name.to_ascii_lowercase())
+        } else {
+            write!(f, "{}", name)
         }
     }
 }
@@ -129,8 +137,14 @@ impl InstructionOperand {
     }
 }
 
+/// Shows the operand with `0x` hex and parentheses for memory, or in RGBDS
+/// syntax with `{:#}`.
 impl fmt::Display for InstructionOperand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_rgbds(f);
+        }
+
         match self {
             InstructionOperand::Register(reg) => reg.fmt(f),
             InstructionOperand::Immediate8(value) => write!(f, "{:#04x}", value),
@@ -154,6 +168,42 @@ impl fmt::Display for InstructionOperand {
     }
 }
 
+impl InstructionOperand {
+    /// The operand as RGBDS writes it, with `$` hex and brackets for memory.
+    /// The high page operands are meant to go with `ldh`.
+    fn fmt_rgbds(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstructionOperand::Register(reg) => write!(f, "{:#}", reg),
+            InstructionOperand::Immediate8(value) => write!(f, "${:02x}", value),
+            InstructionOperand::Immediate16(value) => write!(f, "${:04x}", value),
+            InstructionOperand::OffsetMemoryLocationRegister(0xff00, reg) => {
+                write!(f, "[{:#}]", reg)
+            }
+            InstructionOperand::OffsetMemoryLocationRegister(offset, reg) => {
+                write!(f, "[${:04x}+{:#}]", offset, reg)
+            }
+            InstructionOperand::MemoryLocationRegister(reg) => write!(f, "[{:#}]", reg),
+            InstructionOperand::MemoryLocationRegisterDecrement(reg) => write!(f, "[{:#}-]", reg),
+            InstructionOperand::MemoryLocationRegisterIncrement(reg) => write!(f, "[{:#}+]", reg),
+            InstructionOperand::OffsetMemoryLocationImmediate8(offset, address) => {
+                write!(f, "[${:04x}]", offset.wrapping_add(*address as u16))
+            }
+            InstructionOperand::MemoryLocationImmediate16(address)
+            | InstructionOperand::DoubleMemoryLocationImmediate16(address) => {
+                write!(f, "[${:04x}]", address)
+            }
+        }
+    }
+
+    fn is_high_page(&self) -> bool {
+        matches!(
+            self,
+            InstructionOperand::OffsetMemoryLocationRegister(..)
+                | InstructionOperand::OffsetMemoryLocationImmediate8(..)
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SPOps {
     AddOffset(i8),
@@ -181,7 +231,7 @@ impl fmt::Display for SPOps {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     Noop,
     Stop,
@@ -226,6 +276,9 @@ pub enum Instruction {
 }
 
 impl Instruction {
+    /// The amount of M-cycles the instruction takes. For conditional
+    /// instructions this is the not-taken count; the executor adds the extra
+    /// cycles when the branch is taken.
     pub fn cycles(&self) -> usize {
         match self {
             Instruction::Noop => 1,
@@ -276,10 +329,144 @@ impl Instruction {
             Instruction::Halt => 1,
         }
     }
+
+    /// Shows the instruction in the syntax of RGBDS, with relative jumps
+    /// going to the absolute address they end up at when the instruction is
+    /// at `address`.
+    pub fn rgbds(&self, address: u16) -> Rgbds {
+        Rgbds {
+            instruction: *self,
+            address: Some(address),
+        }
+    }
 }
 
+/// An instruction shown in the syntax of RGBDS, so listings can be
+/// reassembled and compared against RGBDS source, see [`Instruction::rgbds`].
+/// `{:#}` on an instruction shows the same, but with relative jump targets
+/// written relative to the instruction as `@+n`, since where it is isn't
+/// known.
+pub struct Rgbds {
+    instruction: Instruction,
+    address: Option<u16>,
+}
+
+impl fmt::Display for Rgbds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let condition = |flag: &CpuFlag, expected: &bool| {
+            let name = format!("{}{}", if *expected { "" } else { "n" }, flag);
+            name.to_ascii_lowercase()
+        };
+        // Relative jumps are relative to the end of the two byte instruction.
+        let target = |offset: &i8| match self.address {
+            Some(address) => format!(
+                "${:04x}",
+                address.wrapping_add(2).wrapping_add(*offset as u16)
+            ),
+            None => format!("@{:+}", *offset as i16 + 2),
+        };
+
+        match &self.instruction {
+            Instruction::Noop => write!(f, "nop"),
+            Instruction::Stop => write!(f, "stop"),
+            Instruction::Load(to, from) => {
+                let high_page = to.is_high_page() || from.is_high_page();
+                write!(
+                    f,
+                    "ld{} {:#}, {:#}",
+                    if high_page { "h" } else { "" },
+                    to,
+                    from
+                )
+            }
+            Instruction::And(from) => write!(f, "and a, {:#}", from),
+            Instruction::Or(from) => write!(f, "or a, {:#}", from),
+            Instruction::Xor(from) => write!(f, "xor a, {:#}", from),
+            Instruction::Bit(bit, from) => write!(f, "bit {}, {:#}", bit, from),
+            Instruction::Jump(to) => write!(f, "jp {:#}", to),
+            Instruction::JumpIf(flag, expected, to) => {
+                write!(f, "jp {}, ${:04x}", condition(flag, expected), to)
+            }
+            Instruction::JumpRelative(offset) => write!(f, "jr {}", target(offset)),
+            Instruction::JumpRelativeIf(flag, expected, offset) => {
+                write!(f, "jr {}, {}", condition(flag, expected), target(offset))
+            }
+            Instruction::Increment(to) => write!(f, "inc {:#}", to),
+            Instruction::Decrement(to) => write!(f, "dec {:#}", to),
+            Instruction::Call(address) => write!(f, "call ${:04x}", address),
+            Instruction::CallIf(flag, expected, address) => {
+                write!(f, "call {}, ${:04x}", condition(flag, expected), address)
+            }
+            Instruction::Compare(from) => write!(f, "cp a, {:#}", from),
+            Instruction::Add8(to, from, use_carry) => write!(
+                f,
+                "{} {:#}, {:#}",
+                if *use_carry { "adc" } else { "add" },
+                to,
+                from
+            ),
+            Instruction::Add16(to, from) => write!(f, "add {:#}, {:#}", to, from),
+            Instruction::Subtract(from, use_carry) => {
+                write!(
+                    f,
+                    "{} a, {:#}",
+                    if *use_carry { "sbc" } else { "sub" },
+                    from
+                )
+            }
+            Instruction::Push(from) => write!(f, "push {:#}", from),
+            Instruction::Pop(from) => write!(f, "pop {:#}", from),
+            Instruction::RotateLeftA(use_carry) => {
+                write!(f, "{}", if *use_carry { "rla" } else { "rlca" })
+            }
+            Instruction::RotateLeft(to, use_carry) => {
+                write!(f, "{} {:#}", if *use_carry { "rl" } else { "rlc" }, to)
+            }
+            Instruction::RotateRightA(use_carry) => {
+                write!(f, "{}", if *use_carry { "rra" } else { "rrca" })
+            }
+            Instruction::RotateRight(to, use_carry) => {
+                write!(f, "{} {:#}", if *use_carry { "rr" } else { "rrc" }, to)
+            }
+            Instruction::ShiftRight(to, zero) => {
+                write!(f, "sr{} {:#}", if *zero { "l" } else { "a" }, to)
+            }
+            Instruction::ShiftLeft(to) => write!(f, "sla {:#}", to),
+            Instruction::Return => write!(f, "ret"),
+            Instruction::ReturnIf(flag, expected) => {
+                write!(f, "ret {}", condition(flag, expected))
+            }
+            Instruction::ReturnInterrupt => write!(f, "reti"),
+            Instruction::DisableInterrupts => write!(f, "di"),
+            Instruction::EnableInterrupts => write!(f, "ei"),
+            Instruction::Complement => write!(f, "cpl"),
+            Instruction::Swap(to) => write!(f, "swap {:#}", to),
+            Instruction::Rst(vector) => write!(f, "rst ${:02x}", vector * 8),
+            Instruction::DAA => write!(f, "daa"),
+            Instruction::SetBit(bit, to, set) => {
+                write!(f, "{} {}, {:#}", if *set { "set" } else { "res" }, bit, to)
+            }
+            Instruction::SPOps(SPOps::AddOffset(offset)) => write!(f, "add sp, {}", offset),
+            Instruction::SPOps(SPOps::LoadIntoHL(offset)) => write!(f, "ld hl, sp{:+}", offset),
+            Instruction::SPOps(SPOps::LoadFromHL) => write!(f, "ld sp, hl"),
+            Instruction::SetCarryFlag(toggle) => write!(f, "{}cf", if *toggle { "c" } else { "s" }),
+            Instruction::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+/// Shows the instruction for the debugger and traces, or in RGBDS syntax
+/// with `{:#}`, see [`Rgbds`].
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return Rgbds {
+                instruction: *self,
+                address: None,
+            }
+            .fmt(f);
+        }
+
         match self {
             Instruction::Noop => write!(f, "noop"),
             Instruction::Stop => write!(f, "stop"),
@@ -328,17 +515,18 @@ impl fmt::Display for Instruction {
             }
             Instruction::Push(from) => write!(f, "push {}", from),
             Instruction::Pop(from) => write!(f, "pop {}", from),
+            // Rotating through the carry flag is the one without a C.
             Instruction::RotateLeftA(use_carry) => {
-                write!(f, "rl{}a", if *use_carry { "c" } else { "" })
+                write!(f, "rl{}a", if *use_carry { "" } else { "c" })
             }
             Instruction::RotateLeft(to, use_carry) => {
-                write!(f, "rl{} {}", if *use_carry { "c" } else { "" }, to)
+                write!(f, "rl{} {}", if *use_carry { "" } else { "c" }, to)
             }
             Instruction::RotateRightA(use_carry) => {
-                write!(f, "rr{}a", if *use_carry { "c" } else { "" })
+                write!(f, "rr{}a", if *use_carry { "" } else { "c" })
             }
             Instruction::RotateRight(to, use_carry) => {
-                write!(f, "rr{} {}", if *use_carry { "c" } else { "" }, to)
+                write!(f, "rr{} {}", if *use_carry { "" } else { "c" }, to)
             }
             Instruction::ShiftRight(to, zero) => {
                 write!(f, "sr{} {}", if *zero { "l" } else { "a" }, to)
@@ -373,6 +561,7 @@ mod tests {
 
     use crate::{
         cpu::Cpu,
+        decode::{CB_OPCODES, OPCODES},
         memory::{Memory, MemoryError},
     };
 
@@ -440,6 +629,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rgbds() {
+        // (opcode, immediate, address, RGBDS syntax)
+        let cases = [
+            (0x00, 0x0000, 0x0100, "nop"),
+            (0x01, 0x1234, 0x0100, "ld bc, $1234"),
+            (0x07, 0x0000, 0x0100, "rlca"),
+            (0x08, 0xc000, 0x0100, "ld [$c000], sp"),
+            (0x17, 0x0000, 0x0100, "rla"),
+            (0x18, 0x00fe, 0x0150, "jr $0150"),
+            (0x20, 0x0005, 0x0150, "jr nz, $0157"),
+            (0x22, 0x0000, 0x0100, "ld [hl+], a"),
+            (0x3a, 0x0000, 0x0100, "ld a, [hl-]"),
+            (0x36, 0x0042, 0x0100, "ld [hl], $42"),
+            (0x90, 0x0000, 0x0100, "sub a, b"),
+            (0xc4, 0x2000, 0x0100, "call nz, $2000"),
+            (0xd8, 0x0000, 0x0100, "ret c"),
+            (0xe0, 0x0044, 0x0100, "ldh [$ff44], a"),
+            (0xe2, 0x0000, 0x0100, "ldh [c], a"),
+            (0xe8, 0x00ff, 0x0100, "add sp, -1"),
+            (0xe9, 0x0000, 0x0100, "jp hl"),
+            (0xef, 0x0000, 0x0100, "rst $28"),
+            (0xf8, 0x0002, 0x0100, "ld hl, sp+2"),
+            (0xfe, 0x0090, 0x0100, "cp a, $90"),
+        ];
+
+        for (opcode, immediate, address, expected) in cases.iter() {
+            let instruction = (OPCODES[*opcode as usize].unwrap().build)(*immediate);
+            assert_eq!(
+                instruction.rgbds(*address).to_string(),
+                *expected,
+                "{:#04x}",
+                opcode
+            );
+        }
+
+        let cb = |opcode: usize| (CB_OPCODES[opcode].build)(0);
+        assert_eq!(format!("{:#}", cb(0x00)), "rlc b");
+        assert_eq!(format!("{:#}", cb(0x11)), "rl c");
+        assert_eq!(format!("{:#}", cb(0x7e)), "bit 7, [hl]");
+        assert_eq!(format!("{:#}", cb(0xbf)), "res 7, a");
+
+        // Without an address, relative targets are relative to the
+        // instruction itself.
+        let jump = (OPCODES[0x18].unwrap().build)(0x00fe);
+        assert_eq!(format!("{:#}", jump), "jr @+0");
+        assert_eq!(jump.to_string(), "jr -2");
+    }
+
     #[test]
     fn extended_instruction_cycles() {
         let mut memory = InstructionMemory(AtomicU16::new(0));
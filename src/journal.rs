@@ -0,0 +1,314 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::cartridge::{Cartridge, SaveError};
+
+const PAGE_SIZE: usize = 0x100;
+/// A page number, the page and a checksum of both.
+const RECORD_SIZE: usize = 2 + PAGE_SIZE + 4;
+/// Journals are compacted into the save once they hold this many pages, which
+/// is twice the RAM of the largest carts.
+const MAX_RECORDS: usize = 2 * 0x20000 / PAGE_SIZE;
+/// The number of frames between syncs while the device runs, about a second.
+const SYNC_INTERVAL: usize = 60;
+
+/// Writes changes to battery-backed RAM through to disk as they happen, so a
+/// crash loses at most the changes since the last sync.
+///
+/// Changed pages are appended to a journal next to the save, which is only
+/// ever replaced as a whole. Once the journal grows too long it is compacted
+/// into a new save. Loading a save with
+/// [`Cartridge::try_load`](crate::cartridge::Cartridge::try_load) replays the
+/// journal left behind by a crash, skipping a page that was only partly
+/// written.
+pub struct SaveJournal {
+    dir: PathBuf,
+    path: PathBuf,
+    file: File,
+    ram: Vec<u8>,
+    records: usize,
+    frames: usize,
+    error: Option<SaveError>,
+}
+
+impl SaveJournal {
+    /// Starts journaling the cartridge's RAM in the given save directory,
+    /// first writing it as a full save.
+    pub fn open<P: AsRef<Path>>(cart: &Cartridge, dir: P) -> Result<SaveJournal, SaveError> {
+        let dir = dir.as_ref().to_owned();
+        let path = journal_path(&cart.save(&dir)?);
+        let file = create(&path)?;
+
+        Ok(SaveJournal {
+            dir,
+            path,
+            file,
+            ram: cart.ram().to_vec(),
+            records: 0,
+            frames: 0,
+            error: None,
+        })
+    }
+
+    /// The error that stopped journaling, if writing failed.
+    pub fn error(&self) -> Option<&SaveError> {
+        self.error.as_ref()
+    }
+
+    /// Appends the pages that changed since the last sync to the journal and
+    /// waits for them to reach the disk, compacting the journal if it grew too
+    /// long.
+    pub fn sync(&mut self, cart: &Cartridge) -> Result<(), SaveError> {
+        self.append(cart)?;
+
+        if self.records >= MAX_RECORDS {
+            self.compact(cart)?;
+        }
+
+        Ok(())
+    }
+
+    /// Syncs the journal and folds it into a new save, returning the path of
+    /// the save file.
+    pub fn compact(&mut self, cart: &Cartridge) -> Result<PathBuf, SaveError> {
+        self.append(cart)?;
+
+        // The save holds the same RAM as the journal, so replaying the journal
+        // after a crash in between changes nothing.
+        let save_path = cart.save(&self.dir)?;
+        self.file = create(&self.path)?;
+        self.records = 0;
+
+        Ok(save_path)
+    }
+
+    /// Syncs every so often when called by the device after every frame,
+    /// keeping the error around instead of returning it.
+    pub(crate) fn finish_frame(&mut self, cart: &Cartridge) {
+        self.frames += 1;
+
+        if self.frames >= SYNC_INTERVAL && self.error.is_none() {
+            self.frames = 0;
+            if let Err(err) = self.sync(cart) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn append(&mut self, cart: &Cartridge) -> Result<(), SaveError> {
+        let SaveJournal {
+            path,
+            file,
+            ram,
+            records,
+            ..
+        } = self;
+        let mut appended = 0;
+
+        let result = cart
+            .ram()
+            .chunks(PAGE_SIZE)
+            .zip(ram.chunks_mut(PAGE_SIZE))
+            .enumerate()
+            .filter(|(_, (current, synced))| current != synced)
+            .try_for_each(|(page, (current, synced))| {
+                write_record(file, page as u16, current)?;
+                synced.copy_from_slice(current);
+                appended += 1;
+                Ok(())
+            })
+            .and_then(|_| match appended {
+                0 => Ok(()),
+                _ => file.sync_data(),
+            });
+
+        *records += appended;
+        result.map_err(|source| SaveError::Write {
+            path: path.clone(),
+            source,
+        })
+    }
+}
+
+fn create(path: &Path) -> Result<File, SaveError> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|source| SaveError::Write {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// The journal belonging to a save file, like `TETRIS.sav.journal`.
+pub(crate) fn journal_path(save_path: &Path) -> PathBuf {
+    let mut path = save_path.as_os_str().to_owned();
+    path.push(".journal");
+    path.into()
+}
+
+fn write_record<W: Write>(writer: &mut W, page: u16, data: &[u8]) -> io::Result<()> {
+    let mut record = Vec::with_capacity(RECORD_SIZE);
+    record.extend_from_slice(&page.to_le_bytes());
+    record.extend_from_slice(data);
+    record.resize(RECORD_SIZE - 4, 0);
+    let checksum = checksum(&record);
+    record.extend_from_slice(&checksum.to_le_bytes());
+
+    writer.write_all(&record)
+}
+
+/// Applies the pages in a journal to the RAM in order, returning how many were
+/// applied. Replaying stops at the first page that is incomplete or doesn't
+/// match its checksum, which is where a crash interrupted writing.
+pub(crate) fn replay<R: Read>(ram: &mut [u8], mut reader: R) -> io::Result<usize> {
+    let mut journal = Vec::new();
+    reader.read_to_end(&mut journal)?;
+
+    let mut applied = 0;
+    for record in journal.chunks_exact(RECORD_SIZE) {
+        let (contents, stored) = record.split_at(RECORD_SIZE - 4);
+        if checksum(contents).to_le_bytes() != stored {
+            break;
+        }
+
+        let page = u16::from_le_bytes([contents[0], contents[1]]) as usize;
+        let start = page * PAGE_SIZE;
+        if start >= ram.len() {
+            break;
+        }
+
+        let end = (start + PAGE_SIZE).min(ram.len());
+        ram[start..end].copy_from_slice(&contents[2..2 + end - start]);
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+/// The 32-bit FNV-1a hash, which is plenty to catch a torn write.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{journal_path, replay, write_record, SaveJournal, PAGE_SIZE, RECORD_SIZE};
+    use crate::{cartridge::Cartridge, memory::Memory};
+
+    /// A cart with 8 KiB of battery-backed RAM, enabled for writing.
+    fn cart() -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13a].copy_from_slice(b"TETRIS");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut cart = Cartridge::from_bytes(rom);
+        cart.write(0x0000, 0x0a).unwrap();
+        cart
+    }
+
+    #[test]
+    fn journaling() {
+        let dir = env::temp_dir().join(format!("gameboy-rs-journal-{}", process::id()));
+        let mut cart = cart();
+        cart.write(0xa000, 0x11).unwrap();
+
+        let mut journal = SaveJournal::open(&cart, &dir).unwrap();
+        let save_path = cart.save_path(&dir);
+        let path = journal_path(&save_path);
+        assert_eq!(fs::read(&save_path).unwrap()[0], 0x11);
+
+        // Only the pages that changed since the last sync are appended.
+        cart.write(0xa000, 0x22).unwrap();
+        cart.write(0xa001, 0x33).unwrap();
+        cart.write(0xa305, 0x44).unwrap();
+        journal.sync(&cart).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 2 * RECORD_SIZE);
+        journal.sync(&cart).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 2 * RECORD_SIZE);
+
+        cart.write(0xa305, 0x55).unwrap();
+        journal.sync(&cart).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 3 * RECORD_SIZE);
+
+        // After a crash the save is still the old one, and loading it
+        // recovers the journaled changes and folds them into the save.
+        drop(journal);
+        assert_eq!(fs::read(&save_path).unwrap()[0], 0x11);
+        let mut recovered = self::cart();
+        assert_eq!(recovered.try_load(&dir).unwrap(), Some(save_path.clone()));
+        for address in [0xa000, 0xa001, 0xa305].iter() {
+            assert_eq!(recovered.read(*address).ok(), cart.read(*address).ok());
+        }
+        assert_eq!(fs::read(&save_path).unwrap()[0x305], 0x55);
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_journal() {
+        let mut ram = vec![0; 2 * PAGE_SIZE];
+
+        assert_eq!(replay(&mut ram, &[][..]).unwrap(), 0);
+        assert_eq!(replay(&mut ram, &[0xff; 3 * RECORD_SIZE][..]).unwrap(), 0);
+
+        // Pages past the end of the RAM aren't applied, nor anything after.
+        let mut journal = Vec::new();
+        write_record(&mut journal, 2, &[0x11; PAGE_SIZE]).unwrap();
+        write_record(&mut journal, 0, &[0x22; PAGE_SIZE]).unwrap();
+        assert_eq!(replay(&mut ram, &journal[..]).unwrap(), 0);
+        assert_eq!(ram, vec![0; 2 * PAGE_SIZE]);
+
+        // Loading a save with a garbled journal keeps the save as it was.
+        let dir = env::temp_dir().join(format!("gameboy-rs-garbled-{}", process::id()));
+        let mut cart = cart();
+        cart.write(0xa000, 0x11).unwrap();
+        let save_path = cart.save(&dir).unwrap();
+        fs::write(journal_path(&save_path), &[0x42; RECORD_SIZE + 7][..]).unwrap();
+
+        let mut loaded = self::cart();
+        assert_eq!(loaded.try_load(&dir).unwrap(), Some(save_path.clone()));
+        assert_eq!(loaded.read(0xa000).unwrap(), 0x11);
+        assert!(!journal_path(&save_path).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_stops_at_torn_record() {
+        let mut journal = Vec::new();
+        write_record(&mut journal, 1, &[0x11; PAGE_SIZE]).unwrap();
+        write_record(&mut journal, 0, &[0x22; PAGE_SIZE]).unwrap();
+        write_record(&mut journal, 1, &[0x33; PAGE_SIZE]).unwrap();
+        assert_eq!(journal.len(), 3 * RECORD_SIZE);
+
+        let mut ram = vec![0; 2 * PAGE_SIZE];
+        assert_eq!(replay(&mut ram, &journal[..]).unwrap(), 3);
+        assert_eq!((ram[0], ram[PAGE_SIZE]), (0x22, 0x33));
+
+        // The last page was cut short by a crash.
+        let mut ram = vec![0; 2 * PAGE_SIZE];
+        assert_eq!(
+            replay(&mut ram, &journal[..3 * RECORD_SIZE - 1]).unwrap(),
+            2
+        );
+        assert_eq!((ram[0], ram[PAGE_SIZE]), (0x22, 0x11));
+
+        // The second page has a flipped bit, so nothing after it is trusted.
+        journal[RECORD_SIZE + 10] ^= 1;
+        let mut ram = vec![0; 2 * PAGE_SIZE];
+        assert_eq!(replay(&mut ram, &journal[..]).unwrap(), 1);
+        assert_eq!((ram[0], ram[PAGE_SIZE]), (0x00, 0x11));
+    }
+}
@@ -1,10 +1,29 @@
 #![allow(clippy::new_without_default)]
 
+pub mod apu;
 pub mod bios;
 pub mod cartridge;
+pub mod counter;
 pub mod cpu;
+pub mod decode;
 pub mod device;
 pub mod gpu;
+pub mod input;
 pub mod instruction;
+pub mod journal;
+pub mod lint;
 pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod observation;
+pub mod overlay;
+pub mod palette;
+pub mod prelude;
+pub mod profile;
+pub mod rng;
+#[cfg(feature = "serde")]
+mod serde_arrays;
+pub mod serial;
+pub mod sink;
 pub mod timer;
+pub mod trace;
@@ -0,0 +1,71 @@
+use std::{collections::BTreeSet, fmt};
+
+/// Something a game did that the hardware puts up with, but which is almost
+/// always a bug. Mostly useful when developing homebrew.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    /// A write to ROM on a cartridge without a memory bank controller, where
+    /// there is nothing to receive it.
+    RomWrite { pc: u16, address: u16, value: u8 },
+    /// A read of WRAM or HRAM that was never written, which only reliably
+    /// reads as 0 in emulators. Only reported when enabled with
+    /// [`Device::set_uninitialized_reads`](crate::device::Device::set_uninitialized_reads).
+    UninitializedRead { pc: u16, address: u16 },
+}
+
+impl Lint {
+    /// The address of the instruction that caused the lint.
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Lint::RomWrite { pc, .. } | Lint::UninitializedRead { pc, .. } => pc,
+        }
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Lint::RomWrite { .. } => 0,
+            Lint::UninitializedRead { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::RomWrite { pc, address, value } => write!(
+                f,
+                "ROM write attempted at PC={:#06x} ({:#04x} to {:#06x})",
+                pc, value, address
+            ),
+            Lint::UninitializedRead { pc, address } => write!(
+                f,
+                "uninitialized RAM read at PC={:#06x} (from {:#06x})",
+                pc, address
+            ),
+        }
+    }
+}
+
+/// The lints found while running. Every instruction reports each kind of lint
+/// only once, so one in a loop doesn't flood the list.
+#[derive(Default)]
+pub struct Lints {
+    lints: Vec<Lint>,
+    seen: BTreeSet<(u8, u16)>,
+}
+
+impl Lints {
+    pub fn all(&self) -> &[Lint] {
+        &self.lints
+    }
+
+    pub fn clear(&mut self) {
+        *self = Lints::default();
+    }
+
+    pub(crate) fn report(&mut self, lint: Lint) {
+        if self.seen.insert((lint.kind(), lint.pc())) {
+            self.lints.push(lint);
+        }
+    }
+}
@@ -1,16 +1,50 @@
-use std::fs::File;
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
-use clap::{App, Arg};
+use bench::run_bench;
+use clap::{App, AppSettings, Arg};
 use debug::start_debug_view;
-use gameboy::{cartridge::Cartridge, device::Device};
+use dirs::DataDirs;
+use gameboy::{
+    cartridge::Cartridge,
+    device::{Device, DeviceBuilder, UninitializedReads},
+    gpu::Layer,
+    journal::SaveJournal,
+    model::Model,
+    movie::Movie,
+    palette::Palette,
+    sink::{GifRecorder, PngSequence, WavRecorder},
+    trace::{TraceFormat, Tracer},
+};
+use script::{run_headless, Script};
+use template::{Templates, DEFAULT_SCREENSHOT, DEFAULT_TITLE};
 use view::start_view;
 
+mod bench;
+mod condition;
 mod debug;
+mod dirs;
+mod frontend;
+mod screenshot;
+mod script;
+mod session;
+mod stats;
+mod template;
 mod view;
 
+/// The boot ROM needs around 330 frames, so this leaves plenty of headroom.
+const BOOT_FRAME_BUDGET: usize = 600;
+
 fn main() {
     let matches = App::new("gameboy")
         .about("A simple non-color gameboy emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::new("rom")
                 .index(1)
@@ -23,8 +57,256 @@ fn main() {
                 .long("debug")
                 .about("Activates the extra debugging window"),
         )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .takes_value(true)
+                .value_name("FILE")
+                .about("Runs a file of debugger commands, headlessly unless the debugger is active"),
+        )
+        .arg(
+            Arg::new("input")
+                .long("input")
+                .takes_value(true)
+                .value_name("SEQUENCE")
+                .about("Joypad inputs to play back, like \"frame 600: press start\""),
+        )
+        .arg(
+            Arg::new("movie")
+                .long("movie")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("input")
+                .about("Plays back the input of a VBM movie or BizHawk input log"),
+        )
+        .arg(
+            Arg::new("export-movie")
+                .long("export-movie")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("input")
+                .about("Writes the --input sequence as a VBM movie or BizHawk input log"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("N")
+                .about("Seeds the random input of the fuzz script command"),
+        )
+        .arg(
+            Arg::new("debug-session")
+                .long("debug-session")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("debug")
+                .about(
+                    "Imports the debugger's breakpoints from a JSON file, and exports them there",
+                ),
+        )
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .takes_value(true)
+                .value_name("NAME|COLORS")
+                .about(
+                    "The colors to show the screen in, one of grayscale, green, high-contrast, \
+                     blue-orange and red-cyan or four comma-separated rrggbb colors",
+                ),
+        )
+        .arg(
+            Arg::new("obj-palette")
+                .long("obj-palette")
+                .takes_value(true)
+                .value_name("NAME|COLORS")
+                .about("The colors to show sprites in, instead of those of --palette"),
+        )
+        .arg(
+            Arg::new("bios")
+                .long("bios")
+                .takes_value(true)
+                .value_name("FILE")
+                .about("Boots with a dump of the DMG boot ROM instead of the built-in one"),
+        )
+        .arg(
+            Arg::new("skip-boot")
+                .long("skip-boot")
+                .conflicts_with_all(&["bios", "check-boot"])
+                .about("Starts the game right away, set up like the boot ROM would have"),
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .takes_value(true)
+                .value_name("MODEL")
+                .possible_values(&["dmg", "mgb", "cgb", "cgb-dmg"])
+                .about(
+                    "The Game Boy to emulate, where cgb runs games made for it in color \
+                     and cgb-dmg is a Game Boy Color that runs every game in DMG mode. \
+                     Defaults to cgb for games that require it and dmg otherwise",
+                ),
+        )
+        .arg(
+            Arg::new("force-dmg")
+                .long("force-dmg")
+                .conflicts_with("model")
+                .about("Emulates the original Game Boy even for games that require the CGB"),
+        )
+        .arg(
+            Arg::new("save-dir")
+                .long("save-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .about("The directory to keep save games in"),
+        )
+        .arg(
+            Arg::new("screenshot-dir")
+                .long("screenshot-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .about("The directory to write screenshots to"),
+        )
+        .arg(
+            Arg::new("save-name")
+                .long("save-name")
+                .takes_value(true)
+                .value_name("NAME")
+                .about("The file name of the save game, instead of one based on the game's title"),
+        )
+        .arg(
+            Arg::new("journal-saves")
+                .long("journal-saves")
+                .about("Writes changes to the save to disk as they happen, to survive crashes"),
+        )
+        .arg(
+            Arg::new("title-format")
+                .long("title-format")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .default_value(DEFAULT_TITLE)
+                .about("The window title, like \"{title} [{fps}fps] - gameboy-rs\""),
+        )
+        .arg(
+            Arg::new("screenshot-name")
+                .long("screenshot-name")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .default_value(DEFAULT_SCREENSHOT)
+                .about("The screenshot file name, like \"{title}_{date}_{frame}.png\""),
+        )
+        .arg(
+            Arg::new("screenshot-mode")
+                .long("screenshot-mode")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(&["native", "displayed"])
+                .default_value("native")
+                .about("Whether F12 saves the game's 160x144 pixels, or the window as shown"),
+        )
+        .arg(
+            Arg::new("record-png")
+                .long("record-png")
+                .takes_value(true)
+                .value_name("DIR")
+                .about("Writes every frame to a numbered PNG file in a directory"),
+        )
+        .arg(
+            Arg::new("record-gif")
+                .long("record-gif")
+                .takes_value(true)
+                .value_name("FILE")
+                .about("Records the game as an animated GIF"),
+        )
+        .arg(
+            Arg::new("record-wav")
+                .long("record-wav")
+                .takes_value(true)
+                .value_name("FILE")
+                .about("Records the sound as a WAV file"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("FILE")
+                .about("Writes the CPU state before every instruction to a file"),
+        )
+        .arg(
+            Arg::new("trace-format")
+                .long("trace-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["doctor", "binary"])
+                .default_value("doctor")
+                .about("Traces in Gameboy Doctor's text format, or in compact binary records"),
+        )
+        .arg(
+            Arg::new("trace-frames")
+                .long("trace-frames")
+                .takes_value(true)
+                .value_name("START..END")
+                .about("Only traces the frames in this range"),
+        )
+        .arg(
+            Arg::new("no-access-restrictions")
+                .long("no-access-restrictions")
+                .about("Lets the CPU access VRAM and OAM while the GPU is using them"),
+        )
+        .arg(
+            Arg::new("oam-corruption")
+                .long("oam-corruption")
+                .about("Corrupts OAM like the DMG when pointers into it are incremented"),
+        )
+        .arg(
+            Arg::new("uninitialized-reads")
+                .long("uninitialized-reads")
+                .takes_value(true)
+                .value_name("ACTION")
+                .possible_values(&["ignore", "log", "trap"])
+                .default_value("ignore")
+                .about("Warns about, or stops on, reads of RAM the game never wrote"),
+        )
+        .arg(
+            Arg::new("check-boot")
+                .long("check-boot")
+                .about("Checks headlessly that the boot ROM accepts the ROM and jumps to it"),
+        )
+        .subcommand(
+            App::new("bench")
+                .about("Runs a ROM headlessly as fast as possible and reports the speed")
+                .arg(
+                    Arg::new("rom")
+                        .index(1)
+                        .required(true)
+                        .about("The gameboy ROM file to run"),
+                )
+                .arg(
+                    Arg::new("seconds")
+                        .long("seconds")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .default_value("10")
+                        .about("How long to run for"),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        let seconds: f64 = matches
+            .value_of("seconds")
+            .unwrap_or("10")
+            .parse()
+            .expect("invalid number of seconds");
+        let cart = Cartridge::new(
+            File::open(matches.value_of("rom").expect("no rom argument supplied"))
+                .expect("file not found"),
+        )
+        .expect("failed to read file");
+
+        run_bench(Device::new(cart), Duration::from_secs_f64(seconds));
+        return;
+    }
+
     let mut cart = Cartridge::new(
         File::open(
             matches
@@ -34,12 +316,249 @@ fn main() {
         .expect("file not found"),
     )
     .expect("failed to read file");
-    cart.try_load();
-    let device = Device::new(cart);
+    let mut dirs = DataDirs::new();
+    if let Some(dir) = matches.value_of("save-dir") {
+        dirs.saves = dir.into();
+    }
+    if let Some(dir) = matches.value_of("screenshot-dir") {
+        dirs.screenshots = dir.into();
+    }
+
+    if let Some(name) = matches.value_of("save-name") {
+        cart.set_save_name(name.to_owned());
+    }
+
+    let save_loaded = match dirs.load_game(&mut cart) {
+        Ok(Some(path)) => {
+            println!("loaded save from {}", path.display());
+            true
+        }
+        Ok(None) => false,
+        Err(err) => {
+            println!("warning: {}, starting without a save", err);
+            false
+        }
+    };
+    let model = match matches.value_of("model") {
+        Some(model) => model.parse().expect("invalid model"),
+        None if cart.requires_cgb() && !matches.is_present("force-dmg") => {
+            println!("this game requires the Game Boy Color, running it as one");
+            Model::Cgb
+        }
+        None => Model::Dmg,
+    };
+    if model != Model::Cgb && cart.requires_cgb() {
+        println!("warning: this game requires Game Boy Color support and will likely not work");
+    }
+    let mut builder = DeviceBuilder::new(cart).model(model);
+    if let Some(path) = matches.value_of("bios") {
+        let rom = fs::read(path).expect("failed to read boot ROM");
+        builder = builder.boot_rom(rom).expect("invalid boot ROM");
+    }
+    if matches.is_present("skip-boot") {
+        builder = builder.skip_boot();
+    }
+    let mut device = builder.build();
+
+    if matches.is_present("journal-saves") {
+        match SaveJournal::open(device.cart(), &dirs.saves) {
+            Ok(journal) => device.set_save_journal(journal),
+            Err(err) => println!("warning: {}, saving only on exit", err),
+        }
+    }
+
+    if let Some(palette) = matches.value_of("palette") {
+        device.set_palette(Palette::parse(palette).expect("unknown palette"));
+    }
+
+    if let Some(palette) = matches.value_of("obj-palette") {
+        let palette = Palette::parse(palette).expect("unknown palette");
+        device.set_layer_palette(Layer::Object0, palette);
+        device.set_layer_palette(Layer::Object1, palette);
+    }
+
+    if matches.is_present("no-access-restrictions") {
+        device.set_access_restrictions(false);
+    }
+
+    if matches.is_present("oam-corruption") {
+        device.set_oam_corruption(true);
+    }
+
+    device.set_uninitialized_reads(match matches.value_of("uninitialized-reads") {
+        Some("log") => UninitializedReads::Log,
+        Some("trap") => UninitializedReads::Trap,
+        _ => UninitializedReads::Ignore,
+    });
+
+    if let Some(input) = matches.value_of("input") {
+        device.set_input_sequence(input.parse().expect("invalid input sequence"));
+    }
+
+    if let Some(path) = matches.value_of("movie") {
+        let movie = load_movie(path).expect("failed to load movie");
+        device.set_input_sequence(movie.to_input_sequence());
+    }
+
+    if let Some(seed) = matches.value_of("seed") {
+        device.set_rng_seed(seed.parse().expect("invalid seed"));
+    }
+
+    if let Some(path) = matches.value_of("export-movie") {
+        let input = matches
+            .value_of("input")
+            .unwrap_or_default()
+            .parse()
+            .expect("invalid input sequence");
+        let movie = Movie::from_input_sequence(&input);
+        save_movie(path, &movie).expect("failed to export movie");
+        println!("exported {} frames to {}", movie.frames().len(), path);
+
+        return;
+    }
+
+    if let Some(dir) = matches.value_of("record-png") {
+        let sink = PngSequence::new(dir).expect("failed to create PNG directory");
+        device.add_frame_sink(Box::new(sink));
+    }
+
+    if let Some(path) = matches.value_of("record-gif") {
+        let sink = GifRecorder::create(path).expect("failed to create GIF file");
+        device.add_frame_sink(Box::new(sink));
+    }
+
+    if let Some(path) = matches.value_of("record-wav") {
+        let sink = WavRecorder::create(path).expect("failed to create WAV file");
+        device.add_audio_sink(Box::new(sink));
+    }
+
+    if let Some(path) = matches.value_of("trace") {
+        let file = File::create(path).expect("failed to create trace file");
+        let format: TraceFormat = matches
+            .value_of("trace-format")
+            .unwrap_or("doctor")
+            .parse()
+            .expect("invalid trace format");
+        let mut tracer = Tracer::new(BufWriter::new(file), format);
+        if let Some(frames) = matches.value_of("trace-frames") {
+            tracer = tracer.with_frames(parse_frames(frames).expect("invalid trace frame range"));
+        }
+
+        device.set_tracer(tracer);
+    }
+
+    if matches.is_present("check-boot") {
+        match device.check_boot(BOOT_FRAME_BUDGET) {
+            Ok(frames) => println!("boot ok, reached 0x0100 after {} frames", frames),
+            Err(err) => {
+                println!("boot failed: {}", err);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let templates = Templates {
+        title: matches
+            .value_of("title-format")
+            .unwrap_or(DEFAULT_TITLE)
+            .parse()
+            .expect("invalid title format"),
+        screenshot: matches
+            .value_of("screenshot-name")
+            .unwrap_or(DEFAULT_SCREENSHOT)
+            .parse()
+            .expect("invalid screenshot name"),
+    };
+
+    let screenshot_mode = matches
+        .value_of("screenshot-mode")
+        .unwrap_or("native")
+        .parse()
+        .expect("invalid screenshot mode");
+
+    let script = matches
+        .value_of("script")
+        .map(|path| Script::load(path).expect("failed to load script"));
+
+    // Scripts without the debugger run headless, which isn't playing.
+    if save_loaded && (matches.is_present("debug") || script.is_none()) {
+        stats::record_load(&dirs.stats, &device);
+    }
 
     if matches.is_present("debug") {
-        start_debug_view(device);
+        let session = matches.value_of("debug-session").map(PathBuf::from);
+        start_debug_view(device, dirs, templates, script, session, screenshot_mode);
+    } else if let Some(script) = script {
+        if let Err(err) = run_headless(device, script, &dirs) {
+            println!("script failed: {:?}", err);
+        }
+    } else {
+        start_view(device, dirs, templates, screenshot_mode);
+    }
+}
+
+/// Parses a frame range like `100..200`, where either end may be left out.
+fn parse_frames(range: &str) -> Option<Range<usize>> {
+    let (start, end) = range.split_at(range.find("..")?);
+    let start = match start {
+        "" => 0,
+        start => start.parse().ok()?,
+    };
+    let end = match &end[2..] {
+        "" => usize::MAX,
+        end => end.parse().ok()?,
+    };
+
+    if start > end {
+        return None;
+    }
+
+    Some(start..end)
+}
+
+fn is_vbm(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext.eq_ignore_ascii_case("vbm"))
+}
+
+/// Loads a VBM movie, or a BizHawk input log for any other extension.
+fn load_movie<P: AsRef<Path>>(path: P) -> anyhow::Result<Movie> {
+    let path = path.as_ref();
+    let movie = if is_vbm(path) {
+        Movie::from_vbm(&fs::read(path)?)?
+    } else {
+        Movie::from_bk2_log(&fs::read_to_string(path)?)?
+    };
+
+    Ok(movie)
+}
+
+fn save_movie<P: AsRef<Path>>(path: P, movie: &Movie) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    if is_vbm(path) {
+        fs::write(path, movie.to_vbm())?;
     } else {
-        start_view(device);
+        fs::write(path, movie.to_bk2_log())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_frames;
+
+    #[test]
+    fn frame_ranges() {
+        assert_eq!(parse_frames("100..200"), Some(100..200));
+        assert_eq!(parse_frames("100.."), Some(100..usize::MAX));
+        assert_eq!(parse_frames("..200"), Some(0..200));
+        assert_eq!(parse_frames(".."), Some(0..usize::MAX));
+        assert_eq!(parse_frames("100..100"), Some(100..100));
+
+        for range in ["", "100", "200..100", "a..b", "1..b", "-1..5", "1...5"].iter() {
+            assert_eq!(parse_frames(range), None, "{}", range);
+        }
     }
 }
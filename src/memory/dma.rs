@@ -0,0 +1,265 @@
+/// The OAM DMA transfer started by writing the high byte of a source address
+/// to DMA (0xff46), which copies 0xa0 bytes from there to OAM at one byte per
+/// M-cycle. The transfer starts after a cycle of setup and takes 160 M-cycles,
+/// during which the CPU can only reach HRAM and the I/O registers.
+#[derive(Debug, Clone)]
+pub struct OamDma {
+    /// The value last written to DMA, which it reads back as.
+    register: u8,
+    /// The source address of a requested transfer and the M-cycles until it
+    /// starts. A transfer that is already running goes on until then.
+    pending: Option<(u16, u8)>,
+    /// The source address of the next byte of the running transfer.
+    next: Option<u16>,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xff,
+            pending: None,
+            next: None,
+        }
+    }
+
+    pub fn register(&self) -> u8 {
+        self.register
+    }
+
+    /// Requests a transfer from `value * 0x100`, as written during the
+    /// current M-cycle.
+    pub fn start(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some(((value as u16) << 8, 1));
+    }
+
+    /// Whether a transfer is running, keeping the CPU off the bus.
+    pub fn is_active(&self) -> bool {
+        self.next.is_some()
+    }
+
+    /// The bytes of the running transfer left to copy.
+    pub fn remaining(&self) -> usize {
+        self.next.map_or(0, |next| 0xa0 - (next & 0xff) as usize)
+    }
+
+    /// Advances a single M-cycle, returning the address of the byte to copy
+    /// during it. The low byte of the address is the offset into OAM.
+    pub fn cycle(&mut self) -> Option<u16> {
+        let address = self.next;
+        if let Some(address) = address {
+            let next = address + 1;
+            self.next = if next & 0xff < 0xa0 { Some(next) } else { None };
+        }
+
+        if let Some((source, delay)) = self.pending.as_mut() {
+            if *delay == 0 {
+                self.next = Some(*source);
+                self.pending = None;
+            } else {
+                *delay -= 1;
+            }
+        }
+
+        address
+    }
+}
+
+/// The M-cycles the CPU waits for every 16-byte block a VRAM DMA transfer
+/// copies.
+const BLOCK_STALL: usize = 8;
+
+/// The VRAM DMA of the CGB, which copies blocks of 16 bytes from ROM or RAM
+/// to the VRAM bank in use. HDMA1-HDMA4 (0xff51-0xff54) hold the source and
+/// destination addresses, and writing the number of blocks minus one to
+/// HDMA5 (0xff55) starts a transfer. With bit 7 clear, a general-purpose
+/// transfer copies everything at once, and with it set, an H-blank transfer
+/// copies a block at the start of every H-blank. The CPU is halted while
+/// blocks are copied.
+#[derive(Debug, Clone)]
+pub struct VramDma {
+    source: u16,
+    /// The offset into VRAM of the destination.
+    destination: u16,
+    /// The blocks left to copy.
+    remaining: u8,
+    /// Whether an H-blank transfer is running.
+    hblank: bool,
+    /// Whether the GPU was in H-blank during the last M-cycle.
+    in_hblank: bool,
+    /// The M-cycles the CPU still has to wait.
+    stall: usize,
+}
+
+impl VramDma {
+    pub fn new() -> VramDma {
+        VramDma {
+            source: 0,
+            destination: 0,
+            remaining: 0,
+            hblank: false,
+            in_hblank: false,
+            stall: 0,
+        }
+    }
+
+    /// Writes one of HDMA1-HDMA4. The low 4 bits of the addresses are
+    /// ignored, and the destination always lies in VRAM.
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xff51 => self.source = (self.source & 0x00ff) | (value as u16) << 8,
+            0xff52 => self.source = (self.source & 0xff00) | (value & 0xf0) as u16,
+            0xff53 => self.destination = (self.destination & 0x00ff) | ((value & 0x1f) as u16) << 8,
+            0xff54 => self.destination = (self.destination & 0xff00) | (value & 0xf0) as u16,
+            _ => {}
+        }
+    }
+
+    /// The value of HDMA5: the blocks left minus one, with bit 7 clear while
+    /// an H-blank transfer runs. It reads 0xff once a transfer is done.
+    pub fn control(&self) -> u8 {
+        let length = self.remaining.wrapping_sub(1) & 0x7f;
+        if self.hblank {
+            length
+        } else {
+            0x80 | length
+        }
+    }
+
+    /// Writes HDMA5, returning whether it starts a general-purpose transfer,
+    /// whose blocks the caller copies right away. Clearing bit 7 while an
+    /// H-blank transfer runs stops it instead.
+    pub fn start(&mut self, value: u8) -> bool {
+        if self.hblank && value & 0x80 == 0 {
+            self.hblank = false;
+            return false;
+        }
+
+        self.remaining = (value & 0x7f) + 1;
+        self.hblank = value & 0x80 != 0;
+        self.in_hblank = false;
+        !self.hblank
+    }
+
+    /// Whether an H-blank transfer is running.
+    pub fn is_active(&self) -> bool {
+        self.hblank
+    }
+
+    /// The blocks of the current transfer left to copy.
+    pub fn remaining(&self) -> usize {
+        self.remaining as usize
+    }
+
+    /// Takes the next block of the transfer, as its source address and the
+    /// offset into VRAM to copy it to, and halts the CPU while it is copied.
+    pub fn block(&mut self) -> Option<(u16, u16)> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(0x10);
+        self.destination = (self.destination + 0x10) & 0x1ff0;
+        self.remaining -= 1;
+        self.hblank &= self.remaining > 0;
+        self.stall += BLOCK_STALL;
+
+        Some(block)
+    }
+
+    /// Advances a single M-cycle of an H-blank transfer with whether the GPU
+    /// is in H-blank, returning the block to copy when an H-blank starts.
+    pub fn cycle(&mut self, in_hblank: bool) -> Option<(u16, u16)> {
+        let entered = in_hblank && !self.in_hblank;
+        self.in_hblank = in_hblank;
+
+        if entered && self.hblank {
+            self.block()
+        } else {
+            None
+        }
+    }
+
+    /// Takes a single M-cycle the CPU has to wait, if any.
+    pub fn take_stall(&mut self) -> bool {
+        if self.stall > 0 {
+            self.stall -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OamDma, VramDma};
+
+    #[test]
+    fn timing() {
+        let mut dma = OamDma::new();
+        dma.start(0xc1);
+        assert_eq!(dma.register(), 0xc1);
+
+        // The cycle of the write and a cycle of setup.
+        assert_eq!(dma.cycle(), None);
+        assert_eq!(dma.cycle(), None);
+        assert!(dma.is_active());
+
+        let addresses = (0..160).map(|_| dma.cycle()).collect::<Vec<_>>();
+        assert_eq!(addresses[0], Some(0xc100));
+        assert_eq!(addresses[159], Some(0xc19f));
+        assert!(addresses.iter().all(Option::is_some));
+        assert!(!dma.is_active());
+        assert_eq!(dma.cycle(), None);
+
+        // Restarting keeps the running transfer going until the new one
+        // starts over.
+        dma.start(0xc2);
+        dma.cycle();
+        dma.cycle();
+        for _ in 0..10 {
+            dma.cycle();
+        }
+        assert_eq!(dma.remaining(), 150);
+        dma.start(0xc3);
+        assert_eq!(dma.cycle(), Some(0xc20a));
+        assert_eq!(dma.cycle(), Some(0xc20b));
+        assert_eq!(dma.cycle(), Some(0xc300));
+    }
+
+    #[test]
+    fn vram_dma() {
+        let mut dma = VramDma::new();
+        assert_eq!(dma.control(), 0xff);
+
+        dma.write(0xff51, 0xc1);
+        dma.write(0xff52, 0x2f);
+        dma.write(0xff53, 0xe8);
+        dma.write(0xff54, 0x1f);
+        assert!(dma.start(0x01));
+        assert_eq!(dma.block(), Some((0xc120, 0x0810)));
+        assert_eq!(dma.block(), Some((0xc130, 0x0820)));
+        assert_eq!(dma.block(), None);
+        assert_eq!(dma.control(), 0xff);
+        assert_eq!((0..20).filter(|_| dma.take_stall()).count(), 16);
+
+        // An H-blank transfer copies a block whenever H-blank starts, also
+        // when it is already in H-blank when started.
+        assert!(!dma.start(0x82));
+        assert_eq!(dma.control(), 0x02);
+        assert_eq!(dma.cycle(true), Some((0xc140, 0x0830)));
+        assert_eq!(dma.cycle(true), None);
+        assert_eq!(dma.cycle(false), None);
+        assert_eq!(dma.cycle(true), Some((0xc150, 0x0840)));
+        assert_eq!(dma.control(), 0x00);
+
+        // Clearing bit 7 stops it, with bit 7 of HDMA5 set again.
+        assert!(!dma.start(0x00));
+        assert!(!dma.is_active());
+        assert_eq!(dma.control(), 0x80);
+        assert_eq!(dma.cycle(false), None);
+        assert_eq!(dma.cycle(true), None);
+    }
+}
@@ -1,15 +1,29 @@
-use crate::{cpu::Interrupts, timer::Timer};
-use anyhow::Context;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use crate::{apu::Apu, cpu::Interrupts, serial::Serial, timer::Timer};
 
 use crate::{
     cartridge::Cartridge,
-    cpu::Cpu,
+    cpu::{Cpu, CpuError},
     gpu::{Gpu, LcdControl},
 };
 
-use super::{Memory, MemoryError, MemoryOperation};
+use super::{
+    dma::{OamDma, VramDma},
+    watch::{WatchHit, Watchpoints},
+    BusSnooper, Memory, MemoryError, MemoryOperation,
+};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// The number of controllers input can be given for, as many as the Super
+/// Game Boy takes.
+pub const PLAYERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoypadButton {
     Up,
     Down,
@@ -50,133 +64,536 @@ impl JoypadButton {
 }
 
 pub struct Mmu {
-    bios: &'static [u8],
+    bios: Cow<'static, [u8]>,
     pub use_bios: bool,
+    /// Whether VRAM and OAM read as 0xff and ignore writes while the GPU uses
+    /// them, like on hardware. Turning this off can help when debugging.
+    pub access_restrictions: bool,
+    /// Whether OAM gets corrupted like on the DMG, see [`Gpu::corrupt_oam`].
+    pub oam_corruption: bool,
     pub cart: Cartridge,
     pub gpu: Gpu,
     pub timer: Timer,
-    wram: Box<[u8; 0x2000]>,
+    pub serial: Serial,
+    pub apu: Apu,
+    dma: OamDma,
+    vram_dma: VramDma,
+    /// All 8 banks of WRAM. Bank 0 is always at 0xc000-0xcfff, and SVBK
+    /// selects which of the others is at 0xd000-0xdfff in CGB mode.
+    wram: Box<[u8; 0x8000]>,
+    /// The value of SVBK, where 0 selects bank 1 as well.
+    wram_bank: u8,
+    /// Whether the CPU and the timer run twice as fast as the GPU and APU.
+    double_speed: bool,
+    /// Whether KEY1 prepared a speed switch for the next STOP.
+    speed_switch: bool,
+    /// The dots passed since the APU was last advanced.
+    apu_dots: usize,
     hram: Box<[u8; 0x7f]>,
+    /// Which bytes of WRAM and HRAM were written since power on.
+    wram_written: Box<[bool; 0x8000]>,
+    hram_written: [bool; 0x7f],
+    /// Whether reads of WRAM and HRAM that was never written are recorded.
+    pub(crate) check_uninitialized: bool,
+    /// The first address read during the current step that was never
+    /// written.
+    uninitialized_read: Cell<Option<u16>>,
+    watchpoints: Watchpoints,
     interrupts: Interrupts,
     interrupts_enabled: Interrupts,
-    p1: u8,
-    pressed: Vec<JoypadButton>,
+    p1_select: u8,
+    /// The buttons held on every controller. The joypad register only reads
+    /// the first one, as nothing takes the input of the others yet.
+    pressed: [Vec<JoypadButton>; PLAYERS],
+    snoopers: Vec<AttachedSnooper>,
+    frame_finished: bool,
+    /// The cycles the GPU is behind, which it catches up on once they reach
+    /// its next event.
+    gpu_cycles: usize,
+    gpu_event: usize,
+    interrupt_dispatched: bool,
+    rom_writes: Vec<(u16, u8)>,
+    /// The time spent in the GPU, only measured when set.
+    pub(crate) ppu_time: Option<Duration>,
+}
+
+struct AttachedSnooper {
+    range: RangeInclusive<u16>,
+    snooper: RefCell<Box<dyn BusSnooper>>,
 }
 
 impl Mmu {
-    pub fn new(bios: &'static [u8], cart: Cartridge, gpu: Gpu) -> Mmu {
+    pub fn new(bios: impl Into<Cow<'static, [u8]>>, cart: Cartridge, gpu: Gpu) -> Mmu {
+        let gpu_event = gpu.cycles_until_event();
+
         Mmu {
-            bios,
+            bios: bios.into(),
             use_bios: true,
+            access_restrictions: true,
+            oam_corruption: false,
             cart,
             gpu,
             timer: Timer::new(),
-            wram: Box::new([0; 0x2000]),
+            serial: Serial::new(),
+            apu: Apu::new(),
+            dma: OamDma::new(),
+            vram_dma: VramDma::new(),
+            wram: Box::new([0; 0x8000]),
+            wram_bank: 0,
+            double_speed: false,
+            speed_switch: false,
+            apu_dots: 0,
             hram: Box::new([0; 0x7f]),
+            wram_written: Box::new([false; 0x8000]),
+            hram_written: [false; 0x7f],
+            check_uninitialized: false,
+            uninitialized_read: Cell::new(None),
+            watchpoints: Watchpoints::new(),
             interrupts: Interrupts::empty(),
             interrupts_enabled: Interrupts::empty(),
-            p1: 0b1111,
-            pressed: Vec::new(),
+            p1_select: 0,
+            pressed: Default::default(),
+            snoopers: Vec::new(),
+            frame_finished: false,
+            gpu_cycles: 0,
+            gpu_event,
+            interrupt_dispatched: false,
+            rom_writes: Vec::new(),
+            ppu_time: None,
         }
     }
 
-    pub fn step(&mut self, cpu: &mut Cpu) -> bool {
-        let cycles = if cpu.halted {
-            4
-        } else {
-            cpu.exec_next_instruction(self)
-                .context("failed to execute next instruction")
-                .unwrap()
-        };
+    /// The boot ROM, which is mapped over 0x0000-0x00ff, and over
+    /// 0x0200-0x08ff if it is the larger one of the CGB, until it is turned
+    /// off.
+    pub fn boot_rom(&self) -> &[u8] {
+        &self.bios
+    }
 
-        let (frame, new_interrupts) = self.gpu.cycle(4 * cycles);
-        self.interrupts.insert(new_interrupts);
+    /// Whether an address currently reads from the boot ROM instead of the
+    /// cartridge.
+    pub fn in_boot_rom(&self, address: u16) -> bool {
+        match address {
+            0..=0xff => self.use_bios,
+            0x200..=0x8ff => self.use_bios && self.bios.len() > 0x100,
+            _ => false,
+        }
+    }
 
-        let new_interrupts = self.timer.cycle(cycles);
-        self.interrupts.insert(new_interrupts);
+    pub fn attach_snooper(&mut self, range: RangeInclusive<u16>, snooper: Box<dyn BusSnooper>) {
+        self.snoopers.push(AttachedSnooper {
+            range,
+            snooper: RefCell::new(snooper),
+        });
+    }
 
-        let mut to_process_interrupts = self.interrupts;
-        to_process_interrupts.remove(!self.interrupts_enabled);
+    /// Executes a single instruction, or waits a single M-cycle while halted,
+    /// and dispatches any interrupt. Returns whether a frame was finished.
+    /// The CPU first waits for any blocks VRAM DMA copied during the last
+    /// step.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<bool, CpuError> {
+        self.uninitialized_read.set(None);
+        self.watchpoints.take_hit();
 
-        if !to_process_interrupts.is_empty() {
+        while self.vram_dma.take_stall() {
+            // The copy takes as long in double speed, which is twice the
+            // M-cycles.
+            cpu.idle(self);
+            if self.double_speed {
+                cpu.idle(self);
+            }
+        }
+
+        if cpu.halted {
+            cpu.idle(self);
+        } else {
+            cpu.exec_next_instruction(self)?;
+        }
+
+        if self.interrupts.intersects(self.interrupts_enabled) {
             cpu.halted = false;
         }
 
-        let (cycles, handled_interrupts) = cpu.process_interrupts(self, to_process_interrupts);
-        self.interrupts.remove(handled_interrupts);
+        self.interrupt_dispatched = cpu.process_interrupts(self)? > 0;
 
-        if cycles != 0 {
-            let (frame2, new_interrupts) = self.gpu.cycle(4 * cycles);
-            self.interrupts.insert(new_interrupts);
+        Ok(std::mem::take(&mut self.frame_finished))
+    }
 
-            let new_interrupts = self.timer.cycle(cycles);
-            self.interrupts.insert(new_interrupts);
+    /// Resets the GPU to the start of a frame.
+    pub fn reset_gpu(&mut self) {
+        self.gpu.reset();
+        self.gpu_cycles = 0;
+        self.gpu_event = self.gpu.cycles_until_event();
+    }
+
+    /// The interrupts requested in IF, whether or not they are enabled.
+    pub fn requested_interrupts(&self) -> Interrupts {
+        self.interrupts
+    }
 
-            return frame || frame2;
+    /// The interrupts enabled in IE.
+    pub fn enabled_interrupts(&self) -> Interrupts {
+        self.interrupts_enabled
+    }
+
+    /// Requests interrupts as if the hardware raised them, setting their bits
+    /// in IF.
+    pub fn request_interrupt(&mut self, interrupts: Interrupts) {
+        self.interrupts.insert(interrupts);
+    }
+
+    /// Takes the writes to ROM without a memory bank controller since the last
+    /// call, as address and value.
+    pub(crate) fn take_rom_writes(&mut self) -> Vec<(u16, u8)> {
+        std::mem::take(&mut self.rom_writes)
+    }
+
+    /// Writes a byte without the CPU, so watchpoints and bus snoopers don't
+    /// see it and DMA doesn't lock it out.
+    pub(crate) fn poke(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+        self.write_mapped(address, value)
+    }
+
+    /// Whether WRAM or HRAM at an address was written since power on. Other
+    /// memory counts as always initialized.
+    pub fn is_initialized(&self, address: u16) -> bool {
+        match address {
+            0xc000..=0xdfff => self.wram_written[self.wram_index(address)],
+            0xe000..=0xfdff => self.wram_written[self.wram_index(address - 0x2000)],
+            0xff80..=0xfffe => self.hram_written[address as usize - 0xff80],
+            _ => true,
         }
+    }
+
+    /// The first address the last step read before it was ever written, if
+    /// [`check_uninitialized`](Mmu::check_uninitialized) is set. The address
+    /// counts as initialized from then on, so it is only reported once.
+    pub(crate) fn take_uninitialized_read(&mut self) -> Option<u16> {
+        let address = self.uninitialized_read.take()?;
+        match address {
+            0xc000..=0xdfff => self.wram_written[self.wram_index(address)] = true,
+            0xe000..=0xfdff => self.wram_written[self.wram_index(address - 0x2000)] = true,
+            _ => self.hram_written[address as usize - 0xff80] = true,
+        }
+
+        Some(address)
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    /// The first access the last step made that a watchpoint covers.
+    pub(crate) fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watchpoints.take_hit()
+    }
+
+    pub fn oam_dma(&self) -> &OamDma {
+        &self.dma
+    }
+
+    pub fn vram_dma(&self) -> &VramDma {
+        &self.vram_dma
+    }
+
+    /// Whether a CGB switched to double speed.
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
 
-        frame
+    /// Whether the last step ended by jumping to an interrupt handler.
+    pub fn interrupt_dispatched(&self) -> bool {
+        self.interrupt_dispatched
     }
 
+    /// Presses buttons on the first controller.
     pub fn press(&mut self, buttons: &[JoypadButton]) {
-        for button in buttons {
-            self.pressed.push(*button);
+        self.press_player(0, buttons);
+    }
 
-            if self.p1 & button.enabled_bit() != 0 {
-                continue;
-            }
+    pub fn release(&mut self, buttons: &[JoypadButton]) {
+        self.release_player(0, buttons);
+    }
 
-            if self.p1 & button.bit() != 0 {
-                self.interrupts.insert(Interrupts::JOYPAD);
-                self.p1 &= !button.bit();
-            }
+    /// Presses buttons on one of the [`PLAYERS`] controllers, counting from
+    /// 0. Players past the last controller are ignored.
+    pub fn press_player(&mut self, player: usize, buttons: &[JoypadButton]) {
+        let previous = self.p1();
+        if let Some(pressed) = self.pressed.get_mut(player) {
+            pressed.extend_from_slice(buttons);
         }
+        self.update_joypad_interrupt(previous);
     }
 
-    pub fn release(&mut self, buttons: &[JoypadButton]) {
-        self.pressed.retain(|button| !buttons.contains(button));
+    pub fn release_player(&mut self, player: usize, buttons: &[JoypadButton]) {
+        if let Some(pressed) = self.pressed.get_mut(player) {
+            pressed.retain(|button| !buttons.contains(button));
+        }
+    }
 
-        for button in buttons {
-            if self.p1 & button.enabled_bit() == 0 {
-                continue;
+    /// The buttons held on one of the controllers, which is none for players
+    /// past the last one.
+    pub fn pressed(&self, player: usize) -> &[JoypadButton] {
+        self.pressed.get(player).map_or(&[], Vec::as_slice)
+    }
+
+    /// Bits 6 and 7 always read as 1, bits 4 and 5 are the group select bits
+    /// as last written, and the lower nibble has a 0 for every pressed button
+    /// in a selected group.
+    fn p1(&self) -> u8 {
+        let mut value = 0b1100_0000 | self.p1_select | 0b1111;
+
+        for button in self.pressed[0].iter() {
+            if self.p1_select & button.enabled_bit() == 0 {
+                value &= !button.bit();
             }
+        }
 
-            self.p1 |= button.bit();
+        value
+    }
+
+    /// The joypad interrupt is requested whenever any input line goes from
+    /// high to low, either by pressing a button or by selecting its group.
+    fn update_joypad_interrupt(&mut self, previous: u8) {
+        if previous & !self.p1() & 0b1111 != 0 {
+            self.interrupts.insert(Interrupts::JOYPAD);
         }
     }
 }
 
 impl Memory for Mmu {
     fn read(&self, address: u16) -> Result<u8, MemoryError> {
+        let mut value = if self.dma_locked(address) {
+            0xff
+        } else {
+            self.read_mapped(address)?
+        };
+
+        if self.check_uninitialized
+            && self.uninitialized_read.get().is_none()
+            && !self.is_initialized(address)
+        {
+            self.uninitialized_read.set(Some(address));
+        }
+
+        for attached in self.snoopers.iter() {
+            if attached.range.contains(&address) {
+                let mut snooper = attached.snooper.borrow_mut();
+                value = snooper.read(address, value).unwrap_or(value);
+            }
+        }
+
+        self.watchpoints
+            .check(address, MemoryOperation::Read, value, value, value);
+        Ok(value)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+        let old = if self.watchpoints.watches(address) {
+            Some(self.read_mapped(address).unwrap_or(0xff))
+        } else {
+            None
+        };
+
+        let mut intercepted = false;
+
+        for attached in self.snoopers.iter() {
+            if attached.range.contains(&address) {
+                intercepted |= attached.snooper.borrow_mut().write(address, value);
+            }
+        }
+
+        let result = if intercepted || self.dma_locked(address) {
+            Ok(())
+        } else {
+            self.write_mapped(address, value)
+        };
+
+        // Registers with fixed bits, memory locked to the CPU and MBC
+        // registers in the ROM area all read differently than written, so
+        // only what the address reads as afterwards tells if it changed.
+        if let Some(old) = old {
+            let stored = self.read_mapped(address).unwrap_or(0xff);
+            self.watchpoints
+                .check(address, MemoryOperation::Write, value, old, stored);
+        }
+
+        result
+    }
+
+    fn drive_address(&mut self, address: u16) {
+        if self.oam_corruption && (0xfe00..=0xfeff).contains(&address) {
+            self.catch_up_gpu();
+            self.gpu.corrupt_oam();
+        }
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch {
+            return false;
+        }
+
+        self.speed_switch = false;
+        self.double_speed = !self.double_speed;
+        self.timer.reset_divider();
+        true
+    }
+
+    fn tick(&mut self) {
+        // In double speed, an M-cycle only takes 2 dots of the GPU and APU.
+        let dots = if self.double_speed { 2 } else { 4 };
+
+        // Until its next event, the GPU only counts cycles, so those can be
+        // handed over all at once.
+        self.gpu_cycles += dots;
+        if self.gpu_cycles >= self.gpu_event {
+            self.catch_up_gpu();
+        }
+
+        let new_interrupts = self.timer.cycle(1);
+        self.interrupts.insert(new_interrupts);
+
+        let new_interrupts = self.serial.cycle(1);
+        self.interrupts.insert(new_interrupts);
+
+        self.apu_dots += dots;
+        if self.apu_dots >= 4 {
+            self.apu_dots -= 4;
+            self.apu.cycle(1);
+        }
+
+        if let Some(address) = self.dma.cycle() {
+            // Sources past WRAM read from WRAM, like its echo does.
+            let source = if address >= 0xe000 {
+                address - 0x2000
+            } else {
+                address
+            };
+
+            // The DMA writes to OAM even when the CPU can't.
+            let value = self.read_mapped(source).unwrap_or(0xff);
+            self.gpu.oam[(address & 0xff) as usize] = value;
+        }
+
+        if self.vram_dma.is_active() {
+            self.catch_up_gpu();
+            if let Some(block) = self.vram_dma.cycle(self.gpu.in_hblank()) {
+                self.copy_vram_block(block);
+            }
+        }
+    }
+}
+
+impl Mmu {
+    /// Hands the cycles the GPU is behind over to it.
+    fn catch_up_gpu(&mut self) {
+        let start = self.ppu_time.map(|_| Instant::now());
+        let (frame, new_interrupts) = self.gpu.cycle(std::mem::take(&mut self.gpu_cycles));
+        if let (Some(time), Some(start)) = (self.ppu_time.as_mut(), start) {
+            *time += start.elapsed();
+        }
+
+        self.frame_finished |= frame;
+        self.interrupts.insert(new_interrupts);
+        self.gpu_event = self.gpu.cycles_until_event();
+    }
+
+    /// Catches the GPU up before a write to a register it draws with, so it
+    /// knows which pixels were drawn with the old value.
+    fn latch_line_registers(&mut self) {
+        self.catch_up_gpu();
+        self.gpu.latch_line_registers();
+    }
+
+    /// Whether the CPU is locked out of an address because an OAM DMA
+    /// transfer is using the bus.
+    fn dma_locked(&self, address: u16) -> bool {
+        self.access_restrictions && self.dma.is_active() && address < 0xff00
+    }
+
+    /// Copies a block of VRAM DMA, given as its source address and the offset
+    /// into VRAM to copy it to.
+    fn copy_vram_block(&mut self, (source, destination): (u16, u16)) {
+        for i in 0..0x10 {
+            let value = self.read_mapped(source.wrapping_add(i)).unwrap_or(0xff);
+            self.gpu.write_vram(destination + i, value);
+        }
+    }
+
+    /// Whether the CPU is locked out of VRAM because the GPU is using it.
+    fn vram_locked(&self) -> bool {
+        self.access_restrictions && !self.gpu.vram_accessible()
+    }
+
+    /// Whether the CPU is locked out of OAM because the GPU is using it.
+    fn oam_locked(&self) -> bool {
+        self.access_restrictions && !self.gpu.oam_accessible()
+    }
+
+    /// The index into WRAM of an address from 0xc000 to 0xdfff, in the bank
+    /// selected by SVBK.
+    fn wram_index(&self, address: u16) -> usize {
+        let bank = match address {
+            0xc000..=0xcfff => 0,
+            _ => self.wram_bank.max(1) as usize,
+        };
+        bank * 0x1000 + (address as usize & 0xfff)
+    }
+
+    fn read_mapped(&self, address: u16) -> Result<u8, MemoryError> {
         match address {
-            0..=0xff if self.use_bios => Ok(self.bios[address as usize]),
+            _ if self.in_boot_rom(address) => Ok(self.bios[address as usize]),
             0..=0x7fff => self.cart.read(address),
-            0x8000..=0x9fff => Ok(self.gpu.vram[address as usize - 0x8000]),
+            0x8000..=0x9fff if self.vram_locked() => Ok(0xff),
+            0x8000..=0x9fff => Ok(self.gpu.read_vram(address - 0x8000)),
             0xa000..=0xbfff => self.cart.read(address),
-            0xc000..=0xdfff => Ok(self.wram[address as usize - 0xc000]),
-            0xe000..=0xfdff => self.read(address - 0x2000),
+            0xc000..=0xdfff => Ok(self.wram[self.wram_index(address)]),
+            0xe000..=0xfdff => self.read_mapped(address - 0x2000),
+            0xfe00..=0xfe9f if self.oam_locked() => Ok(0xff),
             0xfe00..=0xfe9f => Ok(self.gpu.oam[address as usize - 0xfe00]),
             0xfea0..=0xfeff => Ok(0xff),
-            0xff00 => Ok(self.p1),
+            0xff00 => Ok(self.p1()),
+            0xff01 => Ok(self.serial.data),
+            0xff02 => Ok(self.serial.control()),
             0xff04 => Ok(self.timer.divider),
             0xff05 => Ok(self.timer.counter),
             0xff06 => Ok(self.timer.modulo),
             0xff07 => Ok(self.timer.timer_control()),
-            0xff0f => Ok(self.interrupts.bits()),
-            0xff10..=0xff26 => Ok(0), // Sound
-            0xff30..=0xff3f => Ok(0), // Wave Pattern RAM
+            0xff0f => Ok(0b1110_0000 | self.interrupts.bits()),
+            0xff10..=0xff26 => Ok(self.apu.read(address)),
+            0xff30..=0xff3f => Ok(self.apu.wave_ram[address as usize - 0xff30]),
             0xff40 => Ok(self.gpu.lcd_control.bits()),
             0xff41 => Ok(self.gpu.stat()),
             0xff42 => Ok(self.gpu.scroll_y),
             0xff43 => Ok(self.gpu.scroll_x),
             0xff44 => Ok(self.gpu.scanline()),
-            0xff45 => Ok(self.gpu.lyc),
+            0xff45 => Ok(self.gpu.lyc()),
+            0xff46 => Ok(self.dma.register()),
             0xff47 => Ok(pack_palette(self.gpu.bg_palette)),
             0xff48 => Ok(pack_palette(self.gpu.obj_palette[0])),
             0xff49 => Ok(pack_palette(self.gpu.obj_palette[1])),
             0xff4a => Ok(self.gpu.window_coords.1),
             0xff4b => Ok(self.gpu.window_coords.0),
+            0xff4d if self.gpu.cgb_mode() => {
+                Ok(0x7e | (self.double_speed as u8) << 7 | self.speed_switch as u8)
+            }
             0xff4d => Ok(0xff),
+            0xff4f => Ok(self.gpu.vram_bank()),
+            0xff51..=0xff54 => Ok(0xff),
+            0xff55 if self.gpu.cgb_mode() => Ok(self.vram_dma.control()),
+            0xff55 => Ok(0xff),
+            0xff68..=0xff6b if !self.gpu.cgb_mode() => Ok(0xff),
+            0xff68 => Ok(self.gpu.bg_colors.specification()),
+            0xff69 | 0xff6b if self.vram_locked() => Ok(0xff),
+            0xff69 => Ok(self.gpu.bg_colors.data()),
+            0xff6a => Ok(self.gpu.obj_colors.specification()),
+            0xff6b => Ok(self.gpu.obj_colors.data()),
+            0xff70 if self.gpu.cgb_mode() => Ok(0xf8 | self.wram_bank),
+            0xff70 => Ok(0xff),
             0xff80..=0xfffe => Ok(self.hram[address as usize - 0xff80]),
             0xffff => Ok(self.interrupts_enabled.bits()),
             _ => {
@@ -186,46 +603,53 @@ impl Memory for Mmu {
         }
     }
 
-    fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+    fn write_mapped(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
         match address {
-            0..=0xff if self.use_bios => Err(MemoryError::Illegal {
+            _ if self.in_boot_rom(address) => Err(MemoryError::Illegal {
                 address,
                 op: MemoryOperation::Write,
             }),
-            0..=0x7fff => self.cart.write(address, value),
+            0..=0x7fff => {
+                if !self.cart.has_mbc() {
+                    self.rom_writes.push((address, value));
+                }
+                self.cart.write(address, value)
+            }
+            0x8000..=0x9fff if self.vram_locked() => Ok(()),
             0x8000..=0x9fff => {
-                self.gpu.vram[address as usize - 0x8000] = value;
-                self.gpu.update_tile(address - 0x8000);
+                self.gpu.write_vram(address - 0x8000, value);
                 Ok(())
             }
             0xa000..=0xbfff => self.cart.write(address, value),
             0xc000..=0xdfff => {
-                self.wram[address as usize - 0xc000] = value;
+                let index = self.wram_index(address);
+                self.wram[index] = value;
+                self.wram_written[index] = true;
                 Ok(())
             }
-            0xe000..=0xfdff => self.write(address - 0x2000, value),
+            0xe000..=0xfdff => self.write_mapped(address - 0x2000, value),
+            0xfe00..=0xfe9f if self.oam_locked() => Ok(()),
             0xfe00..=0xfe9f => {
                 self.gpu.oam[address as usize - 0xfe00] = value;
                 Ok(())
             }
             0xfea0..=0xfeff => Ok(()),
             0xff00 => {
-                self.p1 = value & 0b110000 | 0b1111;
-
-                for button in self.pressed.iter() {
-                    if self.p1 & button.enabled_bit() != 0 {
-                        continue;
-                    }
-
-                    self.p1 &= !button.bit();
-                }
-
+                let previous = self.p1();
+                self.p1_select = value & 0b110000;
+                self.update_joypad_interrupt(previous);
+                Ok(())
+            }
+            0xff01 => {
+                self.serial.data = value;
+                Ok(())
+            }
+            0xff02 => {
+                self.serial.set_control(value);
                 Ok(())
             }
-            0xff01 => Ok(()), // Serial transfer data
-            0xff02 => Ok(()), // Serial transfer control
             0xff04 => {
-                self.timer.divider = 0;
+                self.timer.reset_divider();
                 self.timer.counter = 0;
                 Ok(())
             }
@@ -245,41 +669,52 @@ impl Memory for Mmu {
                 self.interrupts = Interrupts::from_bits_truncate(value);
                 Ok(())
             }
-            0xff10..=0xff26 => Ok(()), // Sound
-            0xff30..=0xff3f => Ok(()), // Wave Pattern RAM
+            0xff10..=0xff26 => {
+                self.apu.write(address, value);
+                Ok(())
+            }
+            0xff30..=0xff3f => {
+                self.apu.wave_ram[address as usize - 0xff30] = value;
+                Ok(())
+            }
             0xff40 => {
-                self.gpu.lcd_control = LcdControl::from_bits_truncate(value);
+                self.latch_line_registers();
+                let new_interrupts = self
+                    .gpu
+                    .set_lcd_control(LcdControl::from_bits_truncate(value));
+                self.interrupts.insert(new_interrupts);
+                self.gpu_event = self.gpu.cycles_until_event();
                 Ok(())
             }
             0xff41 => {
-                self.gpu.set_stat(value);
+                self.catch_up_gpu();
+                let new_interrupts = self.gpu.set_stat(value);
+                self.interrupts.insert(new_interrupts);
                 Ok(())
             }
             0xff42 => {
+                self.latch_line_registers();
                 self.gpu.scroll_y = value;
                 Ok(())
             }
             0xff43 => {
+                self.latch_line_registers();
                 self.gpu.scroll_x = value;
                 Ok(())
             }
             0xff44 => Err(MemoryError::ReadOnly { address }),
             0xff45 => {
-                self.gpu.lyc = value;
+                self.catch_up_gpu();
+                let new_interrupts = self.gpu.set_lyc(value);
+                self.interrupts.insert(new_interrupts);
                 Ok(())
             }
             0xff46 => {
-                assert!(value <= 0xf1);
-
-                let base = (value as u16) << 8;
-                for i in 0..0xa0 {
-                    let value = self.read(base + i)?;
-                    self.write(0xfe00 + i, value)?;
-                }
-
+                self.dma.start(value);
                 Ok(())
             }
             0xff47 => {
+                self.latch_line_registers();
                 self.gpu.bg_palette = unpack_palette(value);
                 Ok(())
             }
@@ -299,7 +734,17 @@ impl Memory for Mmu {
                 self.gpu.window_coords.0 = value;
                 Ok(())
             }
-            0xff4d => Ok(()), // GBC Speed switch
+            0xff4c | 0xff6c => Ok(()), // KEY0 and OPRI, only written by the CGB boot ROM
+            0xff4d => {
+                if self.gpu.cgb_mode() {
+                    self.speed_switch = value & 1 != 0;
+                }
+                Ok(())
+            }
+            0xff4f => {
+                self.gpu.set_vram_bank(value);
+                Ok(())
+            }
             0xff50 => {
                 if value != 0 {
                     self.use_bios = false;
@@ -307,9 +752,56 @@ impl Memory for Mmu {
 
                 Ok(())
             }
-            0xff70..=0xff7f => Ok(()), // WRAM Bank Select
+            0xff51..=0xff55 if !self.gpu.cgb_mode() => Ok(()),
+            0xff51..=0xff54 => {
+                self.vram_dma.write(address, value);
+                Ok(())
+            }
+            0xff55 => {
+                if self.vram_dma.start(value) {
+                    while let Some(block) = self.vram_dma.block() {
+                        self.copy_vram_block(block);
+                    }
+                }
+                Ok(())
+            }
+            0xff68..=0xff6b if !self.gpu.cgb_mode() => Ok(()),
+            0xff68 => {
+                self.gpu.bg_colors.set_specification(value);
+                Ok(())
+            }
+            // Palette RAM is locked during mode 3 like VRAM, but the index
+            // still moves on.
+            0xff69 if self.vram_locked() => {
+                self.gpu.bg_colors.increment();
+                Ok(())
+            }
+            0xff69 => {
+                self.gpu.bg_colors.write_data(value);
+                Ok(())
+            }
+            0xff6a => {
+                self.gpu.obj_colors.set_specification(value);
+                Ok(())
+            }
+            0xff6b if self.vram_locked() => {
+                self.gpu.obj_colors.increment();
+                Ok(())
+            }
+            0xff6b => {
+                self.gpu.obj_colors.write_data(value);
+                Ok(())
+            }
+            0xff70 => {
+                if self.gpu.cgb_mode() {
+                    self.wram_bank = value & 0b111;
+                }
+                Ok(())
+            }
+            0xff71..=0xff7f => Ok(()),
             0xff80..=0xfffe => {
                 self.hram[address as usize - 0xff80] = value;
+                self.hram_written[address as usize - 0xff80] = true;
                 Ok(())
             }
             0xffff => {
@@ -343,3 +835,407 @@ pub fn unpack_palette(palette: u8) -> [u8; 4] {
 
     value
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{JoypadButton, Mmu, PLAYERS};
+    use crate::{
+        bios::DMG_BIOS,
+        cartridge::Cartridge,
+        cpu::{Cpu, Interrupts},
+        gpu::Gpu,
+        memory::{
+            watch::{WatchHit, WatchKind, Watchpoint},
+            BusSnooper, Memory, MemoryOperation,
+        },
+    };
+
+    fn mmu() -> Mmu {
+        Mmu::new(DMG_BIOS, Cartridge::from_bytes(vec![0; 0x8000]), Gpu::new())
+    }
+
+    #[test]
+    fn joypad_select() {
+        // (select written, buttons pressed, expected read)
+        let cases = [
+            (0x30, &[][..], 0xff),
+            (0x30, &[JoypadButton::Right, JoypadButton::A][..], 0xff),
+            (0x20, &[][..], 0xef),
+            (0x20, &[JoypadButton::Right, JoypadButton::Up][..], 0xea),
+            (0x20, &[JoypadButton::A, JoypadButton::Start][..], 0xef),
+            (0x10, &[JoypadButton::A, JoypadButton::Start][..], 0xd6),
+            (0x10, &[JoypadButton::Down][..], 0xdf),
+            (0x00, &[JoypadButton::Right, JoypadButton::B][..], 0xcc),
+            (0xff, &[JoypadButton::Left][..], 0xff),
+            (0xcf, &[JoypadButton::Left, JoypadButton::Select][..], 0xc9),
+        ];
+
+        for (select, buttons, expected) in cases.iter() {
+            let mut mmu = mmu();
+            mmu.press(buttons);
+            mmu.write(0xff00, *select).unwrap();
+
+            assert_eq!(
+                mmu.read(0xff00).unwrap(),
+                *expected,
+                "select {:#04x} with {} buttons",
+                select,
+                buttons.len()
+            );
+        }
+    }
+
+    #[test]
+    fn joypad_release() {
+        let mut mmu = mmu();
+        mmu.write(0xff00, 0x10).unwrap();
+
+        mmu.press(&[JoypadButton::A, JoypadButton::B]);
+        mmu.release(&[JoypadButton::A]);
+        assert_eq!(mmu.read(0xff00).unwrap(), 0xdd);
+
+        mmu.release(&[JoypadButton::B]);
+        assert_eq!(mmu.read(0xff00).unwrap(), 0xdf);
+    }
+
+    #[test]
+    fn other_players() {
+        let mut mmu = mmu();
+        mmu.write(0xff00, 0x10).unwrap();
+
+        mmu.press_player(1, &[JoypadButton::A]);
+        assert_eq!(mmu.read(0xff00).unwrap(), 0xdf);
+        assert_eq!(mmu.read(0xff0f).unwrap() & Interrupts::JOYPAD.bits(), 0);
+        assert_eq!(mmu.pressed(1), &[JoypadButton::A]);
+        assert!(mmu.pressed(0).is_empty());
+
+        mmu.press(&[JoypadButton::A]);
+        mmu.release_player(1, &[JoypadButton::A]);
+        assert_eq!(mmu.read(0xff00).unwrap(), 0xde);
+        assert!(mmu.pressed(1).is_empty());
+
+        mmu.press_player(PLAYERS, &[JoypadButton::B]);
+        mmu.release_player(PLAYERS, &[JoypadButton::B]);
+        assert!(mmu.pressed(PLAYERS).is_empty());
+        assert_eq!(mmu.read(0xff00).unwrap(), 0xde);
+    }
+
+    #[test]
+    fn joypad_interrupt() {
+        let mut mmu = mmu();
+        let joypad_requested =
+            |mmu: &Mmu| mmu.read(0xff0f).unwrap() & Interrupts::JOYPAD.bits() != 0;
+
+        // Both groups are selected at power on, so deselect them first.
+        mmu.write(0xff00, 0x30).unwrap();
+        mmu.press(&[JoypadButton::A]);
+        assert!(!joypad_requested(&mmu));
+
+        mmu.write(0xff00, 0x10).unwrap();
+        assert!(joypad_requested(&mmu));
+
+        mmu.write(0xff0f, 0).unwrap();
+        mmu.press(&[JoypadButton::Up]);
+        assert!(!joypad_requested(&mmu));
+
+        mmu.press(&[JoypadButton::B]);
+        assert!(joypad_requested(&mmu));
+    }
+
+    #[test]
+    fn interrupt_flag_clearing() {
+        let mut mmu = mmu();
+        mmu.use_bios = false;
+        mmu.write(0xffff, (Interrupts::TIMER | Interrupts::SERIAL).bits())
+            .unwrap();
+        mmu.write(
+            0xff0f,
+            (Interrupts::TIMER | Interrupts::SERIAL | Interrupts::VBLANK).bits(),
+        )
+        .unwrap();
+
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xfffe;
+        cpu.ime = true;
+        mmu.step(&mut cpu).unwrap();
+
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(
+            mmu.read(0xff0f).unwrap() & Interrupts::all().bits(),
+            (Interrupts::SERIAL | Interrupts::VBLANK).bits()
+        );
+    }
+
+    struct LoggingSnooper(Rc<RefCell<Vec<(u16, u8)>>>);
+
+    impl BusSnooper for LoggingSnooper {
+        fn read(&mut self, address: u16, _value: u8) -> Option<u8> {
+            Some(address as u8)
+        }
+
+        fn write(&mut self, address: u16, value: u8) -> bool {
+            self.0.borrow_mut().push((address, value));
+            address >= 0xc100
+        }
+    }
+
+    #[test]
+    fn snooper() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let mut mmu = mmu();
+        mmu.attach_snooper(0xc000..=0xc1ff, Box::new(LoggingSnooper(log.clone())));
+
+        mmu.write(0xc000, 0x12).unwrap();
+        mmu.write(0xc100, 0x34).unwrap();
+        mmu.write(0xc200, 0x56).unwrap();
+
+        assert_eq!(*log.borrow(), vec![(0xc000, 0x12), (0xc100, 0x34)]);
+        assert_eq!(mmu.read(0xc0aa).unwrap(), 0xaa);
+        assert_eq!(mmu.read_mapped(0xc000).unwrap(), 0x12);
+        assert_eq!(mmu.read_mapped(0xc100).unwrap(), 0x00);
+        assert_eq!(mmu.read(0xc200).unwrap(), 0x56);
+    }
+
+    #[test]
+    fn access_restrictions() {
+        let mut mmu = mmu();
+        mmu.write(0x8000, 0x12).unwrap();
+        mmu.write(0xfe00, 0x34).unwrap();
+        mmu.write(0xff40, 0x80).unwrap();
+
+        let mode = |mmu: &Mmu| mmu.read(0xff41).unwrap() & 0b11;
+        while mode(&mmu) != 2 {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read(0x8000).unwrap(), 0x12);
+        assert_eq!(mmu.read(0xfe00).unwrap(), 0xff);
+        mmu.write(0xfe00, 0x56).unwrap();
+
+        while mode(&mmu) != 3 {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read(0x8000).unwrap(), 0xff);
+        assert_eq!(mmu.read(0xfe00).unwrap(), 0xff);
+        mmu.write(0x8000, 0x78).unwrap();
+
+        mmu.access_restrictions = false;
+        assert_eq!(mmu.read(0x8000).unwrap(), 0x12);
+        assert_eq!(mmu.read(0xfe00).unwrap(), 0x34);
+    }
+
+    #[test]
+    fn change_watchpoints() {
+        let mut mmu = mmu();
+        for (label, address) in [("IF", 0xff0f), ("STAT", 0xff41), ("VRAM", 0x8000)].iter() {
+            mmu.watchpoints_mut().add(Watchpoint {
+                label: label.to_string(),
+                addresses: [*address].iter().copied().collect(),
+                kind: WatchKind::Change,
+            });
+        }
+
+        // IF and STAT read with fixed bits set, so writing what they
+        // already hold doesn't match the value they read as.
+        mmu.write(0xff0f, 0x00).unwrap();
+        mmu.write(0xff41, 0x00).unwrap();
+        assert_eq!(mmu.watchpoints().take_hit(), None);
+
+        mmu.write(0xff0f, 0x01).unwrap();
+        assert_eq!(
+            mmu.watchpoints().take_hit(),
+            Some(WatchHit {
+                address: 0xff0f,
+                op: MemoryOperation::Write,
+                old: 0xe0,
+                new: 0x01,
+            })
+        );
+
+        // Writes to VRAM are ignored while the GPU draws.
+        mmu.write(0xff40, 0x80).unwrap();
+        while mmu.read(0xff41).unwrap() & 0b11 != 3 {
+            mmu.tick();
+        }
+        mmu.write(0x8000, 0x12).unwrap();
+        assert_eq!(mmu.watchpoints().take_hit(), None);
+    }
+
+    #[test]
+    fn cgb_registers() {
+        let mut mmu = mmu();
+        mmu.write(0xff4f, 1).unwrap();
+        mmu.write(0xff68, 0x80).unwrap();
+        assert_eq!(mmu.read(0xff4f).unwrap(), 0xff);
+        assert_eq!(mmu.read(0xff68).unwrap(), 0xff);
+
+        mmu.gpu.set_cgb_mode(true);
+        assert_eq!(mmu.read(0xff4f).unwrap(), 0xfe);
+        mmu.write(0x8010, 0x12).unwrap();
+        mmu.write(0xff4f, 1).unwrap();
+        mmu.write(0x8010, 0x34).unwrap();
+        assert_eq!(mmu.read(0x8010).unwrap(), 0x34);
+        assert_eq!((mmu.gpu.vram[0x10], mmu.gpu.vram[0x2010]), (0x12, 0x34));
+
+        // Writing the data register moves the index on when bit 7 is set.
+        mmu.write(0xff68, 0x80 | 0x08).unwrap();
+        mmu.write(0xff69, 0x1f).unwrap();
+        mmu.write(0xff69, 0x7c).unwrap();
+        assert_eq!(mmu.read(0xff68).unwrap(), 0xca);
+        assert_eq!(mmu.gpu.bg_colors.color(1, 0), 0x7c1f);
+
+        mmu.write(0xff6a, 0x3f).unwrap();
+        mmu.write(0xff6b, 0x00).unwrap();
+        assert_eq!(mmu.read(0xff6a).unwrap(), 0x7f);
+        assert_eq!(mmu.read(0xff6b).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn wram_banks() {
+        let mut mmu = mmu();
+        mmu.write(0xff70, 2).unwrap();
+        mmu.write(0xd000, 0x12).unwrap();
+        assert_eq!(mmu.read(0xff70).unwrap(), 0xff);
+        assert_eq!(mmu.wram[0x1000], 0x12);
+
+        mmu.gpu.set_cgb_mode(true);
+        mmu.write(0xff70, 0xfa).unwrap();
+        assert_eq!(mmu.read(0xff70).unwrap(), 0xfa);
+        assert_eq!(mmu.read(0xd000).unwrap(), 0);
+        mmu.write(0xd000, 0x34).unwrap();
+        mmu.write(0xc000, 0x56).unwrap();
+        assert_eq!(mmu.read(0xf000).unwrap(), 0x34);
+        assert!(mmu.is_initialized(0xd000));
+
+        // Bank 0 can't be selected for 0xd000-0xdfff, so it falls back to 1.
+        mmu.write(0xff70, 0).unwrap();
+        assert_eq!(mmu.read(0xff70).unwrap(), 0xf8);
+        assert_eq!(mmu.read(0xd000).unwrap(), 0x12);
+        assert_eq!(mmu.read(0xc000).unwrap(), 0x56);
+        mmu.write(0xff70, 7).unwrap();
+        assert!(!mmu.is_initialized(0xd000));
+        assert_eq!(mmu.wram[0x2000], 0x34);
+    }
+
+    #[test]
+    fn vram_dma() {
+        let mut mmu = mmu();
+        mmu.use_bios = false;
+        mmu.gpu.set_cgb_mode(true);
+        for i in 0..0x40 {
+            mmu.write(0xc000 + i, i as u8 + 1).unwrap();
+        }
+
+        // A general-purpose transfer copies right away, and the CPU waits
+        // during the next step.
+        mmu.write(0xff4f, 1).unwrap();
+        mmu.write(0xff51, 0xc0).unwrap();
+        mmu.write(0xff52, 0x00).unwrap();
+        mmu.write(0xff53, 0x80).unwrap();
+        mmu.write(0xff54, 0x10).unwrap();
+        mmu.write(0xff55, 0x01).unwrap();
+        assert_eq!(mmu.read(0xff55).unwrap(), 0xff);
+        assert_eq!(mmu.gpu.vram[0x2010..0x2030], mmu.wram[..0x20]);
+
+        let mut cpu = Cpu::new();
+        mmu.step(&mut cpu).unwrap();
+        assert_eq!(cpu.cycles(), 4 * (16 + 1));
+
+        // An H-blank transfer copies a block at the start of every H-blank.
+        mmu.write(0xff55, 0x81).unwrap();
+        assert_eq!(mmu.read(0xff55).unwrap(), 0x01);
+        mmu.write(0xff40, 0x80).unwrap();
+        while !mmu.gpu.in_hblank() {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read(0xff55).unwrap(), 0x00);
+        assert_eq!(mmu.gpu.vram[0x2030..0x2040], mmu.wram[0x20..0x30]);
+        assert_eq!(mmu.gpu.vram[0x2040], 0);
+
+        while mmu.gpu.in_hblank() {
+            mmu.tick();
+        }
+        while !mmu.gpu.in_hblank() {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read(0xff55).unwrap(), 0xff);
+        assert_eq!(mmu.gpu.vram[0x2040..0x2050], mmu.wram[0x30..0x40]);
+    }
+
+    #[test]
+    fn speed_switch() {
+        let mut mmu = mmu();
+        mmu.use_bios = false;
+        mmu.write(0xc000, 0x10).unwrap(); // stop
+        mmu.write(0xff4d, 1).unwrap();
+        assert_eq!(mmu.read(0xff4d).unwrap(), 0xff);
+
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xc000;
+        assert!(mmu.step(&mut cpu).is_err());
+
+        mmu.gpu.set_cgb_mode(true);
+        mmu.write(0xff4d, 1).unwrap();
+        assert_eq!(mmu.read(0xff4d).unwrap(), 0x7f);
+
+        // The switch resets DIV, which then counts from the start.
+        for _ in 0..60 {
+            mmu.tick();
+        }
+        let start = cpu.cycles();
+        mmu.step(&mut cpu).unwrap();
+        assert_eq!(mmu.read(0xff4d).unwrap(), 0xfe);
+        assert_eq!(cpu.pc, 0xc001);
+        assert_eq!(cpu.cycles() - start, 4 * (1 + 2050));
+        assert_eq!(mmu.read(0xff04).unwrap(), (2050 / 64) as u8);
+
+        // A line takes twice the M-cycles.
+        mmu.write(0xff40, 0x80).unwrap();
+        while mmu.read(0xff44).unwrap() != 1 {
+            mmu.tick();
+        }
+        let mut cycles = 0;
+        while mmu.read(0xff44).unwrap() != 2 {
+            mmu.tick();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 2 * 114);
+
+        mmu.write(0xc001, 0x10).unwrap();
+        mmu.write(0xff4d, 1).unwrap();
+        mmu.step(&mut cpu).unwrap();
+        assert!(!mmu.double_speed());
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut mmu = mmu();
+        mmu.use_bios = false;
+        for i in 0..0xa0 {
+            mmu.write(0xc100 + i, i as u8 ^ 0x5a).unwrap();
+        }
+        mmu.write(0xff80, 0x12).unwrap();
+
+        mmu.write(0xff46, 0xc1).unwrap();
+        mmu.tick();
+        mmu.tick();
+
+        // Only HRAM and the registers can be reached while it runs.
+        assert_eq!(mmu.read(0xc100).unwrap(), 0xff);
+        assert_eq!(mmu.read(0x0000).unwrap(), 0xff);
+        mmu.write(0xc000, 0x34).unwrap();
+        assert_eq!(mmu.read(0xff80).unwrap(), 0x12);
+        assert_eq!(mmu.read(0xff46).unwrap(), 0xc1);
+
+        for _ in 0..159 {
+            mmu.tick();
+        }
+        assert_eq!(mmu.read(0xfe00).unwrap(), 0xff);
+        mmu.tick();
+
+        assert_eq!(mmu.read(0xc000).unwrap(), 0);
+        assert!((0..0xa0).all(|i| mmu.gpu.oam[i] == i as u8 ^ 0x5a));
+    }
+}
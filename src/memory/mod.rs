@@ -2,9 +2,11 @@ use std::fmt;
 
 use thiserror::Error;
 
+pub mod dma;
 pub mod mmu;
+pub mod watch;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryOperation {
     Read,
     Write,
@@ -32,4 +34,50 @@ pub enum MemoryError {
 pub trait Memory {
     fn read(&self, address: u16) -> Result<u8, MemoryError>;
     fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError>;
+
+    /// Advances everything else on the bus by a single M-cycle. The CPU calls
+    /// this after every memory access and internal cycle.
+    fn tick(&mut self) {}
+
+    /// Called with the address in a 16-bit register whenever the CPU
+    /// increments or decrements it, which puts the address on the bus. Besides
+    /// 16-bit inc and dec, this happens for `(hl+)` and `(hl-)` operands and
+    /// for SP when pushing and popping.
+    fn drive_address(&mut self, _address: u16) {}
+
+    /// Called when the CPU executes STOP, returning whether that switched its
+    /// speed, as a CGB does when it was prepared with KEY1.
+    fn switch_speed(&mut self) -> bool {
+        false
+    }
+}
+
+/// Accesses memory without letting any time pass, for looking at it outside
+/// of regular execution, such as when disassembling.
+pub struct Untimed<'a, M>(pub &'a mut M);
+
+impl<'a, M: Memory> Memory for Untimed<'a, M> {
+    fn read(&self, address: u16) -> Result<u8, MemoryError> {
+        self.0.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), MemoryError> {
+        self.0.write(address, value)
+    }
+}
+
+/// An external component observing bus accesses within an address range, used
+/// to emulate hardware that lives outside the regular memory map.
+pub trait BusSnooper {
+    /// Called for every read in range with the value that would have been read.
+    /// Returning `Some` replaces that value.
+    fn read(&mut self, _address: u16, _value: u8) -> Option<u8> {
+        None
+    }
+
+    /// Called for every write in range. Returning `true` intercepts the write
+    /// so that it doesn't reach the memory map.
+    fn write(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
 }
@@ -0,0 +1,184 @@
+use std::{cell::Cell, collections::BTreeSet};
+
+use super::MemoryOperation;
+
+/// Which accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+    /// Only writes that change what the address reads as.
+    Change,
+}
+
+impl WatchKind {
+    fn matches(self, op: MemoryOperation, changed: bool) -> bool {
+        matches!(
+            (self, op),
+            (WatchKind::Access, _)
+                | (WatchKind::Read, MemoryOperation::Read)
+                | (WatchKind::Write, MemoryOperation::Write)
+        ) || self == WatchKind::Change && op == MemoryOperation::Write && changed
+    }
+}
+
+/// Stops execution when the game accesses any of a set of addresses, such as
+/// all registers of a sound channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// What the watchpoint is shown as, like `NR1x` or `0xc000`.
+    pub label: String,
+    pub addresses: BTreeSet<u16>,
+    pub kind: WatchKind,
+}
+
+/// The first watched access of a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub op: MemoryOperation,
+    /// The value at the address before the access.
+    pub old: u8,
+    /// The value that was read or written, the same as `old` for reads.
+    pub new: u8,
+}
+
+#[derive(Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    hit: Cell<Option<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Watchpoint {
+        self.watchpoints.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    /// Whether an access to the address would be checked, which is when a
+    /// watchpoint covers it and nothing was recorded since the last
+    /// [`Watchpoints::take_hit`].
+    pub(crate) fn watches(&self, address: u16) -> bool {
+        self.hit.get().is_none()
+            && self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.addresses.contains(&address))
+    }
+
+    /// Records an access of `value` if a watchpoint [watches](Self::watches)
+    /// the address. `old` and `stored` are what the address read as before and
+    /// after the access. These can differ from the value written, as some
+    /// registers have fixed bits and some memory ignores writes at times, so
+    /// they decide whether a write changed anything.
+    pub(crate) fn check(&self, address: u16, op: MemoryOperation, value: u8, old: u8, stored: u8) {
+        if !self.watches(address) {
+            return;
+        }
+
+        let changed = old != stored;
+        if self
+            .watchpoints
+            .iter()
+            .filter(|watchpoint| watchpoint.addresses.contains(&address))
+            .any(|watchpoint| watchpoint.kind.matches(op, changed))
+        {
+            self.hit.set(Some(WatchHit {
+                address,
+                op,
+                old,
+                new: value,
+            }));
+        }
+    }
+
+    pub(crate) fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchHit, WatchKind, Watchpoint, Watchpoints};
+    use crate::memory::MemoryOperation;
+
+    #[test]
+    fn first_hit() {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add(Watchpoint {
+            label: "NR1x".to_string(),
+            addresses: (0xff10..=0xff14).collect(),
+            kind: WatchKind::Write,
+        });
+
+        watchpoints.check(0xff12, MemoryOperation::Read, 0xf3, 0xf3, 0xf3);
+        watchpoints.check(0xff15, MemoryOperation::Write, 0x00, 0xff, 0xff);
+        assert!(!watchpoints.watches(0xff15));
+        assert_eq!(watchpoints.take_hit(), None);
+
+        watchpoints.check(0xff12, MemoryOperation::Write, 0x80, 0xf3, 0x80);
+        assert!(!watchpoints.watches(0xff14));
+        watchpoints.check(0xff14, MemoryOperation::Write, 0x87, 0xff, 0xbf);
+        assert_eq!(
+            watchpoints.take_hit(),
+            Some(WatchHit {
+                address: 0xff12,
+                op: MemoryOperation::Write,
+                old: 0xf3,
+                new: 0x80
+            })
+        );
+        assert_eq!(watchpoints.take_hit(), None);
+
+        watchpoints.remove(0);
+        watchpoints.check(0xff12, MemoryOperation::Write, 0x80, 0x80, 0x80);
+        assert_eq!(watchpoints.take_hit(), None);
+    }
+
+    #[test]
+    fn change() {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add(Watchpoint {
+            label: "c000-c0ff".to_string(),
+            addresses: (0xc000..=0xc0ff).collect(),
+            kind: WatchKind::Change,
+        });
+
+        watchpoints.check(0xc010, MemoryOperation::Read, 0x12, 0x12, 0x12);
+        watchpoints.check(0xc010, MemoryOperation::Write, 0x12, 0x12, 0x12);
+        // Writing 0x34 to memory that ignores writes changes nothing.
+        watchpoints.check(0xc020, MemoryOperation::Write, 0x34, 0xff, 0xff);
+        assert_eq!(watchpoints.take_hit(), None);
+
+        watchpoints.check(0xc0ff, MemoryOperation::Write, 0x34, 0x12, 0x34);
+        assert_eq!(
+            watchpoints.take_hit(),
+            Some(WatchHit {
+                address: 0xc0ff,
+                op: MemoryOperation::Write,
+                old: 0x12,
+                new: 0x34
+            })
+        );
+    }
+}
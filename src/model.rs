@@ -0,0 +1,373 @@
+use std::{collections::BTreeSet, str::FromStr};
+
+use thiserror::Error;
+
+use crate::cpu::CpuSnapshot;
+
+/// The hardware revisions the emulator knows the post-boot state of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// The original Game Boy.
+    Dmg,
+    /// The Game Boy Pocket, which only differs from the DMG after booting in
+    /// the value left in register A.
+    Mgb,
+    /// The Game Boy Color, which runs games made for it in CGB mode and
+    /// others in DMG compatibility mode.
+    Cgb,
+    /// The Game Boy Color held in DMG compatibility mode, even for games
+    /// made for it.
+    CgbDmg,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown model {0:?}, expected dmg, mgb, cgb or cgb-dmg")]
+pub struct UnknownModel(String);
+
+impl FromStr for Model {
+    type Err = UnknownModel;
+
+    fn from_str(name: &str) -> Result<Model, UnknownModel> {
+        match name {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "cgb" => Ok(Model::Cgb),
+            "cgb-dmg" => Ok(Model::CgbDmg),
+            _ => Err(UnknownModel(name.to_owned())),
+        }
+    }
+}
+
+impl Model {
+    /// Whether the hardware is a Game Boy Color, in either mode.
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::CgbDmg)
+    }
+
+    /// The CPU registers the boot ROM leaves behind, as documented in Pan
+    /// Docs. On the DMG and MGB, the H and C flags are only set if the header
+    /// checksum isn't 0. [`Model::Cgb`] is taken to be in CGB mode.
+    pub fn post_boot_cpu(&self, header_checksum: u8) -> CpuSnapshot {
+        let flags = if header_checksum == 0 { 0x80 } else { 0xb0 };
+
+        let registers = match self {
+            Model::Dmg => [0x01, flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::Mgb => [0xff, flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d],
+            Model::CgbDmg => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7c],
+        };
+        let [a, f, b, c, d, e, h, l] = registers;
+
+        CpuSnapshot {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xfffe,
+            pc: 0x0100,
+            ..CpuSnapshot::default()
+        }
+    }
+}
+
+/// An IO register together with the value the boot ROM leaves in it on each
+/// model, as documented in Pan Docs.
+#[derive(Debug, Clone, Copy)]
+pub struct IoRegister {
+    pub address: u16,
+    pub name: &'static str,
+    values: [Option<u8>; 3],
+}
+
+impl IoRegister {
+    /// The value reading the register gives when the boot ROM hands control
+    /// to the cartridge. This is `None` if the model doesn't have the
+    /// register, or if the value depends on things like the boot timing or
+    /// the buttons held while booting.
+    pub fn post_boot_value(&self, model: Model) -> Option<u8> {
+        match model {
+            Model::Dmg => self.values[0],
+            Model::Mgb => self.values[1],
+            Model::Cgb => self.values[2],
+            // Pan Docs only lists the CGB mode values of the registers only
+            // games in that mode can use.
+            Model::CgbDmg => match self.address {
+                0xff4f | 0xff51..=0xff56 | 0xff68..=0xff6b | 0xff70 => None,
+                _ => self.values[2],
+            },
+        }
+    }
+}
+
+const fn register(
+    address: u16,
+    name: &'static str,
+    dmg: Option<u8>,
+    mgb: Option<u8>,
+    cgb: Option<u8>,
+) -> IoRegister {
+    IoRegister {
+        address,
+        name,
+        values: [dmg, mgb, cgb],
+    }
+}
+
+/// Every IO register with its post-boot values, ordered by address.
+#[rustfmt::skip]
+pub const IO_REGISTERS: &[IoRegister] = &[
+    register(0xff00, "P1", Some(0xcf), Some(0xcf), None),
+    register(0xff01, "SB", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff02, "SC", Some(0x7e), Some(0x7e), Some(0x7f)),
+    register(0xff04, "DIV", Some(0xab), Some(0xab), None),
+    register(0xff05, "TIMA", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff06, "TMA", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff07, "TAC", Some(0xf8), Some(0xf8), Some(0xf8)),
+    register(0xff0f, "IF", Some(0xe1), Some(0xe1), Some(0xe1)),
+    register(0xff10, "NR10", Some(0x80), Some(0x80), Some(0x80)),
+    register(0xff11, "NR11", Some(0xbf), Some(0xbf), Some(0xbf)),
+    register(0xff12, "NR12", Some(0xf3), Some(0xf3), Some(0xf3)),
+    register(0xff13, "NR13", Some(0xff), Some(0xff), Some(0xff)),
+    register(0xff14, "NR14", Some(0xbf), Some(0xbf), Some(0xbf)),
+    register(0xff16, "NR21", Some(0x3f), Some(0x3f), Some(0x3f)),
+    register(0xff17, "NR22", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff18, "NR23", Some(0xff), Some(0xff), Some(0xff)),
+    register(0xff19, "NR24", Some(0xbf), Some(0xbf), Some(0xbf)),
+    register(0xff1a, "NR30", Some(0x7f), Some(0x7f), Some(0x7f)),
+    register(0xff1b, "NR31", Some(0xff), Some(0xff), Some(0xff)),
+    register(0xff1c, "NR32", Some(0x9f), Some(0x9f), Some(0x9f)),
+    register(0xff1d, "NR33", Some(0xff), Some(0xff), Some(0xff)),
+    register(0xff1e, "NR34", Some(0xbf), Some(0xbf), Some(0xbf)),
+    register(0xff20, "NR41", Some(0xff), Some(0xff), Some(0xff)),
+    register(0xff21, "NR42", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff22, "NR43", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff23, "NR44", Some(0xbf), Some(0xbf), Some(0xbf)),
+    register(0xff24, "NR50", Some(0x77), Some(0x77), Some(0x77)),
+    register(0xff25, "NR51", Some(0xf3), Some(0xf3), Some(0xf3)),
+    register(0xff26, "NR52", Some(0xf1), Some(0xf1), Some(0xf1)),
+    register(0xff40, "LCDC", Some(0x91), Some(0x91), Some(0x91)),
+    register(0xff41, "STAT", Some(0x85), Some(0x85), None),
+    register(0xff42, "SCY", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff43, "SCX", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff44, "LY", Some(0x00), Some(0x00), None),
+    register(0xff45, "LYC", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff46, "DMA", Some(0xff), Some(0xff), Some(0x00)),
+    register(0xff47, "BGP", Some(0xfc), Some(0xfc), Some(0xfc)),
+    register(0xff48, "OBP0", None, None, None),
+    register(0xff49, "OBP1", None, None, None),
+    register(0xff4a, "WY", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff4b, "WX", Some(0x00), Some(0x00), Some(0x00)),
+    register(0xff4d, "KEY1", Some(0xff), Some(0xff), Some(0x7e)),
+    register(0xff4f, "VBK", None, None, Some(0xfe)),
+    register(0xff51, "HDMA1", None, None, Some(0xff)),
+    register(0xff52, "HDMA2", None, None, Some(0xff)),
+    register(0xff53, "HDMA3", None, None, Some(0xff)),
+    register(0xff54, "HDMA4", None, None, Some(0xff)),
+    register(0xff55, "HDMA5", None, None, Some(0xff)),
+    register(0xff56, "RP", None, None, Some(0x3e)),
+    register(0xff68, "BCPS", None, None, None),
+    register(0xff69, "BCPD", None, None, None),
+    register(0xff6a, "OCPS", None, None, None),
+    register(0xff6b, "OCPD", None, None, None),
+    register(0xff70, "SVBK", None, None, Some(0xf8)),
+    register(0xffff, "IE", Some(0x00), Some(0x00), Some(0x00)),
+];
+
+pub fn io_register(address: u16) -> Option<&'static IoRegister> {
+    IO_REGISTERS
+        .iter()
+        .find(|register| register.address == address)
+}
+
+/// The addresses a pattern names, or `None` if it names none. A pattern is
+/// an IO register name or an address in hex, where `?` or `x` in a name
+/// matches any character like in `NR1x` for the first sound channel, unless
+/// the pattern is a name itself like `SCX`. Two of
+/// them joined by `-`, like `NR10-NR14` or `ff30-ff3f`, name every address
+/// in between. Names ignore case.
+pub fn io_addresses(pattern: &str) -> Option<BTreeSet<u16>> {
+    if let Some((start, end)) = pattern.split_once('-') {
+        let (start, end) = (address(start)?, address(end)?);
+        return if start <= end {
+            Some((start..=end).collect())
+        } else {
+            None
+        };
+    }
+
+    if let Some(address) = address(pattern) {
+        return Some(std::iter::once(address).collect());
+    }
+
+    let pattern = pattern.trim();
+    let addresses: BTreeSet<_> = IO_REGISTERS
+        .iter()
+        .filter(|register| {
+            register.name.len() == pattern.len()
+                && register
+                    .name
+                    .chars()
+                    .zip(pattern.chars())
+                    .all(|(n, p)| matches!(p, 'x' | 'X' | '?') || n.eq_ignore_ascii_case(&p))
+        })
+        .map(|register| register.address)
+        .collect();
+
+    if addresses.is_empty() {
+        None
+    } else {
+        Some(addresses)
+    }
+}
+
+/// The address of an IO register name or of a hex address, optionally
+/// prefixed with `0x` or `$`.
+fn address(s: &str) -> Option<u16> {
+    let s = s.trim();
+    if let Some(register) = IO_REGISTERS
+        .iter()
+        .find(|register| register.name.eq_ignore_ascii_case(s))
+    {
+        return Some(register.address);
+    }
+
+    let hex = s.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{io_addresses, io_register, Model, IO_REGISTERS};
+    use crate::{
+        bios::DMG_BIOS,
+        cartridge::Cartridge,
+        device::{Device, DeviceBuilder},
+    };
+
+    #[test]
+    fn dmg_boot_leaves_documented_io_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&DMG_BIOS[0xa8..0xd8]);
+        rom[0x14d] = rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+
+        let mut device = Device::new(Cartridge::from_bytes(rom));
+        device.check_boot(600).unwrap();
+
+        // DIV depends on exactly how long booting takes, which isn't emulated.
+        let untimed = [0xff04];
+
+        let mismatches: Vec<_> = IO_REGISTERS
+            .iter()
+            .filter(|register| !untimed.contains(&register.address))
+            .filter_map(|register| {
+                let expected = register.post_boot_value(Model::Dmg)?;
+                let actual = device.read(register.address).unwrap();
+                if actual == expected {
+                    None
+                } else {
+                    Some(format!(
+                        "{} is {:#04x} instead of {:#04x}",
+                        register.name, actual, expected
+                    ))
+                }
+            })
+            .collect();
+
+        assert!(mismatches.is_empty(), "{}", mismatches.join(", "));
+    }
+
+    #[test]
+    fn skipped_boot_leaves_documented_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x14d] = 0x42;
+
+        for model in [Model::Dmg, Model::Mgb].iter().copied() {
+            let mut device = DeviceBuilder::new(Cartridge::from_bytes(rom.clone()))
+                .model(model)
+                .skip_boot()
+                .build();
+
+            let cpu = device.cpu().snapshot();
+            assert_eq!((cpu.f, cpu.pc, cpu.sp), (0xb0, 0x0100, 0xfffe));
+            assert_eq!(cpu.a, if model == Model::Dmg { 0x01 } else { 0xff });
+
+            // DIV depends on the boot timing, STAT is in mode 0 since turning
+            // the LCD on starts at the top of line 0 instead of late on line
+            // 153, and DMA isn't written.
+            let untimed = [0xff04, 0xff41, 0xff46];
+            for register in IO_REGISTERS
+                .iter()
+                .filter(|register| !untimed.contains(&register.address))
+            {
+                if let Some(expected) = register.post_boot_value(model) {
+                    assert_eq!(
+                        device.read(register.address).unwrap(),
+                        expected,
+                        "{} on {:?}",
+                        register.name,
+                        model
+                    );
+                }
+            }
+
+            assert_eq!(device.read(0x0000).unwrap(), 0);
+            device.step();
+            assert_eq!(device.cpu().pc, 0x0101);
+        }
+
+        // A CGB runs games not made for it in DMG mode.
+        for model in [Model::Cgb, Model::CgbDmg].iter().copied() {
+            let device = DeviceBuilder::new(Cartridge::from_bytes(rom.clone()))
+                .model(model)
+                .skip_boot()
+                .build();
+
+            assert!(!device.cgb_mode());
+            let cpu = device.cpu().snapshot();
+            assert_eq!((cpu.a, cpu.f, cpu.e, cpu.l), (0x11, 0x80, 0x08, 0x7c));
+            assert_eq!(device.read(0xff40).unwrap(), 0x91);
+        }
+
+        assert_eq!(Model::Dmg.post_boot_cpu(0).f, 0x80);
+        assert_eq!(Model::Cgb.post_boot_cpu(0x42).a, 0x11);
+    }
+
+    #[test]
+    fn patterns() {
+        let addresses =
+            |pattern| io_addresses(pattern).map(|set| set.into_iter().collect::<Vec<_>>());
+
+        assert_eq!(addresses("lcdc"), Some(vec![0xff40]));
+        assert_eq!(addresses("0xff40"), Some(vec![0xff40]));
+        assert_eq!(addresses("$c000"), Some(vec![0xc000]));
+        assert_eq!(
+            addresses("NR1x"),
+            Some(vec![0xff10, 0xff11, 0xff12, 0xff13, 0xff14])
+        );
+        assert_eq!(addresses("SC?"), Some(vec![0xff42, 0xff43]));
+        // A name always means that register, even if it could be a pattern.
+        assert_eq!(addresses("SCx"), Some(vec![0xff43]));
+        assert_eq!(addresses("WX"), Some(vec![0xff4b]));
+        assert_eq!(addresses("OBP0-OBP1"), Some(vec![0xff48, 0xff49]));
+        assert_eq!(addresses("ff30 - ff3f").unwrap().len(), 16);
+        assert_eq!(addresses("NR14-NR10"), None);
+        assert_eq!(addresses("NR6x"), None);
+        assert_eq!(addresses("ff40-"), None);
+
+        assert_eq!(io_register(0xff4b).unwrap().name, "WX");
+        assert!(io_register(0xff03).is_none());
+    }
+
+    #[test]
+    fn registers_are_ordered() {
+        assert!(IO_REGISTERS
+            .windows(2)
+            .all(|pair| pair[0].address < pair[1].address));
+    }
+}
@@ -0,0 +1,366 @@
+use std::convert::TryInto;
+
+use thiserror::Error;
+
+use crate::{
+    input::{InputAction, InputEvent, InputSequence},
+    memory::mmu::JoypadButton,
+};
+
+/// Every button, in the order BizHawk logs them.
+const BUTTONS: [JoypadButton; 8] = [
+    JoypadButton::Up,
+    JoypadButton::Down,
+    JoypadButton::Left,
+    JoypadButton::Right,
+    JoypadButton::Start,
+    JoypadButton::Select,
+    JoypadButton::B,
+    JoypadButton::A,
+];
+
+/// The buttons of a BizHawk Game Boy movie along with the mnemonics of its
+/// input log, used when the log doesn't have a `LogKey` line.
+const BK2_KEYS: [(&str, char); 9] = [
+    ("Up", 'U'),
+    ("Down", 'D'),
+    ("Left", 'L'),
+    ("Right", 'R'),
+    ("Start", 'S'),
+    ("Select", 's'),
+    ("B", 'B'),
+    ("A", 'A'),
+    ("Power", 'P'),
+];
+
+const VBM_SIGNATURE: &[u8; 4] = b"VBM\x1a";
+/// VBM movies put the author and description after the 64 byte header, so the
+/// input starts at 0x100 in the movies we write.
+const VBM_INPUT_OFFSET: usize = 0x100;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    #[error("not a VBM movie")]
+    NotVbm,
+    #[error("the movie ends before its input does")]
+    Truncated,
+    #[error("the movie starts from a save state or save game, not from power on")]
+    NotFromPowerOn,
+    #[error("input log line {line} doesn't match the log key")]
+    Bk2Line { line: usize },
+}
+
+/// A joypad recording as the buttons held during every frame.
+///
+/// This is what the movie formats of other emulators come down to once
+/// everything but the input is left out, so it is what they are converted
+/// through. Frames are counted like in an [`InputSequence`]: the buttons of
+/// frame `n` are the ones held after its events at frame `n` are applied.
+///
+/// Other emulators often skip the boot ROM, in which case their movies are
+/// ahead by the roughly 330 frames the boot ROM takes here.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    frames: Vec<Vec<JoypadButton>>,
+}
+
+impl Movie {
+    pub fn new(frames: Vec<Vec<JoypadButton>>) -> Movie {
+        Movie { frames }
+    }
+
+    pub fn frames(&self) -> &[Vec<JoypadButton>] {
+        &self.frames
+    }
+
+    /// Plays the sequence to find which buttons are held during each frame,
+    /// up until everything is released after its last event.
+    pub fn from_input_sequence(sequence: &InputSequence) -> Movie {
+        let mut frames = Vec::new();
+        let mut held: Vec<JoypadButton> = Vec::new();
+        let mut pressed: Vec<JoypadButton> = Vec::new();
+        let mut events = sequence.events().iter().peekable();
+
+        while events.peek().is_some() || !pressed.is_empty() {
+            let frame = frames.len();
+
+            held.retain(|button| !pressed.contains(button));
+            pressed.clear();
+
+            while let Some(event) = events.next_if(|event| event.frame <= frame) {
+                match event.action {
+                    InputAction::Press => {
+                        pressed.extend_from_slice(&event.buttons);
+                        held.extend_from_slice(&event.buttons);
+                    }
+                    InputAction::Hold => held.extend_from_slice(&event.buttons),
+                    InputAction::Release => held.retain(|button| !event.buttons.contains(button)),
+                }
+            }
+
+            frames.push(sorted(&held));
+        }
+
+        Movie { frames }
+    }
+
+    /// Converts the movie to hold and release events whenever the buttons
+    /// change, releasing everything after the last frame.
+    pub fn to_input_sequence(&self) -> InputSequence {
+        let mut events = Vec::new();
+        let none = Vec::new();
+        let mut previous = &none;
+
+        for (frame, buttons) in self.frames.iter().chain(Some(&none)).enumerate() {
+            let released: Vec<_> = previous
+                .iter()
+                .filter(|button| !buttons.contains(button))
+                .copied()
+                .collect();
+            let held: Vec<_> = buttons
+                .iter()
+                .filter(|button| !previous.contains(button))
+                .copied()
+                .collect();
+
+            if !released.is_empty() {
+                events.push(InputEvent {
+                    frame,
+                    action: InputAction::Release,
+                    buttons: released,
+                });
+            }
+            if !held.is_empty() {
+                events.push(InputEvent {
+                    frame,
+                    action: InputAction::Hold,
+                    buttons: held,
+                });
+            }
+
+            previous = buttons;
+        }
+
+        InputSequence::new(events)
+    }
+
+    /// Reads the input log of a BizHawk movie, the `Input Log.txt` inside a
+    /// `.bk2` archive. Buttons other than the joypad's, like power, are
+    /// ignored.
+    pub fn from_bk2_log(log: &str) -> Result<Movie, MovieError> {
+        let mut key: Vec<Vec<Option<JoypadButton>>> =
+            vec![BK2_KEYS.iter().map(|(name, _)| bk2_button(name)).collect()];
+        let mut frames = Vec::new();
+
+        for (index, line) in log.lines().enumerate() {
+            let line = line.trim();
+
+            if let Some(log_key) = line.strip_prefix("LogKey:") {
+                key = log_key
+                    .split('#')
+                    .filter(|group| !group.is_empty())
+                    .map(|group| {
+                        group
+                            .split('|')
+                            .filter(|name| !name.is_empty())
+                            .map(bk2_button)
+                            .collect()
+                    })
+                    .collect();
+            } else if line.starts_with('|') {
+                let groups: Vec<&str> = line.trim_matches('|').split('|').collect();
+                if groups.len() != key.len() {
+                    return Err(MovieError::Bk2Line { line: index + 1 });
+                }
+
+                let mut buttons = Vec::new();
+                for (group, names) in groups.iter().zip(key.iter()) {
+                    if group.chars().count() != names.len() {
+                        return Err(MovieError::Bk2Line { line: index + 1 });
+                    }
+
+                    for (c, button) in group.chars().zip(names.iter()) {
+                        if let (Some(button), false) = (button, c == '.') {
+                            buttons.push(*button);
+                        }
+                    }
+                }
+
+                frames.push(sorted(&buttons));
+            }
+        }
+
+        Ok(Movie { frames })
+    }
+
+    /// Writes the movie as a BizHawk input log, to be put into a `.bk2`
+    /// archive as `Input Log.txt`.
+    pub fn to_bk2_log(&self) -> String {
+        let mut log = String::from("[Input]\nLogKey:#");
+        for (name, _) in BK2_KEYS.iter() {
+            log.push_str(name);
+            log.push('|');
+        }
+        log.push('\n');
+
+        for buttons in self.frames.iter() {
+            log.push('|');
+            for (name, mnemonic) in BK2_KEYS.iter() {
+                let pressed = matches!(bk2_button(name), Some(button) if buttons.contains(&button));
+                log.push(if pressed { *mnemonic } else { '.' });
+            }
+            log.push_str("|\n");
+        }
+
+        log.push_str("[/Input]\n");
+        log
+    }
+
+    /// Reads the input of the first controller of a VisualBoyAdvance movie.
+    /// Only movies recorded from power on can be played back.
+    pub fn from_vbm(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.len() < 0x40 || &data[0..4] != VBM_SIGNATURE {
+            return Err(MovieError::NotVbm);
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+        };
+
+        if data[0x14] & 0b11 != 0 {
+            return Err(MovieError::NotFromPowerOn);
+        }
+
+        let frame_count = read_u32(0x0c);
+        let controllers = (data[0x15] & 0xf).count_ones().max(1) as usize;
+        let start = read_u32(0x3c);
+        let end = start + frame_count * controllers * 2;
+        let input = data.get(start..end).ok_or(MovieError::Truncated)?;
+
+        let frames = input
+            .chunks(controllers * 2)
+            .map(|frame| {
+                BUTTONS
+                    .iter()
+                    .filter(|button| frame[0] & vbm_bit(**button) != 0)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+
+        Ok(Movie { frames })
+    }
+
+    /// Writes the movie as a VisualBoyAdvance movie for a single controller,
+    /// starting from power on.
+    pub fn to_vbm(&self) -> Vec<u8> {
+        let mut data = vec![0; VBM_INPUT_OFFSET];
+        data[0..4].copy_from_slice(VBM_SIGNATURE);
+        data[0x04..0x08].copy_from_slice(&1u32.to_le_bytes());
+        data[0x0c..0x10].copy_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        data[0x15] = 1;
+        data[0x3c..0x40].copy_from_slice(&(VBM_INPUT_OFFSET as u32).to_le_bytes());
+
+        for buttons in self.frames.iter() {
+            let bits = buttons
+                .iter()
+                .fold(0, |bits, button| bits | vbm_bit(*button));
+            data.extend_from_slice(&[bits, 0]);
+        }
+
+        data
+    }
+}
+
+/// Orders buttons the same way every time, so movies compare equal regardless
+/// of the order buttons were pressed in.
+fn sorted(buttons: &[JoypadButton]) -> Vec<JoypadButton> {
+    BUTTONS
+        .iter()
+        .filter(|button| buttons.contains(button))
+        .copied()
+        .collect()
+}
+
+fn bk2_button(name: &str) -> Option<JoypadButton> {
+    let name = name.trim_start_matches("P1 ");
+    BUTTONS
+        .iter()
+        .zip(BK2_KEYS.iter())
+        .find(|(_, (key, _))| *key == name)
+        .map(|(button, _)| *button)
+}
+
+fn vbm_bit(button: JoypadButton) -> u8 {
+    match button {
+        JoypadButton::A => 1,
+        JoypadButton::B => 1 << 1,
+        JoypadButton::Select => 1 << 2,
+        JoypadButton::Start => 1 << 3,
+        JoypadButton::Right => 1 << 4,
+        JoypadButton::Left => 1 << 5,
+        JoypadButton::Up => 1 << 6,
+        JoypadButton::Down => 1 << 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieError};
+    use crate::{input::InputSequence, memory::mmu::JoypadButton};
+
+    fn movie() -> Movie {
+        Movie::new(vec![
+            vec![],
+            vec![JoypadButton::Start],
+            vec![JoypadButton::Up, JoypadButton::A],
+            vec![JoypadButton::A],
+        ])
+    }
+
+    #[test]
+    fn input_sequence() {
+        let sequence: InputSequence =
+            "frame 1: press start; frame 2: hold up, a; frame 3: release up"
+                .parse()
+                .unwrap();
+
+        assert_eq!(Movie::from_input_sequence(&sequence), movie());
+        // Releasing everything at the end takes an extra frame.
+        let mut frames = movie().frames;
+        frames.push(vec![]);
+        assert_eq!(
+            Movie::from_input_sequence(&movie().to_input_sequence()),
+            Movie::new(frames)
+        );
+    }
+
+    #[test]
+    fn bk2() {
+        let log = movie().to_bk2_log();
+        assert!(log.contains("|U......A.|"));
+        assert_eq!(Movie::from_bk2_log(&log), Ok(movie()));
+
+        let log = "LogKey:#Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|\n\
+                   |P|........|\n\
+                   |.|....S...|\n\
+                   |.|U......A|\n\
+                   |.|.......A|\n";
+        assert_eq!(Movie::from_bk2_log(log), Ok(movie()));
+
+        assert_eq!(
+            Movie::from_bk2_log("|U..|\n"),
+            Err(MovieError::Bk2Line { line: 1 })
+        );
+    }
+
+    #[test]
+    fn vbm() {
+        let data = movie().to_vbm();
+        assert_eq!(&data[0x100..], &[0, 0, 8, 0, 0x41, 0, 1, 0]);
+        assert_eq!(Movie::from_vbm(&data), Ok(movie()));
+
+        assert_eq!(Movie::from_vbm(&data[..0x104]), Err(MovieError::Truncated));
+        assert_eq!(Movie::from_vbm(b"VBA"), Err(MovieError::NotVbm));
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::Range;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// The gray levels of the four shades, from lightest to darkest, independent of
+/// the palette the screen is shown in.
+const GRAY_LEVELS: [u32; 4] = [255, 170, 85, 0];
+
+/// A compact form of the screen for machine learning, written to a
+/// contiguous buffer of one byte per pixel in row-major order, see
+/// [`Device::observe`](crate::device::Device::observe).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observation {
+    /// The 160x144 shade indices from 0 (lightest) to 3 (darkest).
+    Shades,
+    /// Gray levels from 0 to 255, scaled down to the given size by averaging
+    /// the pixels that fall into each output pixel.
+    Grayscale { width: usize, height: usize },
+}
+
+impl Observation {
+    /// The 84x84 grayscale frames commonly used for reinforcement learning.
+    pub const DOWNSAMPLED: Observation = Observation::Grayscale {
+        width: 84,
+        height: 84,
+    };
+
+    /// The number of bytes an observation takes.
+    pub fn size(&self) -> usize {
+        match *self {
+            Observation::Shades => WIDTH * HEIGHT,
+            Observation::Grayscale { width, height } => width * height,
+        }
+    }
+
+    pub(crate) fn write(&self, shades: &[u8], out: &mut [u8]) {
+        assert_eq!(
+            out.len(),
+            self.size(),
+            "observation buffer has the wrong size"
+        );
+
+        let (width, height) = match *self {
+            Observation::Shades => {
+                out.copy_from_slice(shades);
+                return;
+            }
+            Observation::Grayscale { width, height } => (width, height),
+        };
+
+        for y in 0..height {
+            let rows = source_span(y, height, HEIGHT);
+
+            for x in 0..width {
+                let columns = source_span(x, width, WIDTH);
+
+                let mut sum = 0;
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        sum += GRAY_LEVELS[shades[row * WIDTH + column] as usize & 0b11];
+                    }
+                }
+
+                let count = (rows.len() * columns.len()) as u32;
+                out[y * width + x] = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+/// The source pixels along one axis that make up output pixel `i`, at least
+/// one even when scaling up.
+fn source_span(i: usize, size: usize, source_size: usize) -> Range<usize> {
+    let start = i * source_size / size;
+    let end = (i + 1) * source_size / size;
+    start..end.max(start + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Observation, HEIGHT, WIDTH};
+
+    #[test]
+    fn grayscale() {
+        // The left half is white and the right half black.
+        let shades: Vec<u8> = (0..WIDTH * HEIGHT)
+            .map(|i| if i % WIDTH < WIDTH / 2 { 0 } else { 3 })
+            .collect();
+
+        let mut out = vec![0; 4 * 2];
+        Observation::Grayscale {
+            width: 4,
+            height: 2,
+        }
+        .write(&shades, &mut out);
+        assert_eq!(out, vec![255, 255, 0, 0, 255, 255, 0, 0]);
+
+        let mut out = vec![0; Observation::DOWNSAMPLED.size()];
+        Observation::DOWNSAMPLED.write(&shades, &mut out);
+        assert_eq!(&out[40..44], &[255, 255, 0, 0]);
+
+        // Every other column is black, so those average out to gray.
+        let shades: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| (i % 2 * 3) as u8).collect();
+        let mut out = vec![0; 80 * 72];
+        Observation::Grayscale {
+            width: 80,
+            height: 72,
+        }
+        .write(&shades, &mut out);
+        assert!(out.iter().all(|gray| *gray == 128));
+    }
+
+    #[test]
+    fn shades() {
+        let shades: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| (i % 4) as u8).collect();
+        let mut out = vec![0; Observation::Shades.size()];
+        Observation::Shades.write(&shades, &mut out);
+        assert_eq!(out, shades);
+    }
+}
@@ -0,0 +1,261 @@
+use std::borrow::Cow;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 144;
+
+/// The size of a character drawn by [`Overlay::draw_text`], in pixels.
+pub const GLYPH_WIDTH: i32 = 3;
+pub const GLYPH_HEIGHT: i32 = 5;
+
+/// An RGBA color, where an alpha of 0 is fully transparent and 255 hides the
+/// game image completely.
+pub type Color = [u8; 4];
+
+pub const TRANSPARENT: Color = [0, 0, 0, 0];
+
+/// A layer of 160x144 pixels drawn over the game image by the frontend, for
+/// things like hitbox viewers or RAM value HUDs, which never touches the
+/// emulated VRAM.
+///
+/// Drawing is clipped to the screen, so shapes can stick out of it or lie off
+/// screen entirely. What is drawn stays until it is drawn over or cleared.
+pub struct Overlay {
+    pixels: Box<[Color; WIDTH * HEIGHT]>,
+    /// Whether anything was drawn since the last clear, to skip compositing
+    /// when nothing was.
+    drawn: bool,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            pixels: Box::new([TRANSPARENT; WIDTH * HEIGHT]),
+            drawn: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.drawn
+    }
+
+    pub fn clear(&mut self) {
+        if self.drawn {
+            self.pixels
+                .iter_mut()
+                .for_each(|pixel| *pixel = TRANSPARENT);
+            self.drawn = false;
+        }
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Color {
+        index(x, y).map_or(TRANSPARENT, |i| self.pixels[i])
+    }
+
+    /// Replaces a pixel, so drawing [`TRANSPARENT`] erases it.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(i) = index(x, y) {
+            self.pixels[i] = color;
+            self.drawn = true;
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        for y in y.max(0)..(y + height).min(HEIGHT as i32) {
+            for x in x.max(0)..(x + width).min(WIDTH as i32) {
+                self.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Draws the one pixel wide outline of a rectangle, such as a hitbox.
+    pub fn draw_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width - 1, y, 1, height, color);
+    }
+
+    /// Draws text in a 3x5 pixel font with a pixel between characters,
+    /// starting at the top left corner `(x, y)`. Lowercase letters are drawn
+    /// as uppercase ones, and characters the font lacks as `?`.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, color: Color) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i32 * (GLYPH_WIDTH + 1);
+
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> column) != 0 {
+                        self.set_pixel(left + column, y + row as i32, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blends the overlay over 160x144 RGB pixels.
+    pub fn composite(&self, rgb: &mut [u8]) {
+        if !self.drawn {
+            return;
+        }
+
+        for (pixel, color) in rgb.chunks_exact_mut(3).zip(self.pixels.iter()) {
+            let alpha = color[3] as u32;
+            for c in 0..3 {
+                let blended = color[c] as u32 * alpha + pixel[c] as u32 * (255 - alpha);
+                pixel[c] = ((blended + 127) / 255) as u8;
+            }
+        }
+    }
+
+    /// The RGB pixels with the overlay blended over them, which are only
+    /// copied when something was drawn.
+    pub fn composited<'a>(&self, rgb: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self.drawn {
+            return Cow::Borrowed(rgb);
+        }
+
+        let mut composited = rgb.to_vec();
+        self.composite(&mut composited);
+        Cow::Owned(composited)
+    }
+}
+
+/// The width of a line of text drawn by [`Overlay::draw_text`].
+pub fn text_width(text: &str) -> i32 {
+    match text.chars().count() as i32 {
+        0 => 0,
+        count => count * (GLYPH_WIDTH + 1) - 1,
+    }
+}
+
+fn index(x: i32, y: i32) -> Option<usize> {
+    if (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y) {
+        Some(y as usize * WIDTH + x as usize)
+    } else {
+        None
+    }
+}
+
+/// The rows of a character from top to bottom, with the leftmost pixel in
+/// bit 2.
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{text_width, Overlay, TRANSPARENT};
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    #[test]
+    fn clips_to_screen() {
+        let mut overlay = Overlay::new();
+        overlay.set_pixel(-1, 0, RED);
+        overlay.set_pixel(160, 143, RED);
+        assert!(overlay.is_empty());
+
+        overlay.draw_rect(-4, -4, 8, 8, RED);
+        assert_eq!(overlay.pixel(3, 0), RED);
+        assert_eq!(overlay.pixel(0, 3), RED);
+        assert_eq!(overlay.pixel(2, 2), TRANSPARENT);
+
+        overlay.fill_rect(150, 140, 100, 100, RED);
+        assert_eq!(overlay.pixel(159, 143), RED);
+
+        overlay.clear();
+        assert!(overlay.is_empty());
+        assert_eq!(overlay.pixel(159, 143), TRANSPARENT);
+    }
+
+    #[test]
+    fn text() {
+        let mut overlay = Overlay::new();
+        overlay.draw_text(10, 20, "1a", RED);
+
+        // The 1 has a base three pixels wide, the A a crossbar in the middle.
+        let row = |y| {
+            (10..17)
+                .map(|x| overlay.pixel(x, y) == RED)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(row(24), [true, true, true, false, true, false, true]);
+        assert_eq!(row(22), [false, true, false, false, true, true, true]);
+        assert_eq!(text_width("1a"), 7);
+        assert_eq!(text_width(""), 0);
+    }
+
+    #[test]
+    fn blends_over_game_image() {
+        let mut rgb = vec![100; 3 * 160 * 144];
+        let mut overlay = Overlay::new();
+        assert!(matches!(
+            overlay.composited(&rgb),
+            std::borrow::Cow::Borrowed(_)
+        ));
+
+        overlay.set_pixel(0, 0, RED);
+        overlay.set_pixel(1, 0, [0, 0, 200, 128]);
+        overlay.composite(&mut rgb);
+
+        assert_eq!(&rgb[0..3], &[255, 0, 0]);
+        assert_eq!(&rgb[3..6], &[50, 50, 150]);
+        assert_eq!(&rgb[6..9], &[100, 100, 100]);
+    }
+}
@@ -0,0 +1,262 @@
+/// The colors the four DMG shades are shown as, from lightest to darkest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    /// Looks up one of the built-in [`PALETTES`] by name, ignoring case.
+    pub fn by_name(name: &str) -> Option<Palette> {
+        PALETTES
+            .iter()
+            .find(|palette| palette.name.eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    /// A palette of the given colors, from lightest to darkest.
+    pub fn custom(colors: [[u8; 3]; 4]) -> Palette {
+        Palette {
+            name: "custom",
+            colors,
+        }
+    }
+
+    /// Parses the name of a built-in palette, or four custom colors written
+    /// as `rrggbb` and separated by commas like
+    /// `e0f8d0,88c070,346856,081820`.
+    pub fn parse(s: &str) -> Option<Palette> {
+        if let Some(palette) = Palette::by_name(s) {
+            return Some(palette);
+        }
+
+        let mut colors = [[0; 3]; 4];
+        let mut parts = s.split(',');
+        for color in colors.iter_mut() {
+            let part = parts.next()?.trim().trim_start_matches('#');
+            if part.len() != 6 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            let value = u32::from_str_radix(part, 16).ok()?;
+            *color = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Palette::custom(colors)),
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        GRAYSCALE
+    }
+}
+
+pub const GRAYSCALE: Palette = Palette {
+    name: "grayscale",
+    colors: [[255, 255, 255], [192, 192, 192], [96, 96, 96], [0, 0, 0]],
+};
+
+/// Grays spaced evenly in perceived lightness, so the middle shades are as far
+/// apart from each other as from black and white.
+pub const HIGH_CONTRAST: Palette = Palette {
+    name: "high-contrast",
+    colors: [[255, 255, 255], [162, 162, 162], [79, 79, 79], [0, 0, 0]],
+};
+
+/// The greens of the original Game Boy screen.
+pub const GREEN: Palette = Palette {
+    name: "green",
+    colors: [[155, 188, 15], [139, 172, 15], [48, 98, 48], [15, 56, 15]],
+};
+
+/// Yellows against blues, which stay apart with red-green color blindness
+/// (protanopia and deuteranopia).
+pub const BLUE_ORANGE: Palette = Palette {
+    name: "blue-orange",
+    colors: [[255, 244, 214], [245, 178, 80], [44, 123, 182], [8, 29, 88]],
+};
+
+/// Cyans against reds, which stay apart with blue-yellow color blindness
+/// (tritanopia).
+pub const RED_CYAN: Palette = Palette {
+    name: "red-cyan",
+    colors: [
+        [236, 255, 255],
+        [120, 210, 220],
+        [200, 60, 70],
+        [60, 10, 20],
+    ],
+};
+
+pub const PALETTES: [Palette; 5] = [GRAYSCALE, GREEN, HIGH_CONTRAST, BLUE_ORANGE, RED_CYAN];
+
+/// Converts an RGB555 color of the CGB, with red in the lowest 5 bits, to
+/// RGB888 by repeating the upper bits of each channel in the lower ones.
+pub fn rgb555_to_rgb888(color: u16) -> [u8; 3] {
+    let channel = |shift: u16| {
+        let value = (color >> shift & 0x1f) as u8;
+        value << 3 | value >> 2
+    };
+
+    [channel(0), channel(5), channel(10)]
+}
+
+/// Packs an RGB888 color into 16 bits, with red in the upper 5 bits and green
+/// in the middle 6.
+pub fn rgb888_to_rgb565([r, g, b]: [u8; 3]) -> u16 {
+    (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+}
+
+/// Converts the screen the GPU drew into the pixel format a frontend needs,
+/// straight from its shades or colors instead of from another framebuffer.
+pub trait ColorMapper {
+    /// The size of a converted pixel in bytes.
+    fn bytes_per_pixel(&self) -> usize;
+
+    /// Writes a DMG shade, from 0 for the lightest to 3, shown in the colors
+    /// of the palette of the layer that drew it.
+    fn map_shade(&mut self, shade: u8, palette: &Palette, pixel: &mut [u8]);
+
+    /// Writes an RGB555 color drawn in CGB mode.
+    fn map_color(&mut self, color: u16, pixel: &mut [u8]);
+}
+
+/// Three bytes per pixel, the format of the display framebuffer.
+pub struct Rgb888;
+
+impl ColorMapper for Rgb888 {
+    fn bytes_per_pixel(&self) -> usize {
+        3
+    }
+
+    fn map_shade(&mut self, shade: u8, palette: &Palette, pixel: &mut [u8]) {
+        pixel.copy_from_slice(&palette.colors[shade as usize]);
+    }
+
+    fn map_color(&mut self, color: u16, pixel: &mut [u8]) {
+        pixel.copy_from_slice(&rgb555_to_rgb888(color));
+    }
+}
+
+/// Four bytes per pixel with an opaque alpha channel, as GPU textures and web
+/// canvases take them.
+pub struct Rgba8888;
+
+impl ColorMapper for Rgba8888 {
+    fn bytes_per_pixel(&self) -> usize {
+        4
+    }
+
+    fn map_shade(&mut self, shade: u8, palette: &Palette, pixel: &mut [u8]) {
+        pixel[..3].copy_from_slice(&palette.colors[shade as usize]);
+        pixel[3] = 255;
+    }
+
+    fn map_color(&mut self, color: u16, pixel: &mut [u8]) {
+        pixel[..3].copy_from_slice(&rgb555_to_rgb888(color));
+        pixel[3] = 255;
+    }
+}
+
+/// Two bytes per pixel in little endian, as small embedded displays take them.
+pub struct Rgb565;
+
+impl ColorMapper for Rgb565 {
+    fn bytes_per_pixel(&self) -> usize {
+        2
+    }
+
+    fn map_shade(&mut self, shade: u8, palette: &Palette, pixel: &mut [u8]) {
+        let color = rgb888_to_rgb565(palette.colors[shade as usize]);
+        pixel.copy_from_slice(&color.to_le_bytes());
+    }
+
+    fn map_color(&mut self, color: u16, pixel: &mut [u8]) {
+        // Green gets its extra bit from its top one, like rgb555_to_rgb888.
+        let green = (color >> 5 & 0x1f) << 1 | (color >> 9 & 1);
+        let color = (color & 0x1f) << 11 | green << 5 | (color >> 10 & 0x1f);
+        pixel.copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rgb555_to_rgb888, rgb888_to_rgb565, ColorMapper, Palette, Rgb565, GRAYSCALE, GREEN,
+        HIGH_CONTRAST, PALETTES,
+    };
+
+    fn luminance([r, g, b]: [u8; 3]) -> f32 {
+        0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32
+    }
+
+    #[test]
+    fn shades_get_darker() {
+        for palette in PALETTES.iter() {
+            for pair in palette.colors.windows(2) {
+                assert!(
+                    luminance(pair[0]) > luminance(pair[1]),
+                    "{} is not ordered from light to dark",
+                    palette.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn by_name() {
+        assert_eq!(Palette::by_name("High-Contrast"), Some(HIGH_CONTRAST));
+        assert_eq!(Palette::by_name("sepia"), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Palette::parse("green"), Some(GREEN));
+        assert_eq!(
+            Palette::parse("ffffff, #aa5500,550000,000000"),
+            Some(Palette::custom([
+                [255, 255, 255],
+                [170, 85, 0],
+                [85, 0, 0],
+                [0, 0, 0]
+            ]))
+        );
+        assert_eq!(Palette::parse("ffffff,aa5500,550000"), None);
+        assert_eq!(Palette::parse("ffffff,aa5500,550000,000000,000000"), None);
+        assert_eq!(Palette::parse("ffffff,aa5500,550000,00000g"), None);
+    }
+
+    #[test]
+    fn rgb555() {
+        assert_eq!(rgb555_to_rgb888(0x7fff), [255, 255, 255]);
+        assert_eq!(rgb555_to_rgb888(0x0000), [0, 0, 0]);
+        assert_eq!(rgb555_to_rgb888(0x001f), [255, 0, 0]);
+        assert_eq!(rgb555_to_rgb888(0x4210), [132, 132, 132]);
+    }
+
+    #[test]
+    fn rgb565() {
+        assert_eq!(rgb888_to_rgb565([255, 255, 255]), 0xffff);
+        assert_eq!(rgb888_to_rgb565([255, 0, 0]), 0xf800);
+        assert_eq!(rgb888_to_rgb565([0, 255, 0]), 0x07e0);
+
+        let mut pixel = [0; 2];
+        Rgb565.map_shade(2, &GRAYSCALE, &mut pixel);
+        assert_eq!(u16::from_le_bytes(pixel), rgb888_to_rgb565([96, 96, 96]));
+
+        // Every RGB555 color ends up as its RGB888 color would.
+        for color in (0..0x8000).step_by(0x111) {
+            Rgb565.map_color(color, &mut pixel);
+            assert_eq!(
+                u16::from_le_bytes(pixel),
+                rgb888_to_rgb565(rgb555_to_rgb888(color)),
+                "{:#06x}",
+                color
+            );
+        }
+    }
+}
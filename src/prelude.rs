@@ -0,0 +1,19 @@
+//! The types most programs built on the library need, to glob import with
+//! `use gameboy::prelude::*`.
+//!
+//! Everything in here is part of the stable API: it is only removed or
+//! changed in a breaking way along with a bump of the major version (or the
+//! minor one before 1.0).
+
+pub use crate::{
+    cartridge::{Cartridge, SaveError},
+    device::{BootError, BootRomError, Device, DeviceBuilder, StopReason},
+    input::InputSequence,
+    memory::{mmu::JoypadButton, Memory, MemoryError},
+    model::Model,
+    movie::Movie,
+    overlay::{Color, Overlay},
+    palette::{ColorMapper, Palette},
+    rng::Rng,
+    sink::{AudioSink, FrameSink},
+};
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+/// Where an instruction was executed from. ROM addresses are told apart by
+/// the bank they are in, since the same address runs different code depending
+/// on the bank that is mapped in. Hot spots in banks other than 0 are reported
+/// at their address in the switchable 0x4000-0x7fff window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    /// An address from 0x0000 to 0x7fff in the given ROM bank.
+    Rom { bank: usize, address: u16 },
+    /// An address from 0x8000 up, in RAM.
+    Ram(u16),
+}
+
+/// The wall-clock time spent in the parts of the emulator that don't execute
+/// instructions, see [`Device::set_timing`](crate::device::Device::set_timing).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubsystemTimes {
+    /// Drawing scanlines and advancing the LCD.
+    pub ppu: Duration,
+    /// Converting finished frames to RGB framebuffers.
+    pub framebuffer: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotSpot {
+    pub location: Location,
+    pub count: u32,
+}
+
+/// How many times the instruction at every address was executed.
+///
+/// Counts are kept in a flat array per ROM bank, which is only allocated once
+/// code in that bank runs, and one for everything outside of ROM.
+pub struct ExecutionProfile {
+    rom_banks: Vec<Option<Box<[u32]>>>,
+    ram: Box<[u32]>,
+}
+
+impl ExecutionProfile {
+    pub fn new(rom_banks: usize) -> ExecutionProfile {
+        ExecutionProfile {
+            rom_banks: vec![None; rom_banks],
+            ram: vec![0; 0x8000].into_boxed_slice(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, location: Location) {
+        let count = match location {
+            Location::Rom { bank, address } => {
+                if bank >= self.rom_banks.len() {
+                    self.rom_banks.resize(bank + 1, None);
+                }
+
+                let counts =
+                    self.rom_banks[bank].get_or_insert_with(|| vec![0; 0x4000].into_boxed_slice());
+                &mut counts[address as usize & 0x3fff]
+            }
+            Location::Ram(address) => &mut self.ram[address as usize & 0x7fff],
+        };
+
+        *count = count.saturating_add(1);
+    }
+
+    pub fn count(&self, location: Location) -> u32 {
+        match location {
+            Location::Rom { bank, address } => self
+                .rom_banks
+                .get(bank)
+                .and_then(|counts| counts.as_ref())
+                .map_or(0, |counts| counts[address as usize & 0x3fff]),
+            Location::Ram(address) => self.ram[address as usize & 0x7fff],
+        }
+    }
+
+    /// The `max` most executed locations, most executed first.
+    pub fn hot_spots(&self, max: usize) -> Vec<HotSpot> {
+        let rom = self
+            .rom_banks
+            .iter()
+            .enumerate()
+            .filter_map(|(bank, counts)| Some((bank, counts.as_ref()?)))
+            .flat_map(|(bank, counts)| {
+                counts
+                    .iter()
+                    .enumerate()
+                    .map(move |(offset, count)| HotSpot {
+                        location: Location::Rom {
+                            bank,
+                            address: if bank == 0 { 0 } else { 0x4000 } | offset as u16,
+                        },
+                        count: *count,
+                    })
+            });
+        let ram = self.ram.iter().enumerate().map(|(offset, count)| HotSpot {
+            location: Location::Ram(0x8000 | offset as u16),
+            count: *count,
+        });
+
+        let mut hot_spots: Vec<_> = rom.chain(ram).filter(|spot| spot.count > 0).collect();
+        hot_spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.location.cmp(&b.location)));
+        hot_spots.truncate(max);
+        hot_spots
+    }
+
+    pub fn clear(&mut self) {
+        for counts in self.rom_banks.iter_mut() {
+            *counts = None;
+        }
+
+        self.ram.iter_mut().for_each(|count| *count = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExecutionProfile, HotSpot, Location};
+
+    #[test]
+    fn hot_spots() {
+        let mut profile = ExecutionProfile::new(2);
+        let loop_start = Location::Rom {
+            bank: 1,
+            address: 0x4123,
+        };
+
+        for _ in 0..3 {
+            profile.record(loop_start);
+        }
+        profile.record(Location::Ram(0xff80));
+        profile.record(Location::Rom {
+            bank: 0,
+            address: 0x0150,
+        });
+        profile.record(Location::Ram(0xff80));
+
+        assert_eq!(profile.count(loop_start), 3);
+        assert_eq!(
+            profile.count(Location::Rom {
+                bank: 3,
+                address: 0x4123
+            }),
+            0
+        );
+        assert_eq!(
+            profile.hot_spots(2),
+            vec![
+                HotSpot {
+                    location: loop_start,
+                    count: 3
+                },
+                HotSpot {
+                    location: Location::Ram(0xff80),
+                    count: 2
+                },
+            ]
+        );
+
+        profile.clear();
+        assert!(profile.hot_spots(10).is_empty());
+    }
+}
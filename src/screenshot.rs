@@ -0,0 +1,61 @@
+use std::{fs::File, io::BufWriter, path::Path, str::FromStr};
+
+use anyhow::bail;
+use gameboy::device::Device;
+
+/// What F12 captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotMode {
+    /// The 160x144 pixels the game drew, exactly, without any overlay.
+    Native,
+    /// The window as it is shown, at its size and with everything drawn over
+    /// the game.
+    Displayed,
+}
+
+impl FromStr for ScreenshotMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<ScreenshotMode> {
+        match s {
+            "native" => Ok(ScreenshotMode::Native),
+            "displayed" => Ok(ScreenshotMode::Displayed),
+            _ => bail!("unknown screenshot mode {:?}", s),
+        }
+    }
+}
+
+/// Saves the game's 160x144 pixels, optionally with the overlay blended over
+/// them.
+pub fn save_screenshot<P: AsRef<Path>>(
+    device: &Device,
+    path: P,
+    overlay: bool,
+) -> anyhow::Result<()> {
+    let pixels = if overlay {
+        device.overlaid_framebuffer()
+    } else {
+        device.display_framebuffer().into()
+    };
+
+    save_png(path, 160, 144, png::ColorType::RGB, &pixels)
+}
+
+pub fn save_png<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    pixels: &[u8],
+) -> anyhow::Result<()> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    Ok(())
+}
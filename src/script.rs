@@ -0,0 +1,565 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+};
+
+use anyhow::{anyhow, bail, Context};
+use gameboy::{
+    device::Device,
+    memory::{mmu::JoypadButton, Memory},
+    overlay::{self, Color, TRANSPARENT},
+};
+
+use crate::{dirs::DataDirs, screenshot::save_screenshot};
+
+/// How long a headless `run` without a frame count waits for a breakpoint,
+/// ten minutes of emulated time.
+const MAX_RUN_FRAMES: usize = 10 * 60 * 60;
+
+const BUTTONS: [JoypadButton; 8] = [
+    JoypadButton::Up,
+    JoypadButton::Down,
+    JoypadButton::Left,
+    JoypadButton::Right,
+    JoypadButton::Start,
+    JoypadButton::Select,
+    JoypadButton::B,
+    JoypadButton::A,
+];
+
+/// A single debugger action from a script file.
+///
+/// Addresses, lengths and colors are written in hexadecimal (with or without
+/// a `0x` prefix), while frame and instruction counts and overlay coordinates
+/// are decimal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(u16),
+    Run(Option<usize>),
+    Step(usize),
+    DumpMemory {
+        address: u16,
+        length: u16,
+        path: PathBuf,
+    },
+    /// Saves the game's pixels, with the overlay blended over them if asked
+    /// for with `screenshot PATH overlay`.
+    Screenshot {
+        path: PathBuf,
+        overlay: bool,
+    },
+    Overlay(OverlayCommand),
+    /// Changes the seed of the random numbers `fuzz` presses buttons with.
+    Seed(u64),
+    /// Emulates the given amount of frames while pressing a random set of
+    /// buttons every frame, which are the same every run with the same seed.
+    Fuzz(usize),
+    Quit,
+}
+
+/// Drawing on the overlay shown over the game, see [`Overlay`](overlay::Overlay).
+/// Colors are `rrggbb`, or `rrggbbaa` to make them translucent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayCommand {
+    Pixel {
+        x: i32,
+        y: i32,
+        color: Color,
+    },
+    Rect {
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: Color,
+        filled: bool,
+    },
+    Text {
+        x: i32,
+        y: i32,
+        color: Color,
+        text: String,
+    },
+    /// Shows the byte at an address in hexadecimal, redrawn every frame.
+    Watch {
+        address: u16,
+        x: i32,
+        y: i32,
+        color: Color,
+    },
+    /// Erases the overlay and stops all watches.
+    Clear,
+}
+
+pub struct Script {
+    commands: VecDeque<Command>,
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Script> {
+        let source = fs::read_to_string(path).context("failed to read script file")?;
+        Script::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<Script> {
+        let mut commands = VecDeque::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let command = parse_command(line).with_context(|| format!("line {}", i + 1))?;
+            commands.push_back(command);
+        }
+
+        Ok(Script { commands })
+    }
+
+    pub fn next_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+}
+
+fn parse_command(line: &str) -> anyhow::Result<Command> {
+    let mut args = line.split_whitespace();
+    let name = args.next().ok_or_else(|| anyhow!("empty command"))?;
+    let mut arg = |what: &str| args.next().ok_or_else(|| anyhow!("missing {}", what));
+
+    let command = match name {
+        "break" => Command::Break(parse_hex(arg("address")?)?),
+        "run" => Command::Run(arg("frames").ok().map(str::parse).transpose()?),
+        "step" => Command::Step(arg("count").ok().map(str::parse).transpose()?.unwrap_or(1)),
+        "dumpmem" => Command::DumpMemory {
+            address: parse_hex(arg("address")?)?,
+            length: parse_hex(arg("length")?)?,
+            path: arg("output path")?.into(),
+        },
+        "screenshot" => Command::Screenshot {
+            path: arg("output path")?.into(),
+            overlay: match arg("overlay") {
+                Ok("overlay") => true,
+                Ok(other) => bail!("unknown screenshot option {:?}", other),
+                Err(_) => false,
+            },
+        },
+        "overlay" => Command::Overlay(parse_overlay(&mut args)?),
+        "seed" => Command::Seed(arg("seed")?.parse().context("invalid seed")?),
+        "fuzz" => Command::Fuzz(arg("frames")?.parse()?),
+        "quit" => Command::Quit,
+        _ => bail!("unknown command {:?}", name),
+    };
+
+    Ok(command)
+}
+
+fn parse_overlay(args: &mut SplitWhitespace) -> anyhow::Result<OverlayCommand> {
+    let shape = args
+        .next()
+        .ok_or_else(|| anyhow!("missing overlay shape"))?;
+    let mut arg = |what: &str| args.next().ok_or_else(|| anyhow!("missing {}", what));
+
+    let command = match shape {
+        "pixel" => OverlayCommand::Pixel {
+            x: parse_coordinate(arg("x")?)?,
+            y: parse_coordinate(arg("y")?)?,
+            color: parse_color(arg("color")?)?,
+        },
+        "rect" | "fill" => OverlayCommand::Rect {
+            x: parse_coordinate(arg("x")?)?,
+            y: parse_coordinate(arg("y")?)?,
+            width: parse_coordinate(arg("width")?)?,
+            height: parse_coordinate(arg("height")?)?,
+            color: parse_color(arg("color")?)?,
+            filled: shape == "fill",
+        },
+        "text" => OverlayCommand::Text {
+            x: parse_coordinate(arg("x")?)?,
+            y: parse_coordinate(arg("y")?)?,
+            color: parse_color(arg("color")?)?,
+            text: args.collect::<Vec<_>>().join(" "),
+        },
+        "watch" => OverlayCommand::Watch {
+            address: parse_hex(arg("address")?)?,
+            x: parse_coordinate(arg("x")?)?,
+            y: parse_coordinate(arg("y")?)?,
+            color: parse_color(arg("color")?)?,
+        },
+        "clear" => OverlayCommand::Clear,
+        _ => bail!("unknown overlay shape {:?}", shape),
+    };
+
+    Ok(command)
+}
+
+fn parse_coordinate(value: &str) -> anyhow::Result<i32> {
+    value
+        .parse()
+        .with_context(|| format!("invalid coordinate {:?}", value))
+}
+
+fn parse_color(value: &str) -> anyhow::Result<Color> {
+    let digits = value.trim_start_matches("0x");
+    let rgba = u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|rgba| match digits.len() {
+            6 => Some(rgba << 8 | 0xff),
+            8 => Some(rgba),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("invalid color {:?}, expected rrggbb or rrggbbaa", value))?;
+
+    Ok(rgba.to_be_bytes())
+}
+
+fn parse_hex(value: &str) -> anyhow::Result<u16> {
+    let digits = value.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).with_context(|| format!("invalid hex number {:?}", value))
+}
+
+/// Executes the commands that don't affect execution state, i.e. everything
+/// except `break`, `run`, `step` and `quit`.
+pub fn execute(device: &mut Device, command: &Command) -> anyhow::Result<()> {
+    match command {
+        Command::DumpMemory {
+            address,
+            length,
+            path,
+        } => {
+            let bytes = (0..*length)
+                .map(|i| device.read(address.wrapping_add(i)))
+                .collect::<Result<Vec<u8>, _>>()?;
+
+            File::create(path)?.write_all(&bytes)?;
+        }
+        Command::Screenshot { path, overlay } => save_screenshot(device, path, *overlay)?,
+        Command::Overlay(command) => draw_overlay(device, command),
+        Command::Seed(seed) => device.set_rng_seed(*seed),
+        Command::Fuzz(frames) => fuzz(device, *frames),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Emulates frames with random buttons held, stopping early if the CPU
+/// locks up or execution stops.
+fn fuzz(device: &mut Device, frames: usize) {
+    for _ in 0..frames {
+        let mut rng = device.frame_rng();
+        let (pressed, released): (Vec<_>, Vec<_>) =
+            BUTTONS.iter().copied().partition(|_| rng.next_bool());
+        device.press(&pressed);
+        device.release(&released);
+
+        run(device, &BTreeSet::new(), Some(1));
+        if device.is_locked() || device.stop_reason().is_some() {
+            break;
+        }
+    }
+
+    device.release(&BUTTONS);
+}
+
+fn draw_overlay(device: &mut Device, command: &OverlayCommand) {
+    let overlay = device.overlay_mut();
+
+    match *command {
+        OverlayCommand::Pixel { x, y, color } => overlay.set_pixel(x, y, color),
+        OverlayCommand::Rect {
+            x,
+            y,
+            width,
+            height,
+            color,
+            filled: true,
+        } => overlay.fill_rect(x, y, width, height, color),
+        OverlayCommand::Rect {
+            x,
+            y,
+            width,
+            height,
+            color,
+            filled: false,
+        } => overlay.draw_rect(x, y, width, height, color),
+        OverlayCommand::Text {
+            x,
+            y,
+            color,
+            ref text,
+        } => overlay.draw_text(x, y, text, color),
+        OverlayCommand::Watch {
+            address,
+            x,
+            y,
+            color,
+        } => device.add_vblank_hook(Box::new(move |mmu, overlay| {
+            let text = match mmu.read(address) {
+                Ok(value) => format!("{:02x}", value),
+                Err(_) => "--".to_owned(),
+            };
+
+            let width = overlay::text_width(&text);
+            overlay.fill_rect(x, y, width, overlay::GLYPH_HEIGHT, TRANSPARENT);
+            overlay.draw_text(x, y, &text, color);
+        })),
+        OverlayCommand::Clear => {
+            overlay.clear();
+            device.clear_overlay_hooks();
+        }
+    }
+}
+
+/// Runs the script without a window, saving the game once it ends, also when
+/// a command fails.
+pub fn run_headless(mut device: Device, mut script: Script, dirs: &DataDirs) -> anyhow::Result<()> {
+    let result = run_commands(&mut device, &mut script);
+
+    match dirs.save_game(&mut device) {
+        Ok(path) => println!("saved game to {}", path.display()),
+        Err(err) => println!("warning: failed to save game: {}", err),
+    }
+
+    let finished = device
+        .finish_frame_sinks()
+        .context("failed to record video")
+        .and_then(|_| {
+            device
+                .finish_audio_sinks()
+                .context("failed to record sound")
+        });
+
+    result.and(finished)
+}
+
+fn run_commands(device: &mut Device, script: &mut Script) -> anyhow::Result<()> {
+    let mut breakpoints = BTreeSet::new();
+
+    while let Some(command) = script.next_command() {
+        match command {
+            Command::Break(address) => {
+                breakpoints.insert(address);
+            }
+            Command::Run(None) if breakpoints.is_empty() => {
+                bail!("`run` needs a frame count when no breakpoints are set")
+            }
+            Command::Run(None) => {
+                if run(device, &breakpoints, Some(MAX_RUN_FRAMES)) {
+                    bail!("no breakpoint was hit within {} frames", MAX_RUN_FRAMES);
+                }
+            }
+            Command::Run(frames) => {
+                run(device, &breakpoints, frames);
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    device.step();
+                }
+            }
+            Command::Quit => break,
+            command => execute(device, &command)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs until a breakpoint is hit, or until the given amount of frames has
+/// been emulated, returning whether all those frames ran.
+fn run(device: &mut Device, breakpoints: &BTreeSet<u16>, frames: Option<usize>) -> bool {
+    let mut frames_left = frames;
+
+    loop {
+        let frame = device.step();
+
+        if device.is_locked() {
+            println!("cpu locked up at {:#06x}", device.cpu().pc);
+            return false;
+        }
+
+        if let Some(reason) = device.stop_reason() {
+            println!("execution stopped at {:#06x}: {}", device.cpu().pc, reason);
+            return false;
+        }
+
+        if breakpoints.contains(&device.cpu().pc) {
+            return false;
+        }
+
+        if let (true, Some(left)) = (frame, frames_left.as_mut()) {
+            *left = left.saturating_sub(1);
+            if *left == 0 {
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use gameboy::{cartridge::Cartridge, device::Device};
+
+    use super::{parse_command, parse_hex, run_headless, Command, OverlayCommand, Script};
+    use crate::dirs::DataDirs;
+
+    #[test]
+    fn commands() {
+        let cases = [
+            ("break 0150", Command::Break(0x0150)),
+            ("break 0xC0DE", Command::Break(0xc0de)),
+            ("run", Command::Run(None)),
+            ("run 60", Command::Run(Some(60))),
+            ("step", Command::Step(1)),
+            ("step 10", Command::Step(10)),
+            (
+                "dumpmem c000 100 wram.bin",
+                Command::DumpMemory {
+                    address: 0xc000,
+                    length: 0x100,
+                    path: "wram.bin".into(),
+                },
+            ),
+            (
+                "screenshot shot.png",
+                Command::Screenshot {
+                    path: "shot.png".into(),
+                    overlay: false,
+                },
+            ),
+            (
+                "screenshot shot.png overlay",
+                Command::Screenshot {
+                    path: "shot.png".into(),
+                    overlay: true,
+                },
+            ),
+            (
+                "overlay fill 0 -2 10 8 ff000080",
+                Command::Overlay(OverlayCommand::Rect {
+                    x: 0,
+                    y: -2,
+                    width: 10,
+                    height: 8,
+                    color: [0xff, 0x00, 0x00, 0x80],
+                    filled: true,
+                }),
+            ),
+            (
+                "overlay text 1 2 ffffff hello  world",
+                Command::Overlay(OverlayCommand::Text {
+                    x: 1,
+                    y: 2,
+                    color: [0xff, 0xff, 0xff, 0xff],
+                    text: "hello world".to_string(),
+                }),
+            ),
+            (
+                "overlay watch c0a0 14 2 ffff00",
+                Command::Overlay(OverlayCommand::Watch {
+                    address: 0xc0a0,
+                    x: 14,
+                    y: 2,
+                    color: [0xff, 0xff, 0x00, 0xff],
+                }),
+            ),
+            ("overlay clear", Command::Overlay(OverlayCommand::Clear)),
+            ("seed 42", Command::Seed(42)),
+            ("fuzz 600", Command::Fuzz(600)),
+            ("quit", Command::Quit),
+        ];
+
+        for (line, expected) in cases.iter() {
+            assert_eq!(&parse_command(line).unwrap(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let cases = [
+            ("jump 0150", "unknown command \"jump\""),
+            ("break", "missing address"),
+            ("break zz", "invalid hex number \"zz\""),
+            ("break 10000", "invalid hex number \"10000\""),
+            ("run many", "invalid digit found in string"),
+            ("dumpmem c000 100", "missing output path"),
+            (
+                "screenshot shot.png color",
+                "unknown screenshot option \"color\"",
+            ),
+            ("overlay", "missing overlay shape"),
+            ("overlay circle 1 2", "unknown overlay shape \"circle\""),
+            ("overlay pixel x 2 ffffff", "invalid coordinate \"x\""),
+            (
+                "overlay pixel 1 2 fff",
+                "invalid color \"fff\", expected rrggbb or rrggbbaa",
+            ),
+            ("seed -1", "invalid seed"),
+        ];
+
+        for (line, message) in cases.iter() {
+            let err = parse_command(line).unwrap_err();
+            assert_eq!(err.to_string(), *message, "{}", line);
+        }
+    }
+
+    #[test]
+    fn hex() {
+        let cases = [
+            ("ff", Some(0xff)),
+            ("0x1234", Some(0x1234)),
+            ("FFFF", Some(0xffff)),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(parse_hex(value).ok(), *expected, "{}", value);
+        }
+
+        for value in ["", "0x", "10000", "-1", "0xg"].iter() {
+            assert!(parse_hex(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn comments_and_line_numbers() {
+        let mut script = Script::parse("run 1 # until the title\n\n# done\nquit\n").unwrap();
+        assert_eq!(script.next_command(), Some(Command::Run(Some(1))));
+        assert_eq!(script.next_command(), Some(Command::Quit));
+        assert_eq!(script.next_command(), None);
+
+        let err = Script::parse("step\n\nbogus\n").err().unwrap();
+        assert_eq!(err.to_string(), "line 3");
+    }
+
+    #[test]
+    fn headless_runs_save() {
+        let root = env::temp_dir().join(format!("gameboy-rs-script-{}", process::id()));
+        let mut dirs = DataDirs::new();
+        dirs.saves = root.join("saves");
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13a].copy_from_slice(b"TETRIS");
+        let save = dirs.saves.join("TETRIS.sav");
+        let device = || Device::new(Cartridge::from_bytes(rom.clone()));
+
+        // Running without a frame count or a breakpoint would never end.
+        let script = Script::parse("step\nrun\nquit\n").unwrap();
+        let err = run_headless(device(), script, &dirs).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`run` needs a frame count when no breakpoints are set"
+        );
+        assert!(save.exists());
+
+        fs::remove_file(&save).unwrap();
+        let script = Script::parse("run 1\nquit\n").unwrap();
+        run_headless(device(), script, &dirs).unwrap();
+        assert!(save.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
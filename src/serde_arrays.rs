@@ -0,0 +1,73 @@
+//! Serde helpers for arrays longer than the 32 elements serde supports, to
+//! use with `#[serde(with = "...")]`. They are written as sequences.
+
+use std::{convert::TryInto, fmt};
+
+use serde::{
+    de::{Error, Expected},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+struct Length(usize);
+
+impl Expected for Length {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} elements", self.0)
+    }
+}
+
+/// For arrays stored inline, like `[u8; 160]`.
+pub mod inline {
+    use super::*;
+
+    pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        serializer.collect_seq(array.iter())
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &Length(N)))
+    }
+}
+
+/// For arrays too large to keep on the stack, like `Box<[u8; 0x4000]>`.
+pub mod boxed {
+    use super::*;
+
+    // Serde hands over a reference to the field as it is.
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S, T, const N: usize>(
+        array: &Box<[T; N]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        serializer.collect_seq(array.iter())
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<Box<[T; N]>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .into_boxed_slice()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &Length(N)))
+    }
+}
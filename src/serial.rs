@@ -0,0 +1,232 @@
+use crate::cpu::Interrupts;
+
+/// M-cycles per transferred bit with the 8192 Hz internal clock.
+const NORMAL_BIT_CYCLES: usize = 128;
+/// M-cycles per transferred bit with the 262144 Hz CGB fast clock.
+const FAST_BIT_CYCLES: usize = 4;
+
+pub struct Serial {
+    pub data: u8,
+    pub transferring: bool,
+    pub internal_clock: bool,
+    pub fast_clock: bool,
+    /// Whether the CGB-only fast clock bit is available.
+    pub cgb_mode: bool,
+
+    bits_left: u8,
+    clock: usize,
+    output: Option<Vec<u8>>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            transferring: false,
+            internal_clock: false,
+            fast_clock: false,
+            cgb_mode: false,
+
+            bits_left: 0,
+            clock: 0,
+            output: None,
+        }
+    }
+
+    pub fn cycle(&mut self, cycles: usize) -> Interrupts {
+        if !self.transferring || !self.internal_clock {
+            return Interrupts::empty();
+        }
+
+        let period = if self.cgb_mode && self.fast_clock {
+            FAST_BIT_CYCLES
+        } else {
+            NORMAL_BIT_CYCLES
+        };
+
+        self.clock += cycles;
+        while self.clock >= period {
+            self.clock -= period;
+
+            // Without a link partner, the incoming line is pulled high.
+            self.shift(true);
+            if !self.transferring {
+                return Interrupts::SERIAL;
+            }
+        }
+
+        Interrupts::empty()
+    }
+
+    /// Shifts a single bit in from a link partner that provides the clock,
+    /// returning the bit shifted out. Does nothing when we provide the clock
+    /// ourselves or no transfer is in progress.
+    pub fn clock_external(&mut self, bit_in: bool) -> (bool, Interrupts) {
+        if !self.transferring || self.internal_clock {
+            return (true, Interrupts::empty());
+        }
+
+        let bit_out = self.shift(bit_in);
+        if self.transferring {
+            (bit_out, Interrupts::empty())
+        } else {
+            (bit_out, Interrupts::SERIAL)
+        }
+    }
+
+    /// Starts recording every byte we send while providing the clock, which is
+    /// how test ROMs print their results.
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(Vec::new);
+    }
+
+    /// The bytes sent since capturing started.
+    pub fn output(&self) -> &[u8] {
+        self.output.as_deref().unwrap_or_default()
+    }
+
+    /// The amount of bits left in the current transfer.
+    pub fn bits_left(&self) -> u8 {
+        self.bits_left
+    }
+
+    pub fn control(&self) -> u8 {
+        let mut result = if self.cgb_mode {
+            0b0111_1100
+        } else {
+            0b0111_1110
+        };
+
+        if self.transferring {
+            result |= 0b1000_0000;
+        }
+
+        if self.cgb_mode && self.fast_clock {
+            result |= 0b10;
+        }
+
+        if self.internal_clock {
+            result |= 0b1;
+        }
+
+        result
+    }
+
+    pub fn set_control(&mut self, value: u8) {
+        self.transferring = value & 0b1000_0000 != 0;
+        self.fast_clock = value & 0b10 != 0;
+        self.internal_clock = value & 0b1 != 0;
+
+        self.bits_left = if self.transferring { 8 } else { 0 };
+        self.clock = 0;
+
+        if self.transferring && self.internal_clock {
+            if let Some(output) = self.output.as_mut() {
+                output.push(self.data);
+            }
+        }
+    }
+
+    fn shift(&mut self, bit_in: bool) -> bool {
+        let bit_out = self.data & 0x80 != 0;
+        self.data = self.data << 1 | bit_in as u8;
+
+        self.bits_left -= 1;
+        if self.bits_left == 0 {
+            self.transferring = false;
+        }
+
+        bit_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Serial;
+    use crate::cpu::Interrupts;
+
+    /// Returns the amount of M-cycles until the transfer interrupt is raised.
+    fn transfer_cycles(serial: &mut Serial) -> usize {
+        let mut cycles = 0;
+
+        loop {
+            cycles += 1;
+            if serial.cycle(1) == Interrupts::SERIAL {
+                return cycles;
+            }
+
+            assert!(cycles <= 2048, "transfer did not complete");
+        }
+    }
+
+    #[test]
+    fn internal_clock_timing() {
+        // (cgb mode, control, M-cycles until the interrupt)
+        let cases = [
+            (false, 0x81, 1024),
+            (false, 0x83, 1024),
+            (true, 0x81, 1024),
+            (true, 0x83, 32),
+        ];
+
+        for (cgb_mode, control, expected) in cases.iter().copied() {
+            let mut serial = Serial::new();
+            serial.cgb_mode = cgb_mode;
+            serial.data = 0x42;
+            serial.set_control(control);
+
+            assert_eq!(
+                transfer_cycles(&mut serial),
+                expected,
+                "control {:#04x} (cgb={})",
+                control,
+                cgb_mode
+            );
+            assert_eq!(serial.data, 0xff);
+            assert!(!serial.transferring);
+            assert_eq!(serial.control() & 0x80, 0);
+        }
+    }
+
+    #[test]
+    fn external_clock() {
+        let mut serial = Serial::new();
+        serial.data = 0b1010_0000;
+        serial.set_control(0x80);
+
+        assert_eq!(serial.cycle(4096), Interrupts::empty());
+        assert!(serial.transferring);
+
+        let mut sent = 0;
+        for i in 0..8 {
+            let (bit, interrupt) = serial.clock_external(i % 2 == 0);
+            sent = sent << 1 | bit as u8;
+            assert_eq!(interrupt.is_empty(), i != 7);
+        }
+
+        assert_eq!(sent, 0b1010_0000);
+        assert_eq!(serial.data, 0b1010_1010);
+        assert!(!serial.transferring);
+    }
+
+    #[test]
+    fn capture_output() {
+        let mut serial = Serial::new();
+        serial.data = b'a';
+        serial.set_control(0x81);
+        serial.cycle(1024);
+
+        serial.capture_output();
+        for byte in b"ok".iter() {
+            serial.data = *byte;
+            serial.set_control(0x81);
+            serial.cycle(1024);
+        }
+
+        // Bytes clocked by a link partner aren't ours to print.
+        serial.data = b'!';
+        serial.set_control(0x80);
+
+        assert_eq!(serial.output(), b"ok");
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context};
+use gameboy::{
+    memory::watch::{WatchKind, Watchpoint},
+    model::io_addresses,
+};
+use serde_json::{json, Map, Value};
+
+use crate::condition::BreakCondition;
+
+/// The version written to session files, bumped whenever a change would make
+/// older versions of the debugger misread them.
+const VERSION: u64 = 2;
+
+/// The keys a session file may have. Anything else is most likely a typo, so
+/// it is rejected rather than silently dropped.
+const KEYS: [&str; 5] = [
+    "version",
+    "breakpoints",
+    "opcode_breakpoints",
+    "watchpoints",
+    "frozen",
+];
+
+/// The names watch kinds are shown and saved as.
+const WATCH_KINDS: [(&str, WatchKind); 4] = [
+    ("read", WatchKind::Read),
+    ("write", WatchKind::Write),
+    ("access", WatchKind::Access),
+    ("change", WatchKind::Change),
+];
+
+/// The debugger setup that is worth keeping between runs and sharing, such
+/// as in the repository of a homebrew project.
+///
+/// Sessions are stored as JSON, with addresses, opcodes and values written as
+/// hex strings like `"0x0150"` to match the debugger. A breakpoint with a
+/// condition is written as an object, watchpoints as the pattern they watch
+/// and the kind of access they stop on, and frozen addresses map to the value
+/// they are held at:
+/// ```json
+/// {
+///   "breakpoints": ["0x0150", { "address": "0x2a3f", "condition": "a == 0x12" }],
+///   "opcode_breakpoints": ["0xff"],
+///   "watchpoints": [{ "pattern": "NR1x", "kind": "write" }],
+///   "frozen": { "0xc0a0": "0x05" },
+///   "version": 2
+/// }
+/// ```
+/// Version 1 files, which only have plain breakpoints, are read as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Session {
+    /// The breakpoint addresses, with the condition each one only stops on.
+    pub breakpoints: BTreeMap<u16, Option<BreakCondition>>,
+    pub opcode_breakpoints: BTreeSet<u8>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Addresses that are written back to a value at the start of every VBlank.
+    pub frozen: BTreeMap<u16, u8>,
+}
+
+impl Session {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Session> {
+        let source = fs::read_to_string(path).context("failed to read session file")?;
+        Session::parse(&source)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        fs::write(path, self.to_json()).context("failed to write session file")
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<Session> {
+        let json: Value = serde_json::from_str(source).context("invalid JSON")?;
+        let json = json
+            .as_object()
+            .ok_or_else(|| anyhow!("a session should be an object"))?;
+
+        if let Some(key) = json.keys().find(|key| !KEYS.contains(&key.as_str())) {
+            bail!("unknown session key {:?}", key);
+        }
+
+        match json.get("version").and_then(Value::as_u64) {
+            Some(1) | Some(VERSION) => {}
+            Some(version) => bail!("unsupported session version {}", version),
+            None => bail!("missing session version"),
+        }
+
+        let breakpoints = match json.get("breakpoints") {
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(breakpoint)
+                .collect::<anyhow::Result<_>>()?,
+            Some(_) => bail!("breakpoints should be a list"),
+            None => BTreeMap::new(),
+        };
+        let opcode_breakpoints = hex_list(json, "opcode_breakpoints")?
+            .into_iter()
+            .map(|value| u8::from_str_radix(value, 16).map_err(|_| value))
+            .collect::<Result<_, _>>()
+            .map_err(|value| anyhow!("invalid breakpoint opcode {:?}", value))?;
+
+        let watchpoints = match json.get("watchpoints") {
+            Some(Value::Array(values)) => values
+                .iter()
+                .map(watchpoint)
+                .collect::<anyhow::Result<_>>()?,
+            Some(_) => bail!("watchpoints should be a list"),
+            None => Vec::new(),
+        };
+
+        let frozen = match json.get("frozen") {
+            Some(Value::Object(values)) => values
+                .iter()
+                .map(|(address, value)| {
+                    let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                        .map_err(|_| anyhow!("invalid frozen address {:?}", address))?;
+                    let value = value
+                        .as_str()
+                        .and_then(|value| {
+                            u8::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+                        })
+                        .ok_or_else(|| anyhow!("invalid frozen value {}", value))?;
+                    Ok((address, value))
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Some(_) => bail!("frozen should be an object"),
+            None => BTreeMap::new(),
+        };
+
+        Ok(Session {
+            breakpoints,
+            opcode_breakpoints,
+            watchpoints,
+            frozen,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let json = json!({
+            "version": VERSION,
+            "breakpoints": self
+                .breakpoints
+                .iter()
+                .map(|(address, condition)| {
+                    let address = format!("{:#06x}", address);
+                    match condition {
+                        Some(condition) => json!({
+                            "address": address,
+                            "condition": condition.to_string(),
+                        }),
+                        None => json!(address),
+                    }
+                })
+                .collect::<Vec<_>>(),
+            "opcode_breakpoints": self
+                .opcode_breakpoints
+                .iter()
+                .map(|opcode| format!("{:#04x}", opcode))
+                .collect::<Vec<_>>(),
+            "watchpoints": self
+                .watchpoints
+                .iter()
+                .map(|watchpoint| json!({
+                    "pattern": watchpoint.label,
+                    "kind": watch_kind_name(watchpoint.kind),
+                }))
+                .collect::<Vec<_>>(),
+            "frozen": self
+                .frozen
+                .iter()
+                .map(|(address, value)| {
+                    let value = format!("{:#04x}", value);
+                    (format!("{:#06x}", address), Value::String(value))
+                })
+                .collect::<Map<_, _>>(),
+        });
+
+        serde_json::to_string_pretty(&json).expect("sessions always serialize")
+    }
+}
+
+/// A breakpoint, either a hex address or an object with an address and a
+/// condition.
+fn breakpoint(value: &Value) -> anyhow::Result<(u16, Option<BreakCondition>)> {
+    let (address, condition) = match value {
+        Value::String(address) => (address.as_str(), None),
+        Value::Object(fields) => {
+            if let Some(key) = fields
+                .keys()
+                .find(|key| *key != "address" && *key != "condition")
+            {
+                bail!("unknown breakpoint key {:?}", key);
+            }
+
+            let address = fields
+                .get("address")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("breakpoint {} needs an address", value))?;
+            let condition = match fields.get("condition") {
+                Some(Value::String(condition)) => Some(
+                    condition
+                        .parse()
+                        .with_context(|| format!("invalid condition for breakpoint {}", address))?,
+                ),
+                Some(_) => bail!("the condition of breakpoint {} should be a string", address),
+                None => None,
+            };
+            (address, condition)
+        }
+        _ => bail!("breakpoints should be hex strings or objects"),
+    };
+
+    let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+        .map_err(|_| anyhow!("invalid breakpoint address {:?}", address))?;
+    Ok((address, condition))
+}
+
+/// A watchpoint, an object with the pattern of addresses it watches and the
+/// kind of access it stops on.
+fn watchpoint(value: &Value) -> anyhow::Result<Watchpoint> {
+    let fields = value
+        .as_object()
+        .ok_or_else(|| anyhow!("watchpoints should be objects"))?;
+    if let Some(key) = fields
+        .keys()
+        .find(|key| *key != "pattern" && *key != "kind")
+    {
+        bail!("unknown watchpoint key {:?}", key);
+    }
+
+    let pattern = fields
+        .get("pattern")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("watchpoint {} needs a pattern", value))?;
+    let addresses = io_addresses(pattern)
+        .ok_or_else(|| anyhow!("{:?} names no registers or addresses", pattern))?;
+    let kind = fields
+        .get("kind")
+        .and_then(Value::as_str)
+        .and_then(|name| WATCH_KINDS.iter().find(|(kind, _)| *kind == name))
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| anyhow!("watchpoint {} has no valid kind", value))?;
+
+    Ok(Watchpoint {
+        label: pattern.trim().to_string(),
+        addresses,
+        kind,
+    })
+}
+
+pub fn watch_kind_name(kind: WatchKind) -> &'static str {
+    let (name, _) = WATCH_KINDS
+        .iter()
+        .find(|(_, named)| *named == kind)
+        .expect("every watch kind has a name");
+    name
+}
+
+/// The hex digits of a list of hex strings, which may leave out the `0x`
+/// prefix. A missing list is empty, so sessions can leave out what they
+/// don't use.
+fn hex_list<'a>(json: &'a Map<String, Value>, key: &str) -> anyhow::Result<Vec<&'a str>> {
+    let values = match json.get(key) {
+        Some(Value::Array(values)) => values,
+        Some(_) => bail!("{} should be a list", key),
+        None => return Ok(Vec::new()),
+    };
+
+    values
+        .iter()
+        .map(|value| {
+            value
+                .as_str()
+                .map(|value| value.trim_start_matches("0x"))
+                .ok_or_else(|| anyhow!("{} should only contain hex strings", key))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use gameboy::{
+        memory::watch::{WatchKind, Watchpoint},
+        model::io_addresses,
+    };
+
+    use super::Session;
+
+    #[test]
+    fn round_trip() {
+        let mut session = Session::default();
+        session.breakpoints.insert(0x0150, None);
+        session
+            .breakpoints
+            .insert(0x2a3f, Some("a == 12".parse().unwrap()));
+        session.opcode_breakpoints.insert(0xff);
+        session.watchpoints.push(Watchpoint {
+            label: "NR1x".to_string(),
+            addresses: io_addresses("NR1x").unwrap(),
+            kind: WatchKind::Write,
+        });
+        session.watchpoints.push(Watchpoint {
+            label: "c000".to_string(),
+            addresses: io_addresses("c000").unwrap(),
+            kind: WatchKind::Change,
+        });
+        session.frozen.insert(0xc0a0, 0x05);
+
+        assert_eq!(Session::parse(&session.to_json()).unwrap(), session);
+        assert_eq!(
+            Session::parse(&Session::default().to_json()).unwrap(),
+            Session::default()
+        );
+    }
+
+    #[test]
+    fn version_1() {
+        let session =
+            Session::parse(r#"{ "version": 1, "breakpoints": ["0150", "0x2a3f"] }"#).unwrap();
+        assert_eq!(
+            session.breakpoints.keys().copied().collect::<Vec<_>>(),
+            [0x0150, 0x2a3f]
+        );
+        assert!(session.breakpoints.values().all(Option::is_none));
+    }
+
+    #[test]
+    fn malformed() {
+        let cases = [
+            ("[]", "a session should be an object"),
+            (r#"{ "breakpoints": [] }"#, "missing session version"),
+            (r#"{ "version": 3 }"#, "unsupported session version 3"),
+            (
+                r#"{ "version": 2, "brakepoints": [] }"#,
+                "unknown session key \"brakepoints\"",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": "0x0150" }"#,
+                "breakpoints should be a list",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": [150] }"#,
+                "breakpoints should be hex strings or objects",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": ["0xzz"] }"#,
+                "invalid breakpoint address \"0xzz\"",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": [{ "condition": "a == 1" }] }"#,
+                "breakpoint {\"condition\":\"a == 1\"} needs an address",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": [{ "address": "0x0150", "when": "a == 1" }] }"#,
+                "unknown breakpoint key \"when\"",
+            ),
+            (
+                r#"{ "version": 2, "breakpoints": [{ "address": "0x0150", "condition": "a" }] }"#,
+                "invalid condition for breakpoint 0x0150",
+            ),
+            (
+                r#"{ "version": 2, "opcode_breakpoints": ["0x100"] }"#,
+                "invalid breakpoint opcode \"100\"",
+            ),
+            (
+                r#"{ "version": 2, "watchpoints": {} }"#,
+                "watchpoints should be a list",
+            ),
+            (
+                r#"{ "version": 2, "watchpoints": ["NR1x"] }"#,
+                "watchpoints should be objects",
+            ),
+            (
+                r#"{ "version": 2, "watchpoints": [{ "kind": "read" }] }"#,
+                "watchpoint {\"kind\":\"read\"} needs a pattern",
+            ),
+            (
+                r#"{ "version": 2, "watchpoints": [{ "pattern": "NR9x", "kind": "read" }] }"#,
+                "\"NR9x\" names no registers or addresses",
+            ),
+            (
+                r#"{ "version": 2, "watchpoints": [{ "pattern": "LY", "kind": "peek" }] }"#,
+                "watchpoint {\"kind\":\"peek\",\"pattern\":\"LY\"} has no valid kind",
+            ),
+            (
+                r#"{ "version": 2, "frozen": ["0xc0a0"] }"#,
+                "frozen should be an object",
+            ),
+            (
+                r#"{ "version": 2, "frozen": { "0xc0a0": 5 } }"#,
+                "invalid frozen value 5",
+            ),
+            (
+                r#"{ "version": 2, "frozen": { "c0a0x": "0x05" } }"#,
+                "invalid frozen address \"c0a0x\"",
+            ),
+        ];
+
+        for (source, message) in cases.iter() {
+            let err = Session::parse(source).unwrap_err();
+            assert_eq!(err.to_string(), *message, "{}", source);
+        }
+        assert!(Session::parse("{").is_err());
+    }
+}
@@ -0,0 +1,669 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use crate::apu::SAMPLE_RATE;
+
+const WIDTH: u16 = 160;
+const HEIGHT: u16 = 144;
+
+/// The frames per second of the Game Boy screen.
+const FRAME_RATE: f64 = 4_194_304.0 / 70_224.0;
+
+/// Takes every frame the device finishes, for frontends and recorders that
+/// want the video as it is made. Sinks are added with
+/// [`Device::add_frame_sink`](crate::device::Device::add_frame_sink).
+pub trait FrameSink {
+    /// Takes a finished frame as 160x144 RGB pixels, with the overlay blended
+    /// over them, along with the number of frames finished since power on.
+    fn frame(&mut self, rgb: &[u8], frame_no: u64);
+
+    /// Finishes writing once no more frames come, returning the error that
+    /// stopped the sink early if there was one.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Takes the sound the device makes, as 16-bit stereo samples at
+/// [`SAMPLE_RATE`]. Sinks are added with
+/// [`Device::add_audio_sink`](crate::device::Device::add_audio_sink), and get
+/// the samples of every frame once it is finished.
+pub trait AudioSink {
+    /// Takes the samples made since the last call, interleaved as left and
+    /// right.
+    fn samples(&mut self, interleaved: &[i16]);
+
+    /// Finishes writing once no more samples come, returning the error that
+    /// stopped the sink early if there was one.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Throws every frame and sample away.
+pub struct NullSink;
+
+impl FrameSink for NullSink {
+    fn frame(&mut self, _rgb: &[u8], _frame_no: u64) {}
+}
+
+impl AudioSink for NullSink {
+    fn samples(&mut self, _interleaved: &[i16]) {}
+}
+
+/// Keeps the most recent frame for a frontend that draws at its own pace,
+/// like the window. Clones share the same frame, so one can be handed to the
+/// device while the frontend keeps another.
+#[derive(Clone, Default)]
+pub struct LatestFrame(Rc<RefCell<Option<Vec<u8>>>>);
+
+impl LatestFrame {
+    pub fn new() -> LatestFrame {
+        LatestFrame::default()
+    }
+
+    /// The frame finished since the last call, if there was one.
+    pub fn take(&self) -> Option<Vec<u8>> {
+        self.0.borrow_mut().take()
+    }
+}
+
+impl FrameSink for LatestFrame {
+    fn frame(&mut self, rgb: &[u8], _frame_no: u64) {
+        *self.0.borrow_mut() = Some(rgb.to_vec());
+    }
+}
+
+/// Writes every frame to a PNG file of its own in a directory, named after
+/// the frame number like `000123.png`.
+pub struct PngSequence {
+    dir: PathBuf,
+    error: Option<io::Error>,
+}
+
+impl PngSequence {
+    /// Writes frames to the given directory, creating it if needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<PngSequence> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(PngSequence {
+            dir: dir.as_ref().to_owned(),
+            error: None,
+        })
+    }
+
+    fn write(&self, rgb: &[u8], frame_no: u64) -> io::Result<()> {
+        let file = File::create(self.dir.join(format!("{:06}.png", frame_no)))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rgb)?;
+        Ok(())
+    }
+}
+
+impl FrameSink for PngSequence {
+    fn frame(&mut self, rgb: &[u8], frame_no: u64) {
+        if self.error.is_none() {
+            self.error = self.write(rgb, frame_no).err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Records the frames as a looping animated GIF.
+///
+/// Only every other frame is kept, as browsers show frames with delays under
+/// 2/100 of a second for much longer, which would slow the animation down.
+/// Each frame gets its own color table, which holds all the colors of a DMG
+/// frame and nearly always those of a CGB one. Frames with more than 256
+/// colors are reduced to 8-bit color.
+pub struct GifRecorder {
+    writer: BufWriter<File>,
+    /// The number of frames taken so far, including skipped ones.
+    frames: u64,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl GifRecorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<GifRecorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&WIDTH.to_le_bytes())?;
+        writer.write_all(&HEIGHT.to_le_bytes())?;
+        // No global color table, background color 0, square pixels.
+        writer.write_all(&[0, 0, 0])?;
+        // Loop forever.
+        writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(GifRecorder {
+            writer,
+            frames: 0,
+            error: None,
+            finished: false,
+        })
+    }
+
+    /// The delay after the frame in hundredths of a second, rounded so the
+    /// delays add up to the real time the frames took.
+    fn delay(&self) -> u16 {
+        let time = |frames: u64| (frames as f64 * 100.0 / FRAME_RATE).round() as u64;
+        (time(self.frames + 2) - time(self.frames)) as u16
+    }
+
+    fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let (colors, indices) = index_colors(rgb);
+        let table_bits = (1..=8).find(|bits| colors.len() <= 1 << bits).unwrap_or(8);
+        let delay = self.delay();
+
+        // A graphic control extension without transparency, then an image
+        // descriptor covering the screen with a local color table.
+        let writer = &mut self.writer;
+        writer.write_all(&[0x21, 0xf9, 4, 0])?;
+        writer.write_all(&delay.to_le_bytes())?;
+        writer.write_all(&[0, 0, 0x2c, 0, 0, 0, 0])?;
+        writer.write_all(&WIDTH.to_le_bytes())?;
+        writer.write_all(&HEIGHT.to_le_bytes())?;
+        writer.write_all(&[0x80 | (table_bits - 1)])?;
+
+        for i in 0..1 << table_bits {
+            writer.write_all(&colors.get(i).copied().unwrap_or([0; 3]))?;
+        }
+
+        let min_code_size = table_bits.max(2);
+        writer.write_all(&[min_code_size])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])?;
+
+        writer.flush()
+    }
+}
+
+impl FrameSink for GifRecorder {
+    fn frame(&mut self, rgb: &[u8], _frame_no: u64) {
+        if self.error.is_none() && !self.finished && self.frames & 1 == 0 {
+            self.error = self.write_frame(rgb).err();
+        }
+
+        self.frames += 1;
+    }
+
+    /// Writes the end of the GIF, after which frames are ignored.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if !self.finished {
+            self.finished = true;
+            self.writer.write_all(&[0x3b])?;
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for GifRecorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Keeps the most recent samples for an audio callback that takes them at its
+/// own pace, possibly on another thread, like those of cpal or Web Audio.
+/// Clones share the same buffer, so one can be handed to the device while the
+/// callback keeps another.
+///
+/// Once the buffer holds `capacity` samples, the oldest ones are dropped, so
+/// the sound never lags further behind than that.
+#[derive(Clone)]
+pub struct AudioRing {
+    buffer: Arc<Mutex<VecDeque<i16>>>,
+    capacity: usize,
+}
+
+impl AudioRing {
+    /// A buffer of `capacity` samples, counting left and right separately.
+    pub fn new(capacity: usize) -> AudioRing {
+        AudioRing {
+            buffer: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            // Whole stereo pairs are dropped, so left and right stay in order.
+            capacity: capacity & !1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `out` with the oldest samples, and the rest of it with silence
+    /// if there aren't enough. Returns how many samples were taken.
+    pub fn pop(&self, out: &mut [i16]) -> usize {
+        let mut buffer = self.buffer.lock().unwrap();
+        let taken = out.len().min(buffer.len());
+
+        for (sample, taken) in out.iter_mut().zip(buffer.drain(..taken)) {
+            *sample = taken;
+        }
+        out[taken..].iter_mut().for_each(|sample| *sample = 0);
+
+        taken
+    }
+}
+
+impl AudioSink for AudioRing {
+    fn samples(&mut self, interleaved: &[i16]) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.extend(interleaved);
+
+        if buffer.len() > self.capacity {
+            let excess = buffer.len() - self.capacity;
+            buffer.drain(..excess);
+        }
+    }
+}
+
+/// The most bytes of samples a WAV file can hold, as the size of the RIFF
+/// chunk includes the rest of the header and has to fit in 32 bits, rounded
+/// down to whole stereo samples. That is a little over 6 hours of sound.
+const MAX_WAV_DATA_SIZE: u32 = (u32::MAX - 36) & !3;
+
+/// Records the sound as a 16-bit stereo WAV file, until it reaches the size
+/// limit of WAV files.
+pub struct WavRecorder {
+    writer: BufWriter<File>,
+    /// The size of the samples written so far, in bytes.
+    data_size: u32,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl WavRecorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<WavRecorder> {
+        let mut recorder = WavRecorder {
+            writer: BufWriter::new(File::create(path)?),
+            data_size: 0,
+            error: None,
+            finished: false,
+        };
+
+        recorder.write_header()?;
+        Ok(recorder)
+    }
+
+    /// Writes the header for the samples written so far, which is written
+    /// again with the final sizes when finishing.
+    fn write_header(&mut self) -> io::Result<()> {
+        let writer = &mut self.writer;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + self.data_size).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        // 16 bytes of PCM format with 2 channels of 16 bits.
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 4).to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&self.data_size.to_le_bytes())
+    }
+
+    /// Writes the samples that still fit in the file. Once it is full, the
+    /// recording is finished and an error is returned.
+    fn write_samples(&mut self, interleaved: &[i16]) -> io::Result<()> {
+        let room = (MAX_WAV_DATA_SIZE - self.data_size) as usize / 2;
+        let (fitting, rest) = interleaved.split_at(interleaved.len().min(room));
+
+        for sample in fitting {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size = (2 * fitting.len() as u32)
+            .checked_add(self.data_size)
+            .expect("samples are only written while they fit");
+
+        if rest.is_empty() {
+            Ok(())
+        } else {
+            self.finish()?;
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the recording reached the 4 GiB size limit of WAV files",
+            ))
+        }
+    }
+}
+
+impl AudioSink for WavRecorder {
+    fn samples(&mut self, interleaved: &[i16]) {
+        if self.error.is_none() && !self.finished {
+            self.error = self.write_samples(interleaved).err();
+        }
+    }
+
+    /// Fills in the sizes in the header, after which samples are ignored.
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if !self.finished {
+            self.finished = true;
+            self.writer.seek(SeekFrom::Start(0))?;
+            self.write_header()?;
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// The colors in RGB pixels and the index of each pixel's color, reducing the
+/// colors to 3 bits of red and green and 2 bits of blue if there are more
+/// than 256 of them.
+fn index_colors(rgb: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut colors = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(rgb.len() / 3);
+
+    for pixel in rgb.chunks_exact(3) {
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = *lookup.entry(color).or_insert_with(|| {
+            colors.push(color);
+            colors.len() - 1
+        });
+
+        if index > 255 {
+            let reduced = rgb
+                .chunks_exact(3)
+                .map(|pixel| [pixel[0] & 0xe0, pixel[1] & 0xe0, pixel[2] & 0xc0])
+                .collect::<Vec<_>>();
+            return index_colors(&reduced.concat());
+        }
+
+        indices.push(index as u8);
+    }
+
+    (colors, indices)
+}
+
+/// Compresses color indices with the variable code size LZW of GIF, which
+/// starts with a clear code and ends with an end of information code.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut out = BitWriter::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_size = min_code_size + 1;
+    out.write(clear, code_size);
+
+    let mut prefix = match indices.first() {
+        Some(index) => *index as u16,
+        None => {
+            out.write(end, code_size);
+            return out.finish();
+        }
+    };
+
+    for index in indices[1..].iter().copied() {
+        if let Some(code) = codes.get(&(prefix, index)) {
+            prefix = *code;
+            continue;
+        }
+
+        out.write(prefix, code_size);
+
+        // Decoders can't go past 12-bit codes, so the table starts over.
+        if next_code >= 4095 {
+            out.write(clear, code_size);
+            codes.clear();
+            next_code = end + 1;
+            code_size = min_code_size + 1;
+        } else {
+            codes.insert((prefix, index), next_code);
+            next_code += 1;
+            if next_code > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+
+        prefix = index as u16;
+    }
+
+    out.write(prefix, code_size);
+    if next_code >= 1 << code_size && code_size < 12 {
+        code_size += 1;
+    }
+    out.write(end, code_size);
+
+    out.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    len: u8,
+}
+
+impl BitWriter {
+    /// Appends a code, least significant bit first.
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= (code as u32) << self.len;
+        self.len += size;
+
+        while self.len >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{
+        index_colors, lzw_encode, AudioRing, AudioSink, GifRecorder, WavRecorder, FRAME_RATE,
+        MAX_WAV_DATA_SIZE,
+    };
+
+    /// Decodes GIF LZW data like common decoders do.
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let mut bits = data
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| byte >> i & 1));
+        let mut read = |size: u8| {
+            (0..size).try_fold(0usize, |code, i| Some(code | (bits.next()? as usize) << i))
+        };
+
+        let mut out = Vec::new();
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+
+        loop {
+            let code = read(size).expect("missing end of information code");
+            if code == clear {
+                table = (0..clear + 2).map(|i| vec![i as u8]).collect();
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            } else if code == clear + 1 {
+                return out;
+            }
+
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let mut entry = previous.clone().unwrap();
+                    entry.push(entry[0]);
+                    entry
+                }
+            };
+
+            if let Some(mut previous) = previous {
+                if table.len() < 4096 {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                }
+            }
+
+            if table.len() >= 1 << size && size < 12 {
+                size += 1;
+            }
+
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Noise fills the code table several times over.
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..160 * 144)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8 % 5
+            })
+            .collect();
+        let flat = vec![2; 160 * 144];
+        let gradient: Vec<u8> = (0..160 * 144).map(|i| (i / 7 % 256) as u8).collect();
+
+        for (indices, min_code_size) in [(noise, 3), (flat, 2), (gradient, 8)].iter() {
+            let encoded = lzw_encode(indices, *min_code_size);
+            assert_eq!(&lzw_decode(&encoded, *min_code_size), indices);
+        }
+
+        assert!(lzw_decode(&lzw_encode(&[], 2), 2).is_empty());
+    }
+
+    #[test]
+    fn gif_delays() {
+        let path = env::temp_dir().join(format!("gameboy-rs-sink-{}.gif", process::id()));
+        let mut recorder = GifRecorder::create(&path).unwrap();
+
+        // Long past where the time in hundredths of a second overflows a u16.
+        let start = 39_990;
+        recorder.frames = start;
+        let mut total = 0;
+        while recorder.frames < start + 100 {
+            let delay = recorder.delay();
+            assert!(delay == 3 || delay == 4, "delay {}", delay);
+            total += delay as u64;
+            recorder.frames += 2;
+        }
+
+        let expected = 100.0 * 100.0 / FRAME_RATE;
+        assert!((total as f64 - expected).abs() <= 1.0, "{}", total);
+
+        drop(recorder);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wav_size_limit() {
+        let path = env::temp_dir().join(format!("gameboy-rs-sink-{}.wav", process::id()));
+        let mut recorder = WavRecorder::create(&path).unwrap();
+
+        // Room for two more stereo samples.
+        recorder.data_size = MAX_WAV_DATA_SIZE - 8;
+        recorder.samples(&[1, 2, 3, 4, 5, 6]);
+        recorder.samples(&[7, 8]);
+        assert_eq!(
+            recorder.finish().unwrap_err().to_string(),
+            "the recording reached the 4 GiB size limit of WAV files"
+        );
+        drop(recorder);
+
+        let wav = fs::read(&path).unwrap();
+        let size = |offset: usize| {
+            u32::from_le_bytes([
+                wav[offset],
+                wav[offset + 1],
+                wav[offset + 2],
+                wav[offset + 3],
+            ])
+        };
+        assert_eq!(size(4), MAX_WAV_DATA_SIZE + 36);
+        assert_eq!(size(40), MAX_WAV_DATA_SIZE);
+        assert_eq!(wav[44..], [1, 0, 2, 0, 3, 0, 4, 0]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn audio_ring() {
+        let mut ring = AudioRing::new(6);
+        ring.samples(&[1, 2, 3, 4]);
+        ring.samples(&[5, 6, 7, 8]);
+        assert_eq!(ring.len(), 6);
+
+        // The oldest pair was dropped, and missing samples are silent.
+        let mut out = [-1; 8];
+        assert_eq!(ring.clone().pop(&mut out), 6);
+        assert_eq!(out, [3, 4, 5, 6, 7, 8, 0, 0]);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn reduces_colors() {
+        let few = [255, 0, 0, 0, 0, 255, 255, 0, 0];
+        let (colors, indices) = index_colors(&few);
+        assert_eq!(colors, [[255, 0, 0], [0, 0, 255]]);
+        assert_eq!(indices, [0, 1, 0]);
+
+        let many: Vec<u8> = (0..300u32)
+            .flat_map(|i| vec![i as u8, (i >> 8) as u8 * 64, 0x3f])
+            .collect();
+        let (colors, indices) = index_colors(&many);
+        assert!(colors.len() <= 256);
+        assert_eq!(colors[indices[257] as usize], [0x00, 0x40, 0x00]);
+    }
+}
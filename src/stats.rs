@@ -0,0 +1,270 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use gameboy::device::Device;
+use serde_json::{json, Value};
+
+use crate::template::civil_date;
+
+/// How often the statistics are written while playing, so a crash loses at
+/// most this much of them.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How much a game was played, kept per ROM as JSON in the stats directory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayStats {
+    pub frames: u64,
+    /// The time the window was open, paused or not.
+    pub play_time: Duration,
+    pub sessions: u64,
+    /// How often the game was saved on exit.
+    pub saves: u64,
+    /// How often the game was started from a save.
+    pub loads: u64,
+    /// Seconds since the Unix epoch.
+    pub last_played: Option<u64>,
+}
+
+impl PlayStats {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<PlayStats> {
+        let source = fs::read_to_string(path).context("failed to read play statistics")?;
+        PlayStats::parse(&source)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        fs::write(path, self.to_json()).context("failed to write play statistics")
+    }
+
+    pub fn parse(source: &str) -> anyhow::Result<PlayStats> {
+        let json: Value = serde_json::from_str(source).context("invalid JSON")?;
+        let number = |key| match json.get(key) {
+            Some(value) => value
+                .as_u64()
+                .with_context(|| format!("{} should be a number", key)),
+            None => bail!("missing {}", key),
+        };
+
+        Ok(PlayStats {
+            frames: number("frames")?,
+            play_time: Duration::from_secs(number("play_seconds")?),
+            sessions: number("sessions")?,
+            saves: number("saves")?,
+            // Older statistics didn't count loads yet.
+            loads: json.get("loads").map_or(Ok(0), |_| number("loads"))?,
+            last_played: json.get("last_played").and_then(Value::as_u64),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let json = json!({
+            "frames": self.frames,
+            "play_seconds": self.play_time.as_secs(),
+            "sessions": self.sessions,
+            "saves": self.saves,
+            "loads": self.loads,
+            "last_played": self.last_played,
+        });
+
+        serde_json::to_string_pretty(&json).expect("play statistics always serialize")
+    }
+
+    /// A line like `played for 3h 12m over 5 sessions, last on 2021-03-14`,
+    /// followed by a line with the saves and loads if there were any.
+    pub fn summary(&self) -> String {
+        let minutes = self.play_time.as_secs() / 60;
+        let mut summary = format!(
+            "played for {}h {:02}m over {} session{}",
+            minutes / 60,
+            minutes % 60,
+            self.sessions,
+            if self.sessions == 1 { "" } else { "s" }
+        );
+
+        if let Some(timestamp) = self.last_played {
+            let (year, month, day) = civil_date(timestamp / 86400);
+            summary += &format!(", last on {}-{:02}-{:02}", year, month, day);
+        }
+
+        if self.saves > 0 || self.loads > 0 {
+            summary += &format!(
+                "\nsaved {} time{}, loaded {} time{}",
+                self.saves,
+                if self.saves == 1 { "" } else { "s" },
+                self.loads,
+                if self.loads == 1 { "" } else { "s" }
+            );
+        }
+
+        summary
+    }
+}
+
+/// Adds up the statistics of the game running in the window, writing them
+/// once a minute and when closing.
+pub struct StatsTracker {
+    path: PathBuf,
+    stats: PlayStats,
+    since: Instant,
+    frame_count: usize,
+    flushed: Instant,
+}
+
+impl StatsTracker {
+    /// Starts a session of the device's game, continuing from the statistics
+    /// in `dir`.
+    pub fn start(dir: &Path, device: &Device) -> StatsTracker {
+        let path = stats_path(dir, device);
+        let mut stats = load_or_default(&path);
+
+        if stats.sessions > 0 {
+            println!("{}", stats.summary());
+        }
+
+        stats.sessions += 1;
+        stats.last_played = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .ok();
+
+        StatsTracker {
+            path,
+            stats,
+            since: Instant::now(),
+            frame_count: device.frame_count(),
+            flushed: Instant::now(),
+        }
+    }
+
+    /// Adds the frames and time since the last update, writing the statistics
+    /// if that is due.
+    pub fn update(&mut self, device: &Device) {
+        self.stats.frames += device.frame_count().saturating_sub(self.frame_count) as u64;
+        self.frame_count = device.frame_count();
+        self.stats.play_time += self.since.elapsed();
+        self.since = Instant::now();
+
+        if self.flushed.elapsed() >= FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    pub fn record_save(&mut self) {
+        self.stats.saves += 1;
+    }
+
+    pub fn flush(&mut self) {
+        self.flushed = Instant::now();
+        write(&self.path, &self.stats);
+    }
+}
+
+/// Counts starting the device's game from a save, which happens before its
+/// session is started.
+pub fn record_load(dir: &Path, device: &Device) {
+    let path = stats_path(dir, device);
+    let mut stats = load_or_default(&path);
+    stats.loads += 1;
+    write(&path, &stats);
+}
+
+/// Where the statistics of the device's game are kept. They are named after
+/// the hash of the ROM, so they don't mix up games that share a title.
+fn stats_path(dir: &Path, device: &Device) -> PathBuf {
+    dir.join(format!("{:016x}.json", device.cart().hash()))
+}
+
+fn load_or_default(path: &Path) -> PlayStats {
+    if !path.exists() {
+        return PlayStats::default();
+    }
+
+    match PlayStats::load(path) {
+        Ok(stats) => stats,
+        Err(err) => {
+            println!("warning: {:?}, starting play statistics over", err);
+            PlayStats::default()
+        }
+    }
+}
+
+fn write(path: &Path, stats: &PlayStats) {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let result = fs::create_dir_all(dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| stats.save(path));
+    if let Err(err) = result {
+        println!("warning: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PlayStats;
+
+    #[test]
+    fn parse() {
+        let stats = PlayStats {
+            frames: 215_000,
+            play_time: Duration::from_secs(3725),
+            sessions: 5,
+            saves: 4,
+            loads: 3,
+            last_played: Some(1_615_680_000),
+        };
+        assert_eq!(PlayStats::parse(&stats.to_json()).unwrap(), stats);
+
+        let old = r#"{ "frames": 10, "play_seconds": 1, "sessions": 1, "saves": 0 }"#;
+        let old = PlayStats::parse(old).unwrap();
+        assert_eq!((old.loads, old.last_played), (0, None));
+
+        let cases = [
+            ("[", "invalid JSON"),
+            (
+                r#"{ "play_seconds": 1, "sessions": 1, "saves": 0 }"#,
+                "missing frames",
+            ),
+            (
+                r#"{ "frames": -1, "play_seconds": 1, "sessions": 1, "saves": 0 }"#,
+                "frames should be a number",
+            ),
+            (
+                r#"{ "frames": 1, "play_seconds": 1, "sessions": 1, "saves": 0, "loads": "2" }"#,
+                "loads should be a number",
+            ),
+        ];
+        for (source, message) in cases.iter() {
+            let err = PlayStats::parse(source).unwrap_err();
+            assert_eq!(err.to_string(), *message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let mut stats = PlayStats {
+            play_time: Duration::from_secs(3 * 3600 + 12 * 60 + 59),
+            sessions: 5,
+            last_played: Some(1_615_680_000),
+            ..PlayStats::default()
+        };
+        assert_eq!(
+            stats.summary(),
+            "played for 3h 12m over 5 sessions, last on 2021-03-14"
+        );
+
+        stats.play_time = Duration::from_secs(59);
+        stats.sessions = 1;
+        stats.last_played = None;
+        stats.saves = 1;
+        stats.loads = 2;
+        assert_eq!(
+            stats.summary(),
+            "played for 0h 00m over 1 session\nsaved 1 time, loaded 2 times"
+        );
+    }
+}
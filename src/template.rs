@@ -0,0 +1,234 @@
+use std::{
+    fmt::Write,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail};
+use gameboy::device::Device;
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Fps,
+    Frame,
+    Date,
+    Time,
+    Timestamp,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A pattern like `{title} [{fps}fps]`, where the fields in braces are filled
+/// in from the running game. Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> anyhow::Result<Template> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| anyhow!("unclosed field in {:?}", source))?;
+                    let field = match &rest[..end] {
+                        "title" => Field::Title,
+                        "fps" => Field::Fps,
+                        "frame" => Field::Frame,
+                        "date" => Field::Date,
+                        "time" => Field::Time,
+                        "timestamp" => Field::Timestamp,
+                        name => bail!("unknown field {{{}}} in {:?}", name, source),
+                    };
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => bail!("unmatched '}}' in {:?}", source),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    pub fn render(&self, values: &Values) -> String {
+        let mut result = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => result.push_str(text),
+                Part::Field(field) => values.write_field(*field, &mut result),
+            }
+        }
+
+        result
+    }
+}
+
+/// What the fields of a [`Template`] are filled in with.
+pub struct Values<'a> {
+    pub title: &'a str,
+    /// The emulated frames per second, as measured over the last second.
+    pub fps: f32,
+    pub frame: usize,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl<'a> Values<'a> {
+    pub fn new(device: &'a Device, fps: f32) -> Values<'a> {
+        Values {
+            title: device.cart().title().unwrap_or("gameboy"),
+            fps,
+            frame: device.frame_count(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn write_field(&self, field: Field, out: &mut String) {
+        let seconds = self.timestamp % 86400;
+
+        let _ = match field {
+            Field::Title => write!(out, "{}", self.title),
+            Field::Fps => write!(out, "{:.0}", self.fps),
+            Field::Frame => write!(out, "{}", self.frame),
+            Field::Date => {
+                let (year, month, day) = civil_date(self.timestamp / 86400);
+                write!(out, "{}-{:02}-{:02}", year, month, day)
+            }
+            Field::Time => write!(
+                out,
+                "{:02}{:02}{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+            Field::Timestamp => write!(out, "{}", self.timestamp),
+        };
+    }
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the
+/// proleptic Gregorian calendar, see http://howardhinnant.github.io/date_algorithms.html.
+pub fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year, month, day)
+}
+
+/// The templates for the window title and screenshot file names.
+pub struct Templates {
+    pub title: Template,
+    pub screenshot: Template,
+}
+
+pub const DEFAULT_TITLE: &str = "{title}";
+pub const DEFAULT_SCREENSHOT: &str = "{title}_{timestamp}.png";
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_date, Template, Values};
+
+    fn values() -> Values<'static> {
+        Values {
+            title: "TETRIS",
+            fps: 59.73,
+            frame: 1200,
+            // 2021-07-04 13:05:09 UTC
+            timestamp: 1_625_403_909,
+        }
+    }
+
+    fn render(source: &str) -> String {
+        source.parse::<Template>().unwrap().render(&values())
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(render("{title} [{fps}fps]"), "TETRIS [60fps]");
+        assert_eq!(render("{frame}"), "1200");
+        assert_eq!(render("{date}_{time}"), "2021-07-04_130509");
+        assert_eq!(render("{timestamp}.png"), "1625403909.png");
+        assert_eq!(render("no fields"), "no fields");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render("{{title}}"), "{title}");
+        assert_eq!(render("{{{title}}}"), "{TETRIS}");
+        assert_eq!(render("}}{{"), "}{");
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [
+            ("{name}", "unknown field {name} in \"{name}\""),
+            ("{}", "unknown field {} in \"{}\""),
+            ("{title", "unclosed field in \"{title\""),
+            ("x {", "unclosed field in \"x {\""),
+            ("title}", "unmatched '}' in \"title}\""),
+        ];
+
+        for (source, message) in cases.iter() {
+            let err = source.parse::<Template>().unwrap_err();
+            assert_eq!(err.to_string(), *message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(59), (1970, 3, 1));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(18628), (2021, 1, 1));
+    }
+}
@@ -18,7 +18,7 @@ impl Timer {
             divider: 0,
             counter: 0,
 
-            modulo: 0xff,
+            modulo: 0,
             speed: 0,
             enabled: false,
 
@@ -58,8 +58,14 @@ impl Timer {
         Interrupts::empty()
     }
 
+    /// Resets DIV along with the cycles counted towards its next increment.
+    pub fn reset_divider(&mut self) {
+        self.divider = 0;
+        self.div_clock = 0;
+    }
+
     pub fn timer_control(&self) -> u8 {
-        let mut result = self.speed;
+        let mut result = 0b1111_1000 | self.speed;
 
         if self.enabled {
             result |= 0b100;
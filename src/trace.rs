@@ -0,0 +1,271 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{
+    cpu::{Cpu, CpuSnapshot},
+    device::Device,
+    instruction::Instruction,
+    memory::Memory,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The text format of [Gameboy Doctor](https://github.com/robert/gameboy-doctor),
+    /// one line per instruction like
+    /// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`.
+    Doctor,
+    /// The same fields as 16-byte records: A, F, B, C, D, E, H and L, then SP
+    /// and PC in little endian, then the four bytes at PC.
+    Binary,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown trace format {0:?}, expected doctor or binary")]
+pub struct UnknownTraceFormat(String);
+
+impl FromStr for TraceFormat {
+    type Err = UnknownTraceFormat;
+
+    fn from_str(name: &str) -> Result<TraceFormat, UnknownTraceFormat> {
+        match name {
+            "doctor" => Ok(TraceFormat::Doctor),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(UnknownTraceFormat(name.to_owned())),
+        }
+    }
+}
+
+/// Writes the CPU state before every executed instruction, for comparing
+/// execution against other emulators.
+///
+/// Tracing starts once the boot ROM has handed over to the cartridge at
+/// 0x0100, which is where logs of other emulators usually start as well.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    frames: Range<usize>,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(writer: W, format: TraceFormat) -> Tracer {
+        Tracer {
+            writer: Box::new(writer),
+            format,
+            frames: 0..usize::MAX,
+            error: None,
+        }
+    }
+
+    /// Only traces instructions within the given frames, see
+    /// [`Device::frame_count`](crate::device::Device::frame_count).
+    pub fn with_frames(mut self, frames: Range<usize>) -> Tracer {
+        self.frames = frames;
+        self
+    }
+
+    /// The error that stopped tracing, if writing failed.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub(crate) fn trace<M: Memory>(&mut self, cpu: &Cpu, mem: &M, frame: usize) {
+        if self.error.is_some() || !self.frames.contains(&frame) {
+            return;
+        }
+
+        if let Err(err) = self.write(cpu, mem) {
+            self.error = Some(err);
+        }
+    }
+
+    fn write<M: Memory>(&mut self, cpu: &Cpu, mem: &M) -> io::Result<()> {
+        let mut pcmem = [0; 4];
+        for (i, byte) in pcmem.iter_mut().enumerate() {
+            *byte = mem.read(cpu.pc.wrapping_add(i as u16)).unwrap_or(0xff);
+        }
+
+        match self.format {
+            TraceFormat::Doctor => writeln!(
+                self.writer,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                cpu.a, cpu.f(), cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, cpu.pc,
+                pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+            ),
+            TraceFormat::Binary => {
+                let [sp_lo, sp_hi] = cpu.sp.to_le_bytes();
+                let [pc_lo, pc_hi] = cpu.pc.to_le_bytes();
+                self.writer.write_all(&[
+                    cpu.a, cpu.f(), cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, sp_lo, sp_hi, pc_lo,
+                    pc_hi, pcmem[0], pcmem[1], pcmem[2], pcmem[3],
+                ])
+            }
+        }
+    }
+}
+
+/// An instruction executed by [`Device::trace`].
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub pc: u16,
+    /// The opcode and its immediates.
+    pub bytes: Vec<u8>,
+    /// Shows as its disassembly.
+    pub instruction: Instruction,
+    /// The CPU state right before the instruction executed.
+    pub cpu: CpuSnapshot,
+}
+
+/// How long a trace waits for an interrupt to end a halt, a second's worth of
+/// T-cycles.
+const MAX_HALT_CYCLES: u64 = 1 << 22;
+
+/// Runs a device one instruction at a time, see [`Device::trace`].
+pub struct Trace<'a> {
+    device: &'a mut Device,
+}
+
+impl<'a> Trace<'a> {
+    pub(crate) fn new(device: &'a mut Device) -> Trace<'a> {
+        Trace { device }
+    }
+}
+
+impl<'a> Iterator for Trace<'a> {
+    type Item = TraceEntry;
+
+    fn next(&mut self) -> Option<TraceEntry> {
+        let start = self.device.cycles();
+        while self.device.cpu().halted {
+            // Without an enabled interrupt nothing ends the halt, and one that
+            // hasn't fired after a while likely never will.
+            if self.device.stop_reason().is_some()
+                || self.device.enabled_interrupts().is_empty()
+                || self.device.cycles() - start >= MAX_HALT_CYCLES
+            {
+                return None;
+            }
+
+            self.device.step();
+        }
+
+        if self.device.is_locked() || self.device.stop_reason().is_some() {
+            return None;
+        }
+
+        let pc = self.device.cpu().pc;
+        let cpu = self.device.cpu().snapshot();
+        let (instruction, next_pc) = self.device.peek_instruction().ok()?;
+        let bytes = (0..next_pc.wrapping_sub(pc))
+            .map(|offset| self.device.read(pc.wrapping_add(offset)).unwrap_or(0xff))
+            .collect();
+
+        self.device.step();
+        if self.device.stop_reason().is_some() {
+            return None;
+        }
+
+        Some(TraceEntry {
+            pc,
+            bytes,
+            instruction,
+            cpu,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use super::{TraceFormat, Tracer};
+    use crate::{
+        cpu::Cpu,
+        memory::{Memory, MemoryError},
+    };
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct CountingMemory;
+
+    impl Memory for CountingMemory {
+        fn read(&self, address: u16) -> Result<u8, MemoryError> {
+            Ok(address as u8)
+        }
+
+        fn write(&mut self, address: u16, _value: u8) -> Result<(), MemoryError> {
+            Err(MemoryError::ReadOnly { address })
+        }
+    }
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.a = 0x01;
+        cpu.f = 0xb0;
+        cpu.c = 0x13;
+        cpu.e = 0xd8;
+        cpu.h = 0x01;
+        cpu.l = 0x4d;
+        cpu.sp = 0xfffe;
+        cpu.pc = 0x01fe;
+        cpu
+    }
+
+    #[test]
+    fn doctor_format() {
+        let output = Output::default();
+        let mut tracer = Tracer::new(output.clone(), TraceFormat::Doctor);
+        tracer.trace(&cpu(), &CountingMemory, 0);
+
+        assert_eq!(
+            String::from_utf8(output.0.take()).unwrap(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:01FE PCMEM:FE,FF,00,01\n"
+        );
+    }
+
+    #[test]
+    fn binary_format() {
+        let output = Output::default();
+        let mut tracer = Tracer::new(output.clone(), TraceFormat::Binary);
+        tracer.trace(&cpu(), &CountingMemory, 0);
+
+        assert_eq!(
+            output.0.take(),
+            [
+                0x01, 0xb0, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d, 0xfe, 0xff, 0xfe, 0x01, 0xfe, 0xff,
+                0x00, 0x01
+            ]
+        );
+    }
+
+    #[test]
+    fn frame_range() {
+        let output = Output::default();
+        let mut tracer = Tracer::new(output.clone(), TraceFormat::Binary).with_frames(2..4);
+        for frame in 0..6 {
+            tracer.trace(&cpu(), &CountingMemory, frame);
+        }
+
+        assert_eq!(output.0.take().len(), 2 * 16);
+    }
+}
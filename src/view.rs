@@ -1,115 +1,265 @@
-use std::{
-    borrow::Cow,
-    time::{Duration, Instant},
-};
+use std::borrow::Cow;
 
-use gameboy::{device::Device, memory::mmu::JoypadButton};
+use gameboy::{
+    device::Device,
+    gpu::{Tilemap, Viewport},
+    sink::LatestFrame,
+};
 use glium::{
     glutin::{
         dpi::LogicalSize,
-        event::{ElementState, Event, VirtualKeyCode, WindowEvent},
-        event_loop::{ControlFlow, EventLoop},
-        window::WindowBuilder,
-        ContextBuilder,
+        event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     },
     texture::{ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat},
     uniforms::MagnifySamplerFilter,
-    BlitTarget, Display, Rect, Surface, Texture2d,
+    BlitTarget, Display, Frame, Rect, Surface, Texture2d,
+};
+
+use crate::{
+    debug,
+    dirs::DataDirs,
+    frontend::{self, Context, Frontend, Runtime, Trace},
+    screenshot::ScreenshotMode,
+    template::Templates,
 };
 
-pub fn start_view(mut device: Device) {
-    let event_loop = EventLoop::new();
-    let context = ContextBuilder::new().with_vsync(true);
-    let builder = WindowBuilder::new()
-        .with_title(device.cart().title().unwrap_or("gameboy"))
-        .with_inner_size(LogicalSize::new(160 * 3, 144 * 3));
-    let display = Display::new(builder, context, &event_loop).expect("failed to create display");
-
-    let texture = Texture2d::empty_with_format(
-        &display,
-        UncompressedFloatFormat::U8U8U8,
-        MipmapsOption::NoMipmap,
-        160,
-        144,
-    )
-    .expect("failed to create display texture");
-
-    let emulation_speed = 4194304.0 / 70224.0;
-    let mut last_frame = Instant::now();
-
-    event_loop.run(move |event, _, control_flow| match event {
-        Event::MainEventsCleared => {
-            let gl_window = display.gl_window();
-            gl_window.window().request_redraw();
+/// How many instructions are kept around to show in the debugger after a crash.
+const TRACE_LENGTH: usize = 256;
+
+/// The color of the visible area outlined in the tile map preview.
+const VIEWPORT_COLOR: [u8; 3] = [255, 0, 0];
+/// The thickness of the outline in tile map pixels, so it survives scaling
+/// the map down.
+const VIEWPORT_THICKNESS: usize = 3;
+
+/// Outlines the 160x144 area of a 256x256 RGB tile map shown on screen, which
+/// wraps around the edges of the map.
+pub fn draw_viewport(map: &mut [u8], viewport: Viewport) {
+    let mut plot = |x: usize, y: usize| {
+        let index = 3 * ((y % 256) * 256 + x % 256);
+        map[index..index + 3].copy_from_slice(&VIEWPORT_COLOR);
+    };
+
+    let (left, top) = (viewport.x as usize, viewport.y as usize);
+    for i in 0..VIEWPORT_THICKNESS {
+        for x in left..left + 160 {
+            plot(x, top + i);
+            plot(x, top + 143 - i);
         }
-        Event::RedrawRequested(_) => {
-            if last_frame.elapsed().as_secs_f32() >= 1.0 / emulation_speed {
-                last_frame += Duration::from_secs_f32(1.0 / emulation_speed);
-                device.step_frame();
-            }
+        for y in top..top + 144 {
+            plot(left + i, y);
+            plot(left + 159 - i, y);
+        }
+    }
+}
+
+struct GameView {
+    texture: Texture2d,
+    /// The frames the device finishes, which are only uploaded to the texture
+    /// once each.
+    frame: LatestFrame,
+    /// Shows both tile maps in the corner, toggled with F2.
+    map_preview: Option<(Texture2d, Vec<u8>)>,
+    trace: Trace,
+    /// Whether the device stopped and the user was told about it.
+    stopped: bool,
+    open_debugger: bool,
+}
 
-            let framebuffer = device.display_framebuffer();
+impl GameView {
+    fn new(display: &Display, trace: Trace, frame: LatestFrame) -> GameView {
+        let texture = Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::U8U8U8,
+            MipmapsOption::NoMipmap,
+            160,
+            144,
+        )
+        .expect("failed to create display texture");
+
+        GameView {
+            texture,
+            frame,
+            map_preview: None,
+            trace,
+            stopped: false,
+            open_debugger: false,
+        }
+    }
+
+    fn toggle_map_preview(&mut self, display: &Display) {
+        if self.map_preview.take().is_some() {
+            return;
+        }
+
+        let texture = Texture2d::empty_with_format(
+            display,
+            UncompressedFloatFormat::U8U8U8,
+            MipmapsOption::NoMipmap,
+            256,
+            256,
+        )
+        .expect("failed to create tile map texture");
+        self.map_preview = Some((texture, vec![0; 3 * 256 * 256]));
+    }
+
+    /// Draws the background tile map with the visible area outlined, and the
+    /// other map below it, in the top right corner.
+    fn draw_map_preview(&mut self, target: &mut Frame, device: &Device) {
+        let (texture, pixels) = match self.map_preview.as_mut() {
+            Some(preview) => preview,
+            None => return,
+        };
+
+        let (target_w, target_h) = target.get_dimensions();
+        let size = target_w / 5;
+        let margin = size / 16;
+
+        let background = device.gpu().background_tilemap();
+        let other = match background {
+            Tilemap::Low => Tilemap::High,
+            Tilemap::High => Tilemap::Low,
+        };
+
+        for (i, tilemap) in [background, other].iter().enumerate() {
+            device.render_tilemap(*tilemap, pixels);
+            if *tilemap == background {
+                draw_viewport(pixels, device.gpu().viewport());
+            }
 
             texture.write(
                 Rect {
                     left: 0,
                     bottom: 0,
-                    width: 160,
-                    height: 144,
+                    width: 256,
+                    height: 256,
                 },
                 RawImage2d {
-                    data: Cow::Borrowed(framebuffer),
-                    width: 160,
-                    height: 144,
+                    data: Cow::Borrowed(pixels),
+                    width: 256,
+                    height: 256,
                     format: ClientFormat::U8U8U8,
                 },
             );
 
-            let target = display.draw();
-            let (target_w, target_h) = target.get_dimensions();
+            let top = target_h.saturating_sub(margin + i as u32 * (size + margin));
             texture.as_surface().blit_whole_color_to(
-                &target,
+                target,
                 &BlitTarget {
-                    left: 0,
-                    bottom: target_h,
-                    width: target_w as i32,
-                    height: -(target_h as i32),
+                    left: target_w - size - margin,
+                    bottom: top,
+                    width: size as i32,
+                    height: -(size as i32),
                 },
-                MagnifySamplerFilter::Nearest,
+                MagnifySamplerFilter::Linear,
             );
-            target.finish().unwrap();
         }
-        Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-        } => {
-            if let Err(err) = device.cart().save() {
-                println!("failed to save game: {:?}", err)
+    }
+
+    /// Pauses on the last frame when the device stops, offering to open the
+    /// debugger to find out what went wrong.
+    fn check_stopped(&mut self, context: &mut Context) {
+        if self.stopped {
+            if self.open_debugger {
+                let debugger =
+                    debug::crash_debugger(context.display, context.device, self.trace.clone());
+                context.switch_to(debugger);
             }
 
-            *control_flow = ControlFlow::Exit
+            return;
         }
-        Event::WindowEvent {
-            event: WindowEvent::KeyboardInput { input, .. },
+
+        if let Some(reason) = context.device.stop_reason() {
+            println!(
+                "emulation stopped at {:#06x}: {}, press F1 to open the debugger",
+                context.device.cpu().pc,
+                reason
+            );
+
+            self.stopped = true;
+        }
+    }
+}
+
+impl Frontend for GameView {
+    fn status(&self) -> Option<&str> {
+        if self.stopped {
+            Some("stopped, press F1 to debug")
+        } else {
+            None
+        }
+    }
+
+    fn handle_event(&mut self, display: &Display, event: &Event<()>) {
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                },
             ..
-        } => {
-            let button = match input.virtual_keycode {
-                Some(VirtualKeyCode::Left) => JoypadButton::Left,
-                Some(VirtualKeyCode::Right) => JoypadButton::Right,
-                Some(VirtualKeyCode::Up) => JoypadButton::Up,
-                Some(VirtualKeyCode::Down) => JoypadButton::Down,
-                Some(VirtualKeyCode::Z) => JoypadButton::B,
-                Some(VirtualKeyCode::X) => JoypadButton::A,
-                Some(VirtualKeyCode::LControl) => JoypadButton::Start,
-                Some(VirtualKeyCode::LShift) => JoypadButton::Select,
-                _ => return,
-            };
-
-            match input.state {
-                ElementState::Pressed => device.press(&[button]),
-                ElementState::Released => device.release(&[button]),
+        } = event
+        {
+            match key {
+                VirtualKeyCode::F1 => self.open_debugger = self.stopped,
+                VirtualKeyCode::F2 => self.toggle_map_preview(display),
+                _ => {}
             }
         }
-        _ => {}
-    });
+    }
+
+    fn draw(&mut self, target: &mut Frame, context: &mut Context) {
+        self.check_stopped(context);
+
+        if let Some(framebuffer) = self.frame.take() {
+            self.texture.write(
+                Rect {
+                    left: 0,
+                    bottom: 0,
+                    width: 160,
+                    height: 144,
+                },
+                RawImage2d {
+                    data: Cow::Owned(framebuffer),
+                    width: 160,
+                    height: 144,
+                    format: ClientFormat::U8U8U8,
+                },
+            );
+        }
+
+        let (target_w, target_h) = target.get_dimensions();
+        self.texture.as_surface().blit_whole_color_to(
+            target,
+            &BlitTarget {
+                left: 0,
+                bottom: target_h,
+                width: target_w as i32,
+                height: -(target_h as i32),
+            },
+            MagnifySamplerFilter::Nearest,
+        );
+
+        self.draw_map_preview(target, context.device);
+    }
+}
+
+pub fn start_view(
+    mut device: Device,
+    dirs: DataDirs,
+    templates: Templates,
+    screenshot_mode: ScreenshotMode,
+) {
+    let trace = frontend::record_trace(&mut device, TRACE_LENGTH);
+    let frame = LatestFrame::new();
+    device.add_frame_sink(Box::new(frame.clone()));
+    let size = LogicalSize::new(160 * 3, 144 * 3);
+    let runtime = Runtime::new(device, dirs, templates, screenshot_mode, size);
+    let view = GameView::new(runtime.display(), trace, frame);
+    runtime.run(view);
 }
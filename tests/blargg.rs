@@ -0,0 +1,118 @@
+//! Runs blargg's test ROMs, which print their results to the serial port.
+#![cfg(feature = "test-roms")]
+
+mod common;
+
+use gameboy::device::Device;
+
+/// The sub-tests of cpu_instrs, each in its own ROM.
+const CPU_INSTRS: [&str; 11] = [
+    "01-special",
+    "02-interrupts",
+    "03-op sp,hl",
+    "04-op r,imm",
+    "05-op rp",
+    "06-ld r,r",
+    "07-jr,jp,call,ret,rst",
+    "08-misc instrs",
+    "09-op r,r",
+    "10-bit ops",
+    "11-op a,(hl)",
+];
+
+/// The sub-tests of mem_timing and mem_timing-2.
+const MEM_TIMING: [&str; 3] = ["01-read_timing", "02-write_timing", "03-modify_timing"];
+
+/// A minute of emulated time per ROM, after which it counts as hung.
+const MAX_FRAMES: usize = 60 * 60;
+
+/// Newer ROMs also report through cartridge RAM: a signature at 0xa001, the
+/// status at 0xa000 which is 0x80 while running and 0 once passed, and the
+/// printed text from 0xa004 on.
+fn memory_result(device: &Device) -> Option<Result<String, String>> {
+    let read = |address| device.read(address).unwrap_or(0);
+
+    if [read(0xa001), read(0xa002), read(0xa003)] != [0xde, 0xb0, 0x61] {
+        return None;
+    }
+
+    let status = read(0xa000);
+    if status == 0x80 {
+        return None;
+    }
+
+    let text: Vec<u8> = (0xa004..0xc000)
+        .map(read)
+        .take_while(|byte| *byte != 0)
+        .collect();
+    let text = String::from_utf8_lossy(&text).into_owned();
+
+    if status == 0 {
+        Some(Ok(text))
+    } else {
+        Some(Err(format!("{}status {:#04x}", text, status)))
+    }
+}
+
+/// Runs the ROM until it reports whether it passed, returning what it printed.
+fn run(mut device: Device) -> Result<String, String> {
+    device.capture_serial_output();
+
+    for _ in 0..MAX_FRAMES {
+        device.step_frame();
+
+        let text = String::from_utf8_lossy(device.serial_output()).into_owned();
+        if text.contains("Passed") {
+            return Ok(text);
+        } else if text.contains("Failed") {
+            return Err(text);
+        } else if let Some(result) = memory_result(&device) {
+            return result;
+        } else if let Some(reason) = device.stop_reason() {
+            return Err(format!("{}stopped: {}", text, reason));
+        }
+    }
+
+    let text = String::from_utf8_lossy(device.serial_output()).into_owned();
+    Err(format!("{}timed out after {} frames", text, MAX_FRAMES))
+}
+
+/// Runs every sub-test ROM in the directory, reporting each result.
+fn run_all(dir: &str, names: &[&str]) {
+    let mut failed = Vec::new();
+
+    for name in names.iter() {
+        let path = format!("{}/{}.gb", dir, name);
+        match run(common::load_rom(&path)) {
+            Ok(_) => println!("{}: ok", name),
+            Err(output) => {
+                println!("{}: failed\n{}", name, output.trim());
+                failed.push(*name);
+            }
+        }
+    }
+
+    assert!(failed.is_empty(), "failed sub-tests: {:?}", failed);
+}
+
+#[test]
+fn cpu_instrs() {
+    run_all("cpu_instrs/individual", &CPU_INSTRS);
+}
+
+#[test]
+fn instr_timing() {
+    if let Err(output) = run(common::load_rom("instr_timing/instr_timing.gb")) {
+        panic!("instr_timing failed\n{}", output.trim());
+    }
+}
+
+#[test]
+fn mem_timing() {
+    run_all("mem_timing/individual", &MEM_TIMING);
+}
+
+#[test]
+fn mem_timing_2() {
+    run_all("mem_timing-2/rom_singles", &MEM_TIMING);
+}
@@ -0,0 +1,51 @@
+use std::{env, fs, path::PathBuf};
+
+use gameboy::{cartridge::Cartridge, device::Device, input::InputSequence};
+
+/// The environment variable pointing to the directory the test ROMs are in.
+pub const ROMS_VAR: &str = "GAMEBOY_TEST_ROMS";
+
+/// The joypad input some ROMs need to run to completion, see the file itself.
+const INPUTS: &str = include_str!("../inputs.txt");
+
+pub fn rom_dir() -> PathBuf {
+    env::var_os(ROMS_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| panic!("{} is not set", ROMS_VAR))
+}
+
+/// The input listed for the ROM at the given path in `tests/inputs.txt`.
+fn input_for(rom: &str) -> Option<InputSequence> {
+    INPUTS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find_map(|line| {
+            let index = line
+                .find('=')
+                .unwrap_or_else(|| panic!("expected `path = input` in {:?}", line));
+            if line[..index].trim() != rom {
+                return None;
+            }
+
+            let input = line[index + 1..]
+                .parse()
+                .unwrap_or_else(|err| panic!("invalid input for {}: {}", rom, err));
+            Some(input)
+        })
+}
+
+/// Powers on a device with the ROM at the given path, relative to the test ROM
+/// directory, playing the input listed for it in `tests/inputs.txt`.
+pub fn load_rom(path: &str) -> Device {
+    let full_path = rom_dir().join(path);
+    let bytes = fs::read(&full_path)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", full_path.display(), err));
+
+    let mut device = Device::new(Cartridge::from_bytes(bytes));
+    if let Some(input) = input_for(path) {
+        device.set_input_sequence(input);
+    }
+
+    device
+}
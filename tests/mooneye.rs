@@ -0,0 +1,138 @@
+//! Runs the acceptance tests of mooneye-gb, which signal the end of a test by
+//! executing `ld b, b` with the result in the registers.
+#![cfg(feature = "test-roms")]
+
+mod common;
+
+use std::{cell::Cell, ffi::OsStr, fs, rc::Rc};
+
+use gameboy::{
+    device::Device,
+    instruction::{CpuRegister, Instruction, InstructionOperand},
+};
+
+/// The registers B, C, D, E, H and L of a passed test, the Fibonacci numbers.
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// Failed tests set every register to 0x42.
+const FAILED: [u8; 6] = [0x42; 6];
+
+/// Most tests finish within a few frames, the slowest take a couple seconds.
+const MAX_FRAMES: usize = 60 * 10;
+
+/// Whether a ROM is meant to pass on the DMG, going by the suffix of its file
+/// name, like `boot_regs-dmgABC` or `di_timing-GS`. Tests without a suffix
+/// apply to every model.
+fn runs_on_dmg(name: &str) -> bool {
+    match name.rfind('-') {
+        Some(index) => {
+            let models = &name[index + 1..];
+            models.contains("dmgABC") || models == "GS"
+        }
+        None => true,
+    }
+}
+
+/// Runs the ROM until it hits the `ld b, b` breakpoint, returning the
+/// registers it ends with on failure.
+fn run(mut device: Device) -> Result<(), String> {
+    let registers = Rc::new(Cell::new(None));
+    let hook_registers = registers.clone();
+    device.add_pre_instruction_hook(Box::new(move |info, cpu| {
+        if let Instruction::Load(
+            InstructionOperand::Register(CpuRegister::B),
+            InstructionOperand::Register(CpuRegister::B),
+        ) = info.instruction
+        {
+            hook_registers.set(Some([cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l]));
+        }
+    }));
+
+    for _ in 0..MAX_FRAMES {
+        while !device.step() {
+            match registers.get() {
+                Some(PASSED) => return Ok(()),
+                Some(FAILED) => return Err("failed".to_owned()),
+                Some(values) => return Err(format!("unexpected registers {:02x?}", values)),
+                None => {}
+            }
+
+            if let Some(reason) = device.stop_reason() {
+                return Err(format!("stopped at {:#06x}: {}", device.cpu().pc, reason));
+            }
+        }
+    }
+
+    Err(format!("timed out after {} frames", MAX_FRAMES))
+}
+
+/// Runs every DMG test ROM directly in the directory, reporting each result.
+fn run_dir(dir: &str) {
+    let mut names: Vec<String> = fs::read_dir(common::rom_dir().join(dir))
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", dir, err))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some(OsStr::new("gb")))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+        .filter(|name| runs_on_dmg(name))
+        .collect();
+    names.sort();
+
+    let mut failed = Vec::new();
+    for name in names.iter() {
+        match run(common::load_rom(&format!("{}/{}.gb", dir, name))) {
+            Ok(()) => println!("{}: ok", name),
+            Err(err) => {
+                println!("{}: {}", name, err);
+                failed.push(name.as_str());
+            }
+        }
+    }
+
+    assert!(
+        failed.is_empty(),
+        "{} of {} failed: {:?}",
+        failed.len(),
+        names.len(),
+        failed
+    );
+}
+
+#[test]
+fn acceptance() {
+    run_dir("acceptance");
+}
+
+#[test]
+fn acceptance_bits() {
+    run_dir("acceptance/bits");
+}
+
+#[test]
+fn acceptance_instr() {
+    run_dir("acceptance/instr");
+}
+
+#[test]
+fn acceptance_interrupts() {
+    run_dir("acceptance/interrupts");
+}
+
+#[test]
+fn acceptance_oam_dma() {
+    run_dir("acceptance/oam_dma");
+}
+
+#[test]
+fn acceptance_ppu() {
+    run_dir("acceptance/ppu");
+}
+
+#[test]
+fn acceptance_serial() {
+    run_dir("acceptance/serial");
+}
+
+#[test]
+fn acceptance_timer() {
+    run_dir("acceptance/timer");
+}
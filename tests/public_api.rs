@@ -0,0 +1,283 @@
+//! Compares the public items of the library with the snapshot in
+//! `tests/public-api.txt`, so changes to the API can't slip into a release
+//! unnoticed. After an intended change, update the snapshot by running
+//! `UPDATE_PUBLIC_API=1 cargo test --test public_api`, and bump the version
+//! in Cargo.toml accordingly when releasing.
+//!
+//! This reads the source rather than the compiled crate, so it only sees
+//! what is written out: public items, methods and fields, enum variants and
+//! their fields, bitflags, the items of public traits, derives, and trait
+//! impls for public types. Impls generated by macros or only derived with a
+//! feature enabled aren't listed.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const SNAPSHOT: &str = "tests/public-api.txt";
+
+/// Lists the public items of a module file and the public modules it
+/// declares, each line prefixed by the module path and the type or impl
+/// block it is in. The names of all types and traits declared, public or
+/// not, are added to `declared`.
+fn collect(file: &Path, module: &str, out: &mut Vec<String>, declared: &mut Vec<String>) {
+    let source = fs::read_to_string(file)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", file.display(), err));
+    let dir = match file.file_stem().and_then(|stem| stem.to_str()) {
+        Some("lib") | Some("mod") => file.parent().unwrap().to_owned(),
+        _ => file.with_extension(""),
+    };
+
+    let mut owner = String::new();
+    // The enum variant or bitflags struct the lines indented further are in.
+    let mut inner = String::new();
+    let mut derive = None;
+    let mut item_derive = None;
+    let mut lines = source.lines();
+
+    while let Some(line) = lines.next() {
+        // Unit tests come last in every file.
+        if line.starts_with("#[cfg(test)]") {
+            break;
+        }
+
+        if line.starts_with("#[derive(") {
+            derive = Some(line.to_owned());
+        }
+
+        // Attributes rustfmt wrapped over several lines, like long `#[error]`
+        // messages, have nothing to list.
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("#[") {
+            if !trimmed.ends_with(']') {
+                for next in &mut lines {
+                    if next.trim_end().ends_with(']') {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+
+        if !line.starts_with(|c: char| c.is_whitespace() || "}#/".contains(c)) && !line.is_empty() {
+            owner = signature(line);
+            item_derive = derive.take();
+            if let Some(name) = declared_name(&owner) {
+                declared.push(name.to_owned());
+            }
+            if owner.starts_with("impl") && owner.contains(" for ") {
+                out.push(format!("{} | {}", module, owner));
+            }
+        }
+
+        let indent = line.len() - trimmed.len();
+        let code = !trimmed.is_empty() && !trimmed.starts_with(|c: char| "}#/".contains(c));
+
+        if owner.starts_with("pub enum ") && indent > 0 && code {
+            if indent == 4 {
+                // Variants with fields rustfmt kept on one line keep them.
+                inner = match trimmed.strip_suffix("},") {
+                    Some(variant) => format!("{}}}", variant),
+                    None => signature(trimmed),
+                };
+                out.push(format!("{} | {} | {}", module, owner, inner));
+            } else if indent == 8 {
+                let field = signature(trimmed);
+                out.push(format!("{} | {} | {} | {}", module, owner, inner, field));
+            }
+            continue;
+        }
+
+        if owner == "bitflags!" && indent == 8 && trimmed.starts_with("const ") {
+            let flag = signature(trimmed);
+            out.push(format!("{} | {} | {} | {}", module, owner, inner, flag));
+            continue;
+        }
+
+        let trait_item = owner.starts_with("pub trait ")
+            && indent == 4
+            && ["fn ", "type ", "const "]
+                .iter()
+                .any(|keyword| trimmed.starts_with(keyword));
+        if !trimmed.starts_with("pub ") && !trait_item {
+            continue;
+        }
+
+        if let Some(name) = trimmed
+            .strip_prefix("pub mod ")
+            .and_then(|rest| rest.strip_suffix(';'))
+        {
+            let path = module_file(&dir, name);
+            collect(&path, &format!("{}::{}", module, name), out, declared);
+            continue;
+        }
+
+        // Signatures and imports rustfmt wrapped over several lines end at the
+        // body or the semicolon.
+        let mut item = trimmed.to_owned();
+        let function = item.starts_with("pub fn") || item.starts_with("fn ");
+        while (function && !item.contains('{') || item.starts_with("pub use "))
+            && !item.ends_with(';')
+        {
+            match lines.next() {
+                Some(next) => {
+                    item.push(' ');
+                    item.push_str(next.trim());
+                }
+                None => break,
+            }
+        }
+
+        let item = signature(&item)
+            .replace("( ", "(")
+            .replace("{ ", "{")
+            .replace(", }", "}")
+            .replace(", )", ")")
+            .replace("< ", "<")
+            .replace(", >", ">");
+
+        if owner == "bitflags!" {
+            inner = item.clone();
+        }
+
+        if line.starts_with(' ') {
+            out.push(format!("{} | {} | {}", module, owner, item));
+        } else {
+            out.push(format!("{} | {}", module, item));
+            if let Some(derive) = item_derive.take() {
+                out.push(format!("{} | {} | {}", module, item, derive));
+            }
+        }
+    }
+}
+
+/// The name of the type or trait an item like `pub(crate) struct Foo<T>`
+/// declares.
+fn declared_name(item: &str) -> Option<&str> {
+    let item = item
+        .trim_start_matches("pub(crate) ")
+        .trim_start_matches("pub ");
+    let rest = ["struct ", "enum ", "trait ", "type "]
+        .iter()
+        .find_map(|keyword| item.strip_prefix(keyword))?;
+    let end = rest
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+/// The trait and type names of an impl like `impl<T> fmt::Display for Foo<T>`.
+fn impl_names(item: &str) -> Option<(&str, &str)> {
+    let mut rest = item.strip_prefix("impl")?;
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let (end, _) = rest.char_indices().find(|(_, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })?;
+        rest = &rest[end + 1..];
+    }
+
+    let split = rest.find(" for ")?;
+    Some((base_name(&rest[..split]), base_name(&rest[split + 5..])))
+}
+
+/// The declaration of an item without its body or value.
+fn signature(line: &str) -> String {
+    let end = [" {", " = ", " where"]
+        .iter()
+        .filter_map(|pattern| line.find(pattern))
+        .min()
+        .unwrap_or(line.len());
+
+    line[..end]
+        .trim()
+        .trim_end_matches(&[';', ','][..])
+        .to_owned()
+}
+
+/// The name in a type like `&fmt::Formatter<'_>`, without its path or
+/// parameters.
+fn base_name(path: &str) -> &str {
+    let path = path.trim().trim_start_matches('&');
+    let path = &path[..path.find('<').unwrap_or(path.len())];
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+fn module_file(dir: &Path, name: &str) -> PathBuf {
+    let file = dir.join(format!("{}.rs", name));
+    if file.exists() {
+        file
+    } else {
+        dir.join(name).join("mod.rs")
+    }
+}
+
+#[test]
+fn public_api() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let mut items = Vec::new();
+    let mut declared = Vec::new();
+    collect(
+        &root.join("src/lib.rs"),
+        "gameboy",
+        &mut items,
+        &mut declared,
+    );
+
+    // Trait impls are only part of the API when the type is public, or when
+    // it is foreign and the trait is public.
+    let public: Vec<_> = items
+        .iter()
+        .filter_map(|item| declared_name(item.split(" | ").nth(1)?))
+        .map(str::to_owned)
+        .collect();
+    items.retain(|item| {
+        let impl_names = item.split(" | ").nth(1).and_then(impl_names);
+        match impl_names {
+            Some((trait_name, type_name)) => {
+                public.iter().any(|name| name == type_name)
+                    || !declared.iter().any(|name| name == type_name)
+                        && public.iter().any(|name| name == trait_name)
+            }
+            None => true,
+        }
+    });
+
+    let api = items.join("\n") + "\n";
+
+    let snapshot = root.join(SNAPSHOT);
+    if env::var_os("UPDATE_PUBLIC_API").is_some() {
+        fs::write(&snapshot, &api).expect("failed to write the public API snapshot");
+        return;
+    }
+
+    let expected = fs::read_to_string(&snapshot).unwrap_or_default();
+    if api != expected {
+        let removed = expected
+            .lines()
+            .filter(|line| !items.iter().any(|item| item == line));
+        let added = items
+            .iter()
+            .filter(|item| !expected.lines().any(|line| line == *item));
+
+        let mut diff = String::new();
+        for line in removed {
+            diff += &format!("- {}\n", line);
+        }
+        for line in added {
+            diff += &format!("+ {}\n", line);
+        }
+
+        panic!(
+            "the public API changed, run with UPDATE_PUBLIC_API=1 if this is intended:\n{}",
+            diff
+        );
+    }
+}